@@ -10,6 +10,7 @@ pub mod server_config {
 
     use crate::server_config::route::Route;
     use crate::type_aliases::{Path, Port};
+    use std::time::Duration;
 
     #[derive(Clone, Debug)]
     pub struct ServerConfig<'a> {
@@ -18,14 +19,98 @@ pub mod server_config {
         pub custom_error_path: Option<Path<'a>>,
         pub body_size_limit: usize,
         pub routes: Vec<Route<'a>>,
+        /// Secrets for signed and encrypted cookies. The first key is used for new cookies,
+        /// the rest are only used to verify cookies issued before a key rotation.
+        pub cookie_keys: Vec<&'a str>,
+        /// Rules for which clients may connect to any route of the server.
+        pub access_rules: Vec<AccessRule<'a>>,
+        /// Proxies, as addresses or CIDRs, whose `X-Forwarded-For` header is used to find the client.
+        pub trusted_proxies: Vec<&'a str>,
+        /// Maximum number of open connections to the server.
+        pub max_connections: Option<usize>,
+        /// Maximum number of open connections to the server from a single client.
+        pub max_connections_per_ip: Option<usize>,
+        /// Requests per client to any route of the server.
+        pub rate_limit: Option<RateLimit>,
+        /// Maximum length of the request line in bytes. Longer requests get `414 URI Too Long`.
+        pub max_request_line_length: usize,
+        /// Maximum number of headers in a request. More get `431 Request Header Fields Too Large`.
+        pub max_header_count: usize,
+        /// Maximum size of all headers of a request in bytes. Larger get `431 Request Header Fields Too Large`.
+        pub max_header_bytes: usize,
+        /// Time a connection may go without receiving anything while waiting for a request.
+        pub idle_timeout: Option<Duration>,
+        /// Time a client has to send the request line and headers, from the first byte of the request.
+        pub header_timeout: Option<Duration>,
+        /// Time a client has to send the body after the headers.
+        pub body_timeout: Option<Duration>,
+        /// Time a connection is kept open for the next request. `None` closes it after each response.
+        pub keep_alive_timeout: Option<Duration>,
+        /// Event loop threads serving the connections of the server. Connections are accepted on
+        /// the first loop and handed to the others in turn. The loops are shared by all servers,
+        /// so as many are started as the largest value of the servers. `0` is the same as `1`.
+        pub workers: usize,
+        /// Threads that handle requests, so handlers, CGI scripts and file responses do not block
+        /// the event loop. `0` handles requests on the event loop that read them.
+        pub handler_threads: usize,
+        /// Certificates for the ports of the server that accept HTTPS.
+        pub tls: Option<Tls<'a>>,
+        /// Serve HTTP/2, negotiated with ALPN on the TLS ports, and with prior knowledge or
+        /// `Upgrade: h2c` on the plain HTTP ports.
+        pub http2: bool,
+        /// Time between the comments sent on an event stream with nothing to send, so proxies
+        /// and clients don't give up on it. `None` sends none.
+        pub event_stream_heartbeat: Option<Duration>,
+        /// Format of the line written to `access.log` after every response, like
+        /// `COMBINED_LOG_FORMAT`. `None` keeps no access log.
+        pub access_log: Option<&'a str>,
+    }
+
+    /// HTTPS for some of the ports of a server.
+    #[derive(Clone, Debug)]
+    pub struct Tls<'a> {
+        /// Ports, out of the ports of the server, that accept TLS connections. The others accept plain HTTP.
+        pub ports: Vec<Port>,
+        /// Certificates chosen by the host name the client asks for with SNI. The first is used when the
+        /// client asks for no name, or for a name none of them is for.
+        pub certificates: Vec<Certificate<'a>>,
+        /// Redirect requests on the plain HTTP ports to the first TLS port with `308 Permanent Redirect`.
+        pub redirect_http: bool,
+    }
+
+    #[derive(Clone, Debug)]
+    pub struct Certificate<'a> {
+        /// Host names the certificate is used for, like `localhost` or `*.example.com`.
+        pub server_names: Vec<&'a str>,
+        /// PEM file with the certificate chain, starting with the certificate of the server.
+        pub cert_path: Path<'a>,
+        /// PEM file with the private key of the certificate.
+        pub key_path: Path<'a>,
+    }
+
+    /// Token bucket that allows `burst` requests at once and refills at `per_second` requests per second.
+    /// Servers with a `per_second` of 0 or less are not started.
+    #[derive(Clone, Copy, Debug)]
+    pub struct RateLimit {
+        pub per_second: f64,
+        pub burst: u32,
+    }
+
+    /// Allow or deny clients by address or CIDR, like `127.0.0.1`, `10.0.0.0/8`, `::1/128` or `all`.
+    /// Rules are checked in order and the first one that matches the client is used.
+    #[derive(Clone, Debug)]
+    pub enum AccessRule<'a> {
+        Allow(&'a str),
+        Deny(&'a str),
     }
 
     pub mod route {
-        use crate::server::Cgi;
-        use crate::server_config::ServerConfig;
+        use crate::server::{Cgi, WebSocketHandler};
+        use crate::server_config::{AccessRule, RateLimit, ServerConfig};
         use crate::type_aliases::{Bytes, FileExtension, Path};
         use http::{Method, Request, Response, StatusCode};
         use std::collections::HashMap;
+        use std::time::Duration;
 
         pub type HandlerFunc =
             fn(req: &Request<Bytes>, conf: &ServerConfig) -> Result<Response<Bytes>, StatusCode>;
@@ -35,10 +120,14 @@ pub mod server_config {
             pub url_path: Path<'a>,
             pub methods: Vec<Method>,
             pub handler: Option<HandlerFunc>,
+            /// Upgrades requests to the route to WebSocket connections served by the handler.
+            /// Requests that are not a WebSocket handshake get `426 Upgrade Required`.
+            pub websocket: Option<WebSocketHandler>,
             pub settings: Option<Settings<'a>>,
         }
 
-        #[derive(Clone, Debug)]
+        /// Settings of a route. Fields that are left out with `..Settings::default()` are off.
+        #[derive(Clone, Debug, Default)]
         pub struct Settings<'a> {
             pub http_redirections: Option<Vec<Path<'a>>>, // From endpoint, to path
             pub redirect_status_code: Option<StatusCode>,
@@ -47,6 +136,76 @@ pub mod server_config {
             pub default_if_request_is_dir: Option<Path<'a>>, // TODO: Implement
             pub cgi_def: Option<HashMap<FileExtension<'a>, Cgi>>,
             pub list_directory: bool,
+            pub basic_auth: Option<BasicAuth<'a>>,
+            pub bearer_auth: Option<BearerAuth<'a>>,
+            /// Rules checked after the rules of the server.
+            pub access_rules: Vec<AccessRule<'a>>,
+            /// Requests per client to the route, checked after the limit of the server.
+            pub rate_limit: Option<RateLimit>,
+            /// Store the files of `multipart/form-data` POST requests instead of the body.
+            pub upload: Option<Upload<'a>>,
+            /// Serve the files under the root as a WebDAV share, with the methods of
+            /// `webdav_methods` and locks. The route must also allow those methods.
+            pub webdav: bool,
+            /// Accept resumable uploads with the tus protocol.
+            pub tus: Option<Tus>,
+            /// Create the missing parent directories of files written with `PUT`, `POST` and
+            /// `PATCH`. Without it those requests get `409 Conflict`.
+            pub create_parent_dirs: bool,
+            /// Let `DELETE` remove directories with everything in them. Without it only empty
+            /// directories are removed, and others get `409 Conflict`.
+            pub recursive_delete: bool,
+        }
+
+        /// Resumable uploads with the tus protocol, version 1.0.0. Uploads are created with
+        /// `POST` to the route, and their parts appended with `PATCH` to the upload.
+        #[derive(Clone, Copy, Debug)]
+        pub struct Tus {
+            /// Largest upload, in bytes. Larger uploads get `413 Payload Too Large`.
+            pub max_size: Option<u64>,
+            /// Time after the last part before an unfinished upload is removed.
+            pub expiration: Option<Duration>,
+        }
+
+        /// Store the file parts of `multipart/form-data` POST requests in the directory of
+        /// the request path, under the root of the route.
+        #[derive(Clone, Debug)]
+        pub struct Upload<'a> {
+            /// Largest file, in bytes. Larger files get `413 Payload Too Large`.
+            pub max_file_size: Option<usize>,
+            /// Largest sum of all files of a request, in bytes.
+            pub max_total_size: Option<usize>,
+            /// Extensions files may have, like `"png"`. Others get `415 Unsupported Media Type`.
+            /// Empty allows any file.
+            pub allowed_extensions: Vec<&'a str>,
+        }
+
+        /// Require HTTP Basic authentication against an htpasswd file.
+        #[derive(Clone, Debug)]
+        pub struct BasicAuth<'a> {
+            pub realm: &'a str,
+            pub htpasswd_path: Path<'a>,
+        }
+
+        /// Require an `Authorization: Bearer` token. The token must either be one of `tokens`
+        /// or a JWT accepted by `jwt`.
+        #[derive(Clone, Debug)]
+        pub struct BearerAuth<'a> {
+            pub realm: &'a str,
+            pub tokens: Vec<&'a str>,
+            pub jwt: Option<Jwt<'a>>,
+            /// Scopes a JWT must carry in its `scope` claim.
+            pub required_scopes: Vec<&'a str>,
+        }
+
+        /// Settings for verifying HS256 signed JWTs.
+        #[derive(Clone, Debug)]
+        pub struct Jwt<'a> {
+            pub secret: &'a str,
+            pub issuer: Option<&'a str>,
+            pub audience: Option<&'a str>,
+            /// Allowed clock skew in seconds when checking `exp` and `nbf`.
+            pub leeway: u64,
         }
     }
 }
@@ -54,6 +213,12 @@ pub mod server_config {
 pub mod log {
     pub mod logging;
     pub use logging::*;
+
+    pub mod access;
+    pub use access::*;
+
+    pub mod rotation;
+    pub use rotation::*;
 }
 
 pub mod server {
@@ -61,14 +226,13 @@ pub mod server {
     pub use handle::*;
 
     use crate::server_config::route::Route;
-    use crate::type_aliases::Bytes;
+    use crate::type_aliases::{Bytes, Port};
     use http::{Method, Request, Response, StatusCode};
     use std::io;
-    use std::io::Read;
 
     use crate::server_config::ServerConfig;
     use mio::net::{TcpListener, TcpStream};
-    use mio::{Events, Interest, Poll, Token};
+    use mio::{Events, Interest, Poll, Token, Waker};
     use std::collections::HashMap;
     use std::net::SocketAddr;
 
@@ -90,18 +254,79 @@ pub mod server {
     pub mod sessions;
     pub use sessions::*;
 
+    pub mod auth;
+    pub use auth::*;
+
+    pub mod access;
+    pub use access::*;
+
+    pub mod limits;
+    pub use limits::*;
+
+    pub mod reader;
+    pub use reader::*;
+
+    pub mod timeouts;
+    pub use timeouts::*;
+
+    pub mod pool;
+    pub use pool::*;
+
+    pub mod tls;
+    pub use tls::*;
+
+    pub mod hpack;
+    pub use hpack::*;
+
+    pub mod http2;
+    pub use http2::*;
+
+    pub mod websocket;
+    pub use websocket::*;
+
+    pub mod sse;
+    pub use sse::*;
+
+    pub mod metrics;
+    pub use metrics::*;
+
+    pub mod status;
+    pub use status::*;
+
+    pub mod forms;
+    pub use forms::*;
+
+    pub mod upload;
+    pub use upload::*;
+
+    pub mod webdav;
+    pub use webdav::*;
+
+    pub mod tus;
+    pub use tus::*;
+
     mod state;
     pub use state::*;
 
     #[derive(Debug)]
     pub struct Server<'a> {
-        pub listeners: Vec<TcpListener>,
+        /// Listeners with the configured port they were bound for.
+        pub listeners: Vec<(Port, TcpListener)>,
         pub config: ServerConfig<'a>,
+        pub tls: Option<Arc<rustls::ServerConfig>>,
     }
 
     impl<'a> Server<'a> {
-        pub fn new(listeners: Vec<TcpListener>, config: ServerConfig<'a>) -> Self {
-            Self { listeners, config }
+        pub fn new(
+            listeners: Vec<(Port, TcpListener)>,
+            config: ServerConfig<'a>,
+            tls: Option<Arc<rustls::ServerConfig>>,
+        ) -> Self {
+            Self {
+                listeners,
+                config,
+                tls,
+            }
         }
     }
 
@@ -110,6 +335,11 @@ pub mod server {
         pub listener: TcpListener,
         pub token: Token,
         pub config: Arc<ServerConfig<'a>>,
+        /// Open connections of the server, shared by the listeners of the server.
+        pub connections: Arc<ConnectionCounter>,
+        pub pool: Option<Arc<HandlerPool>>,
+        /// Set if the listener accepts TLS connections.
+        pub tls: Option<Arc<rustls::ServerConfig>>,
     }
 
     impl Listener<'_> {
@@ -0,0 +1,167 @@
+use crate::log::{append_to_log, LogFileType};
+use crate::server_config::ServerConfig;
+use crate::type_aliases::Bytes;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{DateTime, Local};
+use http::Response;
+use std::net::IpAddr;
+use std::time::Instant;
+
+/// `%h %l %u %t "%r" %>s %b`, the Common Log Format.
+pub const COMMON_LOG_FORMAT: &str = r#"%h %l %u %t "%r" %>s %b"#;
+/// The Common Log Format with the referer and user agent of the request.
+pub const COMBINED_LOG_FORMAT: &str = r#"%h %l %u %t "%r" %>s %b "%{Referer}i" "%{User-Agent}i""#;
+
+/// # AccessEntry
+///
+/// A request as the access log sees it, from when it was received until its response is sent.
+#[derive(Clone, Debug)]
+pub struct AccessEntry {
+    pub client: IpAddr,
+    /// Request line and headers. Empty for requests that could not be read.
+    pub head: String,
+    pub received: DateTime<Local>,
+    pub started: Instant,
+}
+
+impl AccessEntry {
+    /// Starts an entry for the request with `head`, if the server keeps an access log.
+    pub fn begin(config: &ServerConfig, client: IpAddr, head: &str) -> Option<Self> {
+        config.access_log.map(|_| Self {
+            client,
+            head: head.to_string(),
+            received: Local::now(),
+            started: Instant::now(),
+        })
+    }
+
+    /// Writes the line of the entry to the access log, now that `response` is sent.
+    pub fn finish(self, config: &ServerConfig, response: &Response<Bytes>) {
+        if let Some(format) = config.access_log {
+            append_to_log(
+                LogFileType::Access,
+                &format_access(format, &self, config, response),
+            );
+        }
+    }
+
+    fn request_line(&self) -> &str {
+        self.head.lines().next().unwrap_or_default()
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        head_header(&self.head, name)
+    }
+
+    /// The user of Basic authentication, whether the credentials were accepted or not.
+    fn user(&self) -> Option<String> {
+        let credentials = self.header("authorization")?.strip_prefix("Basic ")?;
+        let decoded = String::from_utf8(STANDARD.decode(credentials.trim()).ok()?).ok()?;
+        decoded.split_once(':').map(|(user, _)| user.to_string())
+    }
+}
+
+/// # head_header
+///
+/// The value of the first header called `name` in the head of a request.
+pub fn head_header<'h>(head: &'h str, name: &str) -> Option<&'h str> {
+    head.lines()
+        .skip(1)
+        .filter_map(|line| line.split_once(':'))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.trim())
+}
+
+/// # log_unread_request
+///
+/// Writes the access log line of `response`, sent to a request that could not be read.
+pub fn log_unread_request(config: &ServerConfig, client: IpAddr, response: &Response<Bytes>) {
+    if let Some(entry) = AccessEntry::begin(config, client, "") {
+        entry.finish(config, response);
+    }
+}
+
+/// # format_access
+///
+/// The access log line of a request and its response, in `format`. It takes the directives of
+/// Apache's `LogFormat`:
+///
+/// - `%h` client address, behind `trusted_proxies` the one they forwarded the request for,
+///   `%l` always `-`, `%u` user of Basic authentication
+/// - `%t` time the request was received, `%r` request line, `%m` method, `%U` path, `%q` query
+///   string, `%H` protocol
+/// - `%s` or `%>s` status, `%b` body bytes or `-`, `%B` body bytes
+/// - `%D` duration in microseconds, `%T` in seconds, `%v` host of the server
+/// - `%{Name}i` request header, `%{Name}o` response header, `%%` a percent sign
+pub fn format_access(
+    format: &str,
+    entry: &AccessEntry,
+    config: &ServerConfig,
+    response: &Response<Bytes>,
+) -> String {
+    let request_line = entry.request_line();
+    let mut parts = request_line.split(' ');
+    let (method, target, protocol) = (parts.next(), parts.next(), parts.next());
+    let (path, query) = match target.map(|target| target.split_once('?')) {
+        Some(Some((path, query))) => (Some(path), Some(query)),
+        Some(None) => (target, None),
+        None => (None, None),
+    };
+    let duration = entry.started.elapsed();
+    let dash = |value: Option<&str>| value.filter(|v| !v.is_empty()).unwrap_or("-").to_string();
+
+    let mut line = String::new();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            line.push(c);
+            continue;
+        }
+        // `>` asks for the final status, which is the only one there is
+        if chars.peek() == Some(&'>') {
+            chars.next();
+        }
+
+        let value = match chars.next() {
+            Some('%') => "%".to_string(),
+            Some('h') => entry.client.to_string(),
+            Some('l') => "-".to_string(),
+            Some('u') => dash(entry.user().as_deref()),
+            Some('t') => entry.received.format("[%d/%b/%Y:%H:%M:%S %z]").to_string(),
+            Some('r') => dash(Some(request_line)),
+            Some('m') => dash(method),
+            Some('U') => dash(path),
+            Some('q') => query.map(|query| format!("?{query}")).unwrap_or_default(),
+            Some('H') => dash(protocol),
+            Some('s') => response.status().as_u16().to_string(),
+            Some('b') => match response.body().len() {
+                0 => "-".to_string(),
+                length => length.to_string(),
+            },
+            Some('B') => response.body().len().to_string(),
+            Some('D') => duration.as_micros().to_string(),
+            Some('T') => duration.as_secs().to_string(),
+            Some('v') => config.host.to_string(),
+            Some('{') => {
+                let name = chars.by_ref().take_while(|c| *c != '}').collect::<String>();
+                match chars.next() {
+                    Some('i') => dash(entry.header(&name)),
+                    Some('o') => dash(
+                        response
+                            .headers()
+                            .get(name.as_str())
+                            .and_then(|value| value.to_str().ok()),
+                    ),
+                    other => format!("%{{{name}}}{}", other.map(String::from).unwrap_or_default()),
+                }
+            }
+            // Unknown directives are written as they are
+            Some(other) => format!("%{other}"),
+            None => "%".to_string(),
+        };
+        // Quotes in values would break the fields apart
+        line.push_str(&value.replace('"', "\\\""));
+    }
+    line
+}
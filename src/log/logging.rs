@@ -1,44 +1,204 @@
-use chrono::Local;
+use crate::log::{rotate_file, LogRotation};
+use chrono::{DateTime, Local, NaiveDate};
+use rand::RngCore;
+use std::cell::RefCell;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
 use std::fmt::Write as FmtWrite;
 use std::fs;
-use std::fs::OpenOptions;
-use std::io::Write;
-use std::path::Path;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::time::Duration;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum LogFileType {
     Server,
     Client,
+    /// A line per response, in the format of the server's `access_log`.
+    Access,
 }
 
-const LOG_DIR: &str = "./src/log/log_files/";
+/// Severity of a log line. Lines below the `level` of the `LogSettings` are dropped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+            LogLevel::Trace => "trace",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// # LogSettings
+///
+/// How the server and client logs are written.
+#[derive(Clone, Debug)]
+pub struct LogSettings {
+    /// Least severe level that is written.
+    pub level: LogLevel,
+    /// Write a JSON object per line instead of text, for log collectors.
+    pub json: bool,
+    /// Directory of the log files. It is created if it doesn't exist.
+    pub dir: PathBuf,
+    /// When the log files are replaced by new ones.
+    pub rotation: LogRotation,
+    /// Rotated files kept per log. The oldest are deleted. `None` keeps all of them.
+    pub retention: Option<usize>,
+    /// Gzip rotated files.
+    pub compress: bool,
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        Self {
+            level: LogLevel::Info,
+            json: false,
+            dir: PathBuf::from(DEFAULT_LOG_DIR),
+            rotation: LogRotation::Startup,
+            retention: None,
+            compress: false,
+        }
+    }
+}
+
+pub const DEFAULT_LOG_DIR: &str = "./logs";
+/// Time buffered lines may wait before they are written to the files.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+/// Longest `X-Request-Id` taken from a client. Longer ones are replaced.
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+/// Errors kept for `recent_errors`.
+const RECENT_ERRORS: usize = 20;
+
+const FILE_TYPES: [LogFileType; 3] = [
+    LogFileType::Server,
+    LogFileType::Client,
+    LogFileType::Access,
+];
 
 impl LogFileType {
-    fn file_path(&self) -> String {
+    fn file_name(&self) -> &'static str {
         match self {
-            LogFileType::Server => format!("{LOG_DIR}server.log"),
-            LogFileType::Client => format!("{LOG_DIR}client.log"),
+            LogFileType::Server => "server.log",
+            LogFileType::Client => "client.log",
+            LogFileType::Access => "access.log",
+        }
+    }
+}
+
+/// `log_file_path` is where lines of `file_type` are written with the current settings.
+pub fn log_file_path(file_type: LogFileType) -> PathBuf {
+    lock(&logger().settings).dir.join(file_type.file_name())
+}
+
+/// An open log file.
+struct LogFile {
+    writer: BufWriter<File>,
+    /// Bytes in the file, including those still buffered.
+    size: u64,
+    /// Day the file was opened on.
+    day: NaiveDate,
+}
+
+impl LogFile {
+    fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            size: file.metadata()?.len(),
+            writer: BufWriter::new(file),
+            day: Local::now().date_naive(),
+        })
+    }
+
+    /// Checks if the file has to be replaced before `length` more bytes are written to it.
+    fn is_due(&self, rotation: LogRotation, length: usize) -> bool {
+        match rotation {
+            LogRotation::Startup => false,
+            LogRotation::Size(max) => self.size > 0 && self.size + length as u64 > max,
+            LogRotation::Daily => self.day != Local::now().date_naive(),
         }
     }
 }
-pub fn init_logs() {
-    let _ = fs::create_dir(LOG_DIR);
-    let files = [LogFileType::Server, LogFileType::Client];
 
-    for file_type in &files {
-        let file_path = file_type.file_path();
-        let path = Path::new(&file_path);
+/// # LoggedError
+///
+/// A line logged at the `Error` level.
+#[derive(Clone, Debug)]
+pub struct LoggedError {
+    pub time: DateTime<Local>,
+    pub message: String,
+    pub request_id: Option<String>,
+}
+
+/// The settings and open files of the logs, shared by all threads.
+struct Logger {
+    settings: Mutex<LogSettings>,
+    files: Mutex<HashMap<LogFileType, LogFile>>,
+    /// The last errors, oldest first.
+    errors: Mutex<VecDeque<LoggedError>>,
+}
+
+fn logger() -> &'static Logger {
+    static LOGGER: OnceLock<Logger> = OnceLock::new();
+    LOGGER.get_or_init(|| {
+        // Lines wait in the buffers at most until the next flush
+        std::thread::spawn(|| loop {
+            std::thread::sleep(FLUSH_INTERVAL);
+            flush_logs();
+        });
+        Logger {
+            settings: Mutex::new(LogSettings::default()),
+            files: Mutex::new(HashMap::new()),
+            errors: Mutex::new(VecDeque::with_capacity(RECENT_ERRORS)),
+        }
+    })
+}
+
+/// Locks `mutex`, even if another thread panicked while holding it.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
 
-        if path.exists() {
-            let now = Local::now();
-            let timestamp = now.format("%Y-%m-%d-%H:%M:%S").to_string();
-            let new_file_name = format!("{}-{}.log", file_path.trim_end_matches(".log"), timestamp);
+/// `init_logs` applies `settings`, and rotates the logs of the previous run. Errors are printed,
+/// since there is no log to write them to.
+pub fn init_logs(settings: LogSettings) {
+    let logger = logger();
+    let mut files = lock(&logger.files);
+    for (_, mut file) in files.drain() {
+        let _ = file.writer.flush();
+    }
 
-            fs::rename(file_path, &new_file_name).expect("Unable to rename file");
+    if let Err(e) = fs::create_dir_all(&settings.dir) {
+        eprintln!(
+            "Error: Failed to create the log directory {}. {e}",
+            settings.dir.display()
+        );
+    }
+    for file_type in FILE_TYPES {
+        let path = settings.dir.join(file_type.file_name());
+        if let Err(e) = rotate_file(&path, settings.compress, settings.retention) {
+            eprintln!("Error: Failed to rotate {}. {e}", path.display());
         }
     }
+    *lock(&logger.settings) = settings;
 }
 
 pub fn log_with_file_line(
+    level: LogLevel,
     file_type: LogFileType,
     log_message: String,
     file_source: &str,
@@ -47,37 +207,166 @@ pub fn log_with_file_line(
     if log_message.is_empty() {
         return;
     }
+    let json = {
+        let settings = lock(&logger().settings);
+        if level > settings.level {
+            return;
+        }
+        settings.json
+    };
 
-    let log_file = file_type.file_path();
-
-    let mut message = String::new();
     let now = Local::now();
-    write!(message, "[{}]", now.format("%d/%m/%y %H:%M:%S")).unwrap();
-    if write!(message, "[{}:{}] ", file_source, line_number).is_err() {
-        message.push_str("[Error writing file source and line]");
+    let request_id = current_request_id();
+    let mut message = String::new();
+
+    if json {
+        let mut line = serde_json::json!({
+            "time": now.to_rfc3339(),
+            "level": level.to_string(),
+            "file": file_source,
+            "line": line_number,
+            "message": &log_message,
+        });
+        if let Some(request_id) = &request_id {
+            line["request_id"] = request_id.as_str().into();
+        }
+        message.push_str(&line.to_string());
+    } else {
+        write!(message, "[{}]", now.format("%d/%m/%y %H:%M:%S")).unwrap();
+        write!(message, "[{}]", level.to_string().to_uppercase()).unwrap();
+        if write!(message, "[{}:{}]", file_source, line_number).is_err() {
+            message.push_str("[Error writing file source and line]");
+        }
+        if let Some(request_id) = &request_id {
+            write!(message, "[{request_id}]").unwrap();
+        }
+        write!(message, " {}", log_message).unwrap();
+    }
+
+    append_to_log(file_type, &message);
+    // Errors are written right away, in case the server doesn't survive them
+    if level == LogLevel::Error {
+        flush_logs();
+        remember_error(LoggedError {
+            time: now,
+            message: log_message,
+            request_id,
+        });
+    }
+}
+
+fn remember_error(error: LoggedError) {
+    let mut errors = lock(&logger().errors);
+    if errors.len() == RECENT_ERRORS {
+        errors.pop_front();
     }
-    writeln!(message, "{}", log_message).unwrap();
+    errors.push_back(error);
+}
+
+/// `recent_errors` are the last lines logged at the `Error` level, newest first.
+pub fn recent_errors() -> Vec<LoggedError> {
+    lock(&logger().errors).iter().rev().cloned().collect()
+}
+
+/// `append_to_log` writes `line` to the log file as it is, after rotating the file if it is due.
+/// Lines are buffered until the next `flush_logs`.
+pub fn append_to_log(file_type: LogFileType, line: &str) {
+    let line = format!("{line}\n");
+    let logger = logger();
+    let settings = lock(&logger.settings).clone();
+    let path = settings.dir.join(file_type.file_name());
+    let mut files = lock(&logger.files);
 
-    let mut file = match OpenOptions::new().create(true).append(true).open(log_file) {
-        Ok(f) => f,
-        _ => return,
+    if let Some(file) = files.get_mut(&file_type) {
+        if file.is_due(settings.rotation, line.len()) {
+            let _ = file.writer.flush();
+            files.remove(&file_type);
+            // On failure the lines keep going to the same file
+            if let Err(e) = rotate_file(&path, settings.compress, settings.retention) {
+                eprintln!("Error: Failed to rotate {}. {e}", path.display());
+            }
+        }
+    }
+
+    let file = match files.entry(file_type) {
+        Entry::Occupied(entry) => entry.into_mut(),
+        Entry::Vacant(entry) => match LogFile::open(&path) {
+            Ok(file) => entry.insert(file),
+            Err(_) => return,
+        },
     };
-    file.write_all(message.as_bytes())
-        .expect("Unable to write to file");
+    match file.writer.write_all(line.as_bytes()) {
+        Ok(()) => file.size += line.len() as u64,
+        // Reopened on the next line
+        Err(_) => {
+            files.remove(&file_type);
+        }
+    }
+}
+
+/// `flush_logs` writes the buffered lines to the log files.
+pub fn flush_logs() {
+    for file in lock(&logger().files).values_mut() {
+        let _ = file.writer.flush();
+    }
+}
+
+thread_local! {
+    static REQUEST_ID: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// `request_id` is the `X-Request-Id` of a request, if it is safe to log and send back, or a
+/// new random one.
+pub fn request_id(header: Option<&str>) -> String {
+    match header.map(str::trim) {
+        Some(id)
+            if !id.is_empty()
+                && id.len() <= MAX_REQUEST_ID_LENGTH
+                && id
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-_.:".contains(c)) =>
+        {
+            id.to_string()
+        }
+        _ => {
+            let mut bytes = [0; 8];
+            rand::thread_rng().fill_bytes(&mut bytes);
+            bytes.iter().map(|b| format!("{b:02x}")).collect()
+        }
+    }
+}
+
+/// `with_request_id` runs `f` with `id` attached to the lines it logs on this thread.
+pub fn with_request_id<T>(id: &str, f: impl FnOnce() -> T) -> T {
+    /// Puts back the previous id, even if `f` panics.
+    struct Restore(Option<String>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            REQUEST_ID.with(|current| *current.borrow_mut() = self.0.take());
+        }
+    }
+
+    let _restore = Restore(REQUEST_ID.with(|current| current.replace(Some(id.to_string()))));
+    f()
+}
+
+/// `current_request_id` is the id of the request being handled on this thread.
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.with(|current| current.borrow().clone())
 }
 
 #[test]
 fn test_log_with_file_line() {
-    log_with_file_line(LogFileType::Client, "".to_string(), "", 10);
+    log_with_file_line(LogLevel::Info, LogFileType::Client, "".to_string(), "", 10);
 }
 
 // Macro to simplify logging
 // Usage example
-// log!(LogFileType::Server, "This is a test log message");
-// log!(LogFileType::Client, "This is a test log message");
+// log!(LogLevel::Info, LogFileType::Server, "This is a test log message");
+// log!(LogLevel::Error, LogFileType::Client, "This is a test log message");
 #[macro_export]
 macro_rules! log {
-    ($file_type:expr, $log_message:expr) => {
-        $crate::log::log_with_file_line($file_type, $log_message, file!(), line!())
+    ($level:expr, $file_type:expr, $log_message:expr) => {
+        $crate::log::log_with_file_line($level, $file_type, $log_message, file!(), line!())
     };
 }
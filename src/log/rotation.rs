@@ -0,0 +1,117 @@
+use chrono::Local;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// When the log files are replaced by new ones. They are always replaced when the logs are
+/// initialized.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogRotation {
+    /// Only when the logs are initialized.
+    Startup,
+    /// Before a file grows past this many bytes.
+    Size(u64),
+    /// On the first line of a new day.
+    Daily,
+}
+
+/// # rotate_file
+///
+/// Renames the log file at `path` with the time it was replaced. The rotated file is compressed
+/// if `compress` is set, and the oldest rotated files beyond `retention` are deleted, on another
+/// thread so logging doesn't wait for them.
+pub fn rotate_file(path: &Path, compress: bool, retention: Option<usize>) -> io::Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    let rotated = rotated_path(path);
+    fs::rename(path, &rotated)?;
+
+    let path = path.to_path_buf();
+    std::thread::spawn(move || {
+        // One at a time, so pruning never sees a file that is being compressed
+        static MAINTENANCE: Mutex<()> = Mutex::new(());
+        let _guard = MAINTENANCE.lock().unwrap_or_else(|e| e.into_inner());
+
+        if compress {
+            if let Err(e) = compress_file(&rotated) {
+                eprintln!("Error: Failed to compress {}. {e}", rotated.display());
+            }
+        }
+        if let Some(retention) = retention {
+            prune(&path, retention);
+        }
+    });
+    Ok(())
+}
+
+/// The path `path` is rotated to, like `server-2024-10-10-135536.123456.log`.
+fn rotated_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let timestamp = Local::now().format("%Y-%m-%d-%H%M%S%.6f");
+
+    let taken = |name: &str| {
+        path.with_file_name(name).exists() || path.with_file_name(format!("{name}.gz")).exists()
+    };
+    let mut name = format!("{stem}-{timestamp}.log");
+    let mut count = 1;
+    while taken(&name) {
+        name = format!("{stem}-{timestamp}-{count}.log");
+        count += 1;
+    }
+    path.with_file_name(name)
+}
+
+/// `compress_file` replaces the file at `path` with a gzip file of the same name ending in `.gz`.
+fn compress_file(path: &Path) -> io::Result<()> {
+    let mut compressed = path.as_os_str().to_owned();
+    compressed.push(".gz");
+
+    let mut encoder = GzEncoder::new(File::create(&compressed)?, Compression::default());
+    io::copy(&mut File::open(path)?, &mut encoder)?;
+    encoder.finish()?;
+    fs::remove_file(path)
+}
+
+/// # rotated_files
+///
+/// The rotated files of the log file at `path`, oldest first.
+pub fn rotated_files(path: &Path) -> Vec<PathBuf> {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let prefix = format!("{stem}-");
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    let mut files = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|file| {
+                    let name = file.file_name().unwrap_or_default().to_string_lossy();
+                    name.starts_with(&prefix)
+                        && (name.ends_with(".log") || name.ends_with(".log.gz"))
+                })
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    // The timestamps in the names sort by time
+    files.sort();
+    files
+}
+
+/// `prune` deletes the oldest rotated files of the log file at `path`, keeping `retention`.
+fn prune(path: &Path, retention: usize) {
+    let files = rotated_files(path);
+    let excess = files.len().saturating_sub(retention);
+    for file in &files[..excess] {
+        if let Err(e) = fs::remove_file(file) {
+            eprintln!("Error: Failed to delete {}. {e}", file.display());
+        }
+    }
+}
@@ -1,13 +1,93 @@
 use localhost::log::init_logs;
-use localhost::server::start;
-use localhost::server_config::server_config;
+use localhost::server::{start, ServerHandle};
+use localhost::server_config::{log_settings, server_config};
+use std::path::PathBuf;
+use std::process::exit;
 
 fn main() {
-    init_logs();
-    start(server_config());
+    let mut settings = log_settings();
+    if let Some(dir) = log_dir_arg(std::env::args().skip(1)) {
+        settings.dir = dir;
+    }
+    init_logs(settings);
+    let server = match start(server_config()) {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("{e}. Exit program.");
+            exit(1);
+        }
+    };
+
+    handle_signals(&server);
+    server.wait();
+}
+
+/// The log directory given with `--log-dir <path>` or `--log-dir=<path>`.
+fn log_dir_arg(mut args: impl Iterator<Item = String>) -> Option<PathBuf> {
+    while let Some(arg) = args.next() {
+        if arg == "--log-dir" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(dir) = arg.strip_prefix("--log-dir=") {
+            return Some(PathBuf::from(dir));
+        }
+    }
+    None
 }
 
+/// Shuts the server down gracefully on SIGINT or SIGTERM. A second signal exits immediately.
+/// SIGHUP applies `server_config()` again. The configuration is compiled in, so this only
+/// re-reads the TLS certificates and retries ports that could not be bound. Changing the routes
+/// needs a rebuild and a restart, or an embedder that calls `ServerHandle::reload`.
+#[cfg(unix)]
+fn handle_signals(server: &ServerHandle) {
+    use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+    use signal_hook::iterator::Signals;
+
+    let mut signals = match Signals::new([SIGHUP, SIGINT, SIGTERM]) {
+        Ok(signals) => signals,
+        Err(e) => {
+            eprintln!("Error: Failed to register signal handlers. {e}");
+            return;
+        }
+    };
+
+    let server = server.clone();
+    std::thread::spawn(move || {
+        let mut shutting_down = false;
+        for signal in signals.forever() {
+            if signal == SIGHUP {
+                println!("Received SIGHUP. Reloading configuration");
+                server.reload(server_config());
+            } else if shutting_down {
+                exit(128 + signal);
+            } else {
+                println!("Received signal {signal}. Shutting down");
+                shutting_down = true;
+                server.shutdown();
+            }
+        }
+    });
+}
+
+#[cfg(not(unix))]
+fn handle_signals(_server: &ServerHandle) {}
+
 #[test]
 fn test_main() {
-    std::thread::spawn(|| main());
+    std::thread::spawn(main);
+}
+
+#[test]
+fn test_log_dir_arg() {
+    let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+    assert_eq!(
+        log_dir_arg(args(&["--log-dir", "/var/log/localhost"]).into_iter()),
+        Some(PathBuf::from("/var/log/localhost"))
+    );
+    assert_eq!(
+        log_dir_arg(args(&["--log-dir=logs"]).into_iter()),
+        Some(PathBuf::from("logs"))
+    );
+    assert_eq!(log_dir_arg(args(&["--log-dir"]).into_iter()), None);
 }
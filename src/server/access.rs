@@ -0,0 +1,156 @@
+use crate::log;
+use crate::log::*;
+use crate::server::{Bytes, Request, StatusCode};
+use crate::server_config::AccessRule;
+use std::net::IpAddr;
+
+/// # ClientIp
+///
+/// Address of the client that sent the request, after resolving `X-Forwarded-For` from trusted proxies.
+/// Inserted into the request extensions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+/// # Cidr
+///
+/// An IPv4 or IPv6 network like `10.0.0.0/8` or `fd00::/8`. A single address is a network with
+/// the full prefix length, and `all` matches every address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cidr {
+    All,
+    Network(IpAddr, u8),
+}
+
+impl Cidr {
+    pub fn parse(cidr: &str) -> Option<Cidr> {
+        let cidr = cidr.trim();
+        if cidr.eq_ignore_ascii_case("all") {
+            return Some(Cidr::All);
+        }
+
+        let (addr, prefix) = match cidr.split_once('/') {
+            Some((addr, prefix)) => (
+                addr.parse::<IpAddr>().ok()?,
+                Some(prefix.parse::<u8>().ok()?),
+            ),
+            None => (cidr.parse::<IpAddr>().ok()?, None),
+        };
+        let network = addr.to_canonical();
+        let max_prefix = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            // IPv4-mapped addresses are matched as IPv4, so the first 96 bits are left out
+            Some(prefix) if addr.is_ipv6() && network.is_ipv4() => prefix.checked_sub(96)?,
+            Some(prefix) => prefix,
+            None => max_prefix,
+        };
+
+        (prefix <= max_prefix).then_some(Cidr::Network(network, prefix))
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let (network, prefix) = match self {
+            Cidr::All => return true,
+            Cidr::Network(network, prefix) => (network, *prefix),
+        };
+
+        match (network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+                u32::from(*network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+                u128::from(*network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+fn matches_any(cidrs: &[&str], ip: IpAddr) -> bool {
+    cidrs
+        .iter()
+        .filter_map(|cidr| Cidr::parse(cidr))
+        .any(|cidr| cidr.contains(ip))
+}
+
+/// # is_allowed
+///
+/// Goes through the `rules` in order and uses the first one that matches `ip`.
+/// Addresses that match no rule are allowed.
+pub fn is_allowed(rules: &[AccessRule], ip: IpAddr) -> bool {
+    for rule in rules {
+        let (cidr, allow) = match rule {
+            AccessRule::Allow(cidr) => (cidr, true),
+            AccessRule::Deny(cidr) => (cidr, false),
+        };
+
+        match Cidr::parse(cidr) {
+            Some(cidr) if cidr.contains(ip) => return allow,
+            Some(_) => {}
+            None => log!(
+                LogLevel::Error,
+                LogFileType::Server,
+                format!("Invalid address in access rule '{cidr}'")
+            ),
+        }
+    }
+    true
+}
+
+/// # client_ip
+///
+/// Gets the address of the client. If `peer` is one of the `trusted_proxies`, the
+/// `X-Forwarded-For` header is read from right to left and the first address that is not a
+/// trusted proxy is used.
+pub fn client_ip(peer: IpAddr, req: &Request<Bytes>, trusted_proxies: &[&str]) -> IpAddr {
+    let peer = peer.to_canonical();
+    if !matches_any(trusted_proxies, peer) {
+        return peer;
+    }
+
+    let forwarded = req
+        .headers()
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|addr| addr.trim().parse::<IpAddr>().map(|ip| ip.to_canonical()))
+        .collect::<Vec<_>>();
+
+    let mut client = peer;
+    for addr in forwarded.into_iter().rev() {
+        match addr {
+            Ok(ip) => {
+                client = ip;
+                if !matches_any(trusted_proxies, ip) {
+                    break;
+                }
+            }
+            // Anything left of a malformed entry can not be trusted
+            Err(_) => break,
+        }
+    }
+    client
+}
+
+/// # check_access
+///
+/// Checks the client against the `rules` and logs the denial.
+pub fn check_access(rules: &[AccessRule], req: &Request<Bytes>) -> Result<(), StatusCode> {
+    let ip = match req.extensions().get::<ClientIp>() {
+        Some(ClientIp(ip)) => *ip,
+        None => return Ok(()),
+    };
+
+    if is_allowed(rules, ip) {
+        Ok(())
+    } else {
+        log!(
+            LogLevel::Warn,
+            LogFileType::Client,
+            format!("Access denied for {ip} to {}", req.uri().path())
+        );
+        Err(StatusCode::FORBIDDEN)
+    }
+}
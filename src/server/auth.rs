@@ -0,0 +1,381 @@
+use crate::log;
+use crate::log::*;
+use crate::server::errors::error;
+use crate::server::{Bytes, Request, Response, Route, ServerConfig, StatusCode};
+use crate::server_config::route::{BasicAuth, BearerAuth, Jwt};
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use http::header::{AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE, HOST, WWW_AUTHENTICATE};
+use http::HeaderValue;
+use serde_json::{Map, Value};
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Page of failed Basic authentication, for servers without `custom_error_path`.
+const UNAUTHORIZED_PAGE: &str = "./files/default_errors/401.html";
+
+/// # AuthenticatedUser
+///
+/// Name of the user that passed authentication on the route.
+/// Handlers can read it with `req.extensions().get::<AuthenticatedUser>()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuthenticatedUser(pub String);
+
+/// # JwtClaims
+///
+/// Claims of a verified bearer JWT. Inserted into the request extensions next to
+/// `AuthenticatedUser`, which holds the `sub` claim.
+#[derive(Clone, Debug, PartialEq)]
+pub struct JwtClaims(pub Map<String, Value>);
+
+/// # BearerError
+///
+/// Error codes from RFC 6750, sent in the `WWW-Authenticate` header.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BearerError {
+    /// No token was sent.
+    Missing,
+    InvalidRequest(&'static str),
+    InvalidToken(&'static str),
+    InsufficientScope(&'static str),
+}
+
+impl BearerError {
+    fn status(&self) -> StatusCode {
+        match self {
+            BearerError::Missing | BearerError::InvalidToken(_) => StatusCode::UNAUTHORIZED,
+            BearerError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            BearerError::InsufficientScope(_) => StatusCode::FORBIDDEN,
+        }
+    }
+
+    fn challenge(&self, auth: &BearerAuth) -> String {
+        let realm = format!("Bearer realm=\"{}\"", auth.realm);
+        let (error, description) = match self {
+            BearerError::Missing => return realm,
+            BearerError::InvalidRequest(description) => ("invalid_request", description),
+            BearerError::InvalidToken(description) => ("invalid_token", description),
+            BearerError::InsufficientScope(description) => ("insufficient_scope", description),
+        };
+
+        let mut challenge =
+            format!("{realm}, error=\"{error}\", error_description=\"{description}\"");
+        if matches!(self, BearerError::InsufficientScope(_)) {
+            challenge.push_str(&format!(", scope=\"{}\"", auth.required_scopes.join(" ")));
+        }
+        challenge
+    }
+}
+
+/// # authorize
+///
+/// Checks the credentials required by the `route`. On success the authenticated identity is
+/// added to the request extensions, otherwise the response that should be served is returned.
+pub fn authorize(
+    route: &Route,
+    req: &mut Request<Bytes>,
+    config: &ServerConfig,
+) -> Result<(), Box<Response<Bytes>>> {
+    let settings = match &route.settings {
+        Some(settings) => settings,
+        None => return Ok(()),
+    };
+
+    if let Some(basic_auth) = &settings.basic_auth {
+        let user = check_basic_auth(basic_auth, req)
+            .map_err(|code| Box::new(basic_challenge(code, basic_auth, config)))?;
+        req.extensions_mut().insert(AuthenticatedUser(user));
+    }
+
+    if let Some(bearer_auth) = &settings.bearer_auth {
+        match check_bearer_auth(bearer_auth, req) {
+            Ok(Some(claims)) => {
+                if let Some(Value::String(subject)) = claims.get("sub") {
+                    req.extensions_mut()
+                        .insert(AuthenticatedUser(subject.to_string()));
+                }
+                req.extensions_mut().insert(JwtClaims(claims));
+            }
+            Ok(None) => {}
+            Err(e) => {
+                log!(
+                    LogLevel::Warn,
+                    LogFileType::Client,
+                    format!("Bearer authentication failed. {e:?}")
+                );
+                return Err(Box::new(bearer_challenge(e, bearer_auth, config)));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn check_basic_auth(auth: &BasicAuth, req: &Request<Bytes>) -> Result<String, StatusCode> {
+    let (user, password) = basic_credentials(req).ok_or(StatusCode::UNAUTHORIZED)?;
+
+    let htpasswd = fs::read_to_string(format!(".{}", auth.htpasswd_path)).map_err(|e| {
+        log!(
+            LogLevel::Error,
+            LogFileType::Server,
+            format!("Unable to read {}. {e}", auth.htpasswd_path)
+        );
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    match find_hash(&htpasswd, &user) {
+        Some(hash) if verify_password(&password, hash) => Ok(user),
+        _ => {
+            log!(
+                LogLevel::Warn,
+                LogFileType::Client,
+                format!("Basic authentication failed for user '{user}'")
+            );
+            Err(StatusCode::UNAUTHORIZED)
+        }
+    }
+}
+
+fn basic_challenge(code: StatusCode, auth: &BasicAuth, config: &ServerConfig) -> Response<Bytes> {
+    let mut resp = error(code, config);
+    if code == StatusCode::UNAUTHORIZED {
+        // Custom error pages have their own 401 page
+        let page = match config.custom_error_path {
+            Some(_) => None,
+            None => fs::read(UNAUTHORIZED_PAGE).ok(),
+        };
+        if let Some(page) = page {
+            resp.headers_mut().insert(CONTENT_LENGTH, page.len().into());
+            *resp.body_mut() = page;
+        }
+        let challenge = format!("Basic realm=\"{}\", charset=\"UTF-8\"", auth.realm);
+        if let Ok(value) = HeaderValue::from_str(&challenge) {
+            resp.headers_mut().insert(WWW_AUTHENTICATE, value);
+        }
+    }
+    resp
+}
+
+fn bearer_challenge(e: BearerError, auth: &BearerAuth, config: &ServerConfig) -> Response<Bytes> {
+    let mut resp = error(e.status(), config);
+    if let Ok(value) = HeaderValue::from_str(&e.challenge(auth)) {
+        resp.headers_mut().insert(WWW_AUTHENTICATE, value);
+    }
+    resp
+}
+
+/// `check_bearer_auth` returns the claims if the token was a JWT, or `None` for a static token.
+fn check_bearer_auth(
+    auth: &BearerAuth,
+    req: &Request<Bytes>,
+) -> Result<Option<Map<String, Value>>, BearerError> {
+    let mut headers = req.headers().get_all(AUTHORIZATION).iter();
+    let value = match (headers.next(), headers.next()) {
+        (None, _) => return Err(BearerError::Missing),
+        (Some(value), None) => value.to_str().unwrap_or_default(),
+        (Some(_), Some(_)) => {
+            return Err(BearerError::InvalidRequest(
+                "Multiple Authorization headers",
+            ))
+        }
+    };
+
+    let token = match value.trim().split_once(' ') {
+        Some((scheme, token)) if scheme.eq_ignore_ascii_case("bearer") => token.trim(),
+        _ => return Err(BearerError::Missing),
+    };
+
+    if auth
+        .tokens
+        .iter()
+        .any(|known| constant_time_eq(known.as_bytes(), token.as_bytes()))
+    {
+        return Ok(None);
+    }
+
+    let jwt = auth
+        .jwt
+        .as_ref()
+        .ok_or(BearerError::InvalidToken("Unknown token"))?;
+    let claims = verify_jwt(token, jwt, unix_time())?;
+
+    let scopes = match claims.get("scope") {
+        Some(Value::String(scopes)) => scopes.split_whitespace().collect::<Vec<_>>(),
+        _ => vec![],
+    };
+    if !auth
+        .required_scopes
+        .iter()
+        .all(|required| scopes.contains(required))
+    {
+        return Err(BearerError::InsufficientScope(
+            "The token is missing a required scope",
+        ));
+    }
+
+    Ok(Some(claims))
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+fn jwt_mac(secret: &str, signing_input: &str) -> HmacSha256 {
+    let mut mac = <HmacSha256 as Mac>::new_from_slice(secret.as_bytes())
+        .expect("HMAC can take a key of any size");
+    mac.update(signing_input.as_bytes());
+    mac
+}
+
+fn decode_json_object(part: &str) -> Option<Map<String, Value>> {
+    match serde_json::from_slice(&URL_SAFE_NO_PAD.decode(part).ok()?).ok()? {
+        Value::Object(object) => Some(object),
+        _ => None,
+    }
+}
+
+/// # encode_jwt
+///
+/// Creates an HS256 signed JWT with the given claims. Useful for clients of routes with `bearer_auth`.
+pub fn encode_jwt(claims: &Map<String, Value>, secret: &str) -> String {
+    let header = URL_SAFE_NO_PAD.encode(r#"{"alg":"HS256","typ":"JWT"}"#);
+    let payload = URL_SAFE_NO_PAD.encode(Value::Object(claims.clone()).to_string());
+    let signing_input = format!("{header}.{payload}");
+    let signature = URL_SAFE_NO_PAD.encode(jwt_mac(secret, &signing_input).finalize().into_bytes());
+    format!("{signing_input}.{signature}")
+}
+
+/// # verify_jwt
+///
+/// Verifies the signature of an HS256 JWT and its `exp`, `nbf`, `iss` and `aud` claims
+/// at the unix time `now`. Returns the claims if the token is valid.
+pub fn verify_jwt(token: &str, jwt: &Jwt, now: u64) -> Result<Map<String, Value>, BearerError> {
+    let malformed = BearerError::InvalidToken("Malformed token");
+    let (signing_input, signature) = token.rsplit_once('.').ok_or(malformed.clone())?;
+    let (header, payload) = signing_input.split_once('.').ok_or(malformed.clone())?;
+
+    // Only HS256 is accepted, which also rules out unsigned tokens with "alg": "none"
+    let header = decode_json_object(header).ok_or(malformed.clone())?;
+    if header.get("alg") != Some(&Value::from("HS256")) {
+        return Err(BearerError::InvalidToken("Unsupported algorithm"));
+    }
+
+    let signature = URL_SAFE_NO_PAD
+        .decode(signature)
+        .map_err(|_| malformed.clone())?;
+    jwt_mac(jwt.secret, signing_input)
+        .verify_slice(&signature)
+        .map_err(|_| BearerError::InvalidToken("Invalid signature"))?;
+
+    let claims = decode_json_object(payload).ok_or(malformed)?;
+    let time_claim = |name: &str| claims.get(name).and_then(Value::as_f64).map(|t| t as u64);
+
+    if time_claim("exp").is_some_and(|exp| now > exp.saturating_add(jwt.leeway)) {
+        return Err(BearerError::InvalidToken("The token has expired"));
+    }
+    if time_claim("nbf").is_some_and(|nbf| now.saturating_add(jwt.leeway) < nbf) {
+        return Err(BearerError::InvalidToken("The token is not valid yet"));
+    }
+
+    if let Some(issuer) = jwt.issuer {
+        if claims.get("iss").and_then(Value::as_str) != Some(issuer) {
+            return Err(BearerError::InvalidToken("Invalid issuer"));
+        }
+    }
+
+    if let Some(audience) = jwt.audience {
+        let accepted = match claims.get("aud") {
+            Some(Value::String(aud)) => aud == audience,
+            Some(Value::Array(auds)) => auds.iter().any(|aud| aud.as_str() == Some(audience)),
+            _ => false,
+        };
+        if !accepted {
+            return Err(BearerError::InvalidToken("Invalid audience"));
+        }
+    }
+
+    Ok(claims)
+}
+
+/// # whoami
+///
+/// Handler that responds with the identity and JWT claims of an authenticated request.
+pub fn whoami(req: &Request<Bytes>, conf: &ServerConfig) -> Result<Response<Bytes>, StatusCode> {
+    let user = req
+        .extensions()
+        .get::<AuthenticatedUser>()
+        .map(|user| Value::from(user.0.as_str()))
+        .unwrap_or(Value::Null);
+    let claims = req
+        .extensions()
+        .get::<JwtClaims>()
+        .map(|claims| Value::Object(claims.0.clone()))
+        .unwrap_or(Value::Null);
+
+    let body = serde_json::json!({ "user": user, "claims": claims }).to_string();
+    Response::builder()
+        .version(req.version())
+        .header(HOST, conf.host)
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Bytes::from(body))
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// # basic_credentials
+///
+/// Gets the user and password from an `Authorization: Basic` header.
+pub fn basic_credentials(req: &Request<Bytes>) -> Option<(String, String)> {
+    let value = req.headers().get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, encoded) = value.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (user, password) = decoded.split_once(':')?;
+    Some((user.to_string(), password.to_string()))
+}
+
+/// `find_hash` gets the password hash of `user` from the contents of an htpasswd file.
+fn find_hash<'a>(htpasswd: &'a str, user: &str) -> Option<&'a str> {
+    htpasswd
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| *name == user)
+        .map(|(_, hash)| hash)
+}
+
+/// # verify_password
+///
+/// Verifies `password` against an htpasswd hash. Supports bcrypt (`$2y$`, `$2b$`, `$2a$`)
+/// and SHA-1 (`{SHA}`) hashes. Any other format is rejected.
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    if hash.starts_with("$2") {
+        bcrypt::verify(password, hash).unwrap_or(false)
+    } else if let Some(digest) = hash.strip_prefix("{SHA}") {
+        let expected = STANDARD.encode(Sha1::digest(password.as_bytes()));
+        constant_time_eq(expected.as_bytes(), digest.as_bytes())
+    } else {
+        log!(
+            LogLevel::Error,
+            LogFileType::Server,
+            "Unsupported htpasswd hash format".to_string()
+        );
+        false
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -1,12 +1,13 @@
 use crate::log;
 use crate::log::*;
 use crate::server::path::add_root_to_path;
-use crate::server::{get_route, Bytes, ServerConfig, StatusCode};
+use crate::server::{get_route, metrics, Bytes, ClientIp, ServerConfig, StatusCode};
 use crate::type_aliases::FileExtension;
 use http::header::*;
 use http::{HeaderMap, HeaderName, HeaderValue, Request, Response};
 use std::env;
 use std::process::Command;
+use std::time::Instant;
 
 #[derive(Clone, Debug)]
 pub enum Cgi {
@@ -95,7 +96,7 @@ pub fn execute_cgi_script(
         .to_string();
 
     let path = format!("{path}.{file_extension}");
-    add_env_variables(req, config, file_extension.as_str());
+    let vars = cgi_env(req, config, file_extension.as_str());
 
     // Check if the file extension is associated with a CGI script
     let (command, arguments) = match settings
@@ -173,18 +174,29 @@ pub fn execute_cgi_script(
 
         None => {
             log!(
+                LogLevel::Warn,
                 LogFileType::Server,
-                format!("Error: CGI not found {}", path)
+                format!("CGI not found {}", path)
             );
             return Err(StatusCode::NOT_FOUND);
         }
     };
 
     // Spawn a new process to execute the CGI script and capture its output
-    let body = match Command::new(command).args(arguments).output() {
+    let started = Instant::now();
+    let output = Command::new(command)
+        .args(arguments)
+        .env_clear()
+        .envs(vars)
+        .output();
+    let failed = !output.as_ref().is_ok_and(|output| output.status.success());
+    metrics().record_cgi(started.elapsed(), failed);
+
+    let body = match output {
         Ok(output) => output.stdout,
         Err(e) => {
             log!(
+                LogLevel::Error,
                 LogFileType::Server,
                 format!("Error executing CGI script: {}", e)
             );
@@ -212,20 +224,36 @@ pub fn execute_cgi_script(
     Ok(response)
 }
 
-fn add_env_variables(req: &Request<Bytes>, config: &ServerConfig, file_extension: FileExtension) {
-    add_http_variables(req.headers());
+/// # cgi_env
+///
+/// The environment of a CGI script for `req`. It is given to the script process alone, so
+/// concurrent requests don't see each other's variables. Only `PATH` is inherited from the server.
+pub fn cgi_env(
+    req: &Request<Bytes>,
+    config: &ServerConfig,
+    file_extension: FileExtension,
+) -> Vec<(&'static str, String)> {
+    let mut vars = Vec::new();
+    if let Ok(path) = env::var("PATH") {
+        vars.push(("PATH", path));
+    }
+    add_http_variables(req.headers(), &mut vars);
     if let Some(query) = req.uri().query() {
-        env::set_var("QUERY_STRING", query);
+        vars.push(("QUERY_STRING", query.to_string()));
     }
 
-    env::set_var("REQUEST_METHOD", req.method().to_string());
-    env::set_var("SERVER_NAME", config.host);
+    vars.push(("REQUEST_METHOD", req.method().to_string()));
+
+    if let Some(ClientIp(ip)) = req.extensions().get::<ClientIp>() {
+        vars.push(("REMOTE_ADDR", ip.to_string()));
+    }
+    vars.push(("SERVER_NAME", config.host.to_string()));
 
     if let Some(port) = req.uri().port_u16() {
-        env::set_var("SERVER_PORT", format!("{port}"));
+        vars.push(("SERVER_PORT", format!("{port}")));
     }
 
-    env::set_var("SERVER_SOFTWARE", "Rust v1.74.0");
+    vars.push(("SERVER_SOFTWARE", "Rust v1.74.0".to_string()));
 
     let path = req
         .uri()
@@ -235,29 +263,31 @@ fn add_env_variables(req: &Request<Bytes>, config: &ServerConfig, file_extension
 
     // localhost:8080/cgi/python.py/path/to/file -> PATH_INFO: /path/to/file
     if contains_path_info(path.clone()) {
-        env::set_var("PATH_INFO", path[1]);
+        vars.push(("PATH_INFO", path[1].to_string()));
     }
+    vars
 }
-fn add_http_variables(headers: &HeaderMap<HeaderValue>) {
+fn add_http_variables(headers: &HeaderMap<HeaderValue>, vars: &mut Vec<(&'static str, String)>) {
     for (key, v) in headers {
         let value = v.to_str().unwrap_or_default();
         if value.is_empty() {
             continue;
         }
-        match *key {
-            ACCEPT => env::set_var("HTTP_ACCEPT", value),
-            CONTENT_LENGTH => env::set_var("CONTENT_LENGTH", value),
-            CONTENT_TYPE => env::set_var("CONTENT_TYPE", value),
-            ACCEPT_CHARSET => env::set_var("HTTP_ACCEPT_CHARSET", value),
-            ACCEPT_ENCODING => env::set_var("HTTP_ACCEPT_ENCODING", value),
-            ACCEPT_LANGUAGE => env::set_var("HTTP_ACCEPT_LANGUAGE", value),
-            FORWARDED => env::set_var("HTTP_FORWARDED", value),
-            HOST => env::set_var("HTTP_HOST", value),
-            PROXY_AUTHORIZATION => env::set_var("HTTP_PROXY_AUTHORIZATION", value),
-            USER_AGENT => env::set_var("HTTP_USER_AGENT", value),
-            COOKIE => env::set_var("COOKIE", value),
-            _ => {}
-        }
+        let name = match *key {
+            ACCEPT => "HTTP_ACCEPT",
+            CONTENT_LENGTH => "CONTENT_LENGTH",
+            CONTENT_TYPE => "CONTENT_TYPE",
+            ACCEPT_CHARSET => "HTTP_ACCEPT_CHARSET",
+            ACCEPT_ENCODING => "HTTP_ACCEPT_ENCODING",
+            ACCEPT_LANGUAGE => "HTTP_ACCEPT_LANGUAGE",
+            FORWARDED => "HTTP_FORWARDED",
+            HOST => "HTTP_HOST",
+            PROXY_AUTHORIZATION => "HTTP_PROXY_AUTHORIZATION",
+            USER_AGENT => "HTTP_USER_AGENT",
+            COOKIE => "COOKIE",
+            _ => continue,
+        };
+        vars.push((name, value.to_string()));
     }
 }
 
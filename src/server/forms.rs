@@ -0,0 +1,559 @@
+use crate::server::{Bytes, Request, Response, ServerConfig, StatusCode};
+use http::header::{CONTENT_TYPE, HOST};
+use http::HeaderMap;
+use serde_json::{json, Map, Value};
+use std::fmt;
+
+/// Longest boundary RFC 2046 allows.
+const MAX_BOUNDARY_LENGTH: usize = 70;
+
+/// # parse_urlencoded
+///
+/// Decodes an `application/x-www-form-urlencoded` body or query string into its fields, in
+/// order. Names may repeat. Invalid UTF-8 is replaced.
+pub fn parse_urlencoded(input: &[u8]) -> Vec<(String, String)> {
+    input
+        .split(|b| *b == b'&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = match pair.iter().position(|b| *b == b'=') {
+                Some(i) => (&pair[..i], &pair[i + 1..]),
+                None => (pair, &[][..]),
+            };
+            (decode_form_component(name), decode_form_component(value))
+        })
+        .collect()
+}
+
+fn decode_form_component(input: &[u8]) -> String {
+    let input = input
+        .iter()
+        .map(|b| if *b == b'+' { b' ' } else { *b })
+        .collect::<Vec<_>>();
+    String::from_utf8_lossy(&percent_decode(&input)).into_owned()
+}
+
+/// # percent_decode
+///
+/// Replaces `%XX` escapes with the bytes they stand for. Malformed escapes are kept as they are.
+pub fn percent_decode(input: &[u8]) -> Vec<u8> {
+    let hex = |b: u8| (b as char).to_digit(16).map(|d| d as u8);
+    let mut output = Vec::with_capacity(input.len());
+    let mut i = 0;
+    while i < input.len() {
+        if input[i] == b'%' && i + 2 < input.len() {
+            if let (Some(high), Some(low)) = (hex(input[i + 1]), hex(input[i + 2])) {
+                output.push(high << 4 | low);
+                i += 3;
+                continue;
+            }
+        }
+        output.push(input[i]);
+        i += 1;
+    }
+    output
+}
+
+/// # urlencoded_form
+///
+/// The fields of a request with an `application/x-www-form-urlencoded` body.
+/// Other content types get `415 Unsupported Media Type`.
+pub fn urlencoded_form(req: &Request<Bytes>) -> Result<Vec<(String, String)>, StatusCode> {
+    match media_type(req.headers()) {
+        Some(media_type) if media_type == "application/x-www-form-urlencoded" => {
+            Ok(parse_urlencoded(req.body()))
+        }
+        _ => Err(StatusCode::UNSUPPORTED_MEDIA_TYPE),
+    }
+}
+
+/// # fields_to_json
+///
+/// The fields of a form as a JSON object. Names that repeat get an array of their values.
+pub fn fields_to_json(fields: Vec<(String, String)>) -> Map<String, Value> {
+    let mut object = Map::new();
+    for (name, value) in fields {
+        match object.get_mut(&name) {
+            Some(Value::Array(values)) => values.push(value.into()),
+            Some(first) => *first = json!([first.take(), value]),
+            None => {
+                object.insert(name, value.into());
+            }
+        }
+    }
+    object
+}
+
+/// The media type of the `Content-Type` of a request, lowercase and without parameters.
+fn media_type(headers: &HeaderMap) -> Option<String> {
+    let content_type = headers.get(CONTENT_TYPE)?.to_str().ok()?;
+    let media_type = content_type.split(';').next()?.trim();
+    Some(media_type.to_ascii_lowercase())
+}
+
+/// # multipart_boundary
+///
+/// The boundary of a request with a `multipart/form-data` body.
+pub fn multipart_boundary(headers: &HeaderMap) -> Option<String> {
+    if media_type(headers)? != "multipart/form-data" {
+        return None;
+    }
+    let content_type = headers.get(CONTENT_TYPE)?.to_str().ok()?;
+    let (_, parameters) = content_type.split_once(';')?;
+    let boundary = parse_parameters(parameters)
+        .into_iter()
+        .find(|(name, _)| name == "boundary")
+        .map(|(_, value)| value)?;
+
+    let valid = !boundary.is_empty()
+        && boundary.len() <= MAX_BOUNDARY_LENGTH
+        && !boundary.ends_with(' ')
+        && boundary
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "'()+_,-./:=? ".contains(c));
+    valid.then_some(boundary)
+}
+
+/// Parses `; name=value; name="quoted value"` parameters of a header. Names are lowercase.
+fn parse_parameters(input: &str) -> Vec<(String, String)> {
+    let mut parameters = Vec::new();
+    let mut chars = input.chars().peekable();
+    loop {
+        // Skip to the next name
+        while chars.peek().is_some_and(|c| *c == ';' || c.is_whitespace()) {
+            chars.next();
+        }
+        let name = chars
+            .by_ref()
+            .take_while(|c| *c != '=')
+            .collect::<String>()
+            .trim()
+            .to_ascii_lowercase();
+        if name.is_empty() {
+            return parameters;
+        }
+
+        let mut value = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => break,
+                    '\\' => value.extend(chars.next()),
+                    c => value.push(c),
+                }
+            }
+            // Anything between the closing quote and the next parameter is dropped
+            while chars.peek().is_some_and(|c| *c != ';') {
+                chars.next();
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| *c != ';') {
+                value.push(c);
+            }
+            value = value.trim().to_string();
+        }
+        parameters.push((name, value));
+    }
+}
+
+/// # MultipartLimits
+///
+/// Limits of a `multipart/form-data` body. Exceeding the sizes gets `413 Payload Too Large`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MultipartLimits {
+    /// Most parts in a body.
+    pub max_parts: usize,
+    /// Largest header block of a part, in bytes.
+    pub max_header_bytes: usize,
+    /// Largest content of a single part, in bytes. `None` for no limit.
+    pub max_part_size: Option<usize>,
+    /// Largest content of all parts together, in bytes. `None` for no limit.
+    pub max_total_size: Option<usize>,
+}
+
+impl Default for MultipartLimits {
+    fn default() -> Self {
+        Self {
+            max_parts: 100,
+            max_header_bytes: 8 * 1024,
+            max_part_size: None,
+            max_total_size: None,
+        }
+    }
+}
+
+/// # MultipartError
+///
+/// Why a `multipart/form-data` body was rejected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MultipartError {
+    /// The body doesn't follow RFC 7578.
+    Malformed(&'static str),
+    /// The body ended before the closing boundary.
+    Incomplete,
+    TooManyParts,
+    HeadersTooLarge,
+    PartTooLarge,
+    BodyTooLarge,
+}
+
+impl MultipartError {
+    /// The status of the response to the request with the body.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            MultipartError::Malformed(_) | MultipartError::Incomplete => StatusCode::BAD_REQUEST,
+            MultipartError::TooManyParts
+            | MultipartError::HeadersTooLarge
+            | MultipartError::PartTooLarge
+            | MultipartError::BodyTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+        }
+    }
+}
+
+impl fmt::Display for MultipartError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MultipartError::Malformed(reason) => write!(f, "malformed multipart body: {reason}"),
+            MultipartError::Incomplete => write!(f, "multipart body ended early"),
+            MultipartError::TooManyParts => write!(f, "too many parts"),
+            MultipartError::HeadersTooLarge => write!(f, "part headers too large"),
+            MultipartError::PartTooLarge => write!(f, "part too large"),
+            MultipartError::BodyTooLarge => write!(f, "multipart body too large"),
+        }
+    }
+}
+
+/// # PartHeaders
+///
+/// The headers of a part of a `multipart/form-data` body.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PartHeaders {
+    /// Name of the form field, from `Content-Disposition`.
+    pub name: Option<String>,
+    /// File name as the client sent it, from `filename*` or `filename`. It may contain paths,
+    /// so it must be sanitized before it is used to store the file.
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    /// All headers of the part, with lowercase names.
+    pub headers: Vec<(String, String)>,
+}
+
+impl PartHeaders {
+    fn parse(block: &[u8]) -> Result<Self, MultipartError> {
+        let block = std::str::from_utf8(block)
+            .map_err(|_| MultipartError::Malformed("part headers are not UTF-8"))?;
+        let mut part = PartHeaders::default();
+
+        for line in block.split("\r\n").filter(|line| !line.is_empty()) {
+            let (name, value) = line
+                .split_once(':')
+                .ok_or(MultipartError::Malformed("part header without a colon"))?;
+            let (name, value) = (name.trim().to_ascii_lowercase(), value.trim().to_string());
+
+            match name.as_str() {
+                "content-disposition" => {
+                    let parameters = value
+                        .split_once(';')
+                        .map(|(_, parameters)| parse_parameters(parameters))
+                        .unwrap_or_default();
+                    let parameter = |key: &str| {
+                        parameters
+                            .iter()
+                            .find(|(name, _)| name == key)
+                            .map(|(_, value)| value.clone())
+                    };
+                    part.name = parameter("name");
+                    part.filename = parameter("filename*")
+                        .and_then(|value| decode_extended(&value))
+                        .or_else(|| parameter("filename"));
+                }
+                "content-type" => part.content_type = Some(value.clone()),
+                _ => {}
+            }
+            part.headers.push((name, value));
+        }
+        Ok(part)
+    }
+}
+
+/// Decodes an RFC 5987 value, like `UTF-8''na%C3%AFve.txt`.
+fn decode_extended(value: &str) -> Option<String> {
+    let (charset, rest) = value.split_once('\'')?;
+    let (_, encoded) = rest.split_once('\'')?;
+    if !charset.eq_ignore_ascii_case("utf-8") {
+        return None;
+    }
+    String::from_utf8(percent_decode(encoded.as_bytes())).ok()
+}
+
+/// # MultipartEvent
+///
+/// What the `MultipartParser` found in the input it was given.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MultipartEvent {
+    /// A part starts with these headers.
+    Part(PartHeaders),
+    /// Content of the current part. A part may be split into any number of these.
+    Data(Bytes),
+    /// The current part is complete.
+    PartEnd,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ParseState {
+    /// Before the first boundary.
+    Preamble,
+    /// After a boundary, which is followed by `--` on the last one.
+    Boundary,
+    Headers,
+    Body,
+    /// After the closing boundary. The rest is ignored.
+    Done,
+}
+
+/// # MultipartParser
+///
+/// Parses a `multipart/form-data` body as it arrives, so the parts don't have to be held in
+/// memory. Feed it the body in chunks of any size, then call `finish`.
+#[derive(Debug)]
+pub struct MultipartParser {
+    /// `\r\n--` and the boundary, which ends the content of a part.
+    delimiter: Vec<u8>,
+    limits: MultipartLimits,
+    state: ParseState,
+    buffer: Bytes,
+    parts: usize,
+    part_size: usize,
+    total_size: usize,
+}
+
+impl MultipartParser {
+    pub fn new(boundary: &str, limits: MultipartLimits) -> Self {
+        Self {
+            delimiter: format!("\r\n--{boundary}").into_bytes(),
+            limits,
+            state: ParseState::Preamble,
+            // The first boundary may be at the very start, without a line break before it
+            buffer: b"\r\n".to_vec(),
+            parts: 0,
+            part_size: 0,
+            total_size: 0,
+        }
+    }
+
+    /// Parses the next chunk of the body.
+    pub fn feed(&mut self, input: &[u8]) -> Result<Vec<MultipartEvent>, MultipartError> {
+        let mut events = Vec::new();
+        if self.state == ParseState::Done {
+            return Ok(events);
+        }
+        self.buffer.extend_from_slice(input);
+
+        loop {
+            match self.state {
+                ParseState::Preamble => match find(&self.buffer, &self.delimiter) {
+                    Some(i) => {
+                        self.buffer.drain(..i + self.delimiter.len());
+                        self.state = ParseState::Boundary;
+                    }
+                    None => {
+                        let keep = self.delimiter.len() - 1;
+                        let end = self.buffer.len().saturating_sub(keep);
+                        self.buffer.drain(..end);
+                        return Ok(events);
+                    }
+                },
+                ParseState::Boundary => {
+                    if self.buffer.starts_with(b"--") {
+                        self.buffer.clear();
+                        self.state = ParseState::Done;
+                        return Ok(events);
+                    }
+                    let line_end = match find(&self.buffer, b"\r\n") {
+                        Some(i) => i,
+                        None if self.buffer.len() > self.limits.max_header_bytes => {
+                            return Err(MultipartError::Malformed("no line break after boundary"))
+                        }
+                        None => return Ok(events),
+                    };
+                    // Only whitespace may follow a boundary on its line
+                    if !self.buffer[..line_end]
+                        .iter()
+                        .all(|b| *b == b' ' || *b == b'\t')
+                    {
+                        return Err(MultipartError::Malformed("text after boundary"));
+                    }
+                    self.buffer.drain(..line_end + 2);
+                    self.state = ParseState::Headers;
+                }
+                ParseState::Headers => {
+                    let (block, length) = if self.buffer.starts_with(b"\r\n") {
+                        (&self.buffer[..0], 2)
+                    } else {
+                        match find(&self.buffer, b"\r\n\r\n") {
+                            Some(i) if i > self.limits.max_header_bytes => {
+                                return Err(MultipartError::HeadersTooLarge)
+                            }
+                            Some(i) => (&self.buffer[..i], i + 4),
+                            None if self.buffer.len() > self.limits.max_header_bytes => {
+                                return Err(MultipartError::HeadersTooLarge)
+                            }
+                            None => return Ok(events),
+                        }
+                    };
+                    self.parts += 1;
+                    if self.parts > self.limits.max_parts {
+                        return Err(MultipartError::TooManyParts);
+                    }
+                    let headers = PartHeaders::parse(block)?;
+                    self.buffer.drain(..length);
+                    self.part_size = 0;
+                    self.state = ParseState::Body;
+                    events.push(MultipartEvent::Part(headers));
+                }
+                ParseState::Body => {
+                    let (end, found) = match find(&self.buffer, &self.delimiter) {
+                        Some(i) => (i, true),
+                        // The end of the buffer may be the start of the delimiter
+                        None => {
+                            let keep = self.delimiter.len() - 1;
+                            (self.buffer.len().saturating_sub(keep), false)
+                        }
+                    };
+                    if end > 0 {
+                        self.count(end)?;
+                        events.push(MultipartEvent::Data(self.buffer.drain(..end).collect()));
+                    }
+                    if !found {
+                        return Ok(events);
+                    }
+                    self.buffer.drain(..self.delimiter.len());
+                    self.state = ParseState::Boundary;
+                    events.push(MultipartEvent::PartEnd);
+                }
+                ParseState::Done => return Ok(events),
+            }
+        }
+    }
+
+    /// Checks that the body ended with the closing boundary.
+    pub fn finish(&self) -> Result<(), MultipartError> {
+        match self.state {
+            ParseState::Done => Ok(()),
+            _ => Err(MultipartError::Incomplete),
+        }
+    }
+
+    /// Adds `length` bytes of content to the sizes of the part and the body.
+    fn count(&mut self, length: usize) -> Result<(), MultipartError> {
+        self.part_size += length;
+        self.total_size += length;
+        if self
+            .limits
+            .max_part_size
+            .is_some_and(|max| self.part_size > max)
+        {
+            return Err(MultipartError::PartTooLarge);
+        }
+        if self
+            .limits
+            .max_total_size
+            .is_some_and(|max| self.total_size > max)
+        {
+            return Err(MultipartError::BodyTooLarge);
+        }
+        Ok(())
+    }
+}
+
+/// Position of the first `needle` in `haystack`.
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// # FormPart
+///
+/// A complete part of a `multipart/form-data` body.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FormPart {
+    pub headers: PartHeaders,
+    pub data: Bytes,
+}
+
+/// # parse_multipart
+///
+/// The parts of a request with a `multipart/form-data` body, within `limits`. Bodies of other
+/// content types are `Malformed`.
+pub fn parse_multipart(
+    req: &Request<Bytes>,
+    limits: MultipartLimits,
+) -> Result<Vec<FormPart>, MultipartError> {
+    let boundary = multipart_boundary(req.headers()).ok_or(MultipartError::Malformed(
+        "not a multipart/form-data request",
+    ))?;
+    let mut parser = MultipartParser::new(&boundary, limits);
+    let mut parts = Vec::new();
+
+    for event in parser.feed(req.body())? {
+        match event {
+            MultipartEvent::Part(headers) => parts.push(FormPart {
+                headers,
+                data: Bytes::new(),
+            }),
+            MultipartEvent::Data(data) => {
+                if let Some(part) = parts.last_mut() {
+                    part.data.extend_from_slice(&data);
+                }
+            }
+            MultipartEvent::PartEnd => {}
+        }
+    }
+    parser.finish()?;
+    Ok(parts)
+}
+
+/// # form_demo
+///
+/// Handler that responds with the fields and files of a form, as JSON. It takes urlencoded and
+/// multipart forms.
+pub fn form_demo(req: &Request<Bytes>, conf: &ServerConfig) -> Result<Response<Bytes>, StatusCode> {
+    let mut fields = Map::new();
+    let mut files = Vec::new();
+
+    if multipart_boundary(req.headers()).is_some() {
+        let limits = MultipartLimits {
+            max_total_size: Some(conf.body_size_limit),
+            ..MultipartLimits::default()
+        };
+        for part in parse_multipart(req, limits).map_err(|e| e.status())? {
+            let name = part.headers.name.clone().unwrap_or_default();
+            match &part.headers.filename {
+                Some(filename) => files.push(json!({
+                    "name": name,
+                    "filename": filename,
+                    "content_type": part.headers.content_type,
+                    "size": part.data.len(),
+                })),
+                None => {
+                    fields.insert(name, String::from_utf8_lossy(&part.data).into());
+                }
+            }
+        }
+    } else {
+        for (name, value) in urlencoded_form(req)? {
+            fields.insert(name, value.into());
+        }
+    }
+
+    let body = json!({ "fields": fields, "files": files }).to_string();
+    Response::builder()
+        .version(req.version())
+        .header(HOST, conf.host)
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Bytes::from(body))
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
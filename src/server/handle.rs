@@ -6,17 +6,143 @@ use crate::server::path::add_root_to_path;
 use crate::server::redirections::redirect;
 use crate::server::safe::get;
 use crate::server::*;
+use http::{HeaderValue, Version};
 use serve::*;
+use std::net::IpAddr;
 use std::path::Path;
+use std::time::Instant;
 
 const KB: usize = 1024;
 pub const BUFFER_SIZE: usize = KB;
-pub fn handle_connection(stream: &mut TcpStream, config: &ServerConfig) -> io::Result<()> {
-    let request_parts =
-        unsafe { parse_http_request(stream) }.map_err(|_| io::Error::from_raw_os_error(35))?;
-    let request = get_request(config, request_parts.clone())
-        .map_err(|e| serve_response(stream, error(e, config)))
-        .unwrap_or_else(|_| Default::default());
+/// # Client
+///
+/// The connection a request was received on.
+#[derive(Clone, Debug)]
+pub struct Client {
+    pub peer: SocketAddr,
+    /// Set if the connection uses TLS.
+    pub tls: Option<TlsInfo>,
+    /// Set if the request is a stream of an HTTP/2 connection.
+    pub http2: bool,
+}
+
+pub fn handle_connection(
+    stream: &mut Stream,
+    peer: SocketAddr,
+    request_parts: (String, Bytes),
+    config: &ServerConfig,
+    rate_limiter: &RateLimiter,
+) -> io::Result<()> {
+    let client = Client {
+        peer,
+        tls: stream.tls_info(),
+        http2: false,
+    };
+    serve_response(
+        stream,
+        respond(request_parts, &client, config, rate_limiter),
+    )
+}
+
+/// # respond
+///
+/// Gets the response to a request from `client`, and writes it to the access log and the metrics
+/// of the server. The request is logged under its `X-Request-Id`, or a new one, which is sent back
+/// with the response.
+pub fn respond(
+    request_parts: (String, Bytes),
+    client: &Client,
+    config: &ServerConfig,
+    rate_limiter: &RateLimiter,
+) -> Response<Bytes> {
+    let started = Instant::now();
+    let id = request_id(head_header(&request_parts.0, "x-request-id"));
+    let method = request_parts
+        .0
+        .split(' ')
+        .next()
+        .unwrap_or_default()
+        .to_string();
+    let entry = AccessEntry::begin(config, client.peer.ip(), &request_parts.0);
+    let mut response = with_request_id(&id, || {
+        respond_to(request_parts, client, config, rate_limiter)
+    });
+
+    let route = response
+        .extensions()
+        .get::<MatchedRoute>()
+        .map_or(UNMATCHED_ROUTE, |route| route.0.as_str());
+    metrics().record_request(
+        route,
+        &method,
+        response.status(),
+        response.body().len(),
+        started.elapsed(),
+    );
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert("x-request-id", value);
+    }
+    if let Some(mut entry) = entry {
+        // Behind trusted proxies, the client is the one they forwarded the request for
+        if let Some(ClientIp(ip)) = response.extensions().get::<ClientIp>() {
+            entry.client = *ip;
+        }
+        entry.finish(config, &response);
+    }
+    response
+}
+
+fn respond_to(
+    request_parts: (String, Bytes),
+    client: &Client,
+    config: &ServerConfig,
+    rate_limiter: &RateLimiter,
+) -> Response<Bytes> {
+    let mut request = match get_request(config, request_parts.clone()) {
+        Ok(request) => request,
+        Err(code) => return error(code, config),
+    };
+
+    if client.http2 {
+        *request.version_mut() = Version::HTTP_2;
+    }
+    match &client.tls {
+        Some(info) => {
+            request.extensions_mut().insert(info.clone());
+        }
+        None => {
+            if let Some(location) = https_redirect(&request, config) {
+                let code = StatusCode::PERMANENT_REDIRECT;
+                return redirect(code, config, request.version(), location);
+            }
+        }
+    }
+
+    // Check the client against the access rules of the server
+    let ip = client_ip(client.peer.ip(), &request, &config.trusted_proxies);
+    request.extensions_mut().insert(ClientIp(ip));
+    let mut response = respond_from(request, request_parts, ip, config, rate_limiter);
+    response.extensions_mut().insert(ClientIp(ip));
+    response
+}
+
+/// Gets the response to a request from `ip`, once the client is known.
+fn respond_from(
+    request: Request<Bytes>,
+    request_parts: (String, Bytes),
+    ip: IpAddr,
+    config: &ServerConfig,
+    rate_limiter: &RateLimiter,
+) -> Response<Bytes> {
+    if let Err(code) = check_access(&config.access_rules, &request) {
+        return error(code, config);
+    }
+
+    if let Some(limit) = &config.rate_limit {
+        if let Err(retry_after) = rate_limiter.check(ip, config.host, limit) {
+            return too_many_requests(retry_after, config);
+        }
+    }
 
     // Get the route from the http::Request
     let route = match get_route(&request, config) {
@@ -24,143 +150,171 @@ pub fn handle_connection(stream: &mut TcpStream, config: &ServerConfig) -> io::R
 
         // Handle the redirections
         Err((code, path)) if code.is_redirection() => {
-            return serve_response(stream, redirect(code, config, request.version(), path));
+            return redirect(code, config, request.version(), path);
         }
 
         // Handle the errors
         Err((code, _)) => {
-            log!(LogFileType::Server, format!("Error: {}", &code));
-            return serve_response(stream, error(code, config));
+            log_status(code);
+            return error(code, config);
         }
     };
 
+    let mut response = respond_with_route(&route, request, request_parts, ip, config, rate_limiter);
+    response
+        .extensions_mut()
+        .insert(MatchedRoute(route.url_path.to_string()));
+    response
+}
+
+/// Gets the response of `route` to a request from `ip`.
+fn respond_with_route(
+    route: &Route,
+    mut request: Request<Bytes>,
+    request_parts: (String, Bytes),
+    ip: IpAddr,
+    config: &ServerConfig,
+    rate_limiter: &RateLimiter,
+) -> Response<Bytes> {
+    // Check the access rules and credentials of the route
+    if let Some(settings) = &route.settings {
+        if let Err(code) = check_access(&settings.access_rules, &request) {
+            return error(code, config);
+        }
+
+        if let Some(limit) = &settings.rate_limit {
+            let scope = format!("{}{}", config.host, route.url_path);
+            if let Err(retry_after) = rate_limiter.check(ip, &scope, limit) {
+                return too_many_requests(retry_after, config);
+            }
+        }
+    }
+    if let Err(response) = authorize(route, &mut request, config) {
+        return *response;
+    }
+
+    if let Some(handler) = route.websocket {
+        let scope = format!("{}{}", config.host, route.url_path);
+        return upgrade_websocket(request, handler, scope, config);
+    }
+
     // Use the associated handler for the route
     if let Some(handler) = route.handler {
         return match handler(&request, config) {
-            Ok(response) => serve_response(stream, response),
+            Ok(response) => response,
             Err(code) => {
-                log!(LogFileType::Server, format!("Error: {}", &code));
-                serve_response(stream, error(code, config))
+                log_status(code);
+                error(code, config)
             }
         };
     }
 
-    let path = &add_root_to_path(&route, request.uri().path());
+    // Resumable uploads to the route
+    if let Some(tus) = route
+        .settings
+        .as_ref()
+        .and_then(|settings| settings.tus.as_ref())
+        .filter(|_| is_tus_request(route, &request))
+    {
+        return match handle_tus(route, tus, &request, config) {
+            Ok(response) => response,
+            Err(code) => {
+                log_status(code);
+                error(code, config)
+            }
+        };
+    }
 
-    // Check if the path is a directory and a default file is specified
-    if Path::new(&path).is_dir() && route.settings.is_some() {
-        let settings = route.settings.as_ref().unwrap();
+    // Store the files of uploads to the route
+    if let Some(upload) = route
+        .settings
+        .as_ref()
+        .and_then(|settings| settings.upload.as_ref())
+        .filter(|_| request.method() == Method::POST)
+    {
+        return match store_uploads(route, upload, &request, config) {
+            Ok(response) => response,
+            Err(code) => {
+                log_status(code);
+                error(code, config)
+            }
+        };
+    }
 
+    let path = &add_root_to_path(route, request.uri().path());
+
+    // Check if the path is a directory and a default file is specified.
+    // WebDAV methods work on the directory itself.
+    if let Some(settings) = route
+        .settings
+        .as_ref()
+        .filter(|_| Path::new(&path).is_dir())
+        .filter(|settings| {
+            !settings.webdav || matches!(*request.method(), Method::GET | Method::HEAD)
+        })
+    {
         // Serve the default file if enabled in config
         if let Some(default_file) = settings.default_if_url_is_dir {
-            let default_path = &add_root_to_path(&route, default_file);
+            let default_path = &add_root_to_path(route, default_file);
             let new_head =
                 replace_path_in_request(request_parts.0, request.uri().path(), default_path);
             let request_parts = (new_head, request_parts.1);
             let request = match get_request(config, request_parts) {
                 Ok(r) => r,
                 Err(code) => {
-                    log!(LogFileType::Server, code.to_string());
-                    return serve_response(stream, error(code, config));
+                    log_status(code);
+                    return error(code, config);
                 }
             };
 
             return match get(&request, config) {
-                Ok(resp) => serve_response(stream, resp),
-                Err(e) => serve_response(stream, error(e, config)),
+                Ok(resp) => resp,
+                Err(e) => error(e, config),
             };
         }
 
         // List directory setting is enabled. Default file is disabled.
         return if settings.list_directory {
-            serve_directory_contents(stream, path)
+            directory_contents(path).unwrap_or_else(|_| error(StatusCode::NOT_FOUND, config))
         } else {
-            serve_response(stream, error(StatusCode::NOT_FOUND, config))
+            error(StatusCode::NOT_FOUND, config)
         };
     }
 
     if is_cgi_request(path) {
         return match execute_cgi_script(&request, config) {
-            Ok(resp) => serve_response(stream, resp),
+            Ok(resp) => resp,
             Err(code) => {
-                log!(LogFileType::Server, format!("Error: {}", &code));
-                serve_response(stream, error(code, config))
+                log_status(code);
+                error(code, config)
             }
         };
     }
 
-    match handle_method(&route, &request, config) {
-        Ok(response) => serve_response(stream, response),
+    match handle_method(route, &request, config) {
+        Ok(response) => response,
         Err(code) => {
-            log!(LogFileType::Server, format!("Error: {}", &code));
-            serve_response(stream, error(code, config))
+            log_status(code);
+            error(code, config)
         }
     }
 }
 
-unsafe fn parse_http_request(stream: &mut TcpStream) -> Result<(String, Vec<u8>), u32> {
-    let mut buffer = [0; BUFFER_SIZE];
-    let mut head = String::new();
-    let mut body = Vec::new();
-
-    // Get the head and first bytes of the body
-    loop {
-        let bytes_read = stream.read(&mut buffer).map_err(|_| line!())?;
-
-        if bytes_read == 0 {
-            return Ok((head, body));
-        }
-
-        match String::from_utf8(buffer[..bytes_read].to_vec()) {
-            Ok(chunk) => {
-                if let Some(index) = chunk.find("\r\n\r\n") {
-                    // Split head and body when finding the double CRLF (Carriage Return Line Feed)
-                    head.push_str(&chunk[..index]);
-                    body.extend(&buffer[index + 4..bytes_read]);
-                    break;
-                } else {
-                    // If no double CRLF found, add the entire chunk to the head
-                    head.push_str(&chunk);
-                }
-            }
-            Err(_) => {
-                let rest;
-                unsafe {
-                    rest = String::from_utf8_unchecked(buffer.to_vec());
-                }
-                let index = rest.find("\r\n\r\n").unwrap_or(0);
-                head.push_str(rest.split_at(index).0);
-                if index == 0 {
-                    body.extend(&buffer[index..bytes_read]);
-                } else {
-                    body.extend(&buffer[index + 4..bytes_read]);
-                }
-                break;
-            }
-        }
-        // Clear the buffer
-    }
-
-    loop {
-        let bytes_read = match stream.read(&mut buffer) {
-            Ok(b) => b,
-            Err(_) => return Ok((head, body)),
-        };
-        body.extend(buffer);
-        if bytes_read < BUFFER_SIZE {
-            break;
-        }
-    }
-
-    Ok((head, body))
+/// `log_status` logs the status a request failed with, as an error if it is the server's fault.
+fn log_status(code: StatusCode) {
+    let level = match code.is_server_error() {
+        true => LogLevel::Error,
+        false => LogLevel::Warn,
+    };
+    log!(level, LogFileType::Server, code.to_string());
 }
 
 fn replace_path_in_request(head: String, path: &str, default_path: &str) -> String {
-    return if let Some(stripped_path) = path.strip_prefix('.') {
+    if let Some(stripped_path) = path.strip_prefix('.') {
         head.replacen(stripped_path, &default_path[1..], 1)
     } else {
         head.replacen(path, &default_path[1..], 1)
-    };
+    }
 }
 
 #[cfg(test)]
@@ -198,40 +352,32 @@ mod tests {
     }
 }
 
-mod serve {
+pub mod serve {
     use crate::server::format_response;
     use crate::type_aliases::Bytes;
     use http::header::CONTENT_TYPE;
     use http::{Response, StatusCode};
-    use mio::net::TcpStream;
     use std::io::Write;
     use std::path::Path;
     use std::{fs, io};
 
-    pub fn serve_response(stream: &mut TcpStream, response: Response<Bytes>) -> io::Result<()> {
-        let formatted_response = format_response(response.clone());
-        let total_size = formatted_response.len();
-        let mut written_size = 0;
-
-        while written_size < total_size {
-            match stream.write(&formatted_response[written_size..]) {
-                Ok(0) => {
-                    break; // No more data to write
-                }
-                Ok(n) => {
-                    written_size += n;
-                }
-                Err(e) if e.kind() != io::ErrorKind::WouldBlock => {
-                    return Err(e); // Error is not WouldBlock, return the error.
-                }
-                _ => {} // Event is now blocking, retry later.
-            }
-        }
+    pub fn serve_response(stream: &mut impl Write, response: Response<Bytes>) -> io::Result<()> {
+        write_all(stream, &format_response(response))
+    }
 
+    /// Writes all of `bytes` to a stream. A `Stream` keeps what the socket doesn't take for
+    /// later, while a bare non-blocking socket fails with `WouldBlock` once it is full.
+    pub fn write_all(stream: &mut impl Write, bytes: &[u8]) -> io::Result<()> {
+        stream.write_all(bytes)?;
         stream.flush()
     }
 
-    pub fn serve_directory_contents(stream: &mut TcpStream, path: &str) -> io::Result<()> {
+    pub fn serve_directory_contents(stream: &mut impl Write, path: &str) -> io::Result<()> {
+        serve_response(stream, directory_contents(path)?)
+    }
+
+    /// Lists the entries of the directory at `path` as links.
+    pub fn directory_contents(path: &str) -> io::Result<Response<Bytes>> {
         // Ensure the path doesn't end with a slash
         let trimmed_path = path.trim_end_matches('/');
 
@@ -261,12 +407,10 @@ mod serve {
             })
         );
 
-        let response = Response::builder()
+        Response::builder()
             .status(StatusCode::OK)
             .header(CONTENT_TYPE, "text/html")
             .body(Bytes::from(body))
-            .map_err(|_| io::Error::new(io::ErrorKind::Other, "Could not build response"))?;
-
-        serve_response(stream, response)
+            .map_err(|_| io::Error::other("Could not build response"))
     }
 }
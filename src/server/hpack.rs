@@ -0,0 +1,682 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::OnceLock;
+
+/// Size of the dynamic table both sides start with.
+pub const DEFAULT_TABLE_SIZE: usize = 4096;
+
+/// # HpackError
+///
+/// A header block that can't be decoded. The connection has to be closed with
+/// `COMPRESSION_ERROR`, since the dynamic tables of the two sides no longer match.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HpackError {
+    Truncated,
+    IntegerOverflow,
+    InvalidIndex(usize),
+    InvalidHuffman,
+    InvalidTableSize(usize),
+}
+
+impl fmt::Display for HpackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HpackError::Truncated => write!(f, "truncated header block"),
+            HpackError::IntegerOverflow => write!(f, "integer overflow"),
+            HpackError::InvalidIndex(index) => write!(f, "invalid table index {index}"),
+            HpackError::InvalidHuffman => write!(f, "invalid huffman code"),
+            HpackError::InvalidTableSize(size) => write!(f, "invalid table size {size}"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct DynamicTable {
+    entries: VecDeque<(String, String)>,
+    size: usize,
+    max_size: usize,
+}
+
+impl DynamicTable {
+    fn new(max_size: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            size: 0,
+            max_size,
+        }
+    }
+
+    fn insert(&mut self, name: String, value: String) {
+        let size = entry_size(&name, &value);
+        self.evict(self.max_size.saturating_sub(size));
+        // An entry larger than the table empties it and is not added
+        if size <= self.max_size {
+            self.size += size;
+            self.entries.push_front((name, value));
+        }
+    }
+
+    fn resize(&mut self, max_size: usize) {
+        self.max_size = max_size;
+        self.evict(max_size);
+    }
+
+    fn evict(&mut self, max_size: usize) {
+        while self.size > max_size {
+            match self.entries.pop_back() {
+                Some((name, value)) => self.size -= entry_size(&name, &value),
+                None => break,
+            }
+        }
+    }
+}
+
+fn entry_size(name: &str, value: &str) -> usize {
+    name.len() + value.len() + 32
+}
+
+/// # Decoder
+///
+/// Decodes the header blocks of one direction of a connection, in the order they were sent.
+#[derive(Debug)]
+pub struct Decoder {
+    table: DynamicTable,
+    /// Largest table the encoder may switch to, as announced in our `SETTINGS_HEADER_TABLE_SIZE`.
+    max_table_size: usize,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new(DEFAULT_TABLE_SIZE)
+    }
+}
+
+impl Decoder {
+    pub fn new(max_table_size: usize) -> Self {
+        Self {
+            table: DynamicTable::new(max_table_size),
+            max_table_size,
+        }
+    }
+
+    /// Decodes a complete header block into names and values.
+    pub fn decode(&mut self, block: &[u8]) -> Result<Vec<(String, String)>, HpackError> {
+        let mut headers = Vec::new();
+        let mut pos = 0;
+
+        while pos < block.len() {
+            let first = block[pos];
+            if first & 0x80 != 0 {
+                // Indexed header field
+                let index = decode_int(block, &mut pos, 7)?;
+                headers.push(self.get(index)?);
+            } else if first & 0x40 != 0 {
+                // Literal header field with incremental indexing
+                let (name, value) = self.decode_literal(block, &mut pos, 6)?;
+                self.table.insert(name.clone(), value.clone());
+                headers.push((name, value));
+            } else if first & 0x20 != 0 {
+                // Dynamic table size update, only allowed before the first field
+                let size = decode_int(block, &mut pos, 5)?;
+                if !headers.is_empty() || size > self.max_table_size {
+                    return Err(HpackError::InvalidTableSize(size));
+                }
+                self.table.resize(size);
+            } else {
+                // Literal header field without indexing, or never indexed
+                headers.push(self.decode_literal(block, &mut pos, 4)?);
+            }
+        }
+
+        Ok(headers)
+    }
+
+    fn decode_literal(
+        &self,
+        block: &[u8],
+        pos: &mut usize,
+        prefix: u8,
+    ) -> Result<(String, String), HpackError> {
+        let index = decode_int(block, pos, prefix)?;
+        let name = match index {
+            0 => decode_string(block, pos)?,
+            index => self.get(index)?.0,
+        };
+        let value = decode_string(block, pos)?;
+        Ok((name, value))
+    }
+
+    fn get(&self, index: usize) -> Result<(String, String), HpackError> {
+        if let Some((name, value)) = index.checked_sub(1).and_then(|i| STATIC_TABLE.get(i)) {
+            return Ok((name.to_string(), value.to_string()));
+        }
+        index
+            .checked_sub(STATIC_TABLE.len() + 1)
+            .and_then(|i| self.table.entries.get(i))
+            .cloned()
+            .ok_or(HpackError::InvalidIndex(index))
+    }
+}
+
+/// # Encoder
+///
+/// Encodes header blocks without the dynamic table, so it never has to be kept in sync with
+/// the table size of the peer. Names and values from the static table are still indexed.
+#[derive(Debug, Default)]
+pub struct Encoder;
+
+impl Encoder {
+    pub fn new() -> Self {
+        Self
+    }
+
+    pub fn encode<'a>(&mut self, headers: impl IntoIterator<Item = (&'a str, &'a str)>) -> Vec<u8> {
+        let mut block = Vec::new();
+
+        for (name, value) in headers {
+            let exact = STATIC_TABLE
+                .iter()
+                .position(|entry| *entry == (name, value));
+            if let Some(index) = exact {
+                encode_int(&mut block, index + 1, 7, 0x80);
+                continue;
+            }
+
+            // Literal header field without indexing
+            match STATIC_TABLE.iter().position(|(n, _)| *n == name) {
+                Some(index) => encode_int(&mut block, index + 1, 4, 0x00),
+                None => {
+                    block.push(0x00);
+                    encode_string(&mut block, name.as_bytes());
+                }
+            }
+            encode_string(&mut block, value.as_bytes());
+        }
+
+        block
+    }
+}
+
+fn decode_int(block: &[u8], pos: &mut usize, prefix: u8) -> Result<usize, HpackError> {
+    let max = (1usize << prefix) - 1;
+    let first = *block.get(*pos).ok_or(HpackError::Truncated)? as usize & max;
+    *pos += 1;
+    if first < max {
+        return Ok(first);
+    }
+
+    let mut value = max;
+    let mut shift = 0;
+    loop {
+        let byte = *block.get(*pos).ok_or(HpackError::Truncated)?;
+        *pos += 1;
+        if shift > 28 {
+            return Err(HpackError::IntegerOverflow);
+        }
+        value += ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+fn encode_int(block: &mut Vec<u8>, value: usize, prefix: u8, flags: u8) {
+    let max = (1usize << prefix) - 1;
+    if value < max {
+        block.push(flags | value as u8);
+        return;
+    }
+
+    block.push(flags | max as u8);
+    let mut rest = value - max;
+    while rest >= 0x80 {
+        block.push((rest & 0x7f) as u8 | 0x80);
+        rest >>= 7;
+    }
+    block.push(rest as u8);
+}
+
+fn decode_string(block: &[u8], pos: &mut usize) -> Result<String, HpackError> {
+    let huffman = block.get(*pos).ok_or(HpackError::Truncated)? & 0x80 != 0;
+    let length = decode_int(block, pos, 7)?;
+    let end = pos.checked_add(length).ok_or(HpackError::Truncated)?;
+    let bytes = block.get(*pos..end).ok_or(HpackError::Truncated)?;
+    *pos = end;
+
+    let bytes = match huffman {
+        true => huffman_decode(bytes)?,
+        false => bytes.to_vec(),
+    };
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Writes `bytes` Huffman encoded if that is shorter.
+fn encode_string(block: &mut Vec<u8>, bytes: &[u8]) {
+    let encoded = huffman_encode(bytes);
+    if encoded.len() < bytes.len() {
+        encode_int(block, encoded.len(), 7, 0x80);
+        block.extend_from_slice(&encoded);
+    } else {
+        encode_int(block, bytes.len(), 7, 0x00);
+        block.extend_from_slice(bytes);
+    }
+}
+
+/// # huffman_encode
+///
+/// Encodes `bytes` with the Huffman code of HPACK, padded with ones to a whole byte.
+pub fn huffman_encode(bytes: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    let mut bits: u64 = 0;
+    let mut count = 0;
+
+    for byte in bytes {
+        let (code, length) = HUFFMAN_CODES[*byte as usize];
+        bits = (bits << length) | code as u64;
+        count += length;
+        while count >= 8 {
+            count -= 8;
+            encoded.push((bits >> count) as u8);
+        }
+        bits &= (1 << count) - 1;
+    }
+    if count > 0 {
+        encoded.push(((bits << (8 - count)) | (0xff >> count)) as u8);
+    }
+
+    encoded
+}
+
+/// # huffman_decode
+///
+/// Decodes a Huffman encoded string. Padding must be the start of the EOS code, at most 7 bits.
+pub fn huffman_decode(bytes: &[u8]) -> Result<Vec<u8>, HpackError> {
+    let tree = huffman_tree();
+    let mut decoded = Vec::with_capacity(bytes.len() * 8 / 5);
+    let mut node = 0;
+    let mut depth = 0;
+    let mut padding = true;
+
+    for byte in bytes {
+        for shift in (0..8).rev() {
+            let bit = (byte >> shift) & 1;
+            depth += 1;
+            padding &= bit == 1;
+
+            match tree[node][bit as usize] {
+                next if next > 0 => node = next as usize,
+                0 => return Err(HpackError::InvalidHuffman),
+                leaf => {
+                    let symbol = (-leaf - 1) as usize;
+                    if symbol == EOS {
+                        return Err(HpackError::InvalidHuffman);
+                    }
+                    decoded.push(symbol as u8);
+                    node = 0;
+                    depth = 0;
+                    padding = true;
+                }
+            }
+        }
+    }
+
+    if depth > 7 || !padding {
+        return Err(HpackError::InvalidHuffman);
+    }
+    Ok(decoded)
+}
+
+const EOS: usize = 256;
+
+/// Binary tree of the Huffman code. Positive children are nodes, negative ones are leaves
+/// holding `-(symbol + 1)`, and `0` is a code that does not exist.
+fn huffman_tree() -> &'static [[i32; 2]] {
+    static TREE: OnceLock<Vec<[i32; 2]>> = OnceLock::new();
+    TREE.get_or_init(|| {
+        let mut tree = vec![[0, 0]];
+        for (symbol, (code, length)) in HUFFMAN_CODES.iter().enumerate() {
+            let mut node = 0;
+            for shift in (0..*length).rev() {
+                let bit = ((code >> shift) & 1) as usize;
+                if shift == 0 {
+                    tree[node][bit] = -(symbol as i32) - 1;
+                } else {
+                    if tree[node][bit] == 0 {
+                        tree.push([0, 0]);
+                        tree[node][bit] = (tree.len() - 1) as i32;
+                    }
+                    node = tree[node][bit] as usize;
+                }
+            }
+        }
+        tree
+    })
+}
+
+/// The static table of RFC 7541, Appendix A. Index 1 is the first entry.
+pub const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// The Huffman code of RFC 7541, Appendix B, as `(code, length in bits)` for each symbol.
+/// Symbol 256 is EOS.
+const HUFFMAN_CODES: [(u32, u8); 257] = [
+    (0x1ff8, 13),
+    (0x7fffd8, 23),
+    (0xfffffe2, 28),
+    (0xfffffe3, 28),
+    (0xfffffe4, 28),
+    (0xfffffe5, 28),
+    (0xfffffe6, 28),
+    (0xfffffe7, 28),
+    (0xfffffe8, 28),
+    (0xffffea, 24),
+    (0x3ffffffc, 30),
+    (0xfffffe9, 28),
+    (0xfffffea, 28),
+    (0x3ffffffd, 30),
+    (0xfffffeb, 28),
+    (0xfffffec, 28),
+    (0xfffffed, 28),
+    (0xfffffee, 28),
+    (0xfffffef, 28),
+    (0xffffff0, 28),
+    (0xffffff1, 28),
+    (0xffffff2, 28),
+    (0x3ffffffe, 30),
+    (0xffffff3, 28),
+    (0xffffff4, 28),
+    (0xffffff5, 28),
+    (0xffffff6, 28),
+    (0xffffff7, 28),
+    (0xffffff8, 28),
+    (0xffffff9, 28),
+    (0xffffffa, 28),
+    (0xffffffb, 28),
+    (0x14, 6),
+    (0x3f8, 10),
+    (0x3f9, 10),
+    (0xffa, 12),
+    (0x1ff9, 13),
+    (0x15, 6),
+    (0xf8, 8),
+    (0x7fa, 11),
+    (0x3fa, 10),
+    (0x3fb, 10),
+    (0xf9, 8),
+    (0x7fb, 11),
+    (0xfa, 8),
+    (0x16, 6),
+    (0x17, 6),
+    (0x18, 6),
+    (0x0, 5),
+    (0x1, 5),
+    (0x2, 5),
+    (0x19, 6),
+    (0x1a, 6),
+    (0x1b, 6),
+    (0x1c, 6),
+    (0x1d, 6),
+    (0x1e, 6),
+    (0x1f, 6),
+    (0x5c, 7),
+    (0xfb, 8),
+    (0x7ffc, 15),
+    (0x20, 6),
+    (0xffb, 12),
+    (0x3fc, 10),
+    (0x1ffa, 13),
+    (0x21, 6),
+    (0x5d, 7),
+    (0x5e, 7),
+    (0x5f, 7),
+    (0x60, 7),
+    (0x61, 7),
+    (0x62, 7),
+    (0x63, 7),
+    (0x64, 7),
+    (0x65, 7),
+    (0x66, 7),
+    (0x67, 7),
+    (0x68, 7),
+    (0x69, 7),
+    (0x6a, 7),
+    (0x6b, 7),
+    (0x6c, 7),
+    (0x6d, 7),
+    (0x6e, 7),
+    (0x6f, 7),
+    (0x70, 7),
+    (0x71, 7),
+    (0x72, 7),
+    (0xfc, 8),
+    (0x73, 7),
+    (0xfd, 8),
+    (0x1ffb, 13),
+    (0x7fff0, 19),
+    (0x1ffc, 13),
+    (0x3ffc, 14),
+    (0x22, 6),
+    (0x7ffd, 15),
+    (0x3, 5),
+    (0x23, 6),
+    (0x4, 5),
+    (0x24, 6),
+    (0x5, 5),
+    (0x25, 6),
+    (0x26, 6),
+    (0x27, 6),
+    (0x6, 5),
+    (0x74, 7),
+    (0x75, 7),
+    (0x28, 6),
+    (0x29, 6),
+    (0x2a, 6),
+    (0x7, 5),
+    (0x2b, 6),
+    (0x76, 7),
+    (0x2c, 6),
+    (0x8, 5),
+    (0x9, 5),
+    (0x2d, 6),
+    (0x77, 7),
+    (0x78, 7),
+    (0x79, 7),
+    (0x7a, 7),
+    (0x7b, 7),
+    (0x7ffe, 15),
+    (0x7fc, 11),
+    (0x3ffd, 14),
+    (0x1ffd, 13),
+    (0xffffffc, 28),
+    (0xfffe6, 20),
+    (0x3fffd2, 22),
+    (0xfffe7, 20),
+    (0xfffe8, 20),
+    (0x3fffd3, 22),
+    (0x3fffd4, 22),
+    (0x3fffd5, 22),
+    (0x7fffd9, 23),
+    (0x3fffd6, 22),
+    (0x7fffda, 23),
+    (0x7fffdb, 23),
+    (0x7fffdc, 23),
+    (0x7fffdd, 23),
+    (0x7fffde, 23),
+    (0xffffeb, 24),
+    (0x7fffdf, 23),
+    (0xffffec, 24),
+    (0xffffed, 24),
+    (0x3fffd7, 22),
+    (0x7fffe0, 23),
+    (0xffffee, 24),
+    (0x7fffe1, 23),
+    (0x7fffe2, 23),
+    (0x7fffe3, 23),
+    (0x7fffe4, 23),
+    (0x1fffdc, 21),
+    (0x3fffd8, 22),
+    (0x7fffe5, 23),
+    (0x3fffd9, 22),
+    (0x7fffe6, 23),
+    (0x7fffe7, 23),
+    (0xffffef, 24),
+    (0x3fffda, 22),
+    (0x1fffdd, 21),
+    (0xfffe9, 20),
+    (0x3fffdb, 22),
+    (0x3fffdc, 22),
+    (0x7fffe8, 23),
+    (0x7fffe9, 23),
+    (0x1fffde, 21),
+    (0x7fffea, 23),
+    (0x3fffdd, 22),
+    (0x3fffde, 22),
+    (0xfffff0, 24),
+    (0x1fffdf, 21),
+    (0x3fffdf, 22),
+    (0x7fffeb, 23),
+    (0x7fffec, 23),
+    (0x1fffe0, 21),
+    (0x1fffe1, 21),
+    (0x3fffe0, 22),
+    (0x1fffe2, 21),
+    (0x7fffed, 23),
+    (0x3fffe1, 22),
+    (0x7fffee, 23),
+    (0x7fffef, 23),
+    (0xfffea, 20),
+    (0x3fffe2, 22),
+    (0x3fffe3, 22),
+    (0x3fffe4, 22),
+    (0x7ffff0, 23),
+    (0x3fffe5, 22),
+    (0x3fffe6, 22),
+    (0x7ffff1, 23),
+    (0x3ffffe0, 26),
+    (0x3ffffe1, 26),
+    (0xfffeb, 20),
+    (0x7fff1, 19),
+    (0x3fffe7, 22),
+    (0x7ffff2, 23),
+    (0x3fffe8, 22),
+    (0x1ffffec, 25),
+    (0x3ffffe2, 26),
+    (0x3ffffe3, 26),
+    (0x3ffffe4, 26),
+    (0x7ffffde, 27),
+    (0x7ffffdf, 27),
+    (0x3ffffe5, 26),
+    (0xfffff1, 24),
+    (0x1ffffed, 25),
+    (0x7fff2, 19),
+    (0x1fffe3, 21),
+    (0x3ffffe6, 26),
+    (0x7ffffe0, 27),
+    (0x7ffffe1, 27),
+    (0x3ffffe7, 26),
+    (0x7ffffe2, 27),
+    (0xfffff2, 24),
+    (0x1fffe4, 21),
+    (0x1fffe5, 21),
+    (0x3ffffe8, 26),
+    (0x3ffffe9, 26),
+    (0xffffffd, 28),
+    (0x7ffffe3, 27),
+    (0x7ffffe4, 27),
+    (0x7ffffe5, 27),
+    (0xfffec, 20),
+    (0xfffff3, 24),
+    (0xfffed, 20),
+    (0x1fffe6, 21),
+    (0x3fffe9, 22),
+    (0x1fffe7, 21),
+    (0x1fffe8, 21),
+    (0x7ffff3, 23),
+    (0x3fffea, 22),
+    (0x3fffeb, 22),
+    (0x1ffffee, 25),
+    (0x1ffffef, 25),
+    (0xfffff4, 24),
+    (0xfffff5, 24),
+    (0x3ffffea, 26),
+    (0x7ffff4, 23),
+    (0x3ffffeb, 26),
+    (0x7ffffe6, 27),
+    (0x3ffffec, 26),
+    (0x3ffffed, 26),
+    (0x7ffffe7, 27),
+    (0x7ffffe8, 27),
+    (0x7ffffe9, 27),
+    (0x7ffffea, 27),
+    (0x7ffffeb, 27),
+    (0xffffffe, 28),
+    (0x7ffffec, 27),
+    (0x7ffffed, 27),
+    (0x7ffffee, 27),
+    (0x7ffffef, 27),
+    (0x7fffff0, 27),
+    (0x3ffffee, 26),
+    (0x3fffffff, 30),
+];
@@ -0,0 +1,882 @@
+use crate::server::hpack::{Decoder, Encoder};
+use crate::server::sse::EventStream;
+use crate::server::{Bytes, Response, ServerConfig, StatusCode};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// The connection preface every HTTP/2 client starts with.
+pub const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+/// What the HTTP/1 request reader sees of the preface: a request line without headers.
+pub const PREFACE_HEAD: &str = "PRI * HTTP/2.0";
+/// Response to an `Upgrade: h2c` request, sent before the first HTTP/2 frame.
+pub const SWITCHING_PROTOCOLS: &[u8] =
+    b"HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: h2c\r\n\r\n";
+
+const FRAME_HEADER_LENGTH: usize = 9;
+const DEFAULT_WINDOW: i64 = 65_535;
+const MAX_WINDOW: i64 = (1 << 31) - 1;
+/// Largest frame accepted from clients, the default `SETTINGS_MAX_FRAME_SIZE`.
+const MAX_FRAME_SIZE: usize = 16_384;
+/// Streams a client may have open at once.
+const MAX_CONCURRENT_STREAMS: usize = 100;
+
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const PRIORITY: u8 = 0x2;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PUSH_PROMISE: u8 = 0x5;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY_FLAG: u8 = 0x20;
+
+const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+const SETTINGS_MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+/// Headers that only apply to a single HTTP/1 connection and are not allowed in HTTP/2.
+const CONNECTION_HEADERS: [&str; 5] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// # ErrorCode
+///
+/// The error codes of RST_STREAM and GOAWAY frames.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorCode {
+    NoError = 0x0,
+    Protocol = 0x1,
+    Internal = 0x2,
+    FlowControl = 0x3,
+    StreamClosed = 0x5,
+    FrameSize = 0x6,
+    RefusedStream = 0x7,
+    Cancel = 0x8,
+    Compression = 0x9,
+    EnhanceYourCalm = 0xb,
+    Http11Required = 0xd,
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ErrorCode::NoError => "NO_ERROR",
+            ErrorCode::Protocol => "PROTOCOL_ERROR",
+            ErrorCode::Internal => "INTERNAL_ERROR",
+            ErrorCode::FlowControl => "FLOW_CONTROL_ERROR",
+            ErrorCode::StreamClosed => "STREAM_CLOSED",
+            ErrorCode::FrameSize => "FRAME_SIZE_ERROR",
+            ErrorCode::RefusedStream => "REFUSED_STREAM",
+            ErrorCode::Cancel => "CANCEL",
+            ErrorCode::Compression => "COMPRESSION_ERROR",
+            ErrorCode::EnhanceYourCalm => "ENHANCE_YOUR_CALM",
+            ErrorCode::Http11Required => "HTTP_1_1_REQUIRED",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// # Event
+///
+/// What the client asked for on a stream.
+#[derive(Debug, PartialEq)]
+pub enum Event {
+    /// A complete request, as the head and body the HTTP/1 request reader would produce.
+    Request(u32, (String, Bytes)),
+    /// A request that exceeds a limit of the server and should be answered with this code.
+    Rejected(u32, StatusCode),
+}
+
+#[derive(Debug, PartialEq)]
+enum State {
+    /// Waiting for the rest of the request.
+    Receiving,
+    /// The request is being handled.
+    Handling,
+    /// Sending the body of the response as the flow control windows allow.
+    Sending,
+}
+
+#[derive(Debug)]
+struct Stream {
+    state: State,
+    headers: Vec<(String, String)>,
+    body: Bytes,
+    /// The client has sent the whole request.
+    received_end: bool,
+    /// Discard the body, the request was rejected.
+    rejected: bool,
+    head_request: bool,
+    send_window: i64,
+    response: Bytes,
+    sent: usize,
+}
+
+impl Stream {
+    fn new(send_window: i64) -> Self {
+        Self {
+            state: State::Receiving,
+            headers: Vec::new(),
+            body: Bytes::new(),
+            received_end: false,
+            rejected: false,
+            head_request: false,
+            send_window,
+            response: Bytes::new(),
+            sent: 0,
+        }
+    }
+}
+
+/// # Http2
+///
+/// The server side of an HTTP/2 connection, without the I/O. Bytes read from the client go into
+/// `receive`, which returns the requests that are complete, and responses go into `send_response`.
+/// Everything to send to the client is collected until `take_output`.
+#[derive(Debug)]
+pub struct Http2 {
+    input: Bytes,
+    output: Bytes,
+    preface_received: bool,
+    settings_received: bool,
+    decoder: Decoder,
+    encoder: Encoder,
+    streams: BTreeMap<u32, Stream>,
+    last_stream_id: u32,
+    /// Header block of a stream that continues in CONTINUATION frames, and if it ends the stream.
+    continuation: Option<(u32, Bytes, bool)>,
+    /// Connection flow control window for sending.
+    send_window: i64,
+    /// `SETTINGS_INITIAL_WINDOW_SIZE` of the client.
+    initial_window: i64,
+    /// `SETTINGS_MAX_FRAME_SIZE` of the client.
+    max_frame_size: usize,
+    /// Set once either side sent GOAWAY. No new streams are accepted.
+    closing: bool,
+    body_size_limit: usize,
+    max_header_bytes: usize,
+    max_header_count: usize,
+}
+
+impl Http2 {
+    /// Starts a connection that begins with the client preface.
+    pub fn new(config: &ServerConfig) -> Self {
+        let mut http2 = Self {
+            input: Bytes::new(),
+            output: Bytes::new(),
+            preface_received: false,
+            settings_received: false,
+            decoder: Decoder::default(),
+            encoder: Encoder::new(),
+            streams: BTreeMap::new(),
+            last_stream_id: 0,
+            continuation: None,
+            send_window: DEFAULT_WINDOW,
+            initial_window: DEFAULT_WINDOW,
+            max_frame_size: MAX_FRAME_SIZE,
+            closing: false,
+            body_size_limit: config.body_size_limit,
+            max_header_bytes: config.max_header_bytes,
+            max_header_count: config.max_header_count,
+        };
+
+        let mut settings = Vec::new();
+        for (id, value) in [
+            (SETTINGS_MAX_CONCURRENT_STREAMS, MAX_CONCURRENT_STREAMS),
+            (SETTINGS_MAX_HEADER_LIST_SIZE, config.max_header_bytes),
+        ] {
+            settings.extend_from_slice(&id.to_be_bytes());
+            settings.extend_from_slice(&(value.min(u32::MAX as usize) as u32).to_be_bytes());
+        }
+        http2.write_frame(SETTINGS, 0, 0, &settings);
+        http2
+    }
+
+    /// Continues a connection upgraded from HTTP/1.1 with `Upgrade: h2c`. The request that asked
+    /// for the upgrade becomes stream 1, and `settings` are the decoded `HTTP2-Settings` header.
+    pub fn upgrade(
+        config: &ServerConfig,
+        settings: &[u8],
+        request: (String, Bytes),
+    ) -> Result<(Self, Vec<Event>), ErrorCode> {
+        let mut http2 = Self::new(config);
+        // The 101 response acknowledges the settings
+        http2.apply_settings(settings)?;
+
+        let mut stream = Stream::new(http2.initial_window);
+        stream.state = State::Handling;
+        stream.received_end = true;
+        stream.head_request = request.0.starts_with("HEAD ");
+        http2.streams.insert(1, stream);
+        http2.last_stream_id = 1;
+
+        Ok((http2, vec![Event::Request(1, request)]))
+    }
+
+    /// Adds bytes read from the client and handles the frames that are complete. A connection
+    /// error is returned after GOAWAY was queued, the connection should be closed once the
+    /// output is sent.
+    pub fn receive(&mut self, bytes: &[u8]) -> Result<Vec<Event>, ErrorCode> {
+        self.input.extend_from_slice(bytes);
+
+        if !self.preface_received {
+            if self.input.len() < PREFACE.len() {
+                return match PREFACE.starts_with(&self.input) {
+                    true => Ok(Vec::new()),
+                    false => Err(self.fail(ErrorCode::Protocol)),
+                };
+            }
+            if !self.input.starts_with(PREFACE) {
+                return Err(self.fail(ErrorCode::Protocol));
+            }
+            self.input.drain(..PREFACE.len());
+            self.preface_received = true;
+        }
+
+        let mut events = Vec::new();
+        let mut pos = 0;
+        while let Some(header) = self.input.get(pos..pos + FRAME_HEADER_LENGTH) {
+            let length = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+            let (kind, flags) = (header[3], header[4]);
+            let id = u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7fff_ffff;
+
+            if length > MAX_FRAME_SIZE {
+                return Err(self.fail(ErrorCode::FrameSize));
+            }
+            let start = pos + FRAME_HEADER_LENGTH;
+            if self.input.len() < start + length {
+                break;
+            }
+            let payload = self.input[start..start + length].to_vec();
+            pos = start + length;
+
+            if let Err(code) = self.frame(kind, flags, id, &payload, &mut events) {
+                return Err(self.fail(code));
+            }
+        }
+        self.input.drain(..pos);
+
+        Ok(events)
+    }
+
+    /// Sends the response to the request on stream `id`. Responses to streams the client
+    /// has reset are dropped.
+    pub fn send_response(&mut self, id: u32, response: Response<Bytes>) {
+        let head_request = match self.streams.get(&id) {
+            Some(stream) if stream.state == State::Handling => stream.head_request,
+            _ => return,
+        };
+        // Event streams keep the connection to themselves, so the client has to retry on HTTP/1.1
+        if response.extensions().get::<EventStream>().is_some() {
+            self.reset(id, ErrorCode::Http11Required);
+            return;
+        }
+
+        let (parts, body) = response.into_parts();
+        let status = parts.status;
+        let body = match head_request
+            || status.is_informational()
+            || status.as_u16() == 204
+            || status.as_u16() == 304
+        {
+            true => Bytes::new(),
+            false => body,
+        };
+
+        let mut fields = vec![(":status".to_string(), status.as_str().to_string())];
+        for (name, value) in &parts.headers {
+            if !CONNECTION_HEADERS.contains(&name.as_str()) {
+                let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+                fields.push((name.as_str().to_string(), value));
+            }
+        }
+        if !head_request && !parts.headers.contains_key(http::header::CONTENT_LENGTH) {
+            fields.push(("content-length".to_string(), body.len().to_string()));
+        }
+
+        let block = self.encoder.encode(
+            fields
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str())),
+        );
+        let end_stream = body.is_empty();
+        self.write_headers(id, &block, end_stream);
+
+        if end_stream {
+            self.finish_stream(id);
+            return;
+        }
+        if let Some(stream) = self.streams.get_mut(&id) {
+            stream.state = State::Sending;
+            stream.response = body;
+        }
+        self.send_pending();
+    }
+
+    /// Stops accepting new streams. The connection can be closed once `is_closed`.
+    pub fn go_away(&mut self) {
+        if !self.closing {
+            self.write_goaway(ErrorCode::NoError);
+        }
+    }
+
+    /// Frames to send to the client.
+    pub fn take_output(&mut self) -> Bytes {
+        std::mem::take(&mut self.output)
+    }
+
+    /// Returns `true` if no stream is open.
+    pub fn is_idle(&self) -> bool {
+        self.streams.is_empty() && self.continuation.is_none()
+    }
+
+    /// Returns `true` if a request is waiting for its response.
+    pub fn is_handling(&self) -> bool {
+        self.streams
+            .values()
+            .any(|stream| stream.state == State::Handling)
+    }
+
+    /// Returns `true` once GOAWAY was sent or received and every stream is done.
+    pub fn is_closed(&self) -> bool {
+        self.closing && self.is_idle()
+    }
+
+    fn frame(
+        &mut self,
+        kind: u8,
+        flags: u8,
+        id: u32,
+        payload: &[u8],
+        events: &mut Vec<Event>,
+    ) -> Result<(), ErrorCode> {
+        // Header blocks can't be interleaved with other frames
+        if let Some((expected, ..)) = self.continuation {
+            if kind != CONTINUATION || id != expected {
+                return Err(ErrorCode::Protocol);
+            }
+        }
+        if !self.settings_received && kind != SETTINGS {
+            return Err(ErrorCode::Protocol);
+        }
+
+        match kind {
+            DATA => self.data(flags, id, payload, events),
+            HEADERS => self.headers(flags, id, payload, events),
+            PRIORITY => match (id, payload.len()) {
+                (0, _) => Err(ErrorCode::Protocol),
+                (_, 5) => Ok(()),
+                _ => Err(ErrorCode::FrameSize),
+            },
+            RST_STREAM => {
+                if id == 0 || id > self.last_stream_id {
+                    return Err(ErrorCode::Protocol);
+                }
+                if payload.len() != 4 {
+                    return Err(ErrorCode::FrameSize);
+                }
+                self.streams.remove(&id);
+                Ok(())
+            }
+            SETTINGS => {
+                if id != 0 {
+                    return Err(ErrorCode::Protocol);
+                }
+                if flags & ACK != 0 {
+                    return match payload.is_empty() {
+                        true => Ok(()),
+                        false => Err(ErrorCode::FrameSize),
+                    };
+                }
+                self.apply_settings(payload)?;
+                self.settings_received = true;
+                self.write_frame(SETTINGS, ACK, 0, &[]);
+                self.send_pending();
+                Ok(())
+            }
+            PUSH_PROMISE => Err(ErrorCode::Protocol),
+            PING => {
+                if id != 0 {
+                    return Err(ErrorCode::Protocol);
+                }
+                if payload.len() != 8 {
+                    return Err(ErrorCode::FrameSize);
+                }
+                if flags & ACK == 0 {
+                    self.write_frame(PING, ACK, 0, payload);
+                }
+                Ok(())
+            }
+            GOAWAY => {
+                if id != 0 {
+                    return Err(ErrorCode::Protocol);
+                }
+                // Finish the open streams, but take no new ones
+                self.closing = true;
+                Ok(())
+            }
+            WINDOW_UPDATE => self.window_update(id, payload),
+            CONTINUATION => {
+                let (id, mut block, end_stream) = match self.continuation.take() {
+                    Some(continuation) => continuation,
+                    None => return Err(ErrorCode::Protocol),
+                };
+                block.extend_from_slice(payload);
+                if block.len() > self.max_header_block() {
+                    return Err(ErrorCode::EnhanceYourCalm);
+                }
+                match flags & END_HEADERS != 0 {
+                    true => self.header_block(id, &block, end_stream, events),
+                    false => {
+                        self.continuation = Some((id, block, end_stream));
+                        Ok(())
+                    }
+                }
+            }
+            // Unknown frames are ignored
+            _ => Ok(()),
+        }
+    }
+
+    fn headers(
+        &mut self,
+        flags: u8,
+        id: u32,
+        payload: &[u8],
+        events: &mut Vec<Event>,
+    ) -> Result<(), ErrorCode> {
+        if id == 0 || id.is_multiple_of(2) {
+            return Err(ErrorCode::Protocol);
+        }
+        let mut fragment = unpad(flags, payload)?;
+        if flags & PRIORITY_FLAG != 0 {
+            fragment = fragment.get(5..).ok_or(ErrorCode::FrameSize)?;
+        }
+        if fragment.len() > self.max_header_block() {
+            return Err(ErrorCode::EnhanceYourCalm);
+        }
+
+        let end_stream = flags & END_STREAM != 0;
+        match flags & END_HEADERS != 0 {
+            true => self.header_block(id, fragment, end_stream, events),
+            false => {
+                self.continuation = Some((id, fragment.to_vec(), end_stream));
+                Ok(())
+            }
+        }
+    }
+
+    fn header_block(
+        &mut self,
+        id: u32,
+        block: &[u8],
+        end_stream: bool,
+        events: &mut Vec<Event>,
+    ) -> Result<(), ErrorCode> {
+        // Decode even when the stream is refused, to keep the table in sync with the client
+        let headers = self
+            .decoder
+            .decode(block)
+            .map_err(|_| ErrorCode::Compression)?;
+
+        if let Some(stream) = self.streams.get_mut(&id) {
+            // Trailers, which have to end the request
+            if stream.state != State::Receiving || stream.received_end || !end_stream {
+                return Err(ErrorCode::Protocol);
+            }
+            stream.received_end = true;
+            self.finish_request(id, events);
+            return Ok(());
+        }
+        if id <= self.last_stream_id {
+            return Err(ErrorCode::StreamClosed);
+        }
+        self.last_stream_id = id;
+
+        if self.closing {
+            return Ok(());
+        }
+        // Streams still sending their response count too, or a client that doesn't open the
+        // flow control windows could have any number of responses held here
+        if self.streams.len() >= MAX_CONCURRENT_STREAMS {
+            self.reset(id, ErrorCode::RefusedStream);
+            return Ok(());
+        }
+
+        let mut stream = Stream::new(self.initial_window);
+        let size: usize = headers
+            .iter()
+            .map(|(name, value)| name.len() + value.len() + 32)
+            .sum();
+        let fields = headers
+            .iter()
+            .filter(|(name, _)| !name.starts_with(':'))
+            .count();
+        stream.head_request = headers.iter().any(|h| h.0 == ":method" && h.1 == "HEAD");
+        stream.headers = headers;
+        stream.received_end = end_stream;
+
+        if size > self.max_header_bytes || fields > self.max_header_count {
+            stream.rejected = true;
+            stream.state = State::Handling;
+            self.streams.insert(id, stream);
+            events.push(Event::Rejected(
+                id,
+                StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+            ));
+            return Ok(());
+        }
+
+        self.streams.insert(id, stream);
+        if end_stream {
+            self.finish_request(id, events);
+        }
+        Ok(())
+    }
+
+    fn data(
+        &mut self,
+        flags: u8,
+        id: u32,
+        payload: &[u8],
+        events: &mut Vec<Event>,
+    ) -> Result<(), ErrorCode> {
+        if id == 0 {
+            return Err(ErrorCode::Protocol);
+        }
+        let data = unpad(flags, payload)?;
+        let end_stream = flags & END_STREAM != 0;
+
+        // Give the window back right away. Bodies are limited by the body size limit instead.
+        if !payload.is_empty() {
+            self.grant(0, payload.len());
+        }
+
+        let stream = match self.streams.get_mut(&id) {
+            Some(stream) if !stream.received_end => stream,
+            _ if id > self.last_stream_id => return Err(ErrorCode::Protocol),
+            _ => {
+                self.reset(id, ErrorCode::StreamClosed);
+                return Ok(());
+            }
+        };
+        stream.received_end = end_stream;
+        if stream.rejected {
+            return Ok(());
+        }
+
+        stream.body.extend_from_slice(data);
+        if stream.body.len() > self.body_size_limit {
+            stream.rejected = true;
+            stream.state = State::Handling;
+            stream.body = Bytes::new();
+            events.push(Event::Rejected(id, StatusCode::PAYLOAD_TOO_LARGE));
+        } else if end_stream {
+            self.finish_request(id, events);
+        }
+
+        if !end_stream && !payload.is_empty() {
+            self.grant(id, payload.len());
+        }
+        Ok(())
+    }
+
+    fn window_update(&mut self, id: u32, payload: &[u8]) -> Result<(), ErrorCode> {
+        let increment = match payload {
+            [a, b, c, d] => (u32::from_be_bytes([*a, *b, *c, *d]) & 0x7fff_ffff) as i64,
+            _ => return Err(ErrorCode::FrameSize),
+        };
+
+        if id == 0 {
+            if increment == 0 {
+                return Err(ErrorCode::Protocol);
+            }
+            self.send_window += increment;
+            if self.send_window > MAX_WINDOW {
+                return Err(ErrorCode::FlowControl);
+            }
+        } else if let Some(stream) = self.streams.get_mut(&id) {
+            stream.send_window += increment;
+            if increment == 0 {
+                self.reset(id, ErrorCode::Protocol);
+            } else if stream.send_window > MAX_WINDOW {
+                self.reset(id, ErrorCode::FlowControl);
+            }
+        }
+
+        self.send_pending();
+        Ok(())
+    }
+
+    fn apply_settings(&mut self, payload: &[u8]) -> Result<(), ErrorCode> {
+        if !payload.len().is_multiple_of(6) {
+            return Err(ErrorCode::FrameSize);
+        }
+
+        for setting in payload.chunks(6) {
+            let id = u16::from_be_bytes([setting[0], setting[1]]);
+            let value = u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+            match id {
+                SETTINGS_ENABLE_PUSH if value > 1 => return Err(ErrorCode::Protocol),
+                SETTINGS_INITIAL_WINDOW_SIZE => {
+                    let value = value as i64;
+                    if value > MAX_WINDOW {
+                        return Err(ErrorCode::FlowControl);
+                    }
+                    // The change applies to the windows of the open streams too
+                    let delta = value - self.initial_window;
+                    for stream in self.streams.values_mut() {
+                        stream.send_window += delta;
+                        if stream.send_window > MAX_WINDOW {
+                            return Err(ErrorCode::FlowControl);
+                        }
+                    }
+                    self.initial_window = value;
+                }
+                SETTINGS_MAX_FRAME_SIZE => {
+                    if !(16_384..=16_777_215).contains(&value) {
+                        return Err(ErrorCode::Protocol);
+                    }
+                    self.max_frame_size = value as usize;
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// `finish_request` turns a received stream into a request for the server.
+    fn finish_request(&mut self, id: u32, events: &mut Vec<Event>) {
+        let stream = match self.streams.get_mut(&id) {
+            Some(stream) => stream,
+            None => return,
+        };
+        stream.state = State::Handling;
+        let body = std::mem::take(&mut stream.body);
+
+        match request_head(&stream.headers, body.len()) {
+            Some(head) => events.push(Event::Request(id, (head, body))),
+            None => self.reset(id, ErrorCode::Protocol),
+        }
+    }
+
+    /// Sends as much of the pending response bodies as the flow control windows allow.
+    fn send_pending(&mut self) {
+        let ids = self
+            .streams
+            .iter()
+            .filter(|(_, stream)| stream.state == State::Sending)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+
+        for id in ids {
+            while let Some(stream) = self.streams.get_mut(&id) {
+                let remaining = stream.response.len() - stream.sent;
+                let size = (remaining as i64)
+                    .min(self.send_window)
+                    .min(stream.send_window)
+                    .min(self.max_frame_size as i64)
+                    .max(0) as usize;
+                if size == 0 {
+                    break;
+                }
+
+                let chunk = stream.response[stream.sent..stream.sent + size].to_vec();
+                stream.sent += size;
+                stream.send_window -= size as i64;
+                self.send_window -= size as i64;
+
+                let end_stream = size == remaining;
+                let flags = if end_stream { END_STREAM } else { 0 };
+                self.write_frame(DATA, flags, id, &chunk);
+                if end_stream {
+                    self.finish_stream(id);
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Closes a stream after its response was sent. If the client is still sending the request,
+    /// it is told to stop.
+    fn finish_stream(&mut self, id: u32) {
+        if let Some(stream) = self.streams.remove(&id) {
+            if !stream.received_end {
+                self.write_rst_stream(id, ErrorCode::NoError);
+            }
+        }
+    }
+
+    fn reset(&mut self, id: u32, code: ErrorCode) {
+        self.streams.remove(&id);
+        self.write_rst_stream(id, code);
+    }
+
+    fn fail(&mut self, code: ErrorCode) -> ErrorCode {
+        self.write_goaway(code);
+        code
+    }
+
+    /// Lets the client send `size` more bytes on stream `id`, or the connection for `0`.
+    fn grant(&mut self, id: u32, size: usize) {
+        self.write_frame(WINDOW_UPDATE, 0, id, &(size as u32).to_be_bytes());
+    }
+
+    fn max_header_block(&self) -> usize {
+        self.max_header_bytes * 2 + MAX_FRAME_SIZE
+    }
+
+    fn write_headers(&mut self, id: u32, block: &[u8], end_stream: bool) {
+        let mut chunks = block.chunks(self.max_frame_size).peekable();
+        let mut kind = HEADERS;
+        let mut flags = if end_stream { END_STREAM } else { 0 };
+
+        if chunks.peek().is_none() {
+            self.write_frame(HEADERS, flags | END_HEADERS, id, &[]);
+            return;
+        }
+        while let Some(chunk) = chunks.next() {
+            if chunks.peek().is_none() {
+                flags |= END_HEADERS;
+            }
+            self.write_frame(kind, flags, id, chunk);
+            kind = CONTINUATION;
+            flags = 0;
+        }
+    }
+
+    fn write_rst_stream(&mut self, id: u32, code: ErrorCode) {
+        self.write_frame(RST_STREAM, 0, id, &(code as u32).to_be_bytes());
+    }
+
+    fn write_goaway(&mut self, code: ErrorCode) {
+        let mut payload = self.last_stream_id.to_be_bytes().to_vec();
+        payload.extend_from_slice(&(code as u32).to_be_bytes());
+        self.write_frame(GOAWAY, 0, 0, &payload);
+        self.closing = true;
+    }
+
+    fn write_frame(&mut self, kind: u8, flags: u8, id: u32, payload: &[u8]) {
+        self.output
+            .extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+        self.output.push(kind);
+        self.output.push(flags);
+        self.output.extend_from_slice(&id.to_be_bytes());
+        self.output.extend_from_slice(payload);
+    }
+}
+
+/// `unpad` gets the payload of a frame without its padding.
+fn unpad(flags: u8, payload: &[u8]) -> Result<&[u8], ErrorCode> {
+    if flags & PADDED == 0 {
+        return Ok(payload);
+    }
+    let padding = *payload.first().ok_or(ErrorCode::FrameSize)? as usize;
+    if padding >= payload.len() {
+        return Err(ErrorCode::Protocol);
+    }
+    Ok(&payload[1..payload.len() - padding])
+}
+
+/// # request_head
+///
+/// Writes the headers of an HTTP/2 request as an HTTP/1.1 request head, so the request can be
+/// handled like any other. `None` if the headers are malformed.
+pub fn request_head(headers: &[(String, String)], body_length: usize) -> Option<String> {
+    let (mut method, mut path, mut authority) = (None, None, None);
+    let mut fields = Vec::new();
+    let mut cookies = Vec::new();
+
+    for (name, value) in headers {
+        // Line breaks would split the head
+        if value.contains(['\r', '\n', '\0'])
+            || name.contains(['\r', '\n', ':', ' ']) && !name.starts_with(':')
+        {
+            return None;
+        }
+
+        match name.strip_prefix(':') {
+            // Pseudo-headers come first and only once
+            Some(pseudo) => {
+                let slot = match pseudo {
+                    "method" => &mut method,
+                    "path" => &mut path,
+                    "authority" => &mut authority,
+                    "scheme" => continue,
+                    _ => return None,
+                };
+                if !fields.is_empty() || slot.replace(value.as_str()).is_some() {
+                    return None;
+                }
+            }
+            None if name.bytes().any(|b| b.is_ascii_uppercase()) => return None,
+            None if CONNECTION_HEADERS.contains(&name.as_str()) => return None,
+            None if name == "te" && value != "trailers" => return None,
+            None if name == "content-length" && value.parse() != Ok(body_length) => return None,
+            None if name == "cookie" => cookies.push(value.as_str()),
+            None => fields.push((name.as_str(), value.as_str())),
+        }
+    }
+
+    let (method, path) = (method?, path?);
+    if path.is_empty() || path.contains(' ') || method.contains(' ') {
+        return None;
+    }
+
+    let mut head = format!("{method} {path} HTTP/1.1");
+    if let Some(authority) = authority.filter(|_| !fields.iter().any(|(name, _)| *name == "host")) {
+        head.push_str(&format!("\r\nhost: {authority}"));
+    }
+    // Cookies may be split into several fields, but HTTP/1.1 expects one
+    if !cookies.is_empty() {
+        head.push_str(&format!("\r\ncookie: {}", cookies.join("; ")));
+    }
+    for (name, value) in fields {
+        head.push_str(&format!("\r\n{name}: {value}"));
+    }
+    Some(head)
+}
+
+/// # h2c_upgrade
+///
+/// Gets the decoded `HTTP2-Settings` of an HTTP/1.1 request asking to upgrade to HTTP/2 with
+/// `Upgrade: h2c`.
+pub fn h2c_upgrade(head: &str) -> Option<Bytes> {
+    let mut lines = head.split("\r\n");
+    if !lines.next()?.trim_end().ends_with("HTTP/1.1") {
+        return None;
+    }
+
+    let mut upgrade = false;
+    let mut settings = None;
+    for (name, value) in lines.filter_map(|line| line.split_once(':')) {
+        let (name, value) = (name.trim(), value.trim());
+        if name.eq_ignore_ascii_case("upgrade") {
+            upgrade = value
+                .split(',')
+                .any(|protocol| protocol.trim().eq_ignore_ascii_case("h2c"));
+        } else if name.eq_ignore_ascii_case("http2-settings") {
+            if settings.is_some() {
+                return None;
+            }
+            settings = Some(URL_SAFE_NO_PAD.decode(value.trim_end_matches('=')).ok()?);
+        }
+    }
+
+    settings.filter(|_| upgrade)
+}
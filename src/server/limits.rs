@@ -0,0 +1,262 @@
+use crate::log;
+use crate::log::*;
+use crate::server::errors::error;
+use crate::server::{Bytes, Response, ServerConfig, StatusCode};
+use crate::server_config::RateLimit;
+use http::header::RETRY_AFTER;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Buckets are pruned once there are more than this many clients.
+const PRUNE_THRESHOLD: usize = 4096;
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+    limit: RateLimit,
+}
+
+impl Bucket {
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.per_second).min(self.capacity());
+        self.last_refill = now;
+    }
+
+    fn capacity(&self) -> f64 {
+        self.limit.burst as f64
+    }
+}
+
+/// # RateLimiter
+///
+/// Token buckets per client and scope. A scope is the server or a route of it.
+/// Clones share the same buckets.
+#[derive(Clone, Debug, Default)]
+pub struct RateLimiter {
+    buckets: Arc<Mutex<HashMap<(IpAddr, String), Bucket>>>,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes a token from the bucket of `ip` in `scope`. If it is empty, the number of
+    /// seconds until the next token is returned as the error.
+    pub fn check(&self, ip: IpAddr, scope: &str, limit: &RateLimit) -> Result<(), u64> {
+        self.check_at(ip, scope, limit, Instant::now())
+    }
+
+    pub fn check_at(
+        &self,
+        ip: IpAddr,
+        scope: &str,
+        limit: &RateLimit,
+        now: Instant,
+    ) -> Result<(), u64> {
+        let mut buckets = match self.buckets.lock() {
+            Ok(buckets) => buckets,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        if buckets.len() > PRUNE_THRESHOLD {
+            // Full buckets behave exactly like new ones, so they can be dropped
+            buckets.retain(|_, bucket| {
+                bucket.refill(now);
+                bucket.tokens < bucket.capacity()
+            });
+        }
+
+        let bucket = buckets
+            .entry((ip, scope.to_string()))
+            .or_insert_with(|| Bucket {
+                tokens: limit.burst as f64,
+                last_refill: now,
+                limit: *limit,
+            });
+        bucket.limit = *limit;
+        bucket.refill(now);
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+
+        let retry_after = ((1.0 - bucket.tokens) / limit.per_second).ceil() as u64;
+        log!(
+            LogLevel::Warn,
+            LogFileType::Client,
+            format!(
+                "Rate limit of {} requests per second (burst {}) exceeded by {ip} on '{scope}'. \
+                 Retry after {retry_after}s",
+                limit.per_second, limit.burst
+            )
+        );
+        Err(retry_after.max(1))
+    }
+}
+
+/// # ConnectionCounter
+///
+/// Open connections of a server, in total and per client. Shared by all event loops,
+/// so the connection limits hold however the connections are spread over them.
+#[derive(Debug, Default)]
+pub struct ConnectionCounter {
+    open: Mutex<(usize, HashMap<IpAddr, usize>)>,
+}
+
+impl ConnectionCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts a connection from `peer`, unless it exceeds the connection limits of `config`.
+    /// The connection is counted until the returned guard is dropped. The error is the status
+    /// code to reject the connection with.
+    pub fn open(
+        self: &Arc<Self>,
+        peer: SocketAddr,
+        config: &ServerConfig,
+    ) -> Result<ConnectionGuard, StatusCode> {
+        let mut open = self.lock();
+        let (total, per_ip) = &mut *open;
+
+        if let Some(max) = config.max_connections {
+            if *total >= max {
+                log!(
+                    LogLevel::Warn,
+                    LogFileType::Server,
+                    format!("Connection limit of {max} reached. Rejected {peer}")
+                );
+                return Err(StatusCode::SERVICE_UNAVAILABLE);
+            }
+        }
+
+        if let Some(max) = config.max_connections_per_ip {
+            if per_ip
+                .get(&peer.ip())
+                .is_some_and(|from_peer| *from_peer >= max)
+                || max == 0
+            {
+                log!(
+                    LogLevel::Warn,
+                    LogFileType::Client,
+                    format!("Connection limit of {max} per client reached. Rejected {peer}")
+                );
+                return Err(StatusCode::TOO_MANY_REQUESTS);
+            }
+        }
+
+        *per_ip.entry(peer.ip()).or_insert(0) += 1;
+        *total += 1;
+        Ok(ConnectionGuard {
+            counter: Arc::clone(self),
+            ip: peer.ip(),
+        })
+    }
+
+    /// Number of open connections.
+    pub fn total(&self) -> usize {
+        self.lock().0
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, (usize, HashMap<IpAddr, usize>)> {
+        match self.open.lock() {
+            Ok(open) => open,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+/// # ConnectionGuard
+///
+/// Keeps a connection counted in its `ConnectionCounter` until dropped.
+#[derive(Debug)]
+pub struct ConnectionGuard {
+    counter: Arc<ConnectionCounter>,
+    ip: IpAddr,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut open = self.counter.lock();
+        let (total, per_ip) = &mut *open;
+        *total = total.saturating_sub(1);
+        if let Some(count) = per_ip.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                per_ip.remove(&self.ip);
+            }
+        }
+    }
+}
+
+/// # too_many_requests
+///
+/// 429 response telling the client to wait `retry_after` seconds.
+pub fn too_many_requests(retry_after: u64, config: &ServerConfig) -> Response<Bytes> {
+    let mut resp = error(StatusCode::TOO_MANY_REQUESTS, config);
+    resp.headers_mut().insert(RETRY_AFTER, retry_after.into());
+    resp
+}
+
+/// # check_limits
+///
+/// Checks that the rate limits of a server and its routes let clients in again. A limit
+/// refilled at zero or less tokens per second would turn clients away for good.
+pub fn check_limits(config: &ServerConfig) -> Result<(), String> {
+    let routes = config.routes.iter().filter_map(|route| {
+        let limit = route.settings.as_ref()?.rate_limit?;
+        Some((route.url_path, limit))
+    });
+    let limits = config
+        .rate_limit
+        .map(|limit| ("server", limit))
+        .into_iter()
+        .chain(routes);
+
+    for (scope, limit) in limits {
+        if limit.per_second.is_nan() || limit.per_second <= 0.0 {
+            return Err(format!(
+                "the rate limit of {scope} on {} must allow more than 0 requests per second, not {}",
+                config.host, limit.per_second
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// # log_limits
+///
+/// Writes the connection and rate limits of a server to the server log.
+pub fn log_limits(config: &ServerConfig) {
+    let describe = |limit: &Option<RateLimit>| match limit {
+        Some(limit) => format!("{}/s (burst {})", limit.per_second, limit.burst),
+        None => "unlimited".to_string(),
+    };
+    let count = |limit: Option<usize>| match limit {
+        Some(limit) => limit.to_string(),
+        None => "unlimited".to_string(),
+    };
+
+    let mut message = format!(
+        "Limits for {}{:?}: connections {}, connections per ip {}, requests per ip {}",
+        config.host,
+        config.ports,
+        count(config.max_connections),
+        count(config.max_connections_per_ip),
+        describe(&config.rate_limit)
+    );
+    for route in &config.routes {
+        if let Some(limit) = route.settings.as_ref().and_then(|s| s.rate_limit) {
+            message.push_str(&format!(", {} {}", route.url_path, describe(&Some(limit))));
+        }
+    }
+    log!(LogLevel::Info, LogFileType::Server, message);
+}
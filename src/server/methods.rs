@@ -3,6 +3,9 @@ use crate::log;
 use crate::log::*;
 use crate::server::content_type;
 use crate::server::utils::{get_line, get_split_index};
+use crate::server::webdav::{
+    check_locks, encode_href, handle_webdav, is_webdav, route_file, DAV_METHODS,
+};
 use http::header::{ALLOW, CONTENT_LENGTH, CONTENT_TYPE, HOST};
 use std::fs;
 use std::str::FromStr;
@@ -23,6 +26,17 @@ pub fn handle_method(
     req: &Request<Bytes>,
     config: &ServerConfig,
 ) -> Result<Response<Bytes>, StatusCode> {
+    if is_webdav(route) {
+        match req.method().as_str() {
+            // Locked resources can only be written by the owners of the locks
+            "PUT" | "POST" | "PATCH" => check_locks(route, req, false)?,
+            method if method == "DELETE" || DAV_METHODS.contains(&method) => {
+                return handle_webdav(route, req, config);
+            }
+            _ => {}
+        }
+    }
+
     match *req.method() {
         // SAFE METHODS
         Method::GET => safe::get(req, config),
@@ -38,6 +52,7 @@ pub fn handle_method(
         _ => {
             // Managed to bypass implemented request methods.
             log!(
+                LogLevel::Warn,
                 LogFileType::Server,
                 format!("Not Implemented: {}", &req.method())
             );
@@ -49,7 +64,6 @@ pub fn handle_method(
 pub mod safe {
     use super::*;
     use crate::server::get_route;
-    use crate::server::path::add_root_to_path;
     use http::header::{TRANSFER_ENCODING, VIA};
     use http::HeaderName;
 
@@ -63,7 +77,7 @@ pub mod safe {
             Err((status_code, _)) => return Err(status_code),
         };
 
-        let path = &add_root_to_path(&route, req.uri().path());
+        let path = &route_file(&route, req.uri().path()).ok_or(StatusCode::FORBIDDEN)?;
         let body = match fs::read(path) {
             Ok(b) => b,
             Err(_) => return Err(StatusCode::NOT_FOUND),
@@ -73,7 +87,7 @@ pub mod safe {
             .version(req.version())
             .header(HOST, config.host)
             .status(StatusCode::OK)
-            .header(CONTENT_TYPE, content_type(path))
+            .header(CONTENT_TYPE, content_type(&path.to_string_lossy()))
             .header(CONTENT_LENGTH, body.len());
 
         for (key, value) in req.headers() {
@@ -94,14 +108,14 @@ pub mod safe {
             Ok(route) => route,
             Err((status, _)) => return Err(status),
         };
-        let path = &add_root_to_path(&route, req.uri().path());
+        let path = &route_file(&route, req.uri().path()).ok_or(StatusCode::FORBIDDEN)?;
         let metadata = fs::metadata(path).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
 
         Response::builder()
             .version(req.version())
             .header(HOST, config.host)
             .status(StatusCode::OK)
-            .header(CONTENT_TYPE, content_type(path))
+            .header(CONTENT_TYPE, content_type(&path.to_string_lossy()))
             .header(CONTENT_LENGTH, metadata.len())
             .body(vec![])
             .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
@@ -160,12 +174,18 @@ pub mod safe {
             .collect::<Vec<&str>>()
             .join(", ");
 
-        Response::builder()
+        let mut resp = Response::builder()
             .version(req.version())
             .header(HOST, config.host)
             .status(StatusCode::OK)
-            .header(ALLOW, allowed_methods)
-            .body(vec![]) // Empty body for OPTIONS
+            .header(ALLOW, allowed_methods);
+
+        // Compliance classes of the WebDAV share, class 2 being locking
+        if is_webdav(route) {
+            resp = resp.header("DAV", "1, 2").header("MS-Author-Via", "DAV");
+        }
+
+        resp.body(vec![]) // Empty body for OPTIONS
             .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
     }
 }
@@ -173,16 +193,106 @@ pub mod safe {
 mod not_safe {
     use super::*;
     use crate::server::get_route;
-    use crate::server::path::add_root_to_path;
-
-    fn unsafe_response(path: &str, body: Bytes) -> Result<Response<Bytes>, StatusCode> {
-        Response::builder()
-            .status(StatusCode::OK)
-            .header(CONTENT_TYPE, content_type(path))
-            .header(CONTENT_LENGTH, body.len())
-            .body(body.clone())
+    use crate::server::upload::{create_temp_file, store_file, store_uploads};
+    use crate::server::{fields_to_json, multipart_boundary, urlencoded_form};
+    use crate::server_config::route::Upload;
+    use http::header::LOCATION;
+    use std::io::{self, Write};
+    use std::path::{Path, PathBuf};
+
+    /// Responds with `status` and an empty body. The content of the request is not echoed.
+    fn unsafe_response(
+        req: &Request<Bytes>,
+        config: &ServerConfig,
+        status: StatusCode,
+        location: Option<&str>,
+    ) -> Result<Response<Bytes>, StatusCode> {
+        let mut resp = Response::builder()
+            .version(req.version())
+            .header(HOST, config.host)
+            .status(status);
+        if let Some(location) = location {
+            resp = resp.header(LOCATION, location);
+        }
+        resp.body(vec![])
             .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
     }
+
+    /// The status of a failed file operation on `path`.
+    fn io_status(e: io::Error, path: &Path) -> StatusCode {
+        match e.kind() {
+            io::ErrorKind::NotFound => StatusCode::CONFLICT,
+            io::ErrorKind::PermissionDenied => StatusCode::FORBIDDEN,
+            _ => {
+                log!(
+                    LogLevel::Error,
+                    LogFileType::Server,
+                    format!("Failed to write {}. {e}", path.display())
+                );
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    /// The file of the request path, and its directory. The directory is created if it is
+    /// missing and the route has `create_parent_dirs` set.
+    fn target(route: &Route, req: &Request<Bytes>) -> Result<(PathBuf, PathBuf), StatusCode> {
+        // Nothing is written outside the root of the route
+        let path = route_file(route, req.uri().path()).ok_or(StatusCode::FORBIDDEN)?;
+        // Directories can't be written to
+        if req.uri().path().ends_with('/') || path.is_dir() {
+            return Err(StatusCode::CONFLICT);
+        }
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        if !dir.is_dir() {
+            let create = route
+                .settings
+                .as_ref()
+                .is_some_and(|settings| settings.create_parent_dirs);
+            if !create {
+                return Err(StatusCode::CONFLICT);
+            }
+            fs::create_dir_all(&dir).map_err(|e| io_status(e, &dir))?;
+        }
+        Ok((path, dir))
+    }
+
+    fn file_name(path: &Path) -> String {
+        path.file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// Writes `body` to a new temporary file in `dir`, so a failed write never leaves part of
+    /// it where the file should be.
+    fn write_temp(dir: &Path, name: &str, body: &[u8]) -> io::Result<PathBuf> {
+        let (temp_path, mut file) = create_temp_file(dir, name)?;
+        if let Err(e) = file.write_all(body).and_then(|_| file.sync_all()) {
+            let _ = fs::remove_file(&temp_path);
+            return Err(e);
+        }
+        Ok(temp_path)
+    }
+
+    /// Replaces the file at `path` with `body` at once. Readers see the old or the new file,
+    /// even if the server stops while writing.
+    fn replace(path: &Path, dir: &Path, body: &[u8]) -> Result<(), StatusCode> {
+        let temp_path = write_temp(dir, &file_name(path), body).map_err(|e| io_status(e, path))?;
+        fs::rename(&temp_path, path).map_err(|e| {
+            let _ = fs::remove_file(&temp_path);
+            io_status(e, path)
+        })
+    }
+
+    /// Creates a new file with the body. If the file already exists, the body is stored as
+    /// `name(1).ext`, `name(2).ext`... Responds with `201 Created` and the `Location` of the file.
+    ///
+    /// Forms are parsed: an `application/x-www-form-urlencoded` body is stored as the JSON of its
+    /// fields, and the files of a `multipart/form-data` body in the directory of the path.
     pub fn post(
         req: &Request<Bytes>,
         config: &ServerConfig,
@@ -191,45 +301,80 @@ mod not_safe {
             Ok(route) => route,
             Err((status, _)) => return Err(status),
         };
-        let path = &add_root_to_path(&route, req.uri().path());
-        let body = req.body().to_vec();
-
-        let resp = unsafe_response(path, body.clone())?;
-
-        // Resource does not exist, so create it.
-        if fs::metadata(path).is_err() {
-            fs::write(path, body.clone()).map_err(|_| StatusCode::BAD_REQUEST)?;
-            return Ok(resp.clone());
+        if multipart_boundary(req.headers()).is_some() {
+            return post_files(&route, req, config);
         }
+        let (path, dir) = target(&route, req)?;
+        let name = file_name(&path);
+
+        // Urlencoded forms are stored as the JSON of their fields
+        let form = urlencoded_form(req)
+            .ok()
+            .map(|fields| serde_json::Value::from(fields_to_json(fields)).to_string());
+        let body = form
+            .as_ref()
+            .map_or(req.body().as_slice(), String::as_bytes);
+
+        let temp_path = write_temp(&dir, &name, body).map_err(|e| io_status(e, &path))?;
+        let stored = store_file(&temp_path, &dir, &name).map_err(|e| {
+            let _ = fs::remove_file(&temp_path);
+            io_status(e, &path)
+        })?;
+
+        // Existing files are kept, so the new file may have another name
+        let uri_path = req.uri().path();
+        let stored = match is_webdav(&route) {
+            true => encode_href(&stored),
+            false => stored,
+        };
+        let location = format!(
+            "{}{stored}",
+            &uri_path[..uri_path.rfind('/').unwrap_or(0) + 1]
+        );
+        unsafe_response(req, config, StatusCode::CREATED, Some(&location))
+    }
 
-        let mut path = String::from(path);
-        let end = path.rfind('.').unwrap_or(path.len());
-        // If the file already exists, modify the path.
-        // /foo.txt -> /foo(1).txt
-        let mut i = 0;
-        path.insert_str(end, &format!("({i})"));
-
-        while fs::metadata(&path).is_ok() {
-            path = path.replace(&format!("({i})"), &format!("({})", i + 1));
-            i += 1;
+    /// Stores the files of a `multipart/form-data` form in the directory of the request path,
+    /// like an upload route without limits besides the `body_size_limit` of the server.
+    fn post_files(
+        route: &Route,
+        req: &Request<Bytes>,
+        config: &ServerConfig,
+    ) -> Result<Response<Bytes>, StatusCode> {
+        let dir = route_file(route, req.uri().path()).ok_or(StatusCode::FORBIDDEN)?;
+        let create = route
+            .settings
+            .as_ref()
+            .is_some_and(|settings| settings.create_parent_dirs);
+        if !create && !dir.is_dir() {
+            return Err(StatusCode::CONFLICT);
         }
-
-        fs::write(path, body.clone()).map_err(|_| StatusCode::BAD_REQUEST)?;
-        Ok(resp)
+        let upload = Upload {
+            max_file_size: None,
+            max_total_size: None,
+            allowed_extensions: Vec::new(),
+        };
+        store_uploads(route, &upload, req, config)
     }
 
+    /// Creates or replaces the file with the body. Responds with `201 Created` for a new file
+    /// and `204 No Content` for a replaced one.
     pub fn put(req: &Request<Bytes>, config: &ServerConfig) -> Result<Response<Bytes>, StatusCode> {
         let route = match get_route(req, config) {
             Ok(route) => route,
             Err((status, _)) => return Err(status),
         };
-        let path = &add_root_to_path(&route, req.uri().path());
-        let body = req.body().to_vec();
+        let (path, dir) = target(&route, req)?;
+        let existed = path.exists();
 
-        fs::write(path, &body).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-        unsafe_response(path, body)
+        replace(&path, &dir, req.body())?;
+        match existed {
+            true => unsafe_response(req, config, StatusCode::NO_CONTENT, None),
+            false => unsafe_response(req, config, StatusCode::CREATED, Some(req.uri().path())),
+        }
     }
 
+    /// Replaces an existing file with the body.
     pub fn patch(
         req: &Request<Bytes>,
         config: &ServerConfig,
@@ -238,14 +383,16 @@ mod not_safe {
             Ok(route) => route,
             Err((status, _)) => return Err(status),
         };
-        let path = &add_root_to_path(&route, req.uri().path());
-        let body = req.body().to_vec();
+        let path = route_file(&route, req.uri().path()).ok_or(StatusCode::FORBIDDEN)?;
+        fs::metadata(&path).map_err(|_| StatusCode::NOT_FOUND)?;
+        let (path, dir) = target(&route, req)?;
 
-        fs::metadata(path).map_err(|_| StatusCode::NOT_FOUND)?;
-        fs::write(path, &body).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-        unsafe_response(path, body)
+        replace(&path, &dir, req.body())?;
+        unsafe_response(req, config, StatusCode::NO_CONTENT, None)
     }
 
+    /// Removes a file or an empty directory. Directories with files in them are only removed
+    /// if the route has `recursive_delete` set.
     pub fn delete(
         req: &Request<Bytes>,
         config: &ServerConfig,
@@ -254,11 +401,32 @@ mod not_safe {
             Ok(route) => route,
             Err((status, _)) => return Err(status),
         };
-        let path = &add_root_to_path(&route, req.uri().path());
-        let body = fs::read(path).map_err(|_| StatusCode::NOT_FOUND)?;
-        if fs::remove_file(path).is_err() {
-            fs::remove_dir_all(path).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
-        }
-        unsafe_response(path, body)
+        // Nothing is removed outside the root of the route
+        let path = route_file(&route, req.uri().path()).ok_or(StatusCode::FORBIDDEN)?;
+        // Links are removed, not what they point to
+        let metadata = fs::symlink_metadata(&path).map_err(|_| StatusCode::NOT_FOUND)?;
+
+        let removed = if metadata.is_dir() {
+            let recursive = route
+                .settings
+                .as_ref()
+                .is_some_and(|settings| settings.recursive_delete);
+            let empty = fs::read_dir(&path).is_ok_and(|mut entries| entries.next().is_none());
+            match (recursive, empty) {
+                (true, _) => fs::remove_dir_all(&path),
+                (false, true) => fs::remove_dir(&path),
+                (false, false) => return Err(StatusCode::CONFLICT),
+            }
+        } else {
+            fs::remove_file(&path)
+        };
+        removed.map_err(|e| io_status(e, &path))?;
+
+        log!(
+            LogLevel::Info,
+            LogFileType::Server,
+            format!("Deleted {}", path.display())
+        );
+        unsafe_response(req, config, StatusCode::NO_CONTENT, None)
     }
 }
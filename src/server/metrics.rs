@@ -0,0 +1,345 @@
+use crate::server::webdav::DAV_METHODS;
+use crate::server::{Bytes, Request, Response, ServerConfig, StatusCode};
+use http::header::CONTENT_TYPE;
+use http::Method;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::time::Duration;
+
+/// Upper bounds of the buckets of the durations, in seconds.
+const DURATION_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+/// Upper bounds of the buckets of the response sizes, in bytes.
+const SIZE_BUCKETS: [f64; 7] = [
+    100.0,
+    1_000.0,
+    10_000.0,
+    100_000.0,
+    1_000_000.0,
+    10_000_000.0,
+    100_000_000.0,
+];
+/// Route label of requests that matched no route.
+pub const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// # MatchedRoute
+///
+/// Response extension with the route that answered the request, for the route label of the
+/// metrics.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MatchedRoute(pub String);
+
+#[derive(Clone, Debug)]
+struct Histogram {
+    bounds: &'static [f64],
+    /// Observations per bucket, not cumulative. The last one is `+Inf`.
+    counts: Vec<u64>,
+    sum: f64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            counts: vec![0; bounds.len() + 1],
+            sum: 0.0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(self.bounds.len());
+        self.counts[bucket] += 1;
+        self.sum += value;
+    }
+
+    /// Writes the `_bucket`, `_sum` and `_count` lines of the histogram.
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let separator = if labels.is_empty() { "" } else { "," };
+        let mut cumulative = 0;
+        for (i, count) in self.counts.iter().enumerate() {
+            cumulative += count;
+            let bound = match self.bounds.get(i) {
+                Some(bound) => bound.to_string(),
+                None => "+Inf".to_string(),
+            };
+            let _ = writeln!(
+                out,
+                "{name}_bucket{{{labels}{separator}le=\"{bound}\"}} {cumulative}"
+            );
+        }
+        let braces = if labels.is_empty() {
+            String::new()
+        } else {
+            format!("{{{labels}}}")
+        };
+        let _ = writeln!(out, "{name}_sum{braces} {}", self.sum);
+        let _ = writeln!(out, "{name}_count{braces} {cumulative}");
+    }
+}
+
+#[derive(Debug)]
+struct Values {
+    /// By route, method and status.
+    requests: BTreeMap<(String, String, u16), u64>,
+    /// By route.
+    durations: BTreeMap<String, Histogram>,
+    /// By route.
+    sizes: BTreeMap<String, Histogram>,
+    active_connections: i64,
+    accepted_connections: u64,
+    timed_out_connections: u64,
+    cgi_durations: Histogram,
+    cgi_failures: u64,
+}
+
+/// # Metrics
+///
+/// Counters and histograms of the traffic of all servers, in the Prometheus text format.
+#[derive(Debug)]
+pub struct Metrics {
+    values: Mutex<Values>,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            values: Mutex::new(Values {
+                requests: BTreeMap::new(),
+                durations: BTreeMap::new(),
+                sizes: BTreeMap::new(),
+                active_connections: 0,
+                accepted_connections: 0,
+                timed_out_connections: 0,
+                cgi_durations: Histogram::new(&DURATION_BUCKETS),
+                cgi_failures: 0,
+            }),
+        }
+    }
+}
+
+impl Metrics {
+    /// Counts a response to a request for `route`, which took `duration` to answer.
+    pub fn record_request(
+        &self,
+        route: &str,
+        method: &str,
+        status: StatusCode,
+        size: usize,
+        duration: Duration,
+    ) {
+        // Methods come from clients, so unknown ones share a label
+        let method = match Method::from_bytes(method.as_bytes()) {
+            Ok(method) if is_known(&method) => method.to_string(),
+            _ => "other".to_string(),
+        };
+        let mut values = self.lock();
+        *values
+            .requests
+            .entry((route.to_string(), method, status.as_u16()))
+            .or_default() += 1;
+        values
+            .durations
+            .entry(route.to_string())
+            .or_insert_with(|| Histogram::new(&DURATION_BUCKETS))
+            .observe(duration.as_secs_f64());
+        values
+            .sizes
+            .entry(route.to_string())
+            .or_insert_with(|| Histogram::new(&SIZE_BUCKETS))
+            .observe(size as f64);
+    }
+
+    /// Counts an accepted connection, which is active until the returned guard is dropped.
+    pub fn connection_opened(&'static self) -> ActiveConnection {
+        let mut values = self.lock();
+        values.accepted_connections += 1;
+        values.active_connections += 1;
+        ActiveConnection(self)
+    }
+
+    pub fn connection_timed_out(&self) {
+        self.lock().timed_out_connections += 1;
+    }
+
+    /// Counts a run of a CGI script that took `duration`, from spawning it to its exit.
+    pub fn record_cgi(&self, duration: Duration, failed: bool) {
+        let mut values = self.lock();
+        values.cgi_durations.observe(duration.as_secs_f64());
+        if failed {
+            values.cgi_failures += 1;
+        }
+    }
+
+    /// Requests answered so far, by status.
+    pub fn requests_by_status(&self) -> BTreeMap<u16, u64> {
+        let mut totals = BTreeMap::new();
+        for ((_, _, status), count) in &self.lock().requests {
+            *totals.entry(*status).or_default() += count;
+        }
+        totals
+    }
+
+    /// The metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let values = self.lock();
+        let mut out = String::new();
+
+        header(
+            &mut out,
+            "http_requests_total",
+            "counter",
+            "Requests by route, method and status.",
+        );
+        for ((route, method, status), count) in &values.requests {
+            let _ = writeln!(
+                out,
+                "http_requests_total{{route=\"{}\",method=\"{method}\",status=\"{status}\"}} {count}",
+                escape(route)
+            );
+        }
+
+        header(
+            &mut out,
+            "http_request_duration_seconds",
+            "histogram",
+            "Time to respond to requests, by route.",
+        );
+        for (route, histogram) in &values.durations {
+            let labels = format!("route=\"{}\"", escape(route));
+            histogram.render(&mut out, "http_request_duration_seconds", &labels);
+        }
+
+        header(
+            &mut out,
+            "http_response_size_bytes",
+            "histogram",
+            "Size of response bodies, by route.",
+        );
+        for (route, histogram) in &values.sizes {
+            let labels = format!("route=\"{}\"", escape(route));
+            histogram.render(&mut out, "http_response_size_bytes", &labels);
+        }
+
+        let gauges = [
+            (
+                "connections_active",
+                "gauge",
+                "Open connections.",
+                values.active_connections.to_string(),
+            ),
+            (
+                "connections_accepted_total",
+                "counter",
+                "Accepted connections.",
+                values.accepted_connections.to_string(),
+            ),
+            (
+                "connections_timed_out_total",
+                "counter",
+                "Connections closed because the client was too slow.",
+                values.timed_out_connections.to_string(),
+            ),
+        ];
+        for (name, kind, help, value) in gauges {
+            header(&mut out, name, kind, help);
+            let _ = writeln!(out, "{name} {value}");
+        }
+
+        header(
+            &mut out,
+            "cgi_duration_seconds",
+            "histogram",
+            "Time CGI scripts took from spawning to their exit.",
+        );
+        values
+            .cgi_durations
+            .render(&mut out, "cgi_duration_seconds", "");
+        header(
+            &mut out,
+            "cgi_failures_total",
+            "counter",
+            "CGI scripts that could not be spawned or exited with an error.",
+        );
+        let _ = writeln!(out, "cgi_failures_total {}", values.cgi_failures);
+        out
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Values> {
+        self.values.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// # ActiveConnection
+///
+/// Counts a connection as active until it is dropped.
+#[derive(Debug)]
+pub struct ActiveConnection(&'static Metrics);
+
+impl Drop for ActiveConnection {
+    fn drop(&mut self) {
+        self.0.lock().active_connections -= 1;
+    }
+}
+
+/// Methods of HTTP and WebDAV, which get a label of their own.
+fn is_known(method: &Method) -> bool {
+    [
+        Method::GET,
+        Method::HEAD,
+        Method::POST,
+        Method::PUT,
+        Method::DELETE,
+        Method::CONNECT,
+        Method::OPTIONS,
+        Method::TRACE,
+        Method::PATCH,
+    ]
+    .contains(method)
+        || DAV_METHODS.contains(&method.as_str())
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+/// Escapes a label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// # metrics
+///
+/// The metrics of all servers.
+pub fn metrics() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(Metrics::default)
+}
+
+/// # prometheus_metrics
+///
+/// Handler that serves the metrics in the Prometheus text format, for the route they should be
+/// scraped from.
+pub fn prometheus_metrics(
+    req: &Request<Bytes>,
+    _conf: &ServerConfig,
+) -> Result<Response<Bytes>, StatusCode> {
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+        return Err(StatusCode::METHOD_NOT_ALLOWED);
+    }
+    Response::builder()
+        .status(StatusCode::OK)
+        .version(req.version())
+        .header(CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8")
+        .body(metrics().render().into_bytes())
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
@@ -0,0 +1,84 @@
+use crate::log;
+use crate::log::*;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// # HandlerPool
+///
+/// Threads that handle requests off the event loop, so a slow handler or CGI script
+/// does not hold up the other connections. The threads stop once the pool is dropped
+/// and their queued jobs are done.
+#[derive(Debug)]
+pub struct HandlerPool {
+    sender: Sender<Job>,
+    size: usize,
+}
+
+impl HandlerPool {
+    /// Starts `size` threads, at least one.
+    pub fn new(size: usize) -> Self {
+        let size = size.max(1);
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        for id in 0..size {
+            let receiver = Arc::clone(&receiver);
+            let spawned = thread::Builder::new()
+                .name(format!("handler-{id}"))
+                .spawn(move || work(&receiver));
+            if let Err(e) = spawned {
+                log!(
+                    LogLevel::Error,
+                    LogFileType::Server,
+                    format!("Failed to start handler thread. {e}")
+                );
+            }
+        }
+
+        Self { sender, size }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Queues `job` to run on the first free thread. Returns `false` if there are no threads
+    /// left to run it, in which case the job is dropped.
+    pub fn execute(&self, job: impl FnOnce() + Send + 'static) -> bool {
+        if self.sender.send(Box::new(job)).is_err() {
+            log!(
+                LogLevel::Error,
+                LogFileType::Server,
+                "Handler threads have stopped".to_string()
+            );
+            return false;
+        }
+        true
+    }
+}
+
+fn work(receiver: &Mutex<Receiver<Job>>) {
+    loop {
+        // The lock is released before the job runs
+        let job = match receiver.lock() {
+            Ok(receiver) => receiver.recv(),
+            Err(poisoned) => poisoned.into_inner().recv(),
+        };
+        let job = match job {
+            Ok(job) => job,
+            Err(_) => return,
+        };
+        // Keep the thread for the next job if this one panics
+        if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+            log!(
+                LogLevel::Error,
+                LogFileType::Server,
+                "Handler thread panicked".to_string()
+            );
+        }
+    }
+}
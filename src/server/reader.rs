@@ -0,0 +1,437 @@
+use crate::log;
+use crate::log::*;
+use crate::server::{streamed_part_length, Bytes, ServerConfig, StatusCode, Timeout, BUFFER_SIZE};
+use std::io;
+use std::io::Read;
+use std::time::Instant;
+
+/// Longest chunk size line accepted in a chunked body, extensions included.
+const MAX_CHUNK_LINE: usize = 1024;
+/// Bytes of a streamed body that are collected before they are passed on, unless the client
+/// stops sending.
+const STREAM_PART_SIZE: usize = 64 * 1024;
+
+/// # ReadState
+///
+/// Progress of a `RequestReader` after reading from the stream.
+#[derive(Debug, PartialEq)]
+pub enum ReadState {
+    /// More bytes are needed to complete the request.
+    Pending,
+    /// The head, without the blank line, and the body of the request.
+    Complete(String, Bytes),
+    /// The head of a request whose body is passed on in parts as it arrives, see
+    /// `streamed_part_length`.
+    Streaming(String),
+    /// The next bytes of a streamed body, and if they are the last.
+    BodyPart(Bytes, bool),
+    /// The request exceeds a limit of the server and should be answered with this code.
+    Rejected(StatusCode),
+    /// The client closed the connection.
+    Closed,
+}
+
+#[derive(Debug)]
+enum Body {
+    None,
+    Length(usize),
+    Chunked {
+        pos: usize,
+        decoded: usize,
+    },
+    /// The body is passed on as it arrives, with `remaining` bytes still to come.
+    Streamed {
+        remaining: usize,
+    },
+}
+
+/// # RequestReader
+///
+/// Collects the bytes of a request as they arrive, so a request can be split over
+/// many reads. The request line, headers and body are checked against the limits of
+/// the server while reading, so oversized requests are rejected before they are buffered.
+/// Bytes after a complete request are kept for the next request on the connection.
+#[derive(Debug)]
+pub struct RequestReader {
+    buf: Bytes,
+    /// Bytes of `buf` already searched for the end of the head.
+    scanned: usize,
+    head_end: Option<usize>,
+    body: Body,
+    started: Option<Instant>,
+    body_started: Option<Instant>,
+}
+
+impl Default for RequestReader {
+    fn default() -> Self {
+        Self::new(Instant::now())
+    }
+}
+
+impl RequestReader {
+    pub fn new(now: Instant) -> Self {
+        Self {
+            buf: Vec::new(),
+            scanned: 0,
+            head_end: None,
+            body: Body::None,
+            started: Some(now),
+            body_started: None,
+        }
+    }
+
+    /// Returns `true` if no bytes of a request were received yet.
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty() && !self.is_streaming()
+    }
+
+    /// Returns `true` while the body of a request is passed on in parts.
+    pub fn is_streaming(&self) -> bool {
+        matches!(self.body, Body::Streamed { .. })
+    }
+
+    /// Takes the bytes received after the last complete request, when the connection
+    /// switches to another protocol.
+    pub fn take_buffered(&mut self) -> Bytes {
+        self.scanned = 0;
+        std::mem::take(&mut self.buf)
+    }
+
+    /// Reads from `stream` until it would block, the request is complete or a limit is exceeded.
+    pub fn read_from(
+        &mut self,
+        stream: &mut impl Read,
+        config: &ServerConfig,
+    ) -> io::Result<ReadState> {
+        // A pipelined request may already be buffered
+        if !self.buf.is_empty() {
+            match self.push(&[], config) {
+                ReadState::Pending => {}
+                state => return Ok(state),
+            }
+        }
+
+        let mut buffer = [0; BUFFER_SIZE];
+        loop {
+            match stream.read(&mut buffer) {
+                // What was received of a streamed body is passed on first
+                Ok(0) => {
+                    return Ok(match self.take_part(true, Instant::now()) {
+                        ReadState::Pending => ReadState::Closed,
+                        part => part,
+                    });
+                }
+                Ok(n) => match self.push(&buffer[..n], config) {
+                    ReadState::Pending => continue,
+                    state => return Ok(state),
+                },
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    return Ok(self.take_part(true, Instant::now()));
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Adds `bytes` to the request and checks it against the limits in `config`.
+    pub fn push(&mut self, bytes: &[u8], config: &ServerConfig) -> ReadState {
+        self.push_at(bytes, config, Instant::now())
+    }
+
+    pub fn push_at(&mut self, bytes: &[u8], config: &ServerConfig, now: Instant) -> ReadState {
+        if self.started.is_none() && !bytes.is_empty() {
+            // The header timeout starts with the first byte of a request
+            self.started = Some(now);
+        }
+        self.buf.extend_from_slice(bytes);
+
+        if self.is_streaming() {
+            if !bytes.is_empty() {
+                // The body timeout counts from the last bytes of a streamed body
+                self.body_started = Some(now);
+            }
+            return self.take_part(false, now);
+        }
+
+        if self.head_end.is_none() {
+            let searched = self.scanned.saturating_sub(3);
+            let head_end = find(&self.buf[searched..], b"\r\n\r\n").map(|i| i + searched);
+            self.scanned = self.buf.len();
+            if let Err(code) = self.check_head(head_end, config) {
+                return ReadState::Rejected(code);
+            }
+
+            match head_end {
+                Some(end) => {
+                    self.body_started = Some(now);
+                    let head = String::from_utf8_lossy(&self.buf[..end]).into_owned();
+                    if let Some(remaining) = streamed_part_length(&head, config) {
+                        self.buf.drain(..end + 4);
+                        self.scanned = 0;
+                        self.body = Body::Streamed { remaining };
+                        return ReadState::Streaming(head);
+                    }
+
+                    self.head_end = Some(end);
+                    match body_length(&self.buf[..end], config) {
+                        Ok(body) => self.body = body,
+                        Err(code) => return ReadState::Rejected(code),
+                    }
+                }
+                None => return ReadState::Pending,
+            }
+        }
+
+        self.check_body(config, now)
+    }
+
+    /// When the request times out, according to the header and body timeouts of `config`.
+    pub fn deadline(&self, config: &ServerConfig) -> Option<(Instant, Timeout)> {
+        match (self.started, self.body_started) {
+            (_, Some(started)) => config
+                .body_timeout
+                .map(|timeout| (started + timeout, Timeout::Body)),
+            (Some(started), None) => config
+                .header_timeout
+                .map(|timeout| (started + timeout, Timeout::Header)),
+            (None, None) => None,
+        }
+    }
+
+    fn check_head(&self, head_end: Option<usize>, config: &ServerConfig) -> Result<(), StatusCode> {
+        let head = &self.buf[..head_end.unwrap_or(self.buf.len())];
+        let line_end = find(head, b"\r\n");
+
+        let line_length = line_end.unwrap_or(head.len());
+        if line_length > config.max_request_line_length {
+            log!(
+                LogLevel::Warn,
+                LogFileType::Client,
+                format!(
+                    "Request line of {line_length} bytes exceeds the limit of {}",
+                    config.max_request_line_length
+                )
+            );
+            return Err(StatusCode::URI_TOO_LONG);
+        }
+
+        let fields = match line_end {
+            Some(end) => &head[end + 2..],
+            None => return Ok(()),
+        };
+        if fields.len() > config.max_header_bytes {
+            log!(
+                LogLevel::Warn,
+                LogFileType::Client,
+                format!(
+                    "Headers exceed the limit of {} bytes",
+                    config.max_header_bytes
+                )
+            );
+            return Err(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+        }
+
+        // Only lines ending with CRLF are complete, unless the whole head was received
+        let mut count = fields.windows(2).filter(|w| w == b"\r\n").count();
+        if head_end.is_some() && !fields.is_empty() {
+            count += 1;
+        }
+        if count > config.max_header_count {
+            log!(
+                LogLevel::Warn,
+                LogFileType::Client,
+                format!("More than {} headers in request", config.max_header_count)
+            );
+            return Err(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE);
+        }
+
+        Ok(())
+    }
+
+    /// Takes the next part of a streamed body, once enough of it was received or `flush` is set.
+    fn take_part(&mut self, flush: bool, now: Instant) -> ReadState {
+        let remaining = match self.body {
+            Body::Streamed { remaining } => remaining,
+            _ => return ReadState::Pending,
+        };
+        let length = remaining.min(self.buf.len());
+        if length == 0 || (length < remaining && length < STREAM_PART_SIZE && !flush) {
+            return ReadState::Pending;
+        }
+
+        let rest = self.buf.split_off(length);
+        let part = std::mem::replace(&mut self.buf, rest);
+        if length < remaining {
+            self.body = Body::Streamed {
+                remaining: remaining - length,
+            };
+            return ReadState::BodyPart(part, false);
+        }
+
+        // Start over with what was sent after the request
+        let rest = std::mem::take(&mut self.buf);
+        *self = Self {
+            started: (!rest.is_empty()).then_some(now),
+            buf: rest,
+            scanned: 0,
+            head_end: None,
+            body: Body::None,
+            body_started: None,
+        };
+        ReadState::BodyPart(part, true)
+    }
+
+    fn check_body(&mut self, config: &ServerConfig, now: Instant) -> ReadState {
+        let body_start = match self.head_end {
+            Some(end) => end + 4,
+            None => return ReadState::Pending,
+        };
+        let body = &self.buf[body_start..];
+
+        let body_length = match &mut self.body {
+            Body::None | Body::Streamed { .. } => 0,
+            Body::Length(length) if body.len() >= *length => *length,
+            Body::Length(_) => return ReadState::Pending,
+            Body::Chunked { pos, decoded } => match scan_chunks(body, pos, decoded, config) {
+                Ok(Some(length)) => length,
+                Ok(None) => return ReadState::Pending,
+                Err(code) => return ReadState::Rejected(code),
+            },
+        };
+
+        let mut buf = std::mem::take(&mut self.buf);
+        let rest = buf.split_off(body_start + body_length);
+        let body = buf.split_off(body_start);
+        buf.truncate(body_start - 4);
+
+        // Start over with what was sent after the request
+        *self = Self {
+            started: (!rest.is_empty()).then_some(now),
+            buf: rest,
+            scanned: 0,
+            head_end: None,
+            body: Body::None,
+            body_started: None,
+        };
+
+        ReadState::Complete(String::from_utf8_lossy(&buf).into_owned(), body)
+    }
+}
+
+/// `body_length` gets how the length of the body is determined from the `head` of a request.
+fn body_length(head: &[u8], config: &ServerConfig) -> Result<Body, StatusCode> {
+    let head = String::from_utf8_lossy(head);
+    let mut body = Body::None;
+    let mut content_length = None;
+
+    for (key, value) in head.split("\r\n").skip(1).filter_map(|h| h.split_once(':')) {
+        let (key, value) = (key.trim(), value.trim());
+
+        if key.eq_ignore_ascii_case("transfer-encoding") {
+            let chunked = value
+                .rsplit(',')
+                .next()
+                .is_some_and(|coding| coding.trim().eq_ignore_ascii_case("chunked"));
+            if !chunked {
+                return Err(StatusCode::NOT_IMPLEMENTED);
+            }
+            body = Body::Chunked { pos: 0, decoded: 0 };
+        } else if key.eq_ignore_ascii_case("content-length") {
+            let length = value
+                .parse::<usize>()
+                .map_err(|_| StatusCode::BAD_REQUEST)?;
+            // Lengths that differ could be read differently by a proxy in front
+            if content_length.is_some_and(|previous| previous != length) {
+                log!(
+                    LogLevel::Warn,
+                    LogFileType::Client,
+                    "Request with different Content-Length values".to_string()
+                );
+                return Err(StatusCode::BAD_REQUEST);
+            }
+            content_length = Some(length);
+            if length > config.body_size_limit {
+                log!(
+                    LogLevel::Warn,
+                    LogFileType::Client,
+                    format!(
+                        "Content-Length of {length} exceeds the body size limit of {}",
+                        config.body_size_limit
+                    )
+                );
+                return Err(StatusCode::PAYLOAD_TOO_LARGE);
+            }
+            // Transfer-Encoding overrides Content-Length
+            if !matches!(body, Body::Chunked { .. }) {
+                body = Body::Length(length);
+            }
+        }
+    }
+
+    Ok(body)
+}
+
+/// `scan_chunks` continues scanning a chunked `body` from `pos`, and returns the length of the
+/// body once the last chunk and the trailers are received.
+fn scan_chunks(
+    body: &[u8],
+    pos: &mut usize,
+    decoded: &mut usize,
+    config: &ServerConfig,
+) -> Result<Option<usize>, StatusCode> {
+    loop {
+        let rest = &body[*pos..];
+        let line_end = match find(rest, b"\r\n") {
+            Some(end) if end <= MAX_CHUNK_LINE => end,
+            None if rest.len() <= MAX_CHUNK_LINE => return Ok(None),
+            _ => return Err(StatusCode::BAD_REQUEST),
+        };
+
+        let line = String::from_utf8_lossy(&rest[..line_end]);
+        let size = line.split(';').next().unwrap_or_default().trim();
+        let size = usize::from_str_radix(size, 16).map_err(|_| StatusCode::BAD_REQUEST)?;
+
+        if size == 0 {
+            // The body ends with the trailers and an empty line
+            let trailers = &rest[line_end..];
+            return match find(trailers, b"\r\n\r\n") {
+                Some(end) => Ok(Some(*pos + line_end + end + 4)),
+                None if trailers.len() > config.max_header_bytes => {
+                    Err(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE)
+                }
+                None => Ok(None),
+            };
+        }
+
+        *decoded = decoded.saturating_add(size);
+        if *decoded > config.body_size_limit {
+            log!(
+                LogLevel::Warn,
+                LogFileType::Client,
+                format!(
+                    "Chunked body exceeds the body size limit of {}",
+                    config.body_size_limit
+                )
+            );
+            return Err(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+
+        let chunk_end = line_end + 2 + size + 2;
+        if rest.len() < chunk_end {
+            *decoded -= size;
+            return Ok(None);
+        }
+        // The data of a chunk ends with CRLF
+        if &rest[chunk_end - 2..chunk_end] != b"\r\n" {
+            return Err(StatusCode::BAD_REQUEST);
+        }
+        *pos += chunk_end;
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
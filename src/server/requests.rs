@@ -19,8 +19,7 @@ pub fn get_request(
 
     for header in headers::get_headers(head) {
         if let Some((key, value)) = headers::format_header(header) {
-            request_builder =
-                request_builder.header(key.to_ascii_lowercase(), value.to_ascii_lowercase());
+            request_builder = request_builder.header(key.to_ascii_lowercase(), value);
         }
     }
 
@@ -48,7 +47,7 @@ pub mod path {
 
     /// `path_exists` gets the `path` and the `index` of the `route` it was a part of if found.
     pub fn path_exists<'a>(
-        requested_path: Path<'a>,
+        requested_path: &str,
         routes: &[Route<'a>],
     ) -> Option<(usize, Path<'a>)> {
         // Check for _exact_ matches in path
@@ -99,6 +98,12 @@ pub mod path {
         }
     }
 
+    /// `escapes_root` checks if `path` could lead out of the root of its route: it has a `..`
+    /// segment, a backslash that Windows takes for a separator, or a NUL byte.
+    pub fn escapes_root(path: &str) -> bool {
+        path.split('/').any(|segment| segment == "..") || path.contains(['\\', '\0'])
+    }
+
     #[cfg(test)]
     mod tests {
         use super::*;
@@ -110,11 +115,21 @@ pub mod path {
                 url_path: path,
                 methods: vec![],
                 handler: None,
+                websocket: None,
                 settings: None,
             };
             let expected_path = "./foo".to_string();
             assert_eq!(add_root_to_path(&route, path), expected_path);
         }
+
+        #[test]
+        fn test_escapes_root() {
+            assert!(!escapes_root("/up/a..b/.hidden"));
+            assert!(escapes_root("/up/../etc"));
+            assert!(escapes_root("/up/.."));
+            assert!(escapes_root("/up/a\\..\\b"));
+            assert!(escapes_root("/up/a\0"));
+        }
     }
 }
 
@@ -133,12 +148,12 @@ pub mod version {
             "HTTP/0.9" => Ok(Version::HTTP_09),
             "HTTP/1.0" => Ok(Version::HTTP_10),
             "HTTP/1.1" => Ok(Version::HTTP_11),
-            "HTTP/2.0" => Ok(Version::HTTP_2),
-            "HTTP/3.0" => Ok(Version::HTTP_3),
+            // HTTP/2 requests are binary frames, never a text request line, and HTTP/3 is not supported
             _ => {
                 log!(
+                    LogLevel::Warn,
                     LogFileType::Server,
-                    format!("Error: Version not supported {}", version_str)
+                    format!("Version not supported {}", version_str)
                 );
                 Err(StatusCode::HTTP_VERSION_NOT_SUPPORTED)
             }
@@ -150,9 +165,13 @@ pub mod version {
         use super::*;
         #[test]
         fn test_get_version() {
-            for version in ["HTTP/0.9", "HTTP/1.0", "HTTP/1.1", "HTTP/2.0", "HTTP/3.0"] {
+            for version in ["HTTP/0.9", "HTTP/1.0", "HTTP/1.1"] {
                 assert!(get_version(version).is_ok());
             }
+            for version in ["HTTP/2.0", "HTTP/3.0"] {
+                assert!(get_version(version)
+                    .is_err_and(|code| code == StatusCode::HTTP_VERSION_NOT_SUPPORTED));
+            }
 
             assert!(get_version("HTTP/BILL_CLINTON")
                 .is_err_and(|code| code == StatusCode::HTTP_VERSION_NOT_SUPPORTED));
@@ -184,7 +203,10 @@ pub mod headers {
         }
 
         if let Some(header) = headers.unwrap().get(TRANSFER_ENCODING) {
-            header.to_str().unwrap_or_default() == "chunked"
+            header
+                .to_str()
+                .unwrap_or_default()
+                .eq_ignore_ascii_case("chunked")
         } else {
             false
         }
@@ -219,7 +241,7 @@ pub mod headers {
 
 pub mod body {
     use crate::log;
-    use crate::log::LogFileType;
+    use crate::log::{LogFileType, LogLevel};
     use crate::type_aliases::Bytes;
     use http::StatusCode;
 
@@ -244,8 +266,9 @@ pub mod body {
                         Ok(size) => size,
                         Err(_) => {
                             log!(
+                                LogLevel::Warn,
                                 LogFileType::Server,
-                                "Error: Failed to parse chunk size".to_string()
+                                "Failed to parse chunk size".to_string()
                             );
                             return Err(StatusCode::BAD_REQUEST);
                         }
@@ -259,8 +282,9 @@ pub mod body {
                 // Ensure there's enough data for the chunk
                 if rest.len() < chunk_size + 2 {
                     log!(
+                        LogLevel::Warn,
                         LogFileType::Server,
-                        "Error: Not enough data for chunk".to_string()
+                        "Not enough data for chunk".to_string()
                     );
                     return Err(StatusCode::BAD_REQUEST);
                 }
@@ -271,7 +295,11 @@ pub mod body {
 
                 // Check body size limit
                 if result_body.len() > limit {
-                    log!(LogFileType::Server, "Error: Body too long".to_string());
+                    log!(
+                        LogLevel::Warn,
+                        LogFileType::Server,
+                        "Body too long".to_string()
+                    );
                     return Err(StatusCode::PAYLOAD_TOO_LARGE);
                 }
 
@@ -279,8 +307,9 @@ pub mod body {
                 remaining_data = &after_chunk[2..];
             } else {
                 log!(
+                    LogLevel::Warn,
                     LogFileType::Server,
-                    "Error: Missing CRLF after chunk size".to_string()
+                    "Missing CRLF after chunk size".to_string()
                 );
                 return Err(StatusCode::BAD_REQUEST); // Missing CRLF after chunk size
             }
@@ -1,11 +1,23 @@
 use crate::server::{Bytes, Response, ServerConfig, StatusCode, BUFFER_SIZE};
-use http::header::TRANSFER_ENCODING;
+use http::header::{CONTENT_LENGTH, TRANSFER_ENCODING};
 use http::Version;
 use std::fs;
 
 pub fn format_response(response: Response<Bytes>) -> Bytes {
     // Split up the response into head and parts
-    let (head, body) = response.into_parts();
+    let (mut head, body) = response.into_parts();
+
+    // Clients need the length to find the end of the response on a kept alive connection
+    let has_body = !(head.status.is_informational()
+        || head.status == StatusCode::NO_CONTENT
+        || head.status == StatusCode::NOT_MODIFIED);
+    if has_body
+        && !head.headers.contains_key(CONTENT_LENGTH)
+        && !head.headers.contains_key(TRANSFER_ENCODING)
+    {
+        head.headers.insert(CONTENT_LENGTH, body.len().into());
+    }
+
     let mut resp = Bytes::from(format!("{:?} {}\r\n", head.version, head.status));
 
     // Get all headers into the response
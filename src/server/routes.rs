@@ -1,5 +1,5 @@
 use crate::log;
-use crate::log::LogFileType;
+use crate::log::{LogFileType, LogLevel};
 use crate::server::method_is_allowed;
 use crate::server::path::path_exists;
 use crate::server::redirections::is_redirect;
@@ -7,7 +7,7 @@ use crate::server::{Request, Route, ServerConfig, StatusCode};
 use crate::type_aliases::Bytes;
 
 pub fn get_route<'a>(
-    req: &'a Request<Bytes>,
+    req: &Request<Bytes>,
     config: &'a ServerConfig,
 ) -> Result<Route<'a>, (StatusCode, String)> {
     // Get the route assigned to the path
@@ -20,8 +20,9 @@ pub fn get_route<'a>(
         routed_path = path;
     } else {
         log!(
+            LogLevel::Warn,
             LogFileType::Server,
-            format!("Error: Path not found {}", url_path)
+            format!("Path not found {}", url_path)
         );
         return Err((StatusCode::NOT_FOUND, "".to_string()));
     }
@@ -41,9 +42,10 @@ pub fn get_route<'a>(
     // Check if the method is allowed on route
     if !method_is_allowed(req.method(), &route) {
         log!(
+            LogLevel::Warn,
             LogFileType::Server,
             format!(
-                "Error: Method '{}' not allowed on path '{}'",
+                "Method '{}' not allowed on path '{}'",
                 req.method(),
                 url_path
             )
@@ -1,63 +1,56 @@
+use crate::log;
+use crate::log::{LogFileType, LogLevel};
 use crate::server::content_type;
 use crate::server_config::ServerConfig;
 use crate::type_aliases::Bytes;
+use chrono::{DateTime, Utc};
 use http::header::{CONTENT_LENGTH, CONTENT_TYPE, COOKIE, HOST, SET_COOKIE};
 use http::response::Builder;
-use http::{HeaderValue, Request, Response, StatusCode};
+use http::{Request, Response, StatusCode};
+use std::fmt;
 use std::fs;
 
-type Cookie = str;
+const SESSION_COOKIE: &str = "grit:lab";
+const SESSION_VALUE: &str = "cookie"; // Replace this with a database value.
 
 pub fn update_cookie(
     req: &Request<Bytes>,
     conf: &ServerConfig,
 ) -> Result<Response<Bytes>, StatusCode> {
-    if req.headers().iter().any(|(_, v)| {
-        v.to_str()
-            .unwrap_or_default()
-            .to_ascii_lowercase()
-            .eq("grit:lab=cookie")
-    }) {
-        return remove_cookie(
-            Response::builder()
-                .status(StatusCode::OK)
-                .version(req.version()),
-            "grit:lab=cookie",
-        ) // Replace this with a database value.
+    let resp = Response::builder()
+        .status(StatusCode::OK)
+        .version(req.version());
+
+    let cookie = if has_session(req, conf) {
+        SetCookie::removal(SESSION_COOKIE).path("/")
+    } else {
+        SetCookie::new(SESSION_COOKIE, SESSION_VALUE)
+            .path("/")
+            .max_age(3600) // Expires in 1 hour
+            .http_only()
+            .signed(&conf.cookie_keys)?
+    };
+
+    set_cookie(resp, &cookie)
         .header(HOST, conf.host)
         .body(vec![])
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR);
-    }
-
-    set_cookie(
-        Response::builder()
-            .status(StatusCode::OK)
-            .version(req.version()),
-        "grit:lab=cookie",
-    ) // Replace this with a database value.
-    .header(HOST, conf.host)
-    .body(vec![])
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
 }
 
 pub fn validate_cookie(
     req: &Request<Bytes>,
     conf: &ServerConfig,
 ) -> Result<Response<Bytes>, StatusCode> {
-    let value = get_cookie(req, "grit:lab=cookie")
-        .ok_or(StatusCode::UNAUTHORIZED)?
-        .to_str()
-        .unwrap_or_default();
-
-    cookie(
-        Response::builder()
-            .status(StatusCode::OK)
-            .version(req.version()),
-        value,
-    )
-    .header(HOST, conf.host)
-    .body(vec![])
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+    if !has_session(req, conf) {
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .version(req.version())
+        .header(HOST, conf.host)
+        .body(vec![])
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
 }
 
 pub fn cookie_demo(
@@ -82,26 +75,340 @@ pub fn cookie_demo(
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
 }
 
-pub fn set_cookie(resp: Builder, value: &Cookie) -> Builder {
-    let value = format!("{value}; path=/; Max-Age=3600"); // Expires in 1 hour
-    resp.header(SET_COOKIE, value)
+fn has_session(req: &Request<Bytes>, conf: &ServerConfig) -> bool {
+    get_signed_cookie(req, SESSION_COOKIE, &conf.cookie_keys)
+        .is_some_and(|value| value == SESSION_VALUE)
+}
+
+/// # SameSite
+///
+/// Value of the `SameSite` attribute of a `Set-Cookie` header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl fmt::Display for SameSite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SameSite::Strict => write!(f, "Strict"),
+            SameSite::Lax => write!(f, "Lax"),
+            SameSite::None => write!(f, "None"),
+        }
+    }
+}
+
+/// # SetCookie
+///
+/// Builder for the value of a `Set-Cookie` response header.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SetCookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    domain: Option<String>,
+    max_age: Option<i64>,
+    expires: Option<DateTime<Utc>>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+impl SetCookie {
+    pub fn new(name: &str, value: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            value: value.to_string(),
+            path: None,
+            domain: None,
+            max_age: None,
+            expires: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    /// Cookie that makes the client drop `name` immediately.
+    pub fn removal(name: &str) -> Self {
+        Self::new(name, "")
+            .max_age(0)
+            .expires(DateTime::<Utc>::UNIX_EPOCH)
+    }
+
+    pub fn path(mut self, path: &str) -> Self {
+        self.path = Some(path.to_string());
+        self
+    }
+
+    pub fn domain(mut self, domain: &str) -> Self {
+        self.domain = Some(domain.to_string());
+        self
+    }
+
+    pub fn max_age(mut self, seconds: i64) -> Self {
+        self.max_age = Some(seconds);
+        self
+    }
+
+    pub fn expires(mut self, expires: DateTime<Utc>) -> Self {
+        self.expires = Some(expires);
+        self
+    }
+
+    pub fn secure(mut self) -> Self {
+        self.secure = true;
+        self
+    }
+
+    pub fn http_only(mut self) -> Self {
+        self.http_only = true;
+        self
+    }
+
+    /// `SameSite=None` is only accepted by browsers together with `Secure`, so it is added as well.
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        if same_site == SameSite::None {
+            self.secure = true;
+        }
+        self.same_site = Some(same_site);
+        self
+    }
+
+    /// Signs the value with the first of `keys`. Read it back with `get_signed_cookie`.
+    pub fn signed(mut self, keys: &[&str]) -> Result<Self, StatusCode> {
+        self.value = signing::sign(&self.name, &self.value, keys).ok_or_else(no_cookie_keys)?;
+        Ok(self)
+    }
+
+    /// Encrypts the value with the first of `keys`. Read it back with `get_private_cookie`.
+    pub fn encrypted(mut self, keys: &[&str]) -> Result<Self, StatusCode> {
+        self.value = signing::encrypt(&self.name, &self.value, keys).ok_or_else(no_cookie_keys)?;
+        Ok(self)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+}
+
+impl fmt::Display for SetCookie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(path) = &self.path {
+            write!(f, "; Path={path}")?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={domain}")?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={max_age}")?;
+        }
+        if let Some(expires) = self.expires {
+            write!(
+                f,
+                "; Expires={}",
+                expires.format("%a, %d %b %Y %H:%M:%S GMT")
+            )?;
+        }
+        if self.secure {
+            write!(f, "; Secure")?;
+        }
+        if self.http_only {
+            write!(f, "; HttpOnly")?;
+        }
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={same_site}")?;
+        }
+        Ok(())
+    }
+}
+
+/// # set_cookie
+///
+/// Adds a `Set-Cookie` header to the response. Call it once per cookie.
+pub fn set_cookie(resp: Builder, cookie: &SetCookie) -> Builder {
+    resp.header(SET_COOKIE, cookie.to_string())
 }
 
-pub fn remove_cookie(resp: Builder, value: &Cookie) -> Builder {
-    let value = format!("{value}; path=/; expires=Thu, 01 Jan 1970 00:00:00 GMT");
-    resp.header(SET_COOKIE, value)
+/// # remove_cookie
+///
+/// Adds a `Set-Cookie` header that expires the cookie called `name`.
+pub fn remove_cookie(resp: Builder, name: &str) -> Builder {
+    set_cookie(resp, &SetCookie::removal(name).path("/"))
 }
 
-/// # cookie
+/// # parse_cookie_header
 ///
-/// Adds a cookie to the response headers. Cookie is specified by `value`
-pub fn cookie(resp: Builder, value: &Cookie) -> Builder {
-    resp.header(COOKIE, value)
+/// Splits the value of a `Cookie` header into `(name, value)` pairs.
+/// Surrounding double quotes are removed from values and malformed pairs are skipped.
+pub fn parse_cookie_header(header: &str) -> Vec<(String, String)> {
+    header
+        .split(';')
+        .filter_map(|pair| {
+            let (name, value) = pair.split_once('=')?;
+            let name = name.trim();
+            if name.is_empty() {
+                return None;
+            }
+
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .unwrap_or(value);
+
+            Some((name.to_string(), value.to_string()))
+        })
+        .collect()
 }
 
-pub fn get_cookie<'a>(req: &'a Request<Bytes>, value: &'a Cookie) -> Option<&'a HeaderValue> {
+/// # get_cookies
+///
+/// Gets all cookies sent with the request, in order, across every `Cookie` header.
+pub fn get_cookies(req: &Request<Bytes>) -> Vec<(String, String)> {
     req.headers()
         .get_all(COOKIE)
         .iter()
-        .find(|&c| c.to_str().unwrap_or_default().eq(value))
+        .filter_map(|header| header.to_str().ok())
+        .flat_map(parse_cookie_header)
+        .collect()
+}
+
+/// # get_cookie
+///
+/// Gets the value of the first cookie called `name`.
+pub fn get_cookie(req: &Request<Bytes>, name: &str) -> Option<String> {
+    get_cookies(req)
+        .into_iter()
+        .find(|(cookie_name, _)| cookie_name == name)
+        .map(|(_, value)| value)
+}
+
+/// # get_signed_cookie
+///
+/// Gets the value of the cookie called `name` if it was signed with one of `keys`.
+/// Cookies with a missing or forged signature are treated as absent.
+pub fn get_signed_cookie(req: &Request<Bytes>, name: &str, keys: &[&str]) -> Option<String> {
+    get_cookies(req)
+        .into_iter()
+        .filter(|(cookie_name, _)| cookie_name == name)
+        .find_map(|(_, value)| signing::verify(name, &value, keys))
+}
+
+/// # get_private_cookie
+///
+/// Gets the decrypted value of the cookie called `name` if it was encrypted with one of `keys`.
+/// Cookies that fail to decrypt are treated as absent.
+pub fn get_private_cookie(req: &Request<Bytes>, name: &str, keys: &[&str]) -> Option<String> {
+    get_cookies(req)
+        .into_iter()
+        .filter(|(cookie_name, _)| cookie_name == name)
+        .find_map(|(_, value)| signing::decrypt(name, &value, keys))
+}
+
+fn no_cookie_keys() -> StatusCode {
+    log!(
+        LogLevel::Error,
+        LogFileType::Server,
+        "No cookie_keys configured for signed cookies".to_string()
+    );
+    StatusCode::INTERNAL_SERVER_ERROR
+}
+
+/// # signing
+///
+/// HMAC-SHA256 signatures and AES-256-GCM encryption of cookie values.
+/// Both bind the value to the cookie name, so a value can not be moved to another cookie.
+pub mod signing {
+    use aes_gcm::aead::{Aead, Payload};
+    use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+    use hmac::{Hmac, Mac};
+    use rand::RngCore;
+    use sha2::Sha256;
+
+    type HmacSha256 = Hmac<Sha256>;
+
+    const NONCE_LEN: usize = 12;
+
+    /// Separate keys are derived for signing and encryption from the same secret.
+    fn derive_key(secret: &str, purpose: &str) -> [u8; 32] {
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(secret.as_bytes())
+            .expect("HMAC can take a key of any size");
+        mac.update(purpose.as_bytes());
+        mac.finalize().into_bytes().into()
+    }
+
+    fn mac(secret: &str, name: &str, value: &str) -> HmacSha256 {
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(&derive_key(secret, "cookie-signing"))
+            .expect("HMAC can take a key of any size");
+        mac.update(name.as_bytes());
+        mac.update(b"=");
+        mac.update(value.as_bytes());
+        mac
+    }
+
+    fn cipher(secret: &str) -> Aes256Gcm {
+        Aes256Gcm::new(&derive_key(secret, "cookie-encryption").into())
+    }
+
+    /// `sign` returns `value.signature`, signed with the first of `keys`.
+    pub fn sign(name: &str, value: &str, keys: &[&str]) -> Option<String> {
+        let tag = mac(keys.first()?, name, value).finalize().into_bytes();
+        Some(format!("{value}.{}", URL_SAFE_NO_PAD.encode(tag)))
+    }
+
+    /// `verify` returns the value of `signed` if any of `keys` produced its signature.
+    pub fn verify(name: &str, signed: &str, keys: &[&str]) -> Option<String> {
+        let (value, tag) = signed.rsplit_once('.')?;
+        let tag = URL_SAFE_NO_PAD.decode(tag).ok()?;
+
+        keys.iter()
+            .any(|key| mac(key, name, value).verify_slice(&tag).is_ok())
+            .then(|| value.to_string())
+    }
+
+    /// `encrypt` returns the base64 encoded nonce and ciphertext, encrypted with the first of `keys`.
+    pub fn encrypt(name: &str, value: &str, keys: &[&str]) -> Option<String> {
+        let mut nonce = [0; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+
+        let payload = Payload {
+            msg: value.as_bytes(),
+            aad: name.as_bytes(),
+        };
+        let ciphertext = cipher(keys.first()?)
+            .encrypt(Nonce::from_slice(&nonce), payload)
+            .ok()?;
+
+        let mut data = nonce.to_vec();
+        data.extend(ciphertext);
+        Some(URL_SAFE_NO_PAD.encode(data))
+    }
+
+    /// `decrypt` returns the plaintext of `encrypted` if any of `keys` can authenticate it.
+    pub fn decrypt(name: &str, encrypted: &str, keys: &[&str]) -> Option<String> {
+        let data = URL_SAFE_NO_PAD.decode(encrypted).ok()?;
+        if data.len() < NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+
+        let plaintext = keys.iter().find_map(|key| {
+            let payload = Payload {
+                msg: ciphertext,
+                aad: name.as_bytes(),
+            };
+            cipher(key).decrypt(Nonce::from_slice(nonce), payload).ok()
+        })?;
+        String::from_utf8(plaintext).ok()
+    }
 }
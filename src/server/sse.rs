@@ -0,0 +1,264 @@
+use crate::server::{Bytes, Request, Response, ServerConfig, StatusCode};
+use http::header::{CACHE_CONTROL, CONTENT_TYPE, TRANSFER_ENCODING};
+use http::Method;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+/// Events kept per channel for clients that reconnect with `Last-Event-ID`.
+const HISTORY_SIZE: usize = 100;
+
+/// Bytes kept for a client that doesn't take its events, before it is disconnected. It can
+/// reconnect with `Last-Event-ID` for what it missed.
+pub const MAX_BACKLOG: usize = 1 << 20;
+
+/// Sent on event streams with nothing to send. Lines starting with a colon are ignored by clients.
+pub const HEARTBEAT: &[u8] = b": heartbeat\n\n";
+
+/// # ServerSentEvent
+///
+/// A message for the subscribers of a channel, written as `id:`, `event:`, `retry:` and
+/// `data:` fields.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ServerSentEvent {
+    pub id: Option<String>,
+    pub event: Option<String>,
+    /// Milliseconds clients wait before reconnecting.
+    pub retry: Option<u64>,
+    pub data: String,
+}
+
+impl ServerSentEvent {
+    pub fn new(data: impl Into<String>) -> Self {
+        Self {
+            data: data.into(),
+            ..Self::default()
+        }
+    }
+
+    pub fn event(mut self, event: impl Into<String>) -> Self {
+        self.event = Some(event.into());
+        self
+    }
+
+    pub fn id(mut self, id: impl Into<String>) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    pub fn retry(mut self, milliseconds: u64) -> Self {
+        self.retry = Some(milliseconds);
+        self
+    }
+}
+
+impl fmt::Display for ServerSentEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // A line break would end the field early
+        let single_line = |value: &str| value.replace(['\r', '\n'], " ");
+
+        if let Some(id) = &self.id {
+            writeln!(f, "id: {}", single_line(id))?;
+        }
+        if let Some(event) = &self.event {
+            writeln!(f, "event: {}", single_line(event))?;
+        }
+        if let Some(retry) = self.retry {
+            writeln!(f, "retry: {retry}")?;
+        }
+        // Every line of the data is a field of its own
+        for line in self.data.split('\n') {
+            writeln!(f, "data: {}", line.trim_end_matches('\r'))?;
+        }
+        writeln!(f)
+    }
+}
+
+/// # EventStream
+///
+/// Response extension of a response that streams the events of `channel`. The connection stays
+/// open and the events follow the head of the response.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EventStream {
+    pub channel: String,
+    /// The last event the client received before it reconnected.
+    pub last_event_id: Option<String>,
+}
+
+/// # event_stream
+///
+/// The response of a handler that subscribes the client to `channel`. Events the client missed
+/// since its `Last-Event-ID` are sent first.
+pub fn event_stream(request: &Request<Bytes>, channel: &str) -> Response<Bytes> {
+    let last_event_id = request
+        .headers()
+        .get("last-event-id")
+        .and_then(|id| id.to_str().ok())
+        .map(str::to_string);
+
+    let mut response = Response::builder()
+        .status(StatusCode::OK)
+        .version(request.version())
+        .header(CONTENT_TYPE, "text/event-stream")
+        .header(CACHE_CONTROL, "no-cache")
+        // The length isn't known, so each event is sent as a chunk
+        .header(TRANSFER_ENCODING, "chunked")
+        .body(Bytes::new())
+        .unwrap_or_default();
+    response.extensions_mut().insert(EventStream {
+        channel: channel.to_string(),
+        last_event_id,
+    });
+    response
+}
+
+type Subscriber = Box<dyn Fn(&ServerSentEvent) -> bool + Send>;
+
+#[derive(Default)]
+struct Channel {
+    history: VecDeque<ServerSentEvent>,
+    subscribers: HashMap<u64, Subscriber>,
+    next_event_id: u64,
+}
+
+/// # EventHub
+///
+/// The channels of the event streams of all servers. Events can be published from any thread.
+#[derive(Default)]
+pub struct EventHub {
+    next_id: AtomicU64,
+    channels: Mutex<HashMap<String, Channel>>,
+}
+
+impl fmt::Debug for EventHub {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventHub")
+            .field("channels", &self.lock().len())
+            .finish()
+    }
+}
+
+impl EventHub {
+    /// Sends `event` to the subscribers of `channel`, and keeps it for clients that reconnect.
+    /// Events without an id get the next number of the channel. Returns the number of subscribers.
+    pub fn publish(&self, channel: &str, mut event: ServerSentEvent) -> usize {
+        let mut channels = self.lock();
+        let channel = channels.entry(channel.to_string()).or_default();
+
+        if event.id.is_none() {
+            channel.next_event_id += 1;
+            event.id = Some(channel.next_event_id.to_string());
+        }
+        // Subscribers whose event loop stopped are dropped
+        channel
+            .subscribers
+            .retain(|_, subscriber| subscriber(&event));
+
+        if channel.history.len() == HISTORY_SIZE {
+            channel.history.pop_front();
+        }
+        channel.history.push_back(event);
+        channel.subscribers.len()
+    }
+
+    /// Adds a subscriber to `channel`. Returns its id, and the events after `last_event_id` if
+    /// that event is still kept.
+    pub fn subscribe(
+        &self,
+        channel: &str,
+        last_event_id: Option<&str>,
+        subscriber: impl Fn(&ServerSentEvent) -> bool + Send + 'static,
+    ) -> (u64, Vec<ServerSentEvent>) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut channels = self.lock();
+        let channel = channels.entry(channel.to_string()).or_default();
+        channel.subscribers.insert(id, Box::new(subscriber));
+
+        let missed = last_event_id
+            .and_then(|last| {
+                let position = channel
+                    .history
+                    .iter()
+                    .position(|event| event.id.as_deref() == Some(last))?;
+                Some(channel.history.iter().skip(position + 1).cloned().collect())
+            })
+            .unwrap_or_default();
+        (id, missed)
+    }
+
+    pub fn unsubscribe(&self, channel: &str, id: u64) {
+        if let Some(channel) = self.lock().get_mut(channel) {
+            channel.subscribers.remove(&id);
+        }
+    }
+
+    /// Number of subscribers of `channel`.
+    pub fn subscribers(&self, channel: &str) -> usize {
+        self.lock()
+            .get(channel)
+            .map_or(0, |channel| channel.subscribers.len())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Channel>> {
+        self.channels.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// # event_hub
+///
+/// The hub of the event stream channels.
+pub fn event_hub() -> &'static EventHub {
+    static HUB: OnceLock<EventHub> = OnceLock::new();
+    HUB.get_or_init(EventHub::default)
+}
+
+/// # Subscription
+///
+/// A connection subscribed to a channel. Unsubscribes when dropped.
+#[derive(Debug)]
+pub struct Subscription {
+    pub channel: String,
+    pub id: u64,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        event_hub().unsubscribe(&self.channel, self.id);
+    }
+}
+
+/// # chunk
+///
+/// `bytes` as a chunk of a chunked response body.
+pub fn chunk(bytes: &[u8]) -> Bytes {
+    let mut chunk = format!("{:X}\r\n", bytes.len()).into_bytes();
+    chunk.extend_from_slice(bytes);
+    chunk.extend_from_slice(b"\r\n");
+    chunk
+}
+
+/// # events_demo
+///
+/// Demo handler. `GET` subscribes to the `demo` channel and `POST` publishes the body to it.
+pub fn events_demo(
+    req: &Request<Bytes>,
+    _conf: &ServerConfig,
+) -> Result<Response<Bytes>, StatusCode> {
+    match *req.method() {
+        Method::GET => Ok(event_stream(req, "demo")),
+        Method::POST => {
+            let data =
+                String::from_utf8(req.body().clone()).map_err(|_| StatusCode::BAD_REQUEST)?;
+            let subscribers = event_hub().publish("demo", ServerSentEvent::new(data));
+
+            Response::builder()
+                .status(StatusCode::OK)
+                .version(req.version())
+                .header(CONTENT_TYPE, "application/json")
+                .body(format!("{{\"subscribers\":{subscribers}}}").into_bytes())
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+        }
+        _ => Err(StatusCode::METHOD_NOT_ALLOWED),
+    }
+}
@@ -1,24 +1,105 @@
-use std::net::ToSocketAddrs;
-use std::process::exit;
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
 
-use crate::server::{Server, ServerState, TcpListener};
+use crate::server::{
+    check_limits, is_tls_port, tls_config, Control, Server, ServerState, TcpListener,
+};
 use crate::server_config::ServerConfig;
 use crate::type_aliases::Port;
 
-pub fn start(configs: Vec<ServerConfig<'static>>) {
+/// Time given to open connections to finish their requests when shutting down.
+pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// # ServerHandle
+///
+/// Controls servers started with `start`. Clones control the same servers.
+#[derive(Clone, Debug)]
+pub struct ServerHandle {
+    /// The event loop with the listeners.
+    control: Arc<Control>,
+    workers: Vec<Arc<Control>>,
+    threads: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl ServerHandle {
+    /// Addresses the servers listen on. Useful when binding to port 0.
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.control.local_addrs()
+    }
+
+    /// Replaces the configuration of the servers with `configs`. New connections use the new
+    /// configuration while open connections finish on the old one. Listeners are opened and
+    /// closed for added and removed ports. Returns immediately, errors are logged.
+    ///
+    /// Configurations are Rust values, so this is how embedders apply a new one, built at runtime.
+    pub fn reload(&self, configs: Vec<ServerConfig<'static>>) {
+        self.control.reload(configs);
+    }
+
+    /// Stops accepting connections and gives open connections `DEFAULT_SHUTDOWN_TIMEOUT` to finish.
+    /// Returns immediately, use `wait` to wait for the servers to stop.
+    pub fn shutdown(&self) {
+        self.shutdown_with_timeout(DEFAULT_SHUTDOWN_TIMEOUT);
+    }
+
+    /// Like `shutdown`, but connections that are still open after `timeout` are closed.
+    pub fn shutdown_with_timeout(&self, timeout: Duration) {
+        self.control.shutdown(timeout);
+        for worker in &self.workers {
+            worker.shutdown(timeout);
+        }
+    }
+
+    /// Blocks until the servers have stopped. Only the first caller waits.
+    pub fn wait(&self) {
+        let threads = match self.threads.lock() {
+            Ok(mut threads) => std::mem::take(&mut *threads),
+            Err(poisoned) => std::mem::take(&mut *poisoned.into_inner()),
+        };
+        for thread in threads {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// # start
+///
+/// Binds the ports of all `configs` and serves them on new threads, one per event loop.
+/// Fails if no port could be bound.
+pub fn start(configs: Vec<ServerConfig<'static>>) -> io::Result<ServerHandle> {
     let servers = get_servers(configs);
     if servers.is_empty() {
-        eprintln!("No servers were added. Exit program.");
-        exit(1);
+        return Err(io::Error::new(
+            io::ErrorKind::AddrNotAvailable,
+            "No servers were added",
+        ));
     }
+
     let mut s = ServerState::init(servers);
-    loop {
-        s.poll();
-        s.handle_events();
-    }
+    let workers = s.add_workers();
+    let control = s.control();
+    let mut threads = vec![thread::spawn(move || s.run())];
+
+    let workers = workers
+        .into_iter()
+        .map(|mut worker| {
+            let control = worker.control();
+            threads.push(thread::spawn(move || worker.run()));
+            control
+        })
+        .collect();
+
+    Ok(ServerHandle {
+        control,
+        workers,
+        threads: Arc::new(Mutex::new(threads)),
+    })
 }
 
-fn bind_port(host: &str, port: &Port) -> Option<TcpListener> {
+pub(crate) fn bind_port(host: &str, port: &Port) -> Option<TcpListener> {
     // Use ToSocketAddrs to resolve the hostname to an IP address
     let host_and_port = format!("{host}:{port}");
     let mut addresses = match host_and_port.to_socket_addrs() {
@@ -53,16 +134,29 @@ pub fn get_servers(configs: Vec<ServerConfig<'static>>) -> Vec<Server<'static>>
                 config.host
             );
         }
+        if let Err(e) = check_limits(&config) {
+            eprintln!("Error: {e}. The ports of {} are not opened", config.host);
+            continue;
+        }
+
+        let tls = match tls_config(&config) {
+            Ok(tls) => tls,
+            Err(e) => {
+                eprintln!("Error: {e}. TLS ports of {} are not opened", config.host);
+                None
+            }
+        };
 
         let listeners = config
             .ports
             .iter()
-            .filter_map(|port| bind_port(config.host, port))
+            .filter(|port| tls.is_some() || !is_tls_port(&config, port))
+            .filter_map(|port| bind_port(config.host, port).map(|listener| (*port, listener)))
             .collect::<Vec<_>>();
 
         if !listeners.is_empty() {
             // Only create a server if there are successful listeners
-            servers.push(Server::new(listeners, config));
+            servers.push(Server::new(listeners, config, tls));
         }
     }
     servers
@@ -71,7 +165,7 @@ pub fn get_servers(configs: Vec<ServerConfig<'static>>) -> Vec<Server<'static>>
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::log::init_logs;
+    use crate::log::{init_logs, LogSettings};
     #[test]
     fn test_bind_port() {
         // Invalid address
@@ -79,7 +173,7 @@ mod tests {
         let invalid_addr = "foo";
         assert!(bind_port(invalid_addr, &valid_port).is_none());
 
-        init_logs();
+        init_logs(LogSettings::default());
         // Invalid ports
         let invalid_port: Port = 1;
         let valid_addr = "127.0.0.1";
@@ -94,6 +188,25 @@ mod tests {
             custom_error_path: None,
             body_size_limit: 0,
             routes: vec![],
+            cookie_keys: vec![],
+            access_rules: vec![],
+            trusted_proxies: vec![],
+            max_connections: None,
+            max_connections_per_ip: None,
+            rate_limit: None,
+            max_request_line_length: 8 * 1024,
+            max_header_count: 100,
+            max_header_bytes: 16 * 1024,
+            idle_timeout: None,
+            header_timeout: None,
+            body_timeout: None,
+            keep_alive_timeout: None,
+            workers: 1,
+            handler_threads: 0,
+            tls: None,
+            http2: false,
+            event_stream_heartbeat: None,
+            access_log: None,
         };
         assert!(get_servers(vec![server_config]).is_empty());
     }
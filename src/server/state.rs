@@ -1,129 +1,972 @@
 use super::{
-    Arc, Events, HashMap, Interest, Listener, Poll, Server, ServerConfig, TcpStream, Token,
+    Arc, Bytes, Events, HashMap, Interest, Listener, Poll, Server, ServerConfig, SocketAddr,
+    TcpStream, Token, Waker,
 };
 
 use crate::log::*;
-use std::io::ErrorKind;
+use crate::server::errors::error;
+use crate::server::http2::{h2c_upgrade, Event, Http2, PREFACE_HEAD, SWITCHING_PROTOCOLS};
+use crate::server::limits::{
+    check_limits, log_limits, ConnectionCounter, ConnectionGuard, RateLimiter,
+};
+use crate::server::metrics::{metrics, ActiveConnection};
+use crate::server::pool::HandlerPool;
+use crate::server::reader::{ReadState, RequestReader};
+use crate::server::serve::{serve_response, write_all};
+use crate::server::sse::{chunk, event_hub, EventStream, Subscription, HEARTBEAT, MAX_BACKLOG};
+use crate::server::start::bind_port;
+use crate::server::status::{status_board, ConnectionEntry, ConnectionSummary, ServerRegistration};
+use crate::server::timeouts::{connection_deadline, Deadlines};
+use crate::server::tls::{is_tls_port, tls_config, Stream};
+use crate::server::tus::TusPart;
+use crate::server::websocket::{
+    websockets, Outgoing, WebSocketConnection, WebSocketUpgrade, ABNORMAL_CLOSURE, GOING_AWAY,
+};
+use crate::server::{respond, Client, BUFFER_SIZE};
+use http::header::CONNECTION;
+use http::{HeaderValue, Response, StatusCode};
+use std::io::Read;
+use std::net::ToSocketAddrs;
 #[cfg(unix)]
 use std::os::fd::{AsRawFd, FromRawFd};
 #[cfg(windows)]
 use std::os::windows::io::{AsRawSocket, FromRawSocket};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Mutex;
 use std::time::Duration;
 use std::time::Instant;
 
 pub const INITIAL_TOKEN_ID: usize = 0;
+/// Token of the waker that interrupts `poll` when the servers are told to stop or reload,
+/// or a message is sent to the event loop.
+const WAKER_TOKEN: Token = Token(usize::MAX);
+/// How long a closing connection without an idle timeout waits for the client to take the
+/// last output.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// # Control
+///
+/// Shared between a `ServerState` and its `ServerHandle`s to stop or reload the servers from another thread.
+#[derive(Debug)]
+pub struct Control {
+    waker: Waker,
+    shutdown: Mutex<Option<Duration>>,
+    reload: Mutex<Option<Vec<ServerConfig<'static>>>>,
+    local_addrs: Mutex<Vec<SocketAddr>>,
+}
+
+/// Locks `mutex`, even if another thread panicked while holding it.
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    match mutex.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+impl Control {
+    /// Asks the servers to stop, giving open connections `timeout` to finish.
+    pub fn shutdown(&self, timeout: Duration) {
+        *lock(&self.shutdown) = Some(timeout);
+        self.wake();
+    }
+
+    /// Asks the servers to replace their configuration with `configs`.
+    pub fn reload(&self, configs: Vec<ServerConfig<'static>>) {
+        *lock(&self.reload) = Some(configs);
+        self.wake();
+    }
+
+    /// Addresses the servers currently listen on.
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        lock(&self.local_addrs).clone()
+    }
+
+    fn wake(&self) {
+        if let Err(e) = self.waker.wake() {
+            log!(
+                LogLevel::Error,
+                LogFileType::Server,
+                format!("Failed to wake server. {e}")
+            );
+        }
+    }
+
+    fn shutdown_requested(&self) -> Option<Duration> {
+        *lock(&self.shutdown)
+    }
+
+    fn reload_requested(&self) -> Option<Vec<ServerConfig<'static>>> {
+        lock(&self.reload).take()
+    }
+}
+
+/// A connection handed to an event loop through its `Mailbox`.
+enum Message<'a> {
+    /// A new connection accepted by another event loop.
+    Accepted(Connection<'a>),
+    /// A connection whose request was handled on a `HandlerPool`, and if it should stay open.
+    Handled(Token, Connection<'a>, bool),
+    /// The response to a request on a stream of an HTTP/2 connection, handled on a `HandlerPool`.
+    Responded(Token, u32, Response<Bytes>),
+    /// A message for a WebSocket connection, sent through the `WebSocketHub`.
+    WebSocket(Token, Outgoing),
+    /// An event for an event stream connection, published through the `EventHub`.
+    Event(Token, Bytes),
+}
+
+/// A protocol the connection switches to after its response is sent.
+enum Upgrade {
+    WebSocket(Box<WebSocketUpgrade>),
+    EventStream(EventStream),
+}
+
+/// A request whose body is written to a tus upload as it arrives, and the response that is
+/// sent once it is complete.
+struct Streaming {
+    part: TusPart,
+    response: Response<Bytes>,
+    keep_alive: bool,
+}
+
+/// # Mailbox
+///
+/// Sends messages to an event loop and wakes it up to receive them.
+#[derive(Clone)]
+struct Mailbox<'a> {
+    sender: Sender<Message<'a>>,
+    control: Arc<Control>,
+}
+
+impl<'a> Mailbox<'a> {
+    /// Returns `false` if the event loop has stopped.
+    fn send(&self, message: Message<'a>) -> bool {
+        if self.sender.send(message).is_err() {
+            return false;
+        }
+        self.control.wake();
+        true
+    }
+}
 
 struct Connection<'a> {
-    stream: TcpStream,
+    stream: Stream,
+    peer: SocketAddr,
     config: Arc<ServerConfig<'a>>,
+    pool: Option<Arc<HandlerPool>>,
+    reader: RequestReader,
     last_activity: Instant,
+    requests_served: usize,
+    deadline: Option<Instant>,
+    /// What the connection is registered for with `poll`.
+    interest: Interest,
+    /// Set once the server is done with the connection, while the client takes the last output.
+    closing: bool,
+    /// Set once the connection switched to HTTP/2.
+    http2: Option<Box<Http2>>,
+    /// Set by a response that accepted a WebSocket handshake or started an event stream, until
+    /// the connection switches.
+    upgrade: Option<Upgrade>,
+    /// Set once the connection switched to WebSocket.
+    websocket: Option<Box<WebSocketConnection>>,
+    /// Set once the connection streams the events of a channel.
+    event_stream: Option<Subscription>,
+    /// Set while the body of a request is written to a tus upload.
+    streaming: Option<Box<Streaming>>,
+    /// Counts the connection against the connection limits until it is dropped.
+    _counted: ConnectionGuard,
+    /// Counts the connection as active in the metrics until it is dropped.
+    _active: ActiveConnection,
+    /// Lists the connection on the status page until it is dropped.
+    status: ConnectionEntry,
 }
 
 impl<'a> Connection<'a> {
-    fn new(stream: TcpStream, config: Arc<ServerConfig<'a>>) -> Self {
+    fn new(
+        stream: Stream,
+        peer: SocketAddr,
+        listener: &Listener<'a>,
+        counted: ConnectionGuard,
+    ) -> Self {
+        let now = Instant::now();
         Self {
             stream,
-            config,
-            last_activity: Instant::now(),
+            peer,
+            config: Arc::clone(&listener.config),
+            pool: listener.pool.clone(),
+            reader: RequestReader::new(now),
+            last_activity: now,
+            requests_served: 0,
+            deadline: None,
+            interest: Interest::READABLE,
+            closing: false,
+            http2: None,
+            upgrade: None,
+            websocket: None,
+            event_stream: None,
+            streaming: None,
+            _counted: counted,
+            _active: metrics().connection_opened(),
+            status: status_board().connection_opened(ConnectionSummary {
+                peer,
+                host: listener.config.host.to_string(),
+                port: listener.listener.local_addr().map_or(0, |addr| addr.port()),
+                tls: listener.tls.is_some(),
+                protocol: "HTTP/1.1",
+                opened: now,
+            }),
+        }
+    }
+
+    /// Puts the first timeout of the connection in `deadlines`, and asks `poll` for writable
+    /// events while output is pending.
+    fn schedule(&mut self, token: Token, poll: &Poll, deadlines: &mut Deadlines) {
+        self.watch(token, poll);
+
+        if self.closing || self.stream.has_pending() {
+            // The client has to take the output in time
+            let timeout = match self.closing {
+                true => self.config.idle_timeout.or(Some(CLOSE_TIMEOUT)),
+                false => self.config.idle_timeout,
+            };
+            self.deadline = timeout.map(|timeout| self.last_activity + timeout);
+            if let Some(deadline) = self.deadline {
+                deadlines.schedule(token, deadline);
+            }
+            return;
+        }
+
+        if let Some(websocket) = &self.websocket {
+            // WebSockets stay open until either side closes them, or the client doesn't
+            // answer a close in time
+            self.deadline = websocket
+                .is_closing()
+                .then_some(self.config.idle_timeout)
+                .flatten()
+                .map(|timeout| Instant::now() + timeout);
+            if let Some(deadline) = self.deadline {
+                deadlines.schedule(token, deadline);
+            }
+            return;
+        }
+
+        if self.event_stream.is_some() {
+            // Event streams stay open until the client leaves, with a heartbeat when quiet
+            self.deadline = self
+                .config
+                .event_stream_heartbeat
+                .map(|heartbeat| self.last_activity + heartbeat);
+            if let Some(deadline) = self.deadline {
+                deadlines.schedule(token, deadline);
+            }
+            return;
+        }
+
+        self.deadline = match &self.http2 {
+            // Requests being handled can't time out
+            Some(http2) if http2.is_handling() => None,
+            Some(http2) if http2.is_idle() => self
+                .config
+                .keep_alive_timeout
+                .or(self.config.idle_timeout)
+                .map(|timeout| self.last_activity + timeout),
+            Some(_) => self
+                .config
+                .idle_timeout
+                .map(|timeout| self.last_activity + timeout),
+            None => connection_deadline(
+                &self.reader,
+                self.last_activity,
+                self.requests_served,
+                &self.config,
+            )
+            .map(|(deadline, _)| deadline),
+        };
+
+        if let Some(deadline) = self.deadline {
+            deadlines.schedule(token, deadline);
+        }
+    }
+
+    /// Registers the connection for writable events while output is pending, and stops reading
+    /// once it is closing.
+    fn watch(&mut self, token: Token, poll: &Poll) {
+        let interest = match (self.closing, self.stream.has_pending()) {
+            (true, _) => Interest::WRITABLE,
+            (false, true) => Interest::READABLE | Interest::WRITABLE,
+            (false, false) => Interest::READABLE,
+        };
+        if interest == self.interest {
+            return;
+        }
+        match poll
+            .registry()
+            .reregister(&mut self.stream, token, interest)
+        {
+            Ok(()) => self.interest = interest,
+            Err(e) => log!(
+                LogLevel::Error,
+                LogFileType::Server,
+                format!("Failed to reregister {}. {e}", self.peer)
+            ),
+        }
+    }
+
+    /// Ends the protocol of the connection before it is closed: WebSocket clients get a close
+    /// frame, event streams their last chunk and TLS clients a close notification. Only the
+    /// first call writes anything.
+    fn end(&mut self) {
+        if self.closing {
+            return;
+        }
+        self.closing = true;
+        if let Some(websocket) = self.websocket.as_mut() {
+            websocket.send(Outgoing::Close(GOING_AWAY, String::new()));
+            let _ = write_all(&mut self.stream, &websocket.take_output());
+            websocket.finish(GOING_AWAY, "");
+        }
+        // The last chunk ends the event stream cleanly
+        if self.event_stream.take().is_some() {
+            let _ = write_all(&mut self.stream, b"0\r\n\r\n");
+        }
+        self.stream.close();
+    }
+
+    /// Responds to a request read from the connection. Returns `false` if the response could not be sent.
+    fn handle(&mut self, request: (String, Bytes), rate_limiter: &RateLimiter) -> bool {
+        let keep_alive = self
+            .reader
+            .is_streaming()
+            .then(|| keep_alive(&request.0, &self.config));
+        let mut response = respond(request, &self.client(), &self.config, rate_limiter);
+        let extensions = response.extensions_mut();
+        // The response to a tus part waits until its body was written
+        if let (Some(part), Some(keep_alive)) = (extensions.remove::<TusPart>(), keep_alive) {
+            self.streaming = Some(Box::new(Streaming {
+                part,
+                response,
+                keep_alive,
+            }));
+            return true;
+        }
+        self.upgrade = match extensions.remove::<WebSocketUpgrade>() {
+            Some(websocket) => Some(Upgrade::WebSocket(Box::new(websocket))),
+            None => extensions.remove::<EventStream>().map(Upgrade::EventStream),
+        };
+        // The body was not taken, so the connection closes after the response
+        if keep_alive.is_some() {
+            response
+                .headers_mut()
+                .insert(CONNECTION, HeaderValue::from_static("close"));
+        }
+
+        if let Err(e) = serve_response(&mut self.stream, response) {
+            log!(
+                LogLevel::Error,
+                LogFileType::Client,
+                format!("Error handling client: {e}")
+            );
+            return false;
+        }
+        self.requests_served += 1;
+        true
+    }
+
+    /// Writes the next bytes of a streamed request body to its upload, and sends the response
+    /// once the body is complete. Returns `false` if the connection should be closed.
+    fn receive_body_part(&mut self, bytes: &[u8], complete: bool) -> bool {
+        let mut streaming = match self.streaming.take() {
+            Some(streaming) => streaming,
+            None => return false,
+        };
+        let written = streaming.part.write(bytes);
+        if written.is_ok() && !complete {
+            self.streaming = Some(streaming);
+            return true;
+        }
+
+        let Streaming {
+            part,
+            response,
+            keep_alive,
+        } = *streaming;
+        let mut response = match written.and_then(|_| part.finish()) {
+            Ok(()) => response,
+            Err(e) => {
+                log!(
+                    LogLevel::Error,
+                    LogFileType::Server,
+                    format!("Failed to store resumable upload. {e}")
+                );
+                error(StatusCode::INTERNAL_SERVER_ERROR, &self.config)
+            }
+        };
+        if self.reader.is_streaming() {
+            response
+                .headers_mut()
+                .insert(CONNECTION, HeaderValue::from_static("close"));
+        }
+        if let Err(e) = serve_response(&mut self.stream, response) {
+            log!(
+                LogLevel::Error,
+                LogFileType::Client,
+                format!("Error handling client: {e}")
+            );
+            return false;
+        }
+        self.requests_served += 1;
+        // The rest of a body that failed to be written is not read
+        keep_alive && !self.reader.is_streaming()
+    }
+
+    /// Returns `true` if the connection is between requests.
+    fn is_idle(&self) -> bool {
+        match &self.http2 {
+            Some(http2) => http2.is_idle(),
+            // WebSockets are closed with a close frame, and event streams never finish
+            None => {
+                self.websocket.is_some() || self.event_stream.is_some() || self.reader.is_empty()
+            }
+        }
+    }
+
+    /// The error response to a request that could not be read, written to the access log.
+    fn rejection(&self, code: StatusCode) -> Response<Bytes> {
+        let response = error(code, &self.config);
+        log_unread_request(&self.config, self.peer.ip(), &response);
+        response
+    }
+
+    fn client(&self) -> Client {
+        Client {
+            peer: self.peer,
+            tls: self.stream.tls_info(),
+            http2: self.http2.is_some(),
         }
     }
 }
+
+/// What to do with a connection after reading from it.
+enum Outcome {
+    KeepOpen,
+    Close,
+    /// Handle the request on the `HandlerPool` of the server, and if the connection should stay open after.
+    Handle((String, Bytes), bool),
+    /// Continue the connection with HTTP/2, with the bytes received so far and the request that
+    /// asked for the upgrade.
+    Http2(Box<Http2>, Bytes, Vec<Event>),
+    /// Continue the connection with the WebSocket handler or event stream of its response.
+    Upgrade,
+}
+
 pub struct ServerState<'a> {
     poll: Poll,
     events: Events,
     token_id: usize,
     listeners: Vec<Listener<'a>>,
     connections: HashMap<Token, Connection<'a>>,
+    deadlines: Deadlines,
+    rate_limiter: RateLimiter,
+    control: Arc<Control>,
+    /// Set while shutting down. Connections are closed after their current request.
+    draining: bool,
+    mailbox: Mailbox<'a>,
+    messages: Receiver<Message<'a>>,
+    /// Other event loops that accepted connections are handed to.
+    workers: Vec<Mailbox<'a>>,
+    next_worker: usize,
+    /// Requests being handled on a `HandlerPool`.
+    handling: usize,
+    /// Lists the servers on the status page. Only set on the event loop with the listeners.
+    registration: Option<ServerRegistration>,
 }
-impl ServerState<'_> {
+impl ServerState<'static> {
     pub fn init(servers: Vec<Server<'static>>) -> ServerState<'static> {
-        let poll = Poll::new().expect("Failed to create Poll instance");
-        let events = Events::with_capacity(4096);
-        let mut token_id = INITIAL_TOKEN_ID;
-        let mut listeners = Vec::new();
-        let connections = HashMap::new();
+        let mut state = Self::new(RateLimiter::new());
 
         // Register all the listeners
         for server in servers {
+            log_limits(&server.config);
             let config = Arc::new(server.config);
+            let connections = Arc::new(ConnectionCounter::new());
+            let pool = handler_pool(&config);
 
             server
                 .listeners
                 .into_iter()
-                .enumerate()
-                .for_each(|(id, mut listener)| {
-                    token_id += 1;
-                    let token = Token(id);
-                    poll.registry()
+                .for_each(|(port, mut listener)| {
+                    let token = Token(state.token_id);
+                    state.token_id += 1;
+                    state
+                        .poll
+                        .registry()
                         .register(&mut listener, token, Interest::READABLE)
                         .expect("Failed to register listener");
 
-                    listeners.push(Listener {
+                    state.listeners.push(Listener {
                         listener,
                         token,
                         config: Arc::clone(&config),
+                        connections: Arc::clone(&connections),
+                        pool: pool.clone(),
+                        tls: server.tls.clone().filter(|_| is_tls_port(&config, &port)),
                     });
                 });
         }
 
+        *lock(&state.control.local_addrs) = local_addrs(&state.listeners);
+        state.registration = Some(status_board().register(&state.listeners));
+        state
+    }
+
+    /// An event loop without listeners.
+    fn new(rate_limiter: RateLimiter) -> ServerState<'static> {
+        let poll = Poll::new().expect("Failed to create Poll instance");
+        let control = Arc::new(Control {
+            waker: Waker::new(poll.registry(), WAKER_TOKEN).expect("Failed to create waker"),
+            shutdown: Mutex::new(None),
+            reload: Mutex::new(None),
+            local_addrs: Mutex::new(Vec::new()),
+        });
+        let (sender, messages) = mpsc::channel();
+
         ServerState {
             poll,
-            events,
-            token_id,
-            listeners,
-            connections,
+            events: Events::with_capacity(4096),
+            token_id: INITIAL_TOKEN_ID,
+            listeners: Vec::new(),
+            connections: HashMap::new(),
+            deadlines: Deadlines::new(),
+            rate_limiter,
+            control: Arc::clone(&control),
+            draining: false,
+            mailbox: Mailbox { sender, control },
+            messages,
+            workers: Vec::new(),
+            next_worker: 0,
+            handling: 0,
+            registration: None,
         }
     }
 
+    /// Creates the event loops that this one hands accepted connections to, as many as the
+    /// `workers` of the servers ask for besides this one. Each has to be `run` on its own thread.
+    pub fn add_workers(&mut self) -> Vec<ServerState<'static>> {
+        let count = self
+            .listeners
+            .iter()
+            .map(|listener| listener.config.workers.max(1))
+            .max()
+            .unwrap_or(1);
+
+        let workers = (1..count)
+            .map(|_| Self::new(self.rate_limiter.clone()))
+            .collect::<Vec<_>>();
+        self.workers
+            .extend(workers.iter().map(|worker| worker.mailbox.clone()));
+
+        log!(
+            LogLevel::Info,
+            LogFileType::Server,
+            format!("Serving connections on {count} event loops")
+        );
+        workers
+    }
+
+    pub(crate) fn control(&self) -> Arc<Control> {
+        Arc::clone(&self.control)
+    }
+
+    /// Serves until the servers are told to stop, then waits for open connections to finish.
+    pub fn run(&mut self) {
+        loop {
+            self.poll();
+            self.handle_events();
+
+            if let Some(timeout) = self.control.shutdown_requested() {
+                self.shutdown(timeout);
+                return;
+            }
+            if let Some(configs) = self.control.reload_requested() {
+                self.reload(configs);
+            }
+        }
+    }
+
+    /// Switches new connections to `configs`. Ports that are still configured keep their listener,
+    /// new ports are bound and removed ports are closed. Open connections finish on the old configuration.
+    fn reload(&mut self, configs: Vec<ServerConfig<'static>>) {
+        let mut old_listeners = std::mem::take(&mut self.listeners);
+        let mut listeners = Vec::new();
+
+        for config in configs {
+            if config.ports.is_empty() {
+                log!(
+                    LogLevel::Error,
+                    LogFileType::Server,
+                    format!("Reload: no ports are specified for {}", config.host)
+                );
+            }
+            if config.workers > self.workers.len() + 1 {
+                log!(
+                    LogLevel::Error,
+                    LogFileType::Server,
+                    format!(
+                        "Reload: {} asks for {} workers, but only {} event loops are running",
+                        config.host,
+                        config.workers,
+                        self.workers.len() + 1
+                    )
+                );
+            }
+            if let Err(e) = check_limits(&config) {
+                log!(
+                    LogLevel::Error,
+                    LogFileType::Server,
+                    format!("Reload: {e}. The ports of {} are closed", config.host)
+                );
+                continue;
+            }
+            log_limits(&config);
+            // Connections opened before the reload still count against the limits of the same
+            // server, which is the one with the same host and ports
+            let connections = old_listeners
+                .iter()
+                .find(|old| old.config.host == config.host && old.config.ports == config.ports)
+                .map(|listener| Arc::clone(&listener.connections))
+                .unwrap_or_default();
+            let config = Arc::new(config);
+            let pool = handler_pool(&config);
+            let tls = match tls_config(&config) {
+                Ok(tls) => tls,
+                Err(e) => {
+                    log!(
+                        LogLevel::Error,
+                        LogFileType::Server,
+                        format!("Reload: {e}. TLS ports of {} are closed", config.host)
+                    );
+                    None
+                }
+            };
+
+            for port in &config.ports {
+                let port_tls = tls.clone().filter(|_| is_tls_port(&config, port));
+                if port_tls.is_none() && is_tls_port(&config, port) {
+                    continue;
+                }
+
+                let addr = format!("{}:{port}", config.host)
+                    .to_socket_addrs()
+                    .ok()
+                    .and_then(|mut addrs| addrs.next());
+
+                // Keep listening on ports that are still configured
+                let existing = old_listeners.iter().position(|listener| {
+                    addr.is_some() && listener.listener.local_addr().ok() == addr
+                });
+                if let Some(index) = existing {
+                    let mut listener = old_listeners.swap_remove(index);
+                    listener.config = Arc::clone(&config);
+                    listener.connections = Arc::clone(&connections);
+                    listener.pool = pool.clone();
+                    listener.tls = port_tls;
+                    listeners.push(listener);
+                    continue;
+                }
+
+                let mut listener = match bind_port(config.host, port) {
+                    Some(listener) => listener,
+                    None => {
+                        log!(
+                            LogLevel::Error,
+                            LogFileType::Server,
+                            format!("Reload: unable to listen to {}:{port}", config.host)
+                        );
+                        continue;
+                    }
+                };
+                let token = Token(self.token_id);
+                self.token_id += 1;
+                if let Err(e) =
+                    self.poll
+                        .registry()
+                        .register(&mut listener, token, Interest::READABLE)
+                {
+                    log!(
+                        LogLevel::Error,
+                        LogFileType::Server,
+                        format!("Reload: failed to register listener. {e}")
+                    );
+                    continue;
+                }
+                listeners.push(Listener {
+                    listener,
+                    token,
+                    config: Arc::clone(&config),
+                    connections: Arc::clone(&connections),
+                    pool: pool.clone(),
+                    tls: port_tls,
+                });
+            }
+        }
+
+        if listeners.is_empty() {
+            log!(
+                LogLevel::Error,
+                LogFileType::Server,
+                "Reload failed, no ports could be bound. Keeping the previous configuration"
+                    .to_string()
+            );
+            self.listeners = old_listeners;
+            return;
+        }
+
+        for mut listener in old_listeners {
+            if let Ok(addr) = listener.listener.local_addr() {
+                println!("Server stopped listening on {addr}");
+            }
+            let _ = self.poll.registry().deregister(&mut listener.listener);
+        }
+
+        self.listeners = listeners;
+        *lock(&self.control.local_addrs) = local_addrs(&self.listeners);
+        if let Some(registration) = &self.registration {
+            registration.update(&self.listeners);
+        }
+        log!(
+            LogLevel::Info,
+            LogFileType::Server,
+            "Reloaded configuration".to_string()
+        );
+    }
+
+    fn shutdown(&mut self, timeout: Duration) {
+        let deadline = Instant::now() + timeout;
+        self.draining = true;
+
+        // Stop accepting connections
+        for mut listener in self.listeners.drain(..) {
+            let _ = self.poll.registry().deregister(&mut listener.listener);
+        }
+        lock(&self.control.local_addrs).clear();
+        self.registration = None;
+
+        // Connections between requests have nothing to finish. HTTP/2 clients are told to start
+        // no new streams.
+        let tokens = self.connections.keys().copied().collect::<Vec<_>>();
+        for token in tokens {
+            match self.connections.get(&token) {
+                Some(conn) if conn.is_idle() => self.close(token),
+                Some(conn) if conn.http2.is_some() => self.flush_http2(token),
+                _ => {}
+            }
+        }
+
+        log!(
+            LogLevel::Info,
+            LogFileType::Server,
+            format!(
+                "Shutting down. Waiting up to {}s for {} connections",
+                timeout.as_secs(),
+                self.connections.len() + self.handling
+            )
+        );
+
+        while (!self.connections.is_empty() || self.handling > 0) && Instant::now() < deadline {
+            self.poll_until(Some(deadline));
+            self.handle_events();
+        }
+
+        if !self.connections.is_empty() || self.handling > 0 {
+            log!(
+                LogLevel::Warn,
+                LogFileType::Server,
+                format!(
+                    "Closed {} connections that did not finish in time",
+                    self.connections.len() + self.handling
+                )
+            );
+        }
+        let tokens = self.connections.keys().copied().collect::<Vec<_>>();
+        for token in tokens {
+            close_connection(&self.poll, token, &mut self.connections);
+        }
+        log!(
+            LogLevel::Info,
+            LogFileType::Server,
+            "Server stopped".to_string()
+        );
+        flush_logs();
+    }
+
     pub fn poll(&mut self) {
-        self.poll
-            .poll(&mut self.events, Some(Duration::from_millis(5000)))
-            .expect("Poll failed");
+        self.poll_until(None);
+    }
+
+    /// Polls for events until the next timeout of a connection, or `limit`, whichever is first.
+    fn poll_until(&mut self, limit: Option<Instant>) {
+        // Wake up in time for the next timeout
+        let wake_at = match (self.next_deadline(), limit) {
+            (Some(deadline), Some(limit)) => Some(deadline.min(limit)),
+            (deadline, limit) => deadline.or(limit),
+        };
+        let timeout = wake_at.map(|at| at.saturating_duration_since(Instant::now()));
+
+        if let Err(e) = self.poll.poll(&mut self.events, timeout) {
+            if e.kind() != std::io::ErrorKind::Interrupted {
+                panic!("Poll failed: {e}");
+            }
+        }
 
         self.handle_timeout();
     }
 
     pub fn handle_events(&mut self) {
-        for event in self.events.iter() {
-            for listener in &self.listeners {
-                while accept_connection(
-                    &self.poll,
-                    &mut self.token_id,
-                    listener,
-                    &mut self.connections,
-                ) {}
+        self.receive_messages();
+
+        let events = self
+            .events
+            .iter()
+            .filter(|event| event.token() != WAKER_TOKEN)
+            .map(|event| (event.token(), event.is_writable()))
+            .collect::<Vec<_>>();
+
+        for (token, writable) in events {
+            match self
+                .listeners
+                .iter()
+                .position(|listener| listener.token == token)
+            {
+                Some(index) => self.accept_connections(index),
+                None if writable => self.write_connection(token),
+                None => self.read_connection(token),
             }
-            handle_existing_connection(&self.poll, event.token(), &mut self.connections);
         }
     }
 
-    fn handle_timeout(&mut self) {
-        let now = Instant::now();
-        let timeout_duration = Duration::from_millis(1000);
-
-        // Remove connections that timed out from `connections` HashMap
-        self.connections.retain(|_, conn| {
-            if now.duration_since(conn.last_activity) > timeout_duration {
-                self.poll
-                    .registry()
-                    .deregister(&mut conn.stream)
-                    .expect("Failed to deregister stream due to timeout");
-                false
-            } else {
-                true
+    /// Sends the pending output of a connection once the socket is writable, and goes on with
+    /// the connection where it waited for the output.
+    fn write_connection(&mut self, token: Token) {
+        let connection = match self.connections.get_mut(&token) {
+            Some(connection) => connection,
+            None => return,
+        };
+        let sent = connection.stream.pending_len();
+        if let Err(e) = connection.stream.send_pending() {
+            log!(
+                LogLevel::Warn,
+                LogFileType::Client,
+                format!("Error writing to client: {e}")
+            );
+            close_connection(&self.poll, token, &mut self.connections);
+            return;
+        }
+        if connection.stream.pending_len() < sent {
+            connection.last_activity = Instant::now();
+        }
+
+        if connection.closing && !connection.stream.has_pending() {
+            close_connection(&self.poll, token, &mut self.connections);
+        } else if connection.closing || connection.stream.has_pending() {
+            connection.schedule(token, &self.poll, &mut self.deadlines);
+        } else {
+            // Reading waited for the output, and input may have arrived meanwhile
+            self.read_connection(token);
+        }
+    }
+
+    /// Closes a connection once the client took its pending output, or the time to take it runs out.
+    fn close(&mut self, token: Token) {
+        let connection = match self.connections.get_mut(&token) {
+            Some(connection) => connection,
+            None => return,
+        };
+        // Closing with an unread body resets the connection, which can discard the response
+        if connection.reader.is_streaming() && !connection.closing {
+            discard_input(&mut connection.stream);
+        }
+        connection.end();
+        if connection.stream.has_pending() {
+            connection.schedule(token, &self.poll, &mut self.deadlines);
+            return;
+        }
+        close_connection(&self.poll, token, &mut self.connections);
+    }
+
+    /// Takes the connections sent by other event loops and handler threads.
+    fn receive_messages(&mut self) {
+        while let Ok(message) = self.messages.try_recv() {
+            match message {
+                Message::Accepted(connection) => self.add_connection(connection),
+                Message::Handled(token, connection, keep_open) => {
+                    self.handling -= 1;
+                    let upgraded = connection.upgrade.is_some();
+                    self.connections.insert(token, connection);
+                    if keep_open && !self.draining && upgraded {
+                        self.open_upgrade(token);
+                    } else if keep_open && !self.draining {
+                        // Events for the connection were missed while it was away
+                        self.read_connection(token);
+                    } else {
+                        self.close(token);
+                    }
+                }
+                Message::Responded(token, id, response) => {
+                    self.handling -= 1;
+                    if let Some(connection) = self.connections.get_mut(&token) {
+                        if let Some(http2) = connection.http2.as_mut() {
+                            http2.send_response(id, response);
+                            connection.requests_served += 1;
+                        }
+                        self.flush_http2(token);
+                    }
+                }
+                Message::WebSocket(token, outgoing) => {
+                    if let Some(websocket) = self
+                        .connections
+                        .get_mut(&token)
+                        .and_then(|connection| connection.websocket.as_mut())
+                    {
+                        websocket.send(outgoing);
+                        self.flush_websocket(token);
+                    }
+                }
+                Message::Event(token, event) => self.write_event_stream(token, &chunk(&event)),
             }
-        });
+        }
     }
-}
 
-fn accept_connection<'a>(
-    poll: &Poll,
-    token_id: &mut usize,
-    listener: &Listener<'a>,
-    connections: &mut HashMap<Token, Connection<'a>>,
-) -> bool {
-    match listener.accept() {
-        Ok((mut stream, _)) => {
+    /// Accepts the pending connections of the listener at `index`, and spreads them over the
+    /// event loops the server is configured to use.
+    fn accept_connections(&mut self, index: usize) {
+        loop {
+            let listener = &self.listeners[index];
+            let (mut stream, peer) = match listener.accept() {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    if e.kind() != std::io::ErrorKind::WouldBlock {
+                        log!(
+                            LogLevel::Error,
+                            LogFileType::Server,
+                            format!("Failed to accept. {e}")
+                        );
+                    }
+                    return;
+                }
+            };
+
+            let counted = match listener.connections.open(peer, &listener.config) {
+                Ok(counted) => counted,
+                Err(code) => {
+                    // Tell the client why before closing the connection. TLS clients can't be told
+                    // without a handshake, so they are just disconnected.
+                    if listener.tls.is_none() {
+                        let _ = serve_response(&mut stream, error(code, &listener.config));
+                    }
+                    continue;
+                }
+            };
+
             let linger_duration = match std::env::consts::OS {
                 "macos" => Some(Duration::from_millis(100)),
                 _ => None,
@@ -132,50 +975,698 @@ fn accept_connection<'a>(
             set_linger_option(&stream, linger_duration).expect("Failed to set linger option");
 
             if let Err(e) = stream.set_ttl(60) {
-                log!(LogFileType::Server, format!("Error: {e}"));
+                log!(LogLevel::Warn, LogFileType::Server, format!("{e}"));
             }
 
-            let connection_token = Token(*token_id);
-            *token_id += 1;
+            let stream = match Stream::new(stream, listener.tls.as_ref()) {
+                Ok(stream) => stream,
+                Err(e) => {
+                    log!(
+                        LogLevel::Warn,
+                        LogFileType::Server,
+                        format!("Failed to start TLS for {peer}. {e}")
+                    );
+                    continue;
+                }
+            };
+            log!(
+                LogLevel::Debug,
+                LogFileType::Client,
+                format!("Accepted connection from {peer}")
+            );
+            let connection = Connection::new(stream, peer, listener, counted);
+            let loops = listener.config.workers.clamp(1, self.workers.len() + 1);
+            let target = self.next_worker % loops;
+            self.next_worker = self.next_worker.wrapping_add(1);
+
+            if target == 0 {
+                self.add_connection(connection);
+            } else if !self.workers[target - 1].send(Message::Accepted(connection)) {
+                log!(
+                    LogLevel::Error,
+                    LogFileType::Server,
+                    format!("Event loop {target} has stopped. Dropped connection from {peer}")
+                );
+            }
+        }
+    }
 
-            poll.registry()
-                .register(&mut stream, connection_token, Interest::READABLE)
-                .expect("Failed to register new connection");
+    /// Registers a connection accepted by this or another event loop.
+    fn add_connection(&mut self, mut connection: Connection<'static>) {
+        let token = Token(self.token_id);
+        self.token_id += 1;
 
-            connections.insert(
-                connection_token,
-                Connection::new(stream, Arc::clone(&listener.config)),
+        if let Err(e) =
+            self.poll
+                .registry()
+                .register(&mut connection.stream, token, Interest::READABLE)
+        {
+            log!(
+                LogLevel::Error,
+                LogFileType::Server,
+                format!(
+                    "Failed to register connection from {}. {e}",
+                    connection.peer
+                )
             );
+            return;
+        }
 
-            true
+        connection.schedule(token, &self.poll, &mut self.deadlines);
+        self.connections.insert(token, connection);
+    }
+
+    /// Reads and handles requests until the client has to send more, or the connection should be closed.
+    fn read_connection(&mut self, token: Token) {
+        let connection = match self.connections.get_mut(&token) {
+            Some(connection) => connection,
+            None => return,
+        };
+        if connection.http2.is_some() {
+            self.read_http2(token);
+            return;
+        }
+        if connection.websocket.is_some() {
+            self.read_websocket(token);
+            return;
+        }
+        if connection.event_stream.is_some() {
+            self.read_event_stream(token);
+            return;
+        }
+        if connection.closing {
+            return;
+        }
+
+        let outcome = loop {
+            // The next request waits until the client took the last response
+            if connection.stream.has_pending() {
+                break Outcome::KeepOpen;
+            }
+            let state = connection
+                .reader
+                .read_from(&mut connection.stream, &connection.config);
+            connection.last_activity = Instant::now();
+
+            match state {
+                Ok(ReadState::Pending) => break Outcome::KeepOpen,
+                Ok(ReadState::Complete(head, body)) => {
+                    let config = &connection.config;
+                    if config.http2 && !self.draining {
+                        // Prior knowledge, or ALPN on a TLS connection
+                        if head == PREFACE_HEAD {
+                            let mut input = format!("{head}\r\n\r\n").into_bytes();
+                            input.extend(connection.reader.take_buffered());
+                            break Outcome::Http2(Box::new(Http2::new(config)), input, Vec::new());
+                        }
+
+                        let settings = match connection.stream.is_tls() {
+                            false => h2c_upgrade(&head),
+                            true => None,
+                        };
+                        if let Some(settings) = settings {
+                            let (http2, events) =
+                                match Http2::upgrade(config, &settings, (head, body)) {
+                                    Ok(upgraded) => upgraded,
+                                    Err(_) => {
+                                        let _ = serve_response(
+                                            &mut connection.stream,
+                                            error(StatusCode::BAD_REQUEST, config),
+                                        );
+                                        break Outcome::Close;
+                                    }
+                                };
+                            if write_all(&mut connection.stream, SWITCHING_PROTOCOLS).is_err() {
+                                break Outcome::Close;
+                            }
+                            let input = connection.reader.take_buffered();
+                            break Outcome::Http2(Box::new(http2), input, events);
+                        }
+                    }
+
+                    let keep_alive = !self.draining && keep_alive(&head, &connection.config);
+                    if connection.pool.is_some() {
+                        break Outcome::Handle((head, body), keep_alive);
+                    }
+                    if !connection.handle((head, body), &self.rate_limiter) {
+                        break Outcome::Close;
+                    }
+                    if connection.upgrade.is_some() {
+                        break Outcome::Upgrade;
+                    }
+                    if !keep_alive {
+                        break Outcome::Close;
+                    }
+                }
+                Ok(ReadState::Streaming(head)) => {
+                    // Only a tus part takes the body, so the connection closes after anything else
+                    if connection.pool.is_some() {
+                        break Outcome::Handle((head, Vec::new()), false);
+                    }
+                    let handled = connection.handle((head, Vec::new()), &self.rate_limiter);
+                    if !handled || connection.streaming.is_none() {
+                        break Outcome::Close;
+                    }
+                }
+                Ok(ReadState::BodyPart(part, complete)) => {
+                    if !connection.receive_body_part(&part, complete) || complete && self.draining {
+                        break Outcome::Close;
+                    }
+                }
+                Ok(ReadState::Rejected(code)) => {
+                    let response = connection.rejection(code);
+                    let _ = serve_response(&mut connection.stream, response);
+                    // Closing with unread data resets the connection, which can discard the response
+                    discard_input(&mut connection.stream);
+                    break Outcome::Close;
+                }
+                Ok(ReadState::Closed) => {
+                    if !connection.reader.is_empty() {
+                        log!(
+                            LogLevel::Warn,
+                            LogFileType::Client,
+                            format!("{} closed the connection mid-request", connection.peer)
+                        );
+                    }
+                    break Outcome::Close;
+                }
+                Err(e) => {
+                    log!(
+                        LogLevel::Warn,
+                        LogFileType::Client,
+                        format!("Error reading from client: {e}")
+                    );
+                    break Outcome::Close;
+                }
+            }
+        };
+
+        match outcome {
+            Outcome::KeepOpen => connection.schedule(token, &self.poll, &mut self.deadlines),
+            Outcome::Close => self.close(token),
+            Outcome::Handle(request, keep_alive) => self.handle_on_pool(token, request, keep_alive),
+            Outcome::Http2(http2, input, events) => {
+                connection.http2 = Some(http2);
+                connection.status.set_protocol("HTTP/2");
+                self.receive_http2(token, &input, events);
+            }
+            Outcome::Upgrade => self.open_upgrade(token),
         }
-        _ => false,
     }
-}
 
-fn handle_existing_connection(
-    poll: &Poll,
-    token: Token,
-    connections: &mut HashMap<Token, Connection>,
-) {
-    let connection = match connections.get_mut(&token) {
-        Some(connection) => connection,
-        None => return,
-    };
+    /// Switches a connection to the protocol its response asked for.
+    fn open_upgrade(&mut self, token: Token) {
+        let upgrade = self
+            .connections
+            .get_mut(&token)
+            .and_then(|connection| connection.upgrade.take());
+        match upgrade {
+            Some(Upgrade::WebSocket(upgrade)) => self.open_websocket(token, upgrade),
+            Some(Upgrade::EventStream(stream)) => self.open_event_stream(token, stream),
+            None => {}
+        }
+    }
+
+    /// Switches a connection to the WebSocket handler its response accepted, and adds it to
+    /// the `WebSocketHub` so messages can be sent to it from anywhere.
+    fn open_websocket(&mut self, token: Token, upgrade: Box<WebSocketUpgrade>) {
+        let connection = match self.connections.get_mut(&token) {
+            Some(connection) => connection,
+            None => return,
+        };
+
+        let mailbox = self.mailbox.clone();
+        let socket = websockets().register(&upgrade.scope, move |outgoing| {
+            mailbox.send(Message::WebSocket(token, outgoing))
+        });
+        let mut websocket =
+            WebSocketConnection::new(socket, upgrade.handler, connection.config.body_size_limit);
+        websocket.open(&upgrade.request);
+        connection.websocket = Some(Box::new(websocket));
+        connection.status.set_protocol("WebSocket");
+
+        // Frames the client sent right after the handshake
+        let input = connection.reader.take_buffered();
+        self.receive_websocket(token, &input);
+    }
+
+    /// Subscribes a connection to the channel of its event stream, and sends the events the
+    /// client missed since it last connected.
+    fn open_event_stream(&mut self, token: Token, stream: EventStream) {
+        let connection = match self.connections.get_mut(&token) {
+            Some(connection) => connection,
+            None => return,
+        };
+
+        let mailbox = self.mailbox.clone();
+        let (id, missed) = event_hub().subscribe(
+            &stream.channel,
+            stream.last_event_id.as_deref(),
+            move |event| mailbox.send(Message::Event(token, event.to_string().into_bytes())),
+        );
+        connection.event_stream = Some(Subscription {
+            channel: stream.channel,
+            id,
+        });
+        connection.status.set_protocol("EventStream");
+
+        let missed = missed
+            .iter()
+            .flat_map(|event| chunk(event.to_string().as_bytes()))
+            .collect::<Bytes>();
+        self.write_event_stream(token, &missed);
+    }
+
+    /// Clients don't send anything on an event stream, so only the end of the connection matters.
+    fn read_event_stream(&mut self, token: Token) {
+        let connection = match self.connections.get_mut(&token) {
+            Some(connection) => connection,
+            None => return,
+        };
+        let last_write = connection.last_activity;
+        match read_available(connection) {
+            Some(_) => {
+                // Reading doesn't delay the heartbeat
+                connection.last_activity = last_write;
+                connection.schedule(token, &self.poll, &mut self.deadlines);
+            }
+            None => close_connection(&self.poll, token, &mut self.connections),
+        }
+    }
+
+    /// Writes `bytes` to an event stream connection, or keeps them until the socket is writable.
+    /// Closes the connection if the client is gone or falls too far behind.
+    fn write_event_stream(&mut self, token: Token, bytes: &[u8]) {
+        let connection = match self.connections.get_mut(&token) {
+            Some(connection) if connection.event_stream.is_some() => connection,
+            _ => return,
+        };
+        if write_all(&mut connection.stream, bytes).is_err() {
+            close_connection(&self.poll, token, &mut self.connections);
+            return;
+        }
+        if connection.stream.pending_len() > MAX_BACKLOG {
+            log!(
+                LogLevel::Warn,
+                LogFileType::Client,
+                format!("{} fell behind on an event stream", connection.peer)
+            );
+            close_connection(&self.poll, token, &mut self.connections);
+            return;
+        }
+        connection.last_activity = Instant::now();
+        connection.schedule(token, &self.poll, &mut self.deadlines);
+    }
+
+    fn read_websocket(&mut self, token: Token) {
+        let connection = match self.connections.get_mut(&token) {
+            Some(connection) => connection,
+            None => return,
+        };
+        match read_available(connection) {
+            Some(input) => self.receive_websocket(token, &input),
+            None => {
+                if let Some(websocket) = connection.websocket.as_mut() {
+                    websocket.finish(ABNORMAL_CLOSURE, "");
+                }
+                close_connection(&self.poll, token, &mut self.connections);
+            }
+        }
+    }
+
+    fn receive_websocket(&mut self, token: Token, input: &[u8]) {
+        if let Some(websocket) = self
+            .connections
+            .get_mut(&token)
+            .and_then(|connection| connection.websocket.as_mut())
+        {
+            websocket.receive(input);
+            self.flush_websocket(token);
+        }
+    }
+
+    /// Sends the pending frames of a WebSocket connection, or keeps them until the socket is
+    /// writable. Closes the connection once the closing handshake is done.
+    fn flush_websocket(&mut self, token: Token) {
+        let connection = match self.connections.get_mut(&token) {
+            Some(connection) => connection,
+            None => return,
+        };
+        let websocket = match connection.websocket.as_mut() {
+            Some(websocket) => websocket,
+            None => return,
+        };
+
+        if write_all(&mut connection.stream, &websocket.take_output()).is_err() {
+            close_connection(&self.poll, token, &mut self.connections);
+            return;
+        }
+        // The close frame still has to reach the client
+        if websocket.is_closed() {
+            self.close(token);
+            return;
+        }
+        connection.schedule(token, &self.poll, &mut self.deadlines);
+    }
+
+    /// Reads what the client sent on an HTTP/2 connection.
+    fn read_http2(&mut self, token: Token) {
+        let connection = match self.connections.get_mut(&token) {
+            Some(connection) => connection,
+            None => return,
+        };
 
-    if let Err(e) = crate::server::handle_connection(&mut connection.stream, &connection.config) {
-        match e.kind() {
-            ErrorKind::WouldBlock => {
-                return; // Therefore, we keep the connection registered and return
+        match read_available(connection) {
+            Some(input) => self.receive_http2(token, &input, Vec::new()),
+            None => close_connection(&self.poll, token, &mut self.connections),
+        }
+    }
+
+    /// Passes `input` to the HTTP/2 connection, handles the requests that are complete and
+    /// sends what the connection has to send.
+    fn receive_http2(&mut self, token: Token, input: &[u8], mut events: Vec<Event>) {
+        let connection = match self.connections.get_mut(&token) {
+            Some(connection) => connection,
+            None => return,
+        };
+        let http2 = match connection.http2.as_mut() {
+            Some(http2) => http2,
+            None => return,
+        };
+
+        match http2.receive(input) {
+            Ok(received) => events.extend(received),
+            Err(code) => {
+                log!(
+                    LogLevel::Warn,
+                    LogFileType::Client,
+                    format!("HTTP/2 connection error from {}. {code}", connection.peer)
+                );
+                let _ = write_all(&mut connection.stream, &http2.take_output());
+                self.close(token);
+                return;
+            }
+        }
+
+        for event in events {
+            self.handle_stream(token, event);
+        }
+        self.flush_http2(token);
+    }
+
+    /// Responds to a request on a stream of an HTTP/2 connection, on the `HandlerPool` of the
+    /// server if it has one.
+    fn handle_stream(&mut self, token: Token, event: Event) {
+        let connection = match self.connections.get_mut(&token) {
+            Some(connection) => connection,
+            None => return,
+        };
+
+        let (id, request) = match event {
+            Event::Request(id, request) => (id, request),
+            Event::Rejected(id, code) => {
+                let response = connection.rejection(code);
+                if let Some(http2) = connection.http2.as_mut() {
+                    http2.send_response(id, response);
+                }
+                return;
+            }
+        };
+        let client = connection.client();
+
+        let pool = match connection.pool.clone() {
+            Some(pool) => pool,
+            None => {
+                let response = respond(request, &client, &connection.config, &self.rate_limiter);
+                if let Some(http2) = connection.http2.as_mut() {
+                    http2.send_response(id, response);
+                }
+                connection.requests_served += 1;
+                return;
             }
-            _ => log!(LogFileType::Client, format!("Error handling client: {e}")),
+        };
+        let config = Arc::clone(&connection.config);
+        let mailbox = self.mailbox.clone();
+        let rate_limiter = self.rate_limiter.clone();
+
+        let queued = pool.execute(move || {
+            let response = panic::catch_unwind(AssertUnwindSafe(|| {
+                respond(request, &client, &config, &rate_limiter)
+            }))
+            .unwrap_or_else(|_| {
+                log!(
+                    LogLevel::Error,
+                    LogFileType::Server,
+                    format!("Handler panicked on request from {}", client.peer)
+                );
+                error(StatusCode::INTERNAL_SERVER_ERROR, &config)
+            });
+            mailbox.send(Message::Responded(token, id, response));
+        });
+
+        if queued {
+            self.handling += 1;
+        } else if let Some(http2) = connection.http2.as_mut() {
+            http2.send_response(
+                id,
+                error(StatusCode::SERVICE_UNAVAILABLE, &connection.config),
+            );
         }
     }
 
-    poll.registry()
-        .deregister(&mut connection.stream)
-        .expect("Failed to deregister stream");
-    connections.remove(&token);
+    /// Sends the pending frames of an HTTP/2 connection, or keeps them until the socket is
+    /// writable. Closes the connection once it is done, and tells the client to stop if the
+    /// server is shutting down.
+    fn flush_http2(&mut self, token: Token) {
+        let connection = match self.connections.get_mut(&token) {
+            Some(connection) => connection,
+            None => return,
+        };
+        let http2 = match connection.http2.as_mut() {
+            Some(http2) => http2,
+            None => return,
+        };
+
+        if self.draining {
+            http2.go_away();
+        }
+        let written = write_all(&mut connection.stream, &http2.take_output());
+
+        if let Err(e) = &written {
+            log!(
+                LogLevel::Warn,
+                LogFileType::Client,
+                format!("Error writing to client: {e}")
+            );
+        }
+        if written.is_err() {
+            close_connection(&self.poll, token, &mut self.connections);
+            return;
+        }
+        if http2.is_closed() {
+            self.close(token);
+            return;
+        }
+        connection.schedule(token, &self.poll, &mut self.deadlines);
+    }
+
+    /// Hands the connection to the `HandlerPool` of its server to handle `request`. The connection
+    /// stays registered and comes back through the mailbox once the response is sent.
+    fn handle_on_pool(&mut self, token: Token, request: (String, Bytes), keep_alive: bool) {
+        let mut connection = match self.connections.remove(&token) {
+            Some(connection) => connection,
+            None => return,
+        };
+        let pool = match connection.pool.clone() {
+            Some(pool) => pool,
+            None => return,
+        };
+        let mailbox = self.mailbox.clone();
+        let rate_limiter = self.rate_limiter.clone();
+
+        let queued = pool.execute(move || {
+            let handled = panic::catch_unwind(AssertUnwindSafe(|| {
+                connection.handle(request, &rate_limiter)
+            }));
+            if handled.is_err() {
+                log!(
+                    LogLevel::Error,
+                    LogFileType::Server,
+                    format!("Handler panicked on request from {}", connection.peer)
+                );
+            }
+            // An accepted WebSocket handshake, an event stream or a streamed body keeps the
+            // connection open regardless
+            let keep_alive =
+                keep_alive || connection.upgrade.is_some() || connection.streaming.is_some();
+            let keep_open = keep_alive && matches!(handled, Ok(true));
+            mailbox.send(Message::Handled(token, connection, keep_open));
+        });
+        if queued {
+            self.handling += 1;
+        }
+    }
+
+    /// `next_deadline` drops deadlines of closed connections, and deadlines that were moved, until
+    /// the earliest current one is found.
+    fn next_deadline(&mut self) -> Option<Instant> {
+        while let Some((deadline, token)) = self.deadlines.peek() {
+            match self.connections.get(&token) {
+                Some(conn) if conn.deadline == Some(deadline) => return Some(deadline),
+                _ => {
+                    self.deadlines.pop();
+                }
+            }
+        }
+        None
+    }
+
+    fn handle_timeout(&mut self) {
+        let now = Instant::now();
+
+        while let Some((deadline, token)) = self.deadlines.pop_expired(now) {
+            let conn = match self.connections.get_mut(&token) {
+                Some(conn) if conn.deadline == Some(deadline) => conn,
+                _ => continue,
+            };
+
+            // The client didn't take the output in time
+            if conn.closing || conn.stream.has_pending() {
+                metrics().connection_timed_out();
+                close_connection(&self.poll, token, &mut self.connections);
+                continue;
+            }
+
+            // Nothing was sent on the event stream for a while
+            if conn.event_stream.is_some() {
+                self.write_event_stream(token, &chunk(HEARTBEAT));
+                continue;
+            }
+            metrics().connection_timed_out();
+
+            // The client didn't answer the close frame
+            if conn.websocket.is_some() {
+                close_connection(&self.poll, token, &mut self.connections);
+                continue;
+            }
+
+            // HTTP/2 clients are told the connection is closing
+            if let Some(http2) = conn.http2.as_mut() {
+                http2.go_away();
+                let _ = write_all(&mut conn.stream, &http2.take_output());
+                self.close(token);
+                continue;
+            }
+
+            let timeout = connection_deadline(
+                &conn.reader,
+                conn.last_activity,
+                conn.requests_served,
+                &conn.config,
+            )
+            .map(|(_, timeout)| timeout);
+
+            // Only a client that started a request is told why it was disconnected
+            if !conn.reader.is_empty() {
+                log!(
+                    LogLevel::Warn,
+                    LogFileType::Client,
+                    format!(
+                        "{} timed out ({}) before sending the request",
+                        conn.peer,
+                        timeout.map(|t| t.to_string()).unwrap_or_default()
+                    )
+                );
+                let response = conn.rejection(StatusCode::REQUEST_TIMEOUT);
+                let _ = serve_response(&mut conn.stream, response);
+            }
+
+            self.close(token);
+        }
+    }
+}
+
+/// `handler_pool` starts the handler threads of a server, if it has any.
+fn handler_pool(config: &ServerConfig) -> Option<Arc<HandlerPool>> {
+    (config.handler_threads > 0).then(|| Arc::new(HandlerPool::new(config.handler_threads)))
+}
+
+fn local_addrs(listeners: &[Listener]) -> Vec<SocketAddr> {
+    listeners
+        .iter()
+        .filter_map(|listener| listener.listener.local_addr().ok())
+        .collect()
+}
+
+/// `keep_alive` checks if the connection should stay open after responding to the request with `head`.
+/// Only HTTP/1.1 connections are kept alive, unless the client asked to close it.
+fn keep_alive(head: &str, config: &ServerConfig) -> bool {
+    let mut lines = head.split("\r\n");
+    let is_http_11 = lines
+        .next()
+        .is_some_and(|line| line.trim_end().ends_with("HTTP/1.1"));
+
+    let close = lines
+        .filter_map(|line| line.split_once(':'))
+        .filter(|(key, _)| key.trim().eq_ignore_ascii_case("connection"))
+        .any(|(_, value)| {
+            value
+                .split(',')
+                .any(|option| option.trim().eq_ignore_ascii_case("close"))
+        });
+
+    config.keep_alive_timeout.is_some() && is_http_11 && !close
+}
+
+/// `close_connection` deregisters and drops the connection, without waiting for its pending
+/// output. A failure to deregister is only logged, since the stream is closed when dropped anyway.
+fn close_connection(poll: &Poll, token: Token, connections: &mut HashMap<Token, Connection>) {
+    if let Some(mut connection) = connections.remove(&token) {
+        connection.end();
+        if let Err(e) = poll.registry().deregister(&mut connection.stream) {
+            log!(
+                LogLevel::Error,
+                LogFileType::Server,
+                format!("Failed to deregister {}. {e}", connection.peer)
+            );
+        }
+    }
+}
+
+/// `read_available` reads what the client sent until the stream would block. `None` if the
+/// connection was closed.
+fn read_available(connection: &mut Connection) -> Option<Bytes> {
+    let mut input = Vec::new();
+    let mut buffer = [0; BUFFER_SIZE];
+    let closed = loop {
+        match connection.stream.read(&mut buffer) {
+            Ok(0) => break true,
+            Ok(n) => input.extend_from_slice(&buffer[..n]),
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break false,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                log!(
+                    LogLevel::Warn,
+                    LogFileType::Client,
+                    format!("Error reading from client: {e}")
+                );
+                break true;
+            }
+        }
+    };
+    connection.last_activity = Instant::now();
+    (!closed).then_some(input)
+}
+
+/// `discard_input` reads and drops what the client already sent, up to a limit.
+fn discard_input(stream: &mut Stream) {
+    let mut buffer = [0; BUFFER_SIZE];
+    for _ in 0..64 {
+        match stream.read(&mut buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+    }
 }
 
 use crate::log;
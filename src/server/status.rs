@@ -0,0 +1,436 @@
+use crate::log::recent_errors;
+use crate::server::{metrics, Bytes, Listener, Request, Response, ServerConfig, StatusCode};
+use http::header::{ACCEPT, CONTENT_TYPE, HOST};
+use http::Method;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant};
+
+/// # ServerSummary
+///
+/// A running server as the status page shows it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServerSummary {
+    pub host: String,
+    /// Ports the server listens on, and if they accept TLS.
+    pub ports: Vec<(u16, bool)>,
+    /// Paths of the routes, with their methods.
+    pub routes: Vec<(String, Vec<String>)>,
+}
+
+impl ServerSummary {
+    fn new(config: &ServerConfig) -> Self {
+        Self {
+            host: config.host.to_string(),
+            ports: Vec::new(),
+            routes: config
+                .routes
+                .iter()
+                .map(|route| {
+                    let methods = route.methods.iter().map(Method::to_string).collect();
+                    (route.url_path.to_string(), methods)
+                })
+                .collect(),
+        }
+    }
+}
+
+/// # ConnectionSummary
+///
+/// An open connection as the status page shows it.
+#[derive(Clone, Debug)]
+pub struct ConnectionSummary {
+    pub peer: SocketAddr,
+    pub host: String,
+    /// Port the connection was accepted on.
+    pub port: u16,
+    pub tls: bool,
+    /// `HTTP/1.1`, `HTTP/2`, `WebSocket` or `EventStream`.
+    pub protocol: &'static str,
+    pub opened: Instant,
+}
+
+/// # StatusBoard
+///
+/// The servers and connections of all event loops, for the status page.
+#[derive(Debug)]
+pub struct StatusBoard {
+    started: Instant,
+    next_id: AtomicUsize,
+    /// Servers by the event loop that registered them.
+    servers: Mutex<BTreeMap<usize, Vec<ServerSummary>>>,
+    connections: Mutex<BTreeMap<usize, ConnectionSummary>>,
+}
+
+/// Locks `mutex`, even if another thread panicked while holding it.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl StatusBoard {
+    fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            next_id: AtomicUsize::new(0),
+            servers: Mutex::new(BTreeMap::new()),
+            connections: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Time since the servers started.
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Lists the servers of `listeners` until the returned registration is dropped.
+    pub(crate) fn register(&'static self, listeners: &[Listener]) -> ServerRegistration {
+        let registration = ServerRegistration {
+            board: self,
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+        };
+        registration.update(listeners);
+        registration
+    }
+
+    /// Lists a connection until the returned entry is dropped.
+    pub fn connection_opened(&'static self, connection: ConnectionSummary) -> ConnectionEntry {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        lock(&self.connections).insert(id, connection);
+        ConnectionEntry { board: self, id }
+    }
+
+    /// The running servers, in the order they were started.
+    pub fn servers(&self) -> Vec<ServerSummary> {
+        lock(&self.servers).values().flatten().cloned().collect()
+    }
+
+    /// The open connections, oldest first.
+    pub fn connections(&self) -> Vec<ConnectionSummary> {
+        lock(&self.connections).values().cloned().collect()
+    }
+}
+
+/// # status_board
+///
+/// The status of all servers.
+pub fn status_board() -> &'static StatusBoard {
+    static STATUS_BOARD: OnceLock<StatusBoard> = OnceLock::new();
+    STATUS_BOARD.get_or_init(StatusBoard::new)
+}
+
+/// # ServerRegistration
+///
+/// Lists the servers of an event loop on the `StatusBoard` until it is dropped.
+#[derive(Debug)]
+pub struct ServerRegistration {
+    board: &'static StatusBoard,
+    id: usize,
+}
+
+impl ServerRegistration {
+    /// Replaces the listed servers with those of `listeners`, after a reload.
+    pub(crate) fn update(&self, listeners: &[Listener]) {
+        let mut servers: Vec<(&Arc<ServerConfig>, ServerSummary)> = Vec::new();
+        for listener in listeners {
+            let index = match servers
+                .iter()
+                .position(|(config, _)| Arc::ptr_eq(config, &listener.config))
+            {
+                Some(index) => index,
+                None => {
+                    servers.push((&listener.config, ServerSummary::new(&listener.config)));
+                    servers.len() - 1
+                }
+            };
+            if let Ok(addr) = listener.listener.local_addr() {
+                servers[index]
+                    .1
+                    .ports
+                    .push((addr.port(), listener.tls.is_some()));
+            }
+        }
+        let servers = servers.into_iter().map(|(_, summary)| summary).collect();
+        lock(&self.board.servers).insert(self.id, servers);
+    }
+}
+
+impl Drop for ServerRegistration {
+    fn drop(&mut self) {
+        lock(&self.board.servers).remove(&self.id);
+    }
+}
+
+/// # ConnectionEntry
+///
+/// Lists a connection on the `StatusBoard` until it is dropped.
+#[derive(Debug)]
+pub struct ConnectionEntry {
+    board: &'static StatusBoard,
+    id: usize,
+}
+
+impl ConnectionEntry {
+    /// Shows the connection as switched to `protocol`.
+    pub fn set_protocol(&self, protocol: &'static str) {
+        if let Some(connection) = lock(&self.board.connections).get_mut(&self.id) {
+            connection.protocol = protocol;
+        }
+    }
+}
+
+impl Drop for ConnectionEntry {
+    fn drop(&mut self) {
+        lock(&self.board.connections).remove(&self.id);
+    }
+}
+
+/// # health_check
+///
+/// Handler for liveness checks. It answers `200 OK` as long as the server serves requests.
+pub fn health_check(
+    req: &Request<Bytes>,
+    conf: &ServerConfig,
+) -> Result<Response<Bytes>, StatusCode> {
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+        return Err(StatusCode::METHOD_NOT_ALLOWED);
+    }
+    Response::builder()
+        .version(req.version())
+        .header(HOST, conf.host)
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(Bytes::from("ok\n"))
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// # server_status
+///
+/// Handler for the status page, with the uptime, servers, routes, open connections, request
+/// totals and recent errors. It is JSON for `?format=json` or an `Accept` header that asks for
+/// JSON, and HTML otherwise.
+pub fn server_status(
+    req: &Request<Bytes>,
+    conf: &ServerConfig,
+) -> Result<Response<Bytes>, StatusCode> {
+    if req.method() != Method::GET && req.method() != Method::HEAD {
+        return Err(StatusCode::METHOD_NOT_ALLOWED);
+    }
+    let status = status_json();
+    let (content_type, body) = if wants_json(req) {
+        ("application/json", status.to_string())
+    } else {
+        ("text/html; charset=utf-8", status_html(&status))
+    };
+
+    Response::builder()
+        .version(req.version())
+        .header(HOST, conf.host)
+        .status(StatusCode::OK)
+        .header(CONTENT_TYPE, content_type)
+        .header("cache-control", "no-store")
+        .body(Bytes::from(body))
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+fn wants_json(req: &Request<Bytes>) -> bool {
+    let query = req.uri().query().unwrap_or_default();
+    if query.split('&').any(|pair| pair == "format=json") {
+        return true;
+    }
+    req.headers()
+        .get(ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| {
+            accept
+                .split(',')
+                .next()
+                .is_some_and(|first| first.trim().starts_with("application/json"))
+        })
+}
+
+/// # status_json
+///
+/// The contents of the status page.
+pub fn status_json() -> Value {
+    let board = status_board();
+    let servers = board
+        .servers()
+        .into_iter()
+        .map(|server| {
+            json!({
+                "host": server.host,
+                "ports": server.ports.iter().map(|(port, tls)| json!({ "port": port, "tls": tls })).collect::<Vec<_>>(),
+                "routes": server.routes.iter().map(|(path, methods)| json!({ "path": path, "methods": methods })).collect::<Vec<_>>(),
+            })
+        })
+        .collect::<Vec<_>>();
+    let connections = board
+        .connections()
+        .into_iter()
+        .map(|connection| {
+            json!({
+                "peer": connection.peer.to_string(),
+                "host": connection.host,
+                "port": connection.port,
+                "tls": connection.tls,
+                "protocol": connection.protocol,
+                "open_seconds": connection.opened.elapsed().as_secs(),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let by_status = metrics().requests_by_status();
+    let errors = recent_errors()
+        .into_iter()
+        .map(|error| {
+            json!({
+                "time": error.time.to_rfc3339(),
+                "message": error.message,
+                "request_id": error.request_id,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    json!({
+        "uptime_seconds": board.uptime().as_secs(),
+        "servers": servers,
+        "connections": {
+            "open": connections.len(),
+            "list": connections,
+        },
+        "requests": {
+            "total": by_status.values().sum::<u64>(),
+            "by_status": by_status
+                .iter()
+                .map(|(status, count)| (status.to_string(), json!(count)))
+                .collect::<serde_json::Map<_, _>>(),
+        },
+        "recent_errors": errors,
+    })
+}
+
+/// The status page in HTML, from the contents of `status_json`.
+fn status_html(status: &Value) -> String {
+    let text = |value: &Value| match value {
+        Value::String(s) => escape_html(s),
+        Value::Null => "-".to_string(),
+        other => escape_html(&other.to_string()),
+    };
+    let rows = |values: &Value, columns: &[&str]| {
+        let mut rows = String::new();
+        for value in values.as_array().into_iter().flatten() {
+            rows.push_str("<tr>");
+            for column in columns {
+                let _ = write!(rows, "<td>{}</td>", text(&value[column]));
+            }
+            rows.push_str("</tr>\n");
+        }
+        rows
+    };
+
+    let mut servers = String::new();
+    for server in status["servers"].as_array().into_iter().flatten() {
+        let ports = server["ports"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|port| match port["tls"].as_bool() {
+                Some(true) => format!("{} (TLS)", port["port"]),
+                _ => port["port"].to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let routes = server["routes"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|route| {
+                let methods = route["methods"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .map(&text)
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                format!("<li><code>{}</code> {methods}</li>", text(&route["path"]))
+            })
+            .collect::<String>();
+        let _ = write!(
+            servers,
+            "<h3>{} on {ports}</h3>\n<ul>{routes}</ul>\n",
+            text(&server["host"])
+        );
+    }
+
+    let by_status = status["requests"]["by_status"]
+        .as_object()
+        .into_iter()
+        .flatten()
+        .map(|(code, count)| format!("<tr><td>{}</td><td>{count}</td></tr>\n", escape_html(code)))
+        .collect::<String>();
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="UTF-8">
+    <title>Server status</title>
+    <style>
+        body {{ font-family: sans-serif; margin: 2em; }}
+        table {{ border-collapse: collapse; }}
+        td, th {{ border: 1px solid #ccc; padding: 0.2em 0.6em; text-align: left; }}
+    </style>
+</head>
+<body>
+<h1>Server status</h1>
+<p>Up for {uptime}</p>
+<h2>Servers</h2>
+{servers}
+<h2>Connections ({open} open)</h2>
+<table>
+<tr><th>Client</th><th>Server</th><th>Port</th><th>TLS</th><th>Protocol</th><th>Open (s)</th></tr>
+{connections}</table>
+<h2>Requests ({total} in total)</h2>
+<table>
+<tr><th>Status</th><th>Requests</th></tr>
+{by_status}</table>
+<h2>Recent errors</h2>
+<table>
+<tr><th>Time</th><th>Request</th><th>Message</th></tr>
+{errors}</table>
+</body>
+</html>"#,
+        uptime = format_uptime(status["uptime_seconds"].as_u64().unwrap_or_default()),
+        open = status["connections"]["open"],
+        connections = rows(
+            &status["connections"]["list"],
+            &["peer", "host", "port", "tls", "protocol", "open_seconds"]
+        ),
+        total = status["requests"]["total"],
+        errors = rows(&status["recent_errors"], &["time", "request_id", "message"]),
+    )
+}
+
+/// Uptime like `2d 3h 4m 5s`.
+fn format_uptime(seconds: u64) -> String {
+    let (days, hours) = (seconds / 86_400, seconds / 3_600 % 24);
+    let (minutes, seconds) = (seconds / 60 % 60, seconds % 60);
+    match (days, hours, minutes) {
+        (0, 0, 0) => format!("{seconds}s"),
+        (0, 0, _) => format!("{minutes}m {seconds}s"),
+        (0, _, _) => format!("{hours}h {minutes}m {seconds}s"),
+        _ => format!("{days}d {hours}h {minutes}m {seconds}s"),
+    }
+}
+
+/// Escapes text for HTML.
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,103 @@
+use crate::server::{RequestReader, ServerConfig, Token};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fmt;
+use std::time::Instant;
+
+/// # Timeout
+///
+/// The timeouts of a connection, configured per server.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Timeout {
+    /// Nothing was received for `idle_timeout`.
+    Idle,
+    /// The request line and headers were not received within `header_timeout`.
+    Header,
+    /// The body was not received within `body_timeout` after the headers.
+    Body,
+    /// No new request was started within `keep_alive_timeout` after the last response.
+    KeepAlive,
+}
+
+impl fmt::Display for Timeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Timeout::Idle => "idle",
+            Timeout::Header => "header",
+            Timeout::Body => "body",
+            Timeout::KeepAlive => "keep-alive",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// # connection_deadline
+///
+/// Gets the first timeout of a connection that last received bytes at `last_activity`.
+/// Between requests on a kept alive connection only the keep-alive timeout applies.
+pub fn connection_deadline(
+    reader: &RequestReader,
+    last_activity: Instant,
+    requests_served: usize,
+    config: &ServerConfig,
+) -> Option<(Instant, Timeout)> {
+    if reader.is_empty() && requests_served > 0 {
+        return config
+            .keep_alive_timeout
+            .map(|timeout| (last_activity + timeout, Timeout::KeepAlive));
+    }
+
+    let idle = config
+        .idle_timeout
+        .map(|timeout| (last_activity + timeout, Timeout::Idle));
+
+    match (idle, reader.deadline(config)) {
+        (Some(idle), Some(request)) => Some(if request.0 < idle.0 { request } else { idle }),
+        (idle, request) => idle.or(request),
+    }
+}
+
+/// # Deadlines
+///
+/// Min-heap of connection deadlines. Entries are never removed when a deadline moves,
+/// so the owner has to check that a popped deadline is still the current one.
+#[derive(Debug, Default)]
+pub struct Deadlines {
+    heap: BinaryHeap<Reverse<(Instant, Token)>>,
+}
+
+impl Deadlines {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn schedule(&mut self, token: Token, deadline: Instant) {
+        self.heap.push(Reverse((deadline, token)));
+    }
+
+    /// The earliest deadline, current or not.
+    pub fn peek(&self) -> Option<(Instant, Token)> {
+        self.heap.peek().map(|Reverse(entry)| *entry)
+    }
+
+    /// Removes the earliest deadline if it is at or before `now`.
+    pub fn pop_expired(&mut self, now: Instant) -> Option<(Instant, Token)> {
+        match self.peek() {
+            Some((deadline, _)) if deadline <= now => self.heap.pop().map(|Reverse(entry)| entry),
+            _ => None,
+        }
+    }
+
+    /// Removes the earliest deadline.
+    pub fn pop(&mut self) -> Option<(Instant, Token)> {
+        self.heap.pop().map(|Reverse(entry)| entry)
+    }
+
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+}
@@ -0,0 +1,321 @@
+use crate::server::{Bytes, Request, ServerConfig, TcpStream};
+use crate::server_config::Certificate;
+use crate::type_aliases::Port;
+use http::header::HOST;
+use mio::event::Source;
+use mio::{Interest, Registry, Token};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::ServerConnection;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::sync::Arc;
+
+/// # TlsInfo
+///
+/// Request extension for requests received over TLS.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TlsInfo {
+    /// Host name the client asked for with SNI.
+    pub server_name: Option<String>,
+}
+
+/// # Stream
+///
+/// A client connection, with or without TLS. Writes never block: what the socket does not
+/// take right away is kept and sent with `send_pending` once the socket is writable again.
+#[derive(Debug)]
+pub struct Stream {
+    sock: TcpStream,
+    tls: Option<Box<ServerConnection>>,
+    /// Bytes the socket did not take yet. TLS records on a TLS connection.
+    pending: Bytes,
+}
+
+impl Stream {
+    /// Starts a TLS connection on `stream` if the listener has a TLS configuration.
+    pub fn new(stream: TcpStream, tls: Option<&Arc<rustls::ServerConfig>>) -> io::Result<Self> {
+        let tls = match tls {
+            Some(config) => {
+                let mut conn =
+                    ServerConnection::new(Arc::clone(config)).map_err(io::Error::other)?;
+                // Output is kept in `pending` instead, where it is limited by the event loop
+                conn.set_buffer_limit(None);
+                Some(Box::new(conn))
+            }
+            None => None,
+        };
+        Ok(Self {
+            sock: stream,
+            tls,
+            pending: Vec::new(),
+        })
+    }
+
+    pub fn tcp(&self) -> &TcpStream {
+        &self.sock
+    }
+
+    pub fn is_tls(&self) -> bool {
+        self.tls.is_some()
+    }
+
+    /// Details of the TLS connection, if it is one.
+    pub fn tls_info(&self) -> Option<TlsInfo> {
+        self.tls.as_ref().map(|conn| TlsInfo {
+            server_name: conn.server_name().map(str::to_string),
+        })
+    }
+
+    /// Tells a TLS client that no more data will be sent, before the connection is closed.
+    pub fn close(&mut self) {
+        if let Some(conn) = self.tls.as_mut() {
+            conn.send_close_notify();
+            let _ = self.send_tls();
+        }
+    }
+
+    /// Returns `true` while written bytes wait for the socket to become writable.
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Number of written bytes waiting for the socket.
+    pub fn pending_len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Sends the pending bytes until the socket would block.
+    pub fn send_pending(&mut self) -> io::Result<()> {
+        let mut sent = 0;
+        let result = loop {
+            if sent == self.pending.len() {
+                break Ok(());
+            }
+            match self.sock.write(&self.pending[sent..]) {
+                Ok(0) => break Err(io::ErrorKind::WriteZero.into()),
+                Ok(n) => sent += n,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => break Err(e),
+            }
+        };
+        self.pending.drain(..sent);
+        result
+    }
+
+    /// Moves the TLS records rustls has ready to `pending`, and sends what the socket takes.
+    fn send_tls(&mut self) -> io::Result<()> {
+        if let Some(conn) = self.tls.as_mut() {
+            while conn.wants_write() {
+                conn.write_tls(&mut self.pending)?;
+            }
+        }
+        self.send_pending()
+    }
+}
+
+impl Read for Stream {
+    /// Reads decrypted bytes on a TLS connection. The handshake is done as part of reading.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let conn = match self.tls.as_mut() {
+                Some(conn) => conn,
+                None => return self.sock.read(buf),
+            };
+            match conn.reader().read(buf) {
+                Ok(n) => return Ok(n),
+                // No decrypted bytes yet
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e),
+            }
+
+            if conn.read_tls(&mut self.sock)? == 0 {
+                return Ok(0);
+            }
+            let processed = conn.process_new_packets();
+
+            // Send handshake messages, or the alert if the records were rejected. What the
+            // socket doesn't take is sent once it is writable.
+            self.send_tls()?;
+            processed.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        }
+    }
+}
+
+impl Write for Stream {
+    /// Takes all of `buf`. What the socket doesn't take now stays pending.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(conn) = self.tls.as_mut() {
+            conn.writer().write_all(buf)?;
+            self.send_tls()?;
+            return Ok(buf.len());
+        }
+
+        // Keep the order of the bytes if some are still pending
+        let mut sent = 0;
+        if self.pending.is_empty() {
+            while sent < buf.len() {
+                match self.sock.write(&buf[sent..]) {
+                    Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                    Ok(n) => sent += n,
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        self.pending.extend_from_slice(&buf[sent..]);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send_tls()
+    }
+}
+
+impl Source for Stream {
+    fn register(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        self.sock.register(registry, token, interests)
+    }
+
+    fn reregister(
+        &mut self,
+        registry: &Registry,
+        token: Token,
+        interests: Interest,
+    ) -> io::Result<()> {
+        self.sock.reregister(registry, token, interests)
+    }
+
+    fn deregister(&mut self, registry: &Registry) -> io::Result<()> {
+        self.sock.deregister(registry)
+    }
+}
+
+/// Picks a certificate by the SNI host name. Wildcard names match one label.
+#[derive(Debug)]
+struct CertificateResolver {
+    by_name: HashMap<String, Arc<CertifiedKey>>,
+    default: Arc<CertifiedKey>,
+}
+
+impl ResolvesServerCert for CertificateResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let name = client_hello.server_name().map(str::to_ascii_lowercase);
+        let wildcard = name
+            .as_deref()
+            .and_then(|name| name.split_once('.'))
+            .map(|(_, parent)| format!("*.{parent}"));
+
+        let key = [name, wildcard]
+            .into_iter()
+            .flatten()
+            .find_map(|name| self.by_name.get(&name))
+            .unwrap_or(&self.default);
+        Some(Arc::clone(key))
+    }
+}
+
+/// # tls_config
+///
+/// Loads the certificates of a server. `None` if the server has no TLS ports.
+pub fn tls_config(config: &ServerConfig) -> Result<Option<Arc<rustls::ServerConfig>>, String> {
+    let tls = match &config.tls {
+        Some(tls) if !tls.ports.is_empty() => tls,
+        _ => return Ok(None),
+    };
+
+    let mut by_name = HashMap::new();
+    let mut default = None;
+    for certificate in &tls.certificates {
+        let key = Arc::new(certified_key(certificate)?);
+        for name in &certificate.server_names {
+            by_name.insert(name.to_ascii_lowercase(), Arc::clone(&key));
+        }
+        default.get_or_insert(key);
+    }
+    let default = default.ok_or_else(|| format!("No certificates for TLS on {}", config.host))?;
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let mut server_config = rustls::ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?
+        .with_no_client_auth()
+        .with_cert_resolver(Arc::new(CertificateResolver { by_name, default }));
+    server_config.alpn_protocols = match config.http2 {
+        true => vec![b"h2".to_vec(), b"http/1.1".to_vec()],
+        false => vec![b"http/1.1".to_vec()],
+    };
+
+    Ok(Some(Arc::new(server_config)))
+}
+
+fn certified_key(certificate: &Certificate) -> Result<CertifiedKey, String> {
+    let open = |path: &str| {
+        File::open(path)
+            .map(BufReader::new)
+            .map_err(|e| format!("Unable to open {path}. {e}"))
+    };
+
+    let certs = rustls_pemfile::certs(&mut open(certificate.cert_path)?)
+        .collect::<Result<Vec<CertificateDer<'static>>, _>>()
+        .map_err(|e| format!("Invalid certificate in {}. {e}", certificate.cert_path))?;
+    if certs.is_empty() {
+        return Err(format!("No certificate in {}", certificate.cert_path));
+    }
+
+    let key: PrivateKeyDer<'static> = rustls_pemfile::private_key(&mut open(certificate.key_path)?)
+        .map_err(|e| format!("Invalid key in {}. {e}", certificate.key_path))?
+        .ok_or_else(|| format!("No private key in {}", certificate.key_path))?;
+    let key = rustls::crypto::ring::sign::any_supported_type(&key)
+        .map_err(|e| format!("Unsupported key in {}. {e}", certificate.key_path))?;
+
+    Ok(CertifiedKey::new(certs, key))
+}
+
+/// # is_tls_port
+///
+/// Checks if `port` of the server accepts TLS connections.
+pub fn is_tls_port(config: &ServerConfig, port: &Port) -> bool {
+    config
+        .tls
+        .as_ref()
+        .is_some_and(|tls| tls.ports.contains(port))
+}
+
+/// # https_redirect
+///
+/// Where to redirect a request that came in over plain HTTP, if the server redirects to HTTPS.
+pub fn https_redirect(request: &Request<Bytes>, config: &ServerConfig) -> Option<String> {
+    let tls = config.tls.as_ref().filter(|tls| tls.redirect_http)?;
+    let port = *tls.ports.first()?;
+
+    let host = request
+        .headers()
+        .get(HOST)
+        .and_then(|host| host.to_str().ok())
+        .unwrap_or(config.host);
+    // Drop the port of the Host header, but not the colons of an IPv6 address
+    let host = match host.rsplit_once(':') {
+        Some((name, p)) if !name.ends_with(':') && p.chars().all(|c| c.is_ascii_digit()) => name,
+        _ => host,
+    };
+    let path = request
+        .uri()
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or("/");
+
+    Some(match port {
+        443 => format!("https://{host}{path}"),
+        _ => format!("https://{host}:{port}{path}"),
+    })
+}
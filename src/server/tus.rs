@@ -0,0 +1,656 @@
+use crate::log;
+use crate::log::*;
+use crate::server::path::{add_root_to_path, path_exists};
+use crate::server::upload::store_file;
+use crate::server::{
+    sanitize_filename, Bytes, Method, Request, Response, ServerConfig, StatusCode,
+};
+use crate::server_config::route::{Route, Tus};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use http::header::{CACHE_CONTROL, CONTENT_LENGTH, HOST, LOCATION};
+use http::Version;
+use rand::RngCore;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// Version of the tus protocol that is supported.
+pub const TUS_VERSION: &str = "1.0.0";
+/// Extensions of the protocol that are supported.
+pub const TUS_EXTENSIONS: &str = "creation,creation-with-upload,termination,expiration";
+/// Content type of the parts of an upload.
+const OFFSET_CONTENT_TYPE: &str = "application/offset+octet-stream";
+/// Directory that holds the unfinished uploads of the routes, outside the directories they serve.
+const STATE_DIR: &str = "./.tus";
+/// Length of the IDs of uploads, in hex digits.
+const ID_LENGTH: usize = 32;
+
+/// # UploadInfo
+///
+/// What is known about an upload besides its data. It is stored next to the data, so uploads
+/// can be resumed after a restart.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UploadInfo {
+    /// Size of the whole upload, in bytes.
+    pub length: u64,
+    /// The `Upload-Metadata` header the upload was created with.
+    pub metadata: Option<String>,
+    pub created: DateTime<Utc>,
+    /// When the upload is removed if no more parts arrive.
+    pub expires: Option<DateTime<Utc>>,
+    /// Name the finished upload was stored as, in the directory of the route.
+    pub file: Option<String>,
+}
+
+impl UploadInfo {
+    fn to_json(&self) -> Value {
+        json!({
+            "length": self.length,
+            "metadata": self.metadata,
+            "created": self.created.to_rfc3339(),
+            "expires": self.expires.map(|expires| expires.to_rfc3339()),
+            "file": self.file,
+        })
+    }
+
+    fn from_json(value: &Value) -> Option<UploadInfo> {
+        let date = |value: &Value| {
+            DateTime::parse_from_rfc3339(value.as_str()?)
+                .ok()
+                .map(|date| date.with_timezone(&Utc))
+        };
+        Some(UploadInfo {
+            length: value["length"].as_u64()?,
+            metadata: value["metadata"].as_str().map(str::to_string),
+            created: date(&value["created"])?,
+            expires: date(&value["expires"]),
+            file: value["file"].as_str().map(str::to_string),
+        })
+    }
+
+    fn is_expired(&self) -> bool {
+        self.expires.is_some_and(|expires| expires <= Utc::now())
+    }
+}
+
+/// # parse_upload_metadata
+///
+/// The pairs of an `Upload-Metadata` header, like `filename d29ybGQ=,private`, with their
+/// values decoded from base64. Keys without a value get an empty value.
+pub fn parse_upload_metadata(header: &str) -> Option<Vec<(String, Bytes)>> {
+    let mut pairs: Vec<(String, Bytes)> = Vec::new();
+    for pair in header.split(',') {
+        let mut parts = pair.trim().split(' ');
+        let key = parts.next().filter(|key| !key.is_empty())?;
+        let value = match parts.next() {
+            Some(value) => STANDARD.decode(value).ok()?,
+            None => Vec::new(),
+        };
+        if parts.next().is_some() || pairs.iter().any(|(existing, _)| existing == key) {
+            return None;
+        }
+        pairs.push((key.to_string(), value));
+    }
+    Some(pairs)
+}
+
+/// # is_tus_request
+///
+/// Checks if a request to the route is part of the tus protocol: `OPTIONS` and `POST` to the
+/// route itself, or `HEAD`, `PATCH` and `DELETE` to an upload under it.
+pub fn is_tus_request(route: &Route, req: &Request<Bytes>) -> bool {
+    let tus = route
+        .settings
+        .as_ref()
+        .is_some_and(|settings| settings.tus.is_some());
+    let path = req.uri().path().trim_end_matches('/');
+    let url_path = route.url_path.trim_end_matches('/');
+
+    tus && match *req.method() {
+        Method::OPTIONS | Method::POST => path == url_path,
+        Method::HEAD | Method::PATCH | Method::DELETE => {
+            upload_id(route, req.uri().path()).is_some()
+        }
+        _ => false,
+    }
+}
+
+/// # streamed_part_length
+///
+/// The length of the body of a request, if it is a `PATCH` to an upload of a route with `tus`
+/// set. Such bodies are written to the upload as they arrive instead of being read first, so
+/// they are not limited by the `body_size_limit` of the server.
+pub fn streamed_part_length(head: &str, config: &ServerConfig) -> Option<usize> {
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next()?.split(' ');
+    if request_line.next()? != Method::PATCH.as_str() {
+        return None;
+    }
+    let path = request_line.next()?.split('?').next().unwrap_or_default();
+    let (index, _) = path_exists(path, &config.routes)?;
+    let route = &config.routes[index];
+    let tus = route
+        .settings
+        .as_ref()
+        .is_some_and(|settings| settings.tus.is_some());
+    // Handlers of the route answer before tus does
+    if !tus || route.handler.is_some() || route.websocket.is_some() {
+        return None;
+    }
+    upload_id(route, path)?;
+
+    let mut length = None;
+    for (key, value) in lines.filter_map(|line| line.split_once(':')) {
+        let (key, value) = (key.trim(), value.trim());
+        if key.eq_ignore_ascii_case("transfer-encoding") {
+            return None;
+        }
+        if key.eq_ignore_ascii_case("content-length") {
+            // Lengths that differ are rejected with the request
+            let value = value.parse::<usize>().ok()?;
+            if length.is_some_and(|length| length != value) {
+                return None;
+            }
+            length = Some(value);
+        }
+    }
+    length.filter(|length| *length > 0)
+}
+
+/// The ID of the upload in the request path, like `/uploads/<id>`.
+fn upload_id<'p>(route: &Route, path: &'p str) -> Option<&'p str> {
+    let id = path
+        .strip_prefix(route.url_path.trim_end_matches('/'))?
+        .strip_prefix('/')?;
+    let valid = id.len() == ID_LENGTH && id.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'));
+    valid.then_some(id)
+}
+
+/// Uploads that are being written, so a part is never appended twice at the same offset.
+fn busy_uploads() -> &'static Mutex<HashSet<PathBuf>> {
+    static BUSY: OnceLock<Mutex<HashSet<PathBuf>>> = OnceLock::new();
+    BUSY.get_or_init(Mutex::default)
+}
+
+/// Marks an upload as busy until it is dropped.
+#[derive(Debug)]
+struct BusyUpload(PathBuf);
+
+impl BusyUpload {
+    fn claim(data: &Path) -> Result<BusyUpload, StatusCode> {
+        let mut busy = busy_uploads().lock().unwrap_or_else(|e| e.into_inner());
+        match busy.insert(data.to_path_buf()) {
+            true => Ok(BusyUpload(data.to_path_buf())),
+            false => Err(StatusCode::LOCKED),
+        }
+    }
+}
+
+impl Drop for BusyUpload {
+    fn drop(&mut self) {
+        let mut busy = busy_uploads().lock().unwrap_or_else(|e| e.into_inner());
+        busy.remove(&self.0);
+    }
+}
+
+/// The files of an upload: its data and its info.
+#[derive(Clone, Debug)]
+struct UploadFiles {
+    /// Directory of the route, where finished uploads are stored.
+    dir: PathBuf,
+    data: PathBuf,
+    info: PathBuf,
+}
+
+impl UploadFiles {
+    fn new(route: &Route, id: &str) -> UploadFiles {
+        let state = state_dir(route);
+        UploadFiles {
+            data: state.join(format!("{id}.bin")),
+            info: state.join(format!("{id}.json")),
+            dir: route_dir(route),
+        }
+    }
+
+    /// The info of the upload, or `None` if there is no such upload or it expired.
+    fn load(&self) -> Option<UploadInfo> {
+        let info = fs::read(&self.info).ok()?;
+        let info = serde_json::from_slice(&info)
+            .ok()
+            .and_then(|info| UploadInfo::from_json(&info))?;
+        if info.is_expired() {
+            self.remove(&info);
+            return None;
+        }
+        Some(info)
+    }
+
+    /// Writes the info of the upload, replacing the old info at once.
+    fn save(&self, info: &UploadInfo) -> io::Result<()> {
+        let temp = self.info.with_extension("json.tmp");
+        let mut file = fs::File::create(&temp)?;
+        file.write_all(info.to_json().to_string().as_bytes())?;
+        file.sync_all()?;
+        fs::rename(temp, &self.info)
+    }
+
+    /// Bytes of the upload received so far.
+    fn offset(&self, info: &UploadInfo) -> u64 {
+        match info.file {
+            Some(_) => info.length,
+            None => fs::metadata(&self.data).map_or(0, |metadata| metadata.len()),
+        }
+    }
+
+    /// Removes the data and info of an unfinished upload, or the info of a finished one.
+    fn remove(&self, info: &UploadInfo) {
+        if info.file.is_none() {
+            let _ = fs::remove_file(&self.data);
+        }
+        let _ = fs::remove_file(&self.info);
+    }
+
+    /// Appends a part to the data of the upload.
+    fn append(&self, part: &[u8]) -> io::Result<()> {
+        let mut file = OpenOptions::new().append(true).open(&self.data)?;
+        file.write_all(part)?;
+        file.sync_data()
+    }
+
+    /// Opens the data of the upload to append a part as it arrives.
+    fn open_part(self, id: &str, info: UploadInfo, busy: BusyUpload) -> io::Result<TusPart> {
+        let data = OpenOptions::new().append(true).open(&self.data)?;
+        Ok(TusPart {
+            id: id.to_string(),
+            files: self,
+            info,
+            data: Arc::new(data),
+            _busy: Arc::new(busy),
+        })
+    }
+
+    /// Moves the data of a finished upload to the directory of the route, named by the
+    /// `filename` in its metadata.
+    fn finish(&self, id: &str, info: &mut UploadInfo) -> io::Result<()> {
+        let filename = info
+            .metadata
+            .as_deref()
+            .and_then(parse_upload_metadata)
+            .and_then(|metadata| {
+                let (_, value) = metadata.into_iter().find(|(key, _)| key == "filename")?;
+                sanitize_filename(&String::from_utf8(value).ok()?)
+            })
+            .unwrap_or_else(|| id.to_string());
+
+        fs::create_dir_all(&self.dir)?;
+        let stored = store_file(&self.data, &self.dir, &filename)?;
+        info.file = Some(stored);
+        self.save(info)?;
+        log!(
+            LogLevel::Info,
+            LogFileType::Server,
+            format!(
+                "Finished upload {id} ({} bytes), stored as {}",
+                info.length,
+                self.dir
+                    .join(info.file.as_deref().unwrap_or_default())
+                    .display()
+            )
+        );
+        Ok(())
+    }
+}
+
+/// # TusPart
+///
+/// A part of an upload whose body is still arriving. The connection writes the body with
+/// `write` as it is read, and calls `finish` before it sends the response the part came with.
+/// The upload stays busy until the part is dropped, and what was written is kept if the client
+/// leaves before the end.
+#[derive(Clone, Debug)]
+pub struct TusPart {
+    id: String,
+    files: UploadFiles,
+    info: UploadInfo,
+    data: Arc<File>,
+    _busy: Arc<BusyUpload>,
+}
+
+impl TusPart {
+    /// Appends `bytes` to the data of the upload.
+    pub fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        (&*self.data).write_all(bytes)
+    }
+
+    /// Syncs the part to disk and renews the expiration of the upload, and stores the upload
+    /// once it is complete.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.data.sync_data()?;
+        self.files.save(&self.info)?;
+        if self.files.offset(&self.info) == self.info.length {
+            self.files.finish(&self.id, &mut self.info)?;
+        }
+        Ok(())
+    }
+}
+
+/// Directory of the route, under its root.
+fn route_dir(route: &Route) -> PathBuf {
+    PathBuf::from(add_root_to_path(
+        route,
+        route.url_path.trim_end_matches('/'),
+    ))
+}
+
+/// Directory of the unfinished uploads of the route, like `./.tus/uploads` for `/uploads`.
+fn state_dir(route: &Route) -> PathBuf {
+    Path::new(STATE_DIR).join(route.url_path.trim_matches('/'))
+}
+
+/// Removes the uploads of the route that expired.
+fn remove_expired(route: &Route) {
+    let entries = match fs::read_dir(state_dir(route)) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let id = match name.strip_suffix(".json") {
+            Some(id) if id.len() == ID_LENGTH => id,
+            _ => continue,
+        };
+        let files = UploadFiles::new(route, id);
+        // Uploads being written are not expired
+        if let Ok(_busy) = BusyUpload::claim(&files.data) {
+            files.load();
+        }
+    }
+}
+
+fn tus_header<'r>(req: &'r Request<Bytes>, name: &str) -> Option<&'r str> {
+    req.headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+}
+
+/// The `Upload-Expires` header value of an upload.
+fn expires_header(info: &UploadInfo) -> Option<String> {
+    let expires = info.expires.filter(|_| info.file.is_none())?;
+    Some(expires.format("%a, %d %b %Y %H:%M:%S GMT").to_string())
+}
+
+fn respond(
+    req: &Request<Bytes>,
+    config: &ServerConfig,
+    status: StatusCode,
+) -> http::response::Builder {
+    Response::builder()
+        .version(req.version())
+        .header(HOST, config.host)
+        .header("Tus-Resumable", TUS_VERSION)
+        .status(status)
+}
+
+fn storage_error(e: io::Error) -> StatusCode {
+    log!(
+        LogLevel::Error,
+        LogFileType::Server,
+        format!("Failed to store resumable upload. {e}")
+    );
+    StatusCode::INTERNAL_SERVER_ERROR
+}
+
+/// # handle_tus
+///
+/// Responds to a request of the tus protocol to a route with `tus` set. Unfinished uploads are
+/// kept in `./.tus`, outside the directories the routes serve, their data and info in separate
+/// files. Finished uploads are moved to the directory of the route, named by their `filename`
+/// metadata.
+///
+/// The body of a `PATCH` is not read with the request: the response carries a `TusPart`, and
+/// the connection appends the body to the upload as it arrives and sends the response once the
+/// part is synced. Parts are only limited by the length of the upload, and the bytes of a part
+/// that is cut off are kept, so the client resumes at the offset of `HEAD`.
+pub fn handle_tus(
+    route: &Route,
+    tus: &Tus,
+    req: &Request<Bytes>,
+    config: &ServerConfig,
+) -> Result<Response<Bytes>, StatusCode> {
+    if req.method() == Method::OPTIONS {
+        let mut response = respond(req, config, StatusCode::NO_CONTENT)
+            .header("Tus-Version", TUS_VERSION)
+            .header("Tus-Extension", TUS_EXTENSIONS);
+        if let Some(max_size) = tus.max_size {
+            response = response.header("Tus-Max-Size", max_size);
+        }
+        return response
+            .body(vec![])
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    if tus_header(req, "tus-resumable") != Some(TUS_VERSION) {
+        return respond(req, config, StatusCode::PRECONDITION_FAILED)
+            .header("Tus-Version", TUS_VERSION)
+            .body(vec![])
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    match *req.method() {
+        Method::POST => create(route, tus, req, config),
+        Method::HEAD => offset(route, req, config),
+        Method::PATCH => append(route, tus, req, config),
+        Method::DELETE => terminate(route, req, config),
+        _ => Err(StatusCode::METHOD_NOT_ALLOWED),
+    }
+}
+
+/// Checks that the body of a request is a part of an upload.
+fn check_part(req: &Request<Bytes>) -> Result<(), StatusCode> {
+    let content_type = tus_header(req, "content-type").unwrap_or_default();
+    match content_type.eq_ignore_ascii_case(OFFSET_CONTENT_TYPE) {
+        true => Ok(()),
+        false => Err(StatusCode::UNSUPPORTED_MEDIA_TYPE),
+    }
+}
+
+fn create(
+    route: &Route,
+    tus: &Tus,
+    req: &Request<Bytes>,
+    config: &ServerConfig,
+) -> Result<Response<Bytes>, StatusCode> {
+    remove_expired(route);
+
+    // Uploads of unknown length are not supported
+    let length = tus_header(req, "upload-length")
+        .and_then(|length| length.parse::<u64>().ok())
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    if tus.max_size.is_some_and(|max_size| length > max_size) {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+    let metadata = tus_header(req, "upload-metadata").filter(|metadata| !metadata.is_empty());
+    if metadata.is_some_and(|metadata| parse_upload_metadata(metadata).is_none()) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let part = req.body();
+    if !part.is_empty() {
+        check_part(req)?;
+        if part.len() as u64 > length {
+            return Err(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+    }
+
+    let mut bytes = [0; ID_LENGTH / 2];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let id = bytes.iter().map(|b| format!("{b:02x}")).collect::<String>();
+    let files = UploadFiles::new(route, &id);
+    let _busy = BusyUpload::claim(&files.data)?;
+
+    let now = Utc::now();
+    let mut info = UploadInfo {
+        length,
+        metadata: metadata.map(str::to_string),
+        created: now,
+        expires: tus.expiration.map(|expiration| now + expiration),
+        file: None,
+    };
+    let stored = (|| {
+        fs::create_dir_all(files.data.parent().unwrap_or(Path::new(".")))?;
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&files.data)?;
+        files.save(&info)?;
+        if !part.is_empty() {
+            files.append(part)?;
+        }
+        if part.len() as u64 == length {
+            files.finish(&id, &mut info)?;
+        }
+        Ok(())
+    })();
+    if let Err(e) = stored {
+        files.remove(&info);
+        return Err(storage_error(e));
+    }
+
+    let location = format!("{}/{id}", route.url_path.trim_end_matches('/'));
+    log!(
+        LogLevel::Info,
+        LogFileType::Server,
+        format!("Created upload {location} of {length} bytes")
+    );
+    let mut response = respond(req, config, StatusCode::CREATED)
+        .header(LOCATION, location)
+        .header("Upload-Offset", part.len());
+    if let Some(expires) = expires_header(&info) {
+        response = response.header("Upload-Expires", expires);
+    }
+    response
+        .body(vec![])
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+fn offset(
+    route: &Route,
+    req: &Request<Bytes>,
+    config: &ServerConfig,
+) -> Result<Response<Bytes>, StatusCode> {
+    let id = upload_id(route, req.uri().path()).ok_or(StatusCode::NOT_FOUND)?;
+    let files = UploadFiles::new(route, id);
+    let info = files.load().ok_or(StatusCode::NOT_FOUND)?;
+
+    let mut response = respond(req, config, StatusCode::OK)
+        .header(CACHE_CONTROL, "no-store")
+        .header("Upload-Offset", files.offset(&info))
+        .header("Upload-Length", info.length);
+    if let Some(metadata) = &info.metadata {
+        response = response.header("Upload-Metadata", metadata);
+    }
+    if let Some(expires) = expires_header(&info) {
+        response = response.header("Upload-Expires", expires);
+    }
+    response
+        .body(vec![])
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// The length of a part whose body is streamed to the upload after the response is made. The
+/// bodies of HTTP/1 requests are only left out for that, see `streamed_part_length`.
+fn streamed_length(req: &Request<Bytes>) -> Option<u64> {
+    if !req.body().is_empty() || req.version() == Version::HTTP_2 {
+        return None;
+    }
+    tus_header(req, CONTENT_LENGTH.as_str())
+        .and_then(|length| length.parse::<u64>().ok())
+        .filter(|length| *length > 0)
+}
+
+fn append(
+    route: &Route,
+    tus: &Tus,
+    req: &Request<Bytes>,
+    config: &ServerConfig,
+) -> Result<Response<Bytes>, StatusCode> {
+    check_part(req)?;
+    let offset = tus_header(req, "upload-offset")
+        .and_then(|offset| offset.parse::<u64>().ok())
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    let id = upload_id(route, req.uri().path()).ok_or(StatusCode::NOT_FOUND)?;
+    let files = UploadFiles::new(route, id);
+    let busy = BusyUpload::claim(&files.data)?;
+    let mut info = files.load().ok_or(StatusCode::NOT_FOUND)?;
+
+    // The client must continue where the upload stopped
+    if offset != files.offset(&info) {
+        return Err(StatusCode::CONFLICT);
+    }
+    let streamed = streamed_length(req);
+    let new_offset = offset + streamed.unwrap_or(req.body().len() as u64);
+    if new_offset > info.length {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    if let Some(expiration) = tus.expiration {
+        info.expires = Some(Utc::now() + expiration);
+    }
+    let mut part = files
+        .open_part(id, info.clone(), busy)
+        .map_err(storage_error)?;
+    if streamed.is_none() {
+        part.write(req.body()).map_err(storage_error)?;
+    }
+
+    let mut response =
+        respond(req, config, StatusCode::NO_CONTENT).header("Upload-Offset", new_offset);
+    // Finished uploads don't expire
+    if let Some(expires) = expires_header(&info).filter(|_| new_offset < info.length) {
+        response = response.header("Upload-Expires", expires);
+    }
+    let mut response = response
+        .body(vec![])
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    match streamed {
+        Some(_) => {
+            response.extensions_mut().insert(part);
+        }
+        None => part.finish().map_err(storage_error)?,
+    }
+    Ok(response)
+}
+
+fn terminate(
+    route: &Route,
+    req: &Request<Bytes>,
+    config: &ServerConfig,
+) -> Result<Response<Bytes>, StatusCode> {
+    let id = upload_id(route, req.uri().path()).ok_or(StatusCode::NOT_FOUND)?;
+    let files = UploadFiles::new(route, id);
+    let _busy = BusyUpload::claim(&files.data)?;
+    let info = files.load().ok_or(StatusCode::NOT_FOUND)?;
+
+    files.remove(&info);
+    // The info is a file on disk, so its name is checked before it leads anywhere
+    if let Some(file) = info
+        .file
+        .as_deref()
+        .filter(|file| sanitize_filename(file).as_deref() == Some(*file))
+    {
+        let _ = fs::remove_file(files.dir.join(file));
+    }
+    log!(
+        LogLevel::Info,
+        LogFileType::Server,
+        format!("Terminated upload {id}")
+    );
+    respond(req, config, StatusCode::NO_CONTENT)
+        .body(vec![])
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
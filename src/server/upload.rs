@@ -0,0 +1,367 @@
+use crate::log;
+use crate::log::*;
+use crate::server::{
+    multipart_boundary, route_file, Bytes, MultipartError, MultipartEvent, MultipartLimits,
+    MultipartParser, Request, Response, ServerConfig, StatusCode,
+};
+use crate::server_config::route::{Route, Upload};
+use http::header::{CONTENT_LENGTH, CONTENT_TYPE, HOST, LOCATION};
+use rand::RngCore;
+use serde_json::json;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Longest file name most file systems allow, in bytes.
+const MAX_FILENAME_LENGTH: usize = 255;
+
+/// Names Windows reserves for devices, in any case and with any extension.
+const RESERVED_NAMES: [&str; 22] = [
+    "con", "prn", "aux", "nul", "com1", "com2", "com3", "com4", "com5", "com6", "com7", "com8",
+    "com9", "lpt1", "lpt2", "lpt3", "lpt4", "lpt5", "lpt6", "lpt7", "lpt8", "lpt9",
+];
+
+/// # sanitize_filename
+///
+/// A file name from a client that is safe to store. Directories are dropped, characters other
+/// than ASCII letters, digits, `-`, `_` and `.` are replaced with `_`, and leading dots are
+/// removed so the file can't be hidden. `None` if nothing is left.
+pub fn sanitize_filename(filename: &str) -> Option<String> {
+    // Browsers on Windows may send the whole path
+    let name = filename.rsplit(['/', '\\']).next().unwrap_or_default();
+    let mut name = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+            _ => '_',
+        })
+        .collect::<String>()
+        .trim_start_matches('.')
+        .trim_end_matches(['.', '_'])
+        .to_string();
+
+    if name.trim_matches('_').is_empty() {
+        return None;
+    }
+    let stem = name.split('.').next().unwrap_or_default();
+    if RESERVED_NAMES.contains(&stem.to_ascii_lowercase().as_str()) {
+        name.insert(0, '_');
+    }
+    if name.len() > MAX_FILENAME_LENGTH {
+        // Keep the extension, so the type of the file is still known
+        let extension = match name.rsplit_once('.') {
+            Some((_, extension)) if extension.len() < 16 => format!(".{extension}"),
+            _ => String::new(),
+        };
+        name.truncate(MAX_FILENAME_LENGTH - extension.len());
+        name.push_str(&extension);
+    }
+    Some(name)
+}
+
+/// Checks the extension of `filename` against the allowed extensions of the upload.
+fn is_allowed(filename: &str, upload: &Upload) -> bool {
+    if upload.allowed_extensions.is_empty() {
+        return true;
+    }
+    match filename.rsplit_once('.') {
+        Some((_, extension)) => upload.allowed_extensions.iter().any(|allowed| {
+            allowed
+                .trim_start_matches('.')
+                .eq_ignore_ascii_case(extension)
+        }),
+        None => false,
+    }
+}
+
+/// A file part being written to a temporary file in the upload directory.
+struct PendingFile {
+    field: String,
+    filename: String,
+    stored_name: String,
+    content_type: Option<String>,
+    temp_path: PathBuf,
+    file: File,
+    size: usize,
+}
+
+/// Removes the temporary files of the request if it fails before they are stored.
+struct PendingFiles(Vec<PendingFile>);
+
+impl Drop for PendingFiles {
+    fn drop(&mut self) {
+        for pending in &self.0 {
+            let _ = fs::remove_file(&pending.temp_path);
+        }
+    }
+}
+
+/// Creates a new hidden temporary file in `dir` for the upload of `name`.
+pub(crate) fn create_temp_file(dir: &Path, name: &str) -> io::Result<(PathBuf, File)> {
+    loop {
+        let mut bytes = [0; 8];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let suffix = bytes.iter().map(|b| format!("{b:02x}")).collect::<String>();
+        let path = dir.join(format!(".{name}.{suffix}.upload"));
+
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Moves the temporary file to `name` in `dir`, or `name(1)`, `name(2)`... if it is taken.
+/// Returns the name the file was stored as. Existing files are never replaced.
+pub(crate) fn store_file(temp_path: &Path, dir: &Path, name: &str) -> io::Result<String> {
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{extension}")),
+        _ => (name, String::new()),
+    };
+    let mut candidate = name.to_string();
+    let mut i = 0;
+    loop {
+        // Linking fails if the name is taken, so a file stored at the same time isn't lost
+        match fs::hard_link(temp_path, dir.join(&candidate)) {
+            Ok(()) => {
+                fs::remove_file(temp_path)?;
+                return Ok(candidate);
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                i += 1;
+                candidate = format!("{stem}({i}){extension}");
+            }
+            // File systems without hard links
+            Err(_) if !dir.join(&candidate).exists() => {
+                fs::rename(temp_path, dir.join(&candidate))?;
+                return Ok(candidate);
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Bytes of the body fed to the multipart parser at a time.
+const FEED_SIZE: usize = 64 * 1024;
+
+/// Largest body a request to the upload can have: the content its parts may have, plus the
+/// delimiter and headers of each part. `None` without size limits.
+fn max_body_size(upload: &Upload, limits: &MultipartLimits, boundary: &str) -> Option<usize> {
+    let content = [
+        upload.max_total_size,
+        upload
+            .max_file_size
+            .map(|max| max.saturating_mul(limits.max_parts)),
+    ]
+    .into_iter()
+    .flatten()
+    .min()?;
+    let part = boundary.len() + limits.max_header_bytes + 16;
+    Some(content.saturating_add(part.saturating_mul(limits.max_parts + 1)))
+}
+
+/// The files of an upload request, written as the parser produces them.
+struct UploadWriter<'a> {
+    upload: &'a Upload<'a>,
+    dir: &'a Path,
+    pending: PendingFiles,
+    /// Set while the current part is a file
+    writing: bool,
+}
+
+impl UploadWriter<'_> {
+    fn handle(&mut self, event: MultipartEvent) -> Result<(), StatusCode> {
+        match event {
+            MultipartEvent::Part(headers) => {
+                // Fields and empty file inputs have nothing to store
+                let filename = match headers.filename.filter(|name| !name.is_empty()) {
+                    Some(filename) => filename,
+                    None => {
+                        self.writing = false;
+                        return Ok(());
+                    }
+                };
+                let stored_name = sanitize_filename(&filename).ok_or(StatusCode::BAD_REQUEST)?;
+                if !is_allowed(&stored_name, self.upload) {
+                    log!(
+                        LogLevel::Warn,
+                        LogFileType::Client,
+                        format!("Upload of {stored_name} rejected by its extension")
+                    );
+                    return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+                }
+
+                let (temp_path, file) = create_temp_file(self.dir, &stored_name)
+                    .map_err(|e| write_error(self.dir, e))?;
+                self.pending.0.push(PendingFile {
+                    field: headers.name.unwrap_or_default(),
+                    filename,
+                    stored_name,
+                    content_type: headers.content_type,
+                    temp_path,
+                    file,
+                    size: 0,
+                });
+                self.writing = true;
+            }
+            MultipartEvent::Data(data) if self.writing => {
+                if let Some(current) = self.pending.0.last_mut() {
+                    current
+                        .file
+                        .write_all(&data)
+                        .map_err(|e| write_error(self.dir, e))?;
+                    current.size += data.len();
+                }
+            }
+            MultipartEvent::Data(_) => {}
+            MultipartEvent::PartEnd => {
+                if let Some(current) = self.pending.0.last_mut().filter(|_| self.writing) {
+                    current
+                        .file
+                        .sync_all()
+                        .map_err(|e| write_error(self.dir, e))?;
+                }
+                self.writing = false;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_error(dir: &Path, e: io::Error) -> StatusCode {
+    log!(
+        LogLevel::Error,
+        LogFileType::Server,
+        format!("Failed to write upload to {}. {e}", dir.display())
+    );
+    StatusCode::INTERNAL_SERVER_ERROR
+}
+
+fn rejected(e: MultipartError) -> StatusCode {
+    log!(
+        LogLevel::Warn,
+        LogFileType::Client,
+        format!("Upload rejected: {e}")
+    );
+    e.status()
+}
+
+/// # store_uploads
+///
+/// Stores the files of a `multipart/form-data` POST request in the directory of the request
+/// path, under the root of the route. The files are written to temporary files as the body is
+/// parsed, and only stored once the whole body was read, so a failed request leaves no files
+/// or directories behind. Responds with `201 Created`, a `Location` header per file and a JSON summary of the
+/// files.
+pub fn store_uploads(
+    route: &Route,
+    upload: &Upload,
+    req: &Request<Bytes>,
+    config: &ServerConfig,
+) -> Result<Response<Bytes>, StatusCode> {
+    let dir = route_file(route, req.uri().path()).ok_or(StatusCode::FORBIDDEN)?;
+    let boundary = multipart_boundary(req.headers()).ok_or(StatusCode::UNSUPPORTED_MEDIA_TYPE)?;
+    let limits = MultipartLimits {
+        max_part_size: upload.max_file_size,
+        max_total_size: upload.max_total_size,
+        ..MultipartLimits::default()
+    };
+
+    // Bodies that can't fit the limits are rejected before anything is written
+    let content_length = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok());
+    if let (Some(length), Some(max)) = (content_length, max_body_size(upload, &limits, &boundary)) {
+        if length > max {
+            log!(
+                LogLevel::Warn,
+                LogFileType::Client,
+                format!("Upload of {length} bytes rejected, the limit is {max}")
+            );
+            return Err(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+    }
+
+    // The directory is only created once the body was read, the temporary files are written
+    // to the closest directory that already exists
+    let temp_dir = dir
+        .ancestors()
+        .find(|ancestor| ancestor.is_dir())
+        .unwrap_or(Path::new("."));
+
+    let mut parser = MultipartParser::new(&boundary, limits);
+    let mut writer = UploadWriter {
+        upload,
+        dir: temp_dir,
+        pending: PendingFiles(Vec::new()),
+        writing: false,
+    };
+    for input in req.body().chunks(FEED_SIZE) {
+        for event in parser.feed(input).map_err(rejected)? {
+            writer.handle(event)?;
+        }
+    }
+    parser.finish().map_err(rejected)?;
+    let mut pending = writer.pending;
+    if pending.0.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    fs::create_dir_all(&dir).map_err(|e| {
+        log!(
+            LogLevel::Error,
+            LogFileType::Server,
+            format!("Failed to create upload directory {}. {e}", dir.display())
+        );
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    let base = req.uri().path().trim_end_matches('/');
+    let mut response = Response::builder()
+        .version(req.version())
+        .header(HOST, config.host)
+        .status(StatusCode::CREATED)
+        .header(CONTENT_TYPE, "application/json");
+    let mut files = Vec::new();
+    let mut stored_paths = Vec::new();
+
+    while !pending.0.is_empty() {
+        // Files that are not stored yet stay pending, so they are removed on failure
+        let file = pending.0.remove(0);
+        drop(file.file);
+        let stored = match store_file(&file.temp_path, &dir, &file.stored_name) {
+            Ok(stored) => stored,
+            Err(e) => {
+                // The files stored before are removed too, the request failed as a whole
+                let _ = fs::remove_file(&file.temp_path);
+                for path in &stored_paths {
+                    let _ = fs::remove_file(path);
+                }
+                return Err(write_error(&dir, e));
+            }
+        };
+        stored_paths.push(dir.join(&stored));
+        let location = format!("{base}/{stored}");
+        log!(
+            LogLevel::Info,
+            LogFileType::Server,
+            format!("Stored upload {location} ({} bytes)", file.size)
+        );
+
+        response = response.header(LOCATION, &location);
+        files.push(json!({
+            "field": file.field,
+            "filename": file.filename,
+            "location": location,
+            "size": file.size,
+            "content_type": file.content_type,
+        }));
+    }
+
+    let body = json!({ "files": files }).to_string();
+    response
+        .body(Bytes::from(body))
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
@@ -0,0 +1,1240 @@
+use crate::log;
+use crate::log::*;
+use crate::server::path::{add_root_to_path, escapes_root, path_exists};
+use crate::server::{
+    authorize, check_access, content_type, percent_decode, Bytes, Method, Request, Response, Route,
+    ServerConfig, StatusCode,
+};
+use chrono::{DateTime, Utc};
+use http::header::{CONTENT_TYPE, HOST};
+use http::Uri;
+use rand::RngCore;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::fs::{self, Metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+/// Namespace of the WebDAV elements and properties.
+pub const DAV_NAMESPACE: &str = "DAV:";
+/// Methods WebDAV adds to HTTP, for the `methods` of a route with `webdav` set.
+pub const DAV_METHODS: [&str; 7] = [
+    "PROPFIND",
+    "PROPPATCH",
+    "MKCOL",
+    "COPY",
+    "MOVE",
+    "LOCK",
+    "UNLOCK",
+];
+/// Lock timeout when the client asks for none.
+const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(3600);
+/// Longest lock timeout granted, so abandoned locks expire.
+const MAX_LOCK_TIMEOUT: Duration = Duration::from_secs(86_400);
+
+const XML_CONTENT_TYPE: &str = "application/xml; charset=utf-8";
+
+/// # webdav_methods
+///
+/// The methods of WebDAV, to add to the `methods` of a route with `webdav` set.
+pub fn webdav_methods() -> Vec<Method> {
+    DAV_METHODS
+        .iter()
+        .filter_map(|method| Method::from_bytes(method.as_bytes()).ok())
+        .collect()
+}
+
+/// # is_webdav
+///
+/// Checks if the route serves its files as a WebDAV share.
+pub fn is_webdav(route: &Route) -> bool {
+    route
+        .settings
+        .as_ref()
+        .is_some_and(|settings| settings.webdav)
+}
+
+/// # XmlElement
+///
+/// An element of an XML request body, with its namespace resolved.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct XmlElement {
+    pub namespace: String,
+    pub name: String,
+    pub children: Vec<XmlElement>,
+    /// Text directly inside the element, with entities decoded.
+    pub text: String,
+}
+
+impl XmlElement {
+    pub fn is(&self, namespace: &str, name: &str) -> bool {
+        self.namespace == namespace && self.name == name
+    }
+
+    /// The first child element in the DAV namespace called `name`.
+    pub fn dav_child(&self, name: &str) -> Option<&XmlElement> {
+        self.children
+            .iter()
+            .find(|child| child.is(DAV_NAMESPACE, name))
+    }
+
+    /// Writes the element back as XML, declaring its namespace.
+    fn write(&self, out: &mut String) {
+        let _ = write!(
+            out,
+            "<{} xmlns=\"{}\">{}",
+            self.name,
+            escape_xml(&self.namespace),
+            escape_xml(&self.text)
+        );
+        for child in &self.children {
+            child.write(out);
+        }
+        let _ = write!(out, "</{}>", self.name);
+    }
+}
+
+/// An element being parsed, with its qualified name and the namespaces it declares.
+type OpenElement = (XmlElement, String, Vec<(String, String)>);
+
+/// # parse_xml
+///
+/// Parses the root element of an XML document. Document type declarations are rejected, since
+/// their entities are not supported.
+pub fn parse_xml(input: &str) -> Option<XmlElement> {
+    let mut stack: Vec<OpenElement> = Vec::new();
+    let mut root = None;
+    let mut rest = input.trim_start_matches('\u{feff}');
+
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("<?") {
+            rest = &after[after.find("?>")? + 2..];
+        } else if let Some(after) = rest.strip_prefix("<!--") {
+            rest = &after[after.find("-->")? + 3..];
+        } else if let Some(after) = rest.strip_prefix("<![CDATA[") {
+            let end = after.find("]]>")?;
+            stack.last_mut()?.0.text.push_str(&after[..end]);
+            rest = &after[end + 3..];
+        } else if rest.starts_with("<!") {
+            return None;
+        } else if let Some(after) = rest.strip_prefix("</") {
+            let end = after.find('>')?;
+            let (element, qname, _) = stack.pop()?;
+            if after[..end].trim() != qname {
+                return None;
+            }
+            rest = &after[end + 1..];
+            match stack.last_mut() {
+                Some(parent) => parent.0.children.push(element),
+                None => root = Some(element),
+            }
+        } else if let Some(after) = rest.strip_prefix('<') {
+            let end = tag_end(after)?;
+            let tag = &after[..end];
+            rest = &after[end + 1..];
+            let (tag, empty) = match tag.strip_suffix('/') {
+                Some(tag) => (tag, true),
+                None => (tag, false),
+            };
+
+            let (qname, attributes) = parse_tag(tag)?;
+            let scope = attributes
+                .into_iter()
+                .filter_map(|(name, value)| match name.as_str() {
+                    "xmlns" => Some((String::new(), value)),
+                    _ => name
+                        .strip_prefix("xmlns:")
+                        .map(|prefix| (prefix.to_string(), value)),
+                })
+                .collect::<Vec<_>>();
+            let (prefix, name) = qname.split_once(':').unwrap_or(("", &qname));
+            let namespace = scope
+                .iter()
+                .rev()
+                .chain(stack.iter().rev().flat_map(|frame| frame.2.iter().rev()))
+                .find(|(declared, _)| declared == prefix)
+                .map(|(_, namespace)| namespace.clone());
+            let namespace = match namespace {
+                Some(namespace) => namespace,
+                None if prefix.is_empty() => String::new(),
+                None => return None,
+            };
+
+            let element = XmlElement {
+                namespace,
+                name: name.to_string(),
+                ..XmlElement::default()
+            };
+            if !empty {
+                stack.push((element, qname, scope));
+                continue;
+            }
+            match stack.last_mut() {
+                Some(parent) => parent.0.children.push(element),
+                None => root = Some(element),
+            }
+        } else {
+            let end = rest.find('<').unwrap_or(rest.len());
+            let text = unescape_xml(&rest[..end])?;
+            match stack.last_mut() {
+                Some(frame) => frame.0.text.push_str(&text),
+                None if text.trim().is_empty() => {}
+                None => return None,
+            }
+            rest = &rest[end..];
+        }
+    }
+    if !stack.is_empty() {
+        return None;
+    }
+    root
+}
+
+/// Position of the `>` that ends a tag, outside of quoted attribute values.
+fn tag_end(input: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in input.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(open), c) if c == open => quote = None,
+            (None, '>') => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The qualified name and attributes of the inside of a tag.
+fn parse_tag(tag: &str) -> Option<(String, Vec<(String, String)>)> {
+    let tag = tag.trim();
+    let name_end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+    let name = tag[..name_end].to_string();
+    if name.is_empty() {
+        return None;
+    }
+
+    let mut attributes = Vec::new();
+    let mut rest = tag[name_end..].trim_start();
+    while !rest.is_empty() {
+        let equals = rest.find('=')?;
+        let attribute = rest[..equals].trim().to_string();
+        let value = rest[equals + 1..].trim_start();
+        let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+        let close = value[1..].find(quote)? + 1;
+        attributes.push((attribute, unescape_xml(&value[1..close])?));
+        rest = value[close + 1..].trim_start();
+    }
+    Some((name, attributes))
+}
+
+fn unescape_xml(text: &str) -> Option<String> {
+    let mut output = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        output.push_str(&rest[..start]);
+        let end = rest[start..].find(';')? + start;
+        let entity = &rest[start + 1..end];
+        let c = match entity {
+            "lt" => '<',
+            "gt" => '>',
+            "amp" => '&',
+            "quot" => '"',
+            "apos" => '\'',
+            _ => {
+                let code = match entity.strip_prefix("#x") {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                    None => entity.strip_prefix('#')?.parse().ok()?,
+                };
+                char::from_u32(code)?
+            }
+        };
+        output.push(c);
+        rest = &rest[end + 1..];
+    }
+    output.push_str(rest);
+    Some(output)
+}
+
+/// # escape_xml
+///
+/// Escapes text for XML content and attribute values.
+pub fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// # DavLock
+///
+/// A write lock on a resource and, for `infinite` locks, everything under it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DavLock {
+    /// `opaquelocktoken:` URI the client submits to write to the resource.
+    pub token: String,
+    /// File of the locked resource.
+    pub path: PathBuf,
+    /// URL path of the locked resource.
+    pub root: String,
+    pub exclusive: bool,
+    pub infinite: bool,
+    /// The `owner` element the client sent, as XML.
+    pub owner: Option<String>,
+    pub timeout: Duration,
+    expires: Instant,
+}
+
+impl DavLock {
+    /// Checks if the lock applies to the resource at `path`.
+    fn covers(&self, path: &Path) -> bool {
+        path == self.path || (self.infinite && path.starts_with(&self.path))
+    }
+
+    fn write_active(&self, out: &mut String) {
+        let scope = if self.exclusive {
+            "exclusive"
+        } else {
+            "shared"
+        };
+        let depth = if self.infinite { "infinity" } else { "0" };
+        let _ = write!(
+            out,
+            "<D:activelock><D:locktype><D:write/></D:locktype><D:lockscope><D:{scope}/></D:lockscope>\
+             <D:depth>{depth}</D:depth>{}<D:timeout>Second-{}</D:timeout>\
+             <D:locktoken><D:href>{}</D:href></D:locktoken>\
+             <D:lockroot><D:href>{}</D:href></D:lockroot></D:activelock>",
+            self.owner
+                .as_ref()
+                .map(|owner| format!("<D:owner>{owner}</D:owner>"))
+                .unwrap_or_default(),
+            self.expires
+                .saturating_duration_since(Instant::now())
+                .as_secs(),
+            escape_xml(&self.token),
+            escape_xml(&encode_href(&self.root)),
+        );
+    }
+}
+
+/// Dead properties of a resource, by namespace and name.
+type Properties = BTreeMap<(String, String), String>;
+
+/// # DavStore
+///
+/// The locks and dead properties of the WebDAV resources of all servers. They are kept in
+/// memory, so they are gone after a restart.
+#[derive(Debug, Default)]
+pub struct DavStore {
+    locks: Mutex<Vec<DavLock>>,
+    properties: Mutex<HashMap<PathBuf, Properties>>,
+}
+
+/// Locks `mutex`, even if another thread panicked while holding it.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl DavStore {
+    /// The unexpired locks.
+    fn locks(&self) -> MutexGuard<'_, Vec<DavLock>> {
+        let mut locks = lock(&self.locks);
+        let now = Instant::now();
+        locks.retain(|lock| lock.expires > now);
+        locks
+    }
+
+    /// The locks on the resource at `path`, and on the resources under it if `descendants` is set.
+    pub fn locks_on(&self, path: &Path, descendants: bool) -> Vec<DavLock> {
+        self.locks()
+            .iter()
+            .filter(|lock| lock.covers(path) || (descendants && lock.path.starts_with(path)))
+            .cloned()
+            .collect()
+    }
+
+    /// Checks that every lock on the resource at `path`, and the resources under it if
+    /// `descendants` is set, is one of the `tokens` the client submitted.
+    pub fn check(
+        &self,
+        path: &Path,
+        descendants: bool,
+        tokens: &[String],
+    ) -> Result<(), StatusCode> {
+        match self
+            .locks_on(path, descendants)
+            .iter()
+            .all(|lock| tokens.contains(&lock.token))
+        {
+            true => Ok(()),
+            false => Err(StatusCode::LOCKED),
+        }
+    }
+
+    /// Adds a lock, unless it conflicts with the locks on the resource.
+    fn add_lock(&self, new: DavLock) -> Result<DavLock, StatusCode> {
+        let mut locks = self.locks();
+        let conflict = locks.iter().any(|lock| {
+            let overlaps = lock.covers(&new.path) || new.covers(&lock.path);
+            overlaps && (lock.exclusive || new.exclusive)
+        });
+        if conflict {
+            return Err(StatusCode::LOCKED);
+        }
+        locks.push(new.clone());
+        Ok(new)
+    }
+
+    /// Restarts the timeout of the lock with `token` on the resource at `path`.
+    fn refresh(&self, path: &Path, token: &str, timeout: Duration) -> Option<DavLock> {
+        let mut locks = self.locks();
+        let lock = locks
+            .iter_mut()
+            .find(|lock| lock.token == token && lock.covers(path))?;
+        lock.timeout = timeout;
+        lock.expires = Instant::now() + timeout;
+        Some(lock.clone())
+    }
+
+    /// Removes the lock with `token` that applies to the resource at `path`.
+    fn unlock(&self, path: &Path, token: &str) -> bool {
+        let mut locks = self.locks();
+        let count = locks.len();
+        locks.retain(|lock| !(lock.token == token && lock.covers(path)));
+        locks.len() < count
+    }
+
+    /// Drops the locks and properties of the resource at `path` and the resources under it.
+    pub fn forget(&self, path: &Path) {
+        self.locks().retain(|lock| !lock.path.starts_with(path));
+        lock(&self.properties).retain(|resource, _| !resource.starts_with(path));
+    }
+
+    /// Copies the properties of the resource at `from` and the resources under it to `to`.
+    fn copy_properties(&self, from: &Path, to: &Path) {
+        let mut properties = lock(&self.properties);
+        let copies = properties
+            .iter()
+            .filter_map(|(resource, props)| {
+                let relative = resource.strip_prefix(from).ok()?;
+                Some((to.join(relative), props.clone()))
+            })
+            .collect::<Vec<_>>();
+        properties.extend(copies);
+    }
+
+    fn properties(&self, path: &Path) -> Properties {
+        lock(&self.properties)
+            .get(path)
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+/// # dav_store
+///
+/// The locks and dead properties of all WebDAV routes.
+pub fn dav_store() -> &'static DavStore {
+    static DAV_STORE: OnceLock<DavStore> = OnceLock::new();
+    DAV_STORE.get_or_init(DavStore::default)
+}
+
+/// # submitted_tokens
+///
+/// The lock tokens in the `If` header of a request.
+pub fn submitted_tokens(req: &Request<Bytes>) -> Vec<String> {
+    let header = req
+        .headers()
+        .get("if")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    header
+        .split('<')
+        .skip(1)
+        .filter_map(|part| part.split_once('>'))
+        .map(|(uri, _)| uri.trim().to_string())
+        .filter(|uri| uri.starts_with("opaquelocktoken:"))
+        .collect()
+}
+
+/// # check_locks
+///
+/// Checks that a request to a WebDAV route may write to the resource of its path. `descendants`
+/// also checks the locks on the resources under it, for requests that replace or remove them.
+pub fn check_locks(
+    route: &Route,
+    req: &Request<Bytes>,
+    descendants: bool,
+) -> Result<(), StatusCode> {
+    let path = dav_path(route, req.uri().path()).ok_or(StatusCode::FORBIDDEN)?;
+    dav_store().check(&path, descendants, &submitted_tokens(req))
+}
+
+/// # route_file
+///
+/// The file of the URL path under the root of the route. On WebDAV routes the path is
+/// percent-decoded, so every method finds the files WebDAV clients name. `None` for paths that
+/// would leave the root.
+pub fn route_file(route: &Route, url_path: &str) -> Option<PathBuf> {
+    if is_webdav(route) {
+        return dav_path(route, url_path);
+    }
+    (!escapes_root(url_path)).then(|| PathBuf::from(add_root_to_path(route, url_path)))
+}
+
+/// The file of the URL path of a WebDAV route, with its percent-escapes decoded. `None` for
+/// paths that would leave the root of the route.
+fn dav_path(route: &Route, url_path: &str) -> Option<PathBuf> {
+    let decoded = decode_path(url_path)?;
+    Some(PathBuf::from(add_root_to_path(
+        route,
+        decoded.trim_end_matches('/'),
+    )))
+}
+
+fn decode_path(url_path: &str) -> Option<String> {
+    let decoded = String::from_utf8(percent_decode(url_path.as_bytes())).ok()?;
+    (!escapes_root(&decoded)).then_some(decoded)
+}
+
+/// Percent-encodes a URL path for an `href` or a `Location`.
+pub(crate) fn encode_href(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for b in path.bytes() {
+        match b {
+            // Parentheses are allowed in paths, and name the copies POST keeps next to a file
+            b'a'..=b'z'
+            | b'A'..=b'Z'
+            | b'0'..=b'9'
+            | b'-'
+            | b'.'
+            | b'_'
+            | b'~'
+            | b'/'
+            | b'('
+            | b')' => encoded.push(b as char),
+            _ => {
+                let _ = write!(encoded, "%{b:02X}");
+            }
+        }
+    }
+    encoded
+}
+
+/// # handle_webdav
+///
+/// Responds to a WebDAV request to a route with `webdav` set: `PROPFIND`, `PROPPATCH`, `MKCOL`,
+/// `COPY`, `MOVE`, `LOCK`, `UNLOCK` or `DELETE`.
+pub fn handle_webdav(
+    route: &Route,
+    req: &Request<Bytes>,
+    config: &ServerConfig,
+) -> Result<Response<Bytes>, StatusCode> {
+    let url_path = decode_path(req.uri().path()).ok_or(StatusCode::FORBIDDEN)?;
+    let path = dav_path(route, req.uri().path()).ok_or(StatusCode::FORBIDDEN)?;
+    let resource = Resource {
+        url_path: &url_path,
+        path: &path,
+    };
+    let recursive_delete = route
+        .settings
+        .as_ref()
+        .is_some_and(|settings| settings.recursive_delete);
+
+    match req.method().as_str() {
+        "PROPFIND" => propfind(&resource, req, config),
+        "PROPPATCH" => proppatch(&resource, req, config),
+        "MKCOL" => mkcol(&resource, req, config),
+        "COPY" => copy_or_move(&resource, req, config, false, recursive_delete),
+        "MOVE" => copy_or_move(&resource, req, config, true, recursive_delete),
+        "LOCK" => lock_resource(&resource, req, config),
+        "UNLOCK" => unlock_resource(&resource, req, config),
+        "DELETE" => delete(&resource, req, config, recursive_delete),
+        _ => Err(StatusCode::NOT_IMPLEMENTED),
+    }
+}
+
+/// The resource of a WebDAV request.
+struct Resource<'r> {
+    /// URL path, decoded.
+    url_path: &'r str,
+    path: &'r Path,
+}
+
+fn respond(
+    req: &Request<Bytes>,
+    config: &ServerConfig,
+    status: StatusCode,
+    body: String,
+) -> Result<Response<Bytes>, StatusCode> {
+    let mut response = Response::builder()
+        .version(req.version())
+        .header(HOST, config.host)
+        .status(status);
+    if !body.is_empty() {
+        response = response.header(CONTENT_TYPE, XML_CONTENT_TYPE);
+    }
+    response
+        .body(Bytes::from(body))
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// The status line of a `propstat` or `response`.
+fn status_line(status: StatusCode) -> String {
+    format!(
+        "HTTP/1.1 {} {}",
+        status.as_u16(),
+        status.canonical_reason().unwrap_or_default()
+    )
+}
+
+/// The XML body of a request, if it has one.
+fn xml_body(req: &Request<Bytes>) -> Result<Option<XmlElement>, StatusCode> {
+    let body = std::str::from_utf8(req.body()).map_err(|_| StatusCode::BAD_REQUEST)?;
+    if body.trim().is_empty() {
+        return Ok(None);
+    }
+    parse_xml(body).map(Some).ok_or(StatusCode::BAD_REQUEST)
+}
+
+fn header<'r>(req: &'r Request<Bytes>, name: &str) -> Option<&'r str> {
+    req.headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+}
+
+/// Properties a `PROPFIND` asks for.
+enum PropRequest {
+    All,
+    Names,
+    Props(Vec<(String, String)>),
+}
+
+const LIVE_PROPERTIES: [&str; 9] = [
+    "creationdate",
+    "displayname",
+    "getcontentlength",
+    "getcontenttype",
+    "getetag",
+    "getlastmodified",
+    "resourcetype",
+    "supportedlock",
+    "lockdiscovery",
+];
+
+/// The value of a live property of a resource, as XML, if the resource has it.
+fn live_property(name: &str, path: &Path, metadata: &Metadata) -> Option<String> {
+    let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+    match name {
+        "creationdate" => {
+            let created = DateTime::<Utc>::from(metadata.created().unwrap_or(modified));
+            Some(created.format("%Y-%m-%dT%H:%M:%SZ").to_string())
+        }
+        "displayname" => Some(escape_xml(
+            &path.file_name().unwrap_or_default().to_string_lossy(),
+        )),
+        "getcontentlength" if metadata.is_file() => Some(metadata.len().to_string()),
+        "getcontenttype" if metadata.is_file() => {
+            Some(escape_xml(&content_type(&path.to_string_lossy())))
+        }
+        "getetag" => {
+            let nanos = modified
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos();
+            Some(format!("&quot;{:x}-{nanos:x}&quot;", metadata.len()))
+        }
+        "getlastmodified" => Some(
+            DateTime::<Utc>::from(modified)
+                .format("%a, %d %b %Y %H:%M:%S GMT")
+                .to_string(),
+        ),
+        "resourcetype" => Some(match metadata.is_dir() {
+            true => "<D:collection/>".to_string(),
+            false => String::new(),
+        }),
+        "supportedlock" => Some(
+            ["exclusive", "shared"]
+                .iter()
+                .map(|scope| {
+                    format!(
+                        "<D:lockentry><D:lockscope><D:{scope}/></D:lockscope>\
+                         <D:locktype><D:write/></D:locktype></D:lockentry>"
+                    )
+                })
+                .collect(),
+        ),
+        "lockdiscovery" => {
+            let mut out = String::new();
+            for lock in dav_store().locks_on(path, false) {
+                lock.write_active(&mut out);
+            }
+            Some(out)
+        }
+        _ => None,
+    }
+}
+
+/// Writes a property element with its value.
+fn write_property(out: &mut String, namespace: &str, name: &str, value: &str) {
+    if namespace == DAV_NAMESPACE {
+        let _ = write!(out, "<D:{name}>{value}</D:{name}>");
+    } else {
+        let _ = write!(
+            out,
+            "<{name} xmlns=\"{}\">{value}</{name}>",
+            escape_xml(namespace)
+        );
+    }
+}
+
+fn propfind(
+    resource: &Resource,
+    req: &Request<Bytes>,
+    config: &ServerConfig,
+) -> Result<Response<Bytes>, StatusCode> {
+    let metadata = fs::metadata(resource.path).map_err(|_| StatusCode::NOT_FOUND)?;
+    let depth = match header(req, "depth") {
+        None | Some("infinity") => None,
+        Some("0") => Some(0),
+        Some("1") => Some(1),
+        Some(_) => return Err(StatusCode::BAD_REQUEST),
+    };
+    let request = match xml_body(req)? {
+        None => PropRequest::All,
+        Some(root) if root.is(DAV_NAMESPACE, "propfind") => {
+            if root.dav_child("allprop").is_some() {
+                PropRequest::All
+            } else if root.dav_child("propname").is_some() {
+                PropRequest::Names
+            } else if let Some(prop) = root.dav_child("prop") {
+                PropRequest::Props(
+                    prop.children
+                        .iter()
+                        .map(|child| (child.namespace.clone(), child.name.clone()))
+                        .collect(),
+                )
+            } else {
+                return Err(StatusCode::BAD_REQUEST);
+            }
+        }
+        Some(_) => return Err(StatusCode::BAD_REQUEST),
+    };
+
+    let mut out = String::from(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">",
+    );
+    let mut pending = vec![(
+        resource.path.to_path_buf(),
+        resource.url_path.trim_end_matches('/').to_string(),
+        metadata,
+        0,
+    )];
+    while let Some((path, url_path, metadata, level)) = pending.pop() {
+        write_propstats(&mut out, &path, &url_path, &metadata, &request);
+        if !metadata.is_dir() || depth.is_some_and(|depth| level >= depth) {
+            continue;
+        }
+        let mut entries = fs::read_dir(&path)
+            .map(|entries| entries.filter_map(|entry| entry.ok()).collect::<Vec<_>>())
+            .unwrap_or_default();
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.file_name()));
+        for entry in entries {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            // Temporary files of uploads in progress are not resources yet
+            if name.ends_with(".upload") && name.starts_with('.') {
+                continue;
+            }
+            if let Ok(metadata) = entry.metadata() {
+                pending.push((
+                    entry.path(),
+                    format!("{url_path}/{name}"),
+                    metadata,
+                    level + 1,
+                ));
+            }
+        }
+    }
+    out.push_str("</D:multistatus>");
+    respond(req, config, StatusCode::MULTI_STATUS, out)
+}
+
+/// Writes the `response` of a resource to a `PROPFIND`.
+fn write_propstats(
+    out: &mut String,
+    path: &Path,
+    url_path: &str,
+    metadata: &Metadata,
+    request: &PropRequest,
+) {
+    let mut href = encode_href(if url_path.is_empty() { "/" } else { url_path });
+    if metadata.is_dir() && !href.ends_with('/') {
+        href.push('/');
+    }
+    let dead = dav_store().properties(path);
+    let mut found = String::new();
+    let mut missing = String::new();
+
+    match request {
+        PropRequest::All | PropRequest::Names => {
+            let names = request_names(request);
+            for name in LIVE_PROPERTIES {
+                if let Some(value) = live_property(name, path, metadata) {
+                    write_property(
+                        &mut found,
+                        DAV_NAMESPACE,
+                        name,
+                        value_or_name(&value, names),
+                    );
+                }
+            }
+            for ((namespace, name), value) in &dead {
+                let value = escape_xml(value);
+                write_property(&mut found, namespace, name, value_or_name(&value, names));
+            }
+        }
+        PropRequest::Props(props) => {
+            for (namespace, name) in props {
+                let value = match namespace.as_str() {
+                    DAV_NAMESPACE => live_property(name, path, metadata),
+                    _ => None,
+                }
+                .or_else(|| {
+                    dead.get(&(namespace.clone(), name.clone()))
+                        .map(|value| escape_xml(value))
+                });
+                match value {
+                    Some(value) => write_property(&mut found, namespace, name, &value),
+                    None => write_property(&mut missing, namespace, name, ""),
+                }
+            }
+        }
+    }
+
+    let _ = write!(out, "<D:response><D:href>{}</D:href>", escape_xml(&href));
+    for (props, status) in [(found, StatusCode::OK), (missing, StatusCode::NOT_FOUND)] {
+        if !props.is_empty() {
+            let _ = write!(
+                out,
+                "<D:propstat><D:prop>{props}</D:prop><D:status>{}</D:status></D:propstat>",
+                status_line(status)
+            );
+        }
+    }
+    out.push_str("</D:response>");
+}
+
+fn request_names(request: &PropRequest) -> bool {
+    matches!(request, PropRequest::Names)
+}
+
+/// The value of a property, or nothing for a `propname` request.
+fn value_or_name(value: &str, names: bool) -> &str {
+    if names {
+        ""
+    } else {
+        value
+    }
+}
+
+fn proppatch(
+    resource: &Resource,
+    req: &Request<Bytes>,
+    config: &ServerConfig,
+) -> Result<Response<Bytes>, StatusCode> {
+    if !resource.path.exists() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    dav_store().check(resource.path, false, &submitted_tokens(req))?;
+    let root = xml_body(req)?.ok_or(StatusCode::BAD_REQUEST)?;
+    if !root.is(DAV_NAMESPACE, "propertyupdate") {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    // Instructions in order, with the value to set or `None` to remove
+    let mut updates = Vec::new();
+    for instruction in &root.children {
+        let set = match instruction.name.as_str() {
+            "set" if instruction.namespace == DAV_NAMESPACE => true,
+            "remove" if instruction.namespace == DAV_NAMESPACE => false,
+            _ => continue,
+        };
+        for prop in instruction
+            .children
+            .iter()
+            .filter(|child| child.is(DAV_NAMESPACE, "prop"))
+        {
+            for property in &prop.children {
+                let value = set.then(|| property.text.clone());
+                updates.push((property.namespace.clone(), property.name.clone(), value));
+            }
+        }
+    }
+
+    // Live properties can't be changed, and then none of the others are either
+    let protected = updates
+        .iter()
+        .any(|(namespace, _, _)| namespace == DAV_NAMESPACE);
+    if !protected {
+        let mut properties = lock(&dav_store().properties);
+        let dead = properties.entry(resource.path.to_path_buf()).or_default();
+        for (namespace, name, value) in &updates {
+            match value {
+                Some(value) => dead.insert((namespace.clone(), name.clone()), value.clone()),
+                None => dead.remove(&(namespace.clone(), name.clone())),
+            };
+        }
+    }
+
+    let mut out = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:multistatus xmlns:D=\"DAV:\">\
+         <D:response><D:href>{}</D:href>",
+        escape_xml(&encode_href(resource.url_path))
+    );
+    for (namespace, name, _) in &updates {
+        let status = match (protected, namespace == DAV_NAMESPACE) {
+            (false, _) => StatusCode::OK,
+            (true, true) => StatusCode::FORBIDDEN,
+            (true, false) => StatusCode::FAILED_DEPENDENCY,
+        };
+        out.push_str("<D:propstat><D:prop>");
+        write_property(&mut out, namespace, name, "");
+        let _ = write!(
+            out,
+            "</D:prop><D:status>{}</D:status></D:propstat>",
+            status_line(status)
+        );
+    }
+    out.push_str("</D:response></D:multistatus>");
+    respond(req, config, StatusCode::MULTI_STATUS, out)
+}
+
+fn mkcol(
+    resource: &Resource,
+    req: &Request<Bytes>,
+    config: &ServerConfig,
+) -> Result<Response<Bytes>, StatusCode> {
+    if !req.body().is_empty() {
+        return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+    if resource.path.exists() {
+        return Err(StatusCode::METHOD_NOT_ALLOWED);
+    }
+    if !resource.path.parent().is_some_and(Path::is_dir) {
+        return Err(StatusCode::CONFLICT);
+    }
+    dav_store().check(resource.path, false, &submitted_tokens(req))?;
+
+    fs::create_dir(resource.path).map_err(|e| io_status(e, resource.path))?;
+    respond(req, config, StatusCode::CREATED, String::new())
+}
+
+/// The file and URL path of the `Destination` of a `COPY` or `MOVE`.
+fn destination(
+    req: &Request<Bytes>,
+    config: &ServerConfig,
+) -> Result<(PathBuf, String), StatusCode> {
+    let destination = header(req, "destination").ok_or(StatusCode::BAD_REQUEST)?;
+    let uri = destination
+        .parse::<Uri>()
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    // Resources on other servers can't be reached
+    if let (Some(authority), Some(host)) = (uri.authority(), header(req, "host")) {
+        if !authority.as_str().eq_ignore_ascii_case(host) {
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    }
+    let (index, _) = path_exists(uri.path(), &config.routes).ok_or(StatusCode::FORBIDDEN)?;
+    let route = &config.routes[index];
+    if !is_webdav(route) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    // The client must be let into the route of the destination, like into the route of the request
+    let mut request = req.clone();
+    *request.uri_mut() = uri.clone();
+    if let Some(settings) = &route.settings {
+        check_access(&settings.access_rules, &request)?;
+    }
+    authorize(route, &mut request, config).map_err(|response| response.status())?;
+
+    let path = dav_path(route, uri.path()).ok_or(StatusCode::FORBIDDEN)?;
+    let url_path = decode_path(uri.path()).ok_or(StatusCode::FORBIDDEN)?;
+    Ok((path, url_path))
+}
+
+fn copy_or_move(
+    resource: &Resource,
+    req: &Request<Bytes>,
+    config: &ServerConfig,
+    moving: bool,
+    recursive_delete: bool,
+) -> Result<Response<Bytes>, StatusCode> {
+    let source = resource.path;
+    let metadata = fs::metadata(source).map_err(|_| StatusCode::NOT_FOUND)?;
+    let (target, _) = destination(req, config)?;
+    let overwrite = match header(req, "overwrite") {
+        None | Some("T") | Some("t") => true,
+        Some("F") | Some("f") => false,
+        Some(_) => return Err(StatusCode::BAD_REQUEST),
+    };
+    let recursive = match header(req, "depth") {
+        None | Some("infinity") => true,
+        Some("0") if !moving => false,
+        Some(_) => return Err(StatusCode::BAD_REQUEST),
+    };
+
+    // A collection can't be put inside of itself
+    if target == source || (metadata.is_dir() && target.starts_with(source)) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    if !target.parent().is_some_and(Path::is_dir) {
+        return Err(StatusCode::CONFLICT);
+    }
+    let exists = target.exists();
+    if exists && !overwrite {
+        return Err(StatusCode::PRECONDITION_FAILED);
+    }
+    // Overwriting a collection deletes its members, like DELETE does
+    if exists && !recursive_delete && has_members(&target) {
+        return Err(StatusCode::CONFLICT);
+    }
+    let tokens = submitted_tokens(req);
+    if moving {
+        dav_store().check(source, true, &tokens)?;
+    }
+    dav_store().check(&target, true, &tokens)?;
+
+    if exists {
+        remove(&target, recursive_delete).map_err(|e| io_status(e, &target))?;
+        dav_store().forget(&target);
+    }
+    let result = if moving {
+        fs::rename(source, &target)
+    } else {
+        copy(source, &target, recursive)
+    };
+    result.map_err(|e| io_status(e, source))?;
+
+    dav_store().copy_properties(source, &target);
+    if moving {
+        dav_store().forget(source);
+    }
+    log!(
+        LogLevel::Info,
+        LogFileType::Server,
+        format!(
+            "{} {} to {}",
+            if moving { "Moved" } else { "Copied" },
+            source.display(),
+            target.display()
+        )
+    );
+
+    let status = match exists {
+        true => StatusCode::NO_CONTENT,
+        false => StatusCode::CREATED,
+    };
+    respond(req, config, status, String::new())
+}
+
+/// Copies a file, or a directory with everything in it if `recursive` is set.
+fn copy(from: &Path, to: &Path, recursive: bool) -> io::Result<()> {
+    if !from.is_dir() {
+        return fs::copy(from, to).map(|_| ());
+    }
+    fs::create_dir(to)?;
+    if !recursive {
+        return Ok(());
+    }
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        copy(&entry.path(), &to.join(entry.file_name()), true)?;
+    }
+    Ok(())
+}
+
+/// Removes a file, or a directory, with everything in it if `recursive` is set.
+fn remove(path: &Path, recursive: bool) -> io::Result<()> {
+    match (path.is_dir(), recursive) {
+        (true, true) => fs::remove_dir_all(path),
+        (true, false) => fs::remove_dir(path),
+        (false, _) => fs::remove_file(path),
+    }
+}
+
+/// Returns `true` if `path` is a directory with something in it.
+fn has_members(path: &Path) -> bool {
+    fs::read_dir(path).is_ok_and(|mut entries| entries.next().is_some())
+}
+
+/// The status of a failed file operation on `path`.
+fn io_status(e: io::Error, path: &Path) -> StatusCode {
+    match e.kind() {
+        io::ErrorKind::NotFound => StatusCode::CONFLICT,
+        io::ErrorKind::PermissionDenied => StatusCode::FORBIDDEN,
+        _ => {
+            log!(
+                LogLevel::Error,
+                LogFileType::Server,
+                format!("WebDAV operation on {} failed. {e}", path.display())
+            );
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+fn delete(
+    resource: &Resource,
+    req: &Request<Bytes>,
+    config: &ServerConfig,
+    recursive: bool,
+) -> Result<Response<Bytes>, StatusCode> {
+    if !resource.path.exists() {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    // Collections with members are only removed if the route allows recursive deletes
+    if !recursive && has_members(resource.path) {
+        return Err(StatusCode::CONFLICT);
+    }
+    dav_store().check(resource.path, true, &submitted_tokens(req))?;
+    remove(resource.path, recursive).map_err(|e| io_status(e, resource.path))?;
+    dav_store().forget(resource.path);
+    respond(req, config, StatusCode::NO_CONTENT, String::new())
+}
+
+/// The `Timeout` the client asked for, within `MAX_LOCK_TIMEOUT`.
+fn lock_timeout(req: &Request<Bytes>) -> Duration {
+    let requested = header(req, "timeout")
+        .unwrap_or_default()
+        .split(',')
+        .find_map(|timeout| match timeout.trim() {
+            "Infinite" => Some(MAX_LOCK_TIMEOUT),
+            timeout => timeout
+                .strip_prefix("Second-")
+                .and_then(|seconds| seconds.parse().ok())
+                .map(Duration::from_secs),
+        });
+    requested
+        .unwrap_or(DEFAULT_LOCK_TIMEOUT)
+        .min(MAX_LOCK_TIMEOUT)
+}
+
+fn new_lock_token() -> String {
+    let mut bytes = [0; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    // Version 4 UUID
+    bytes[6] = bytes[6] & 0x0f | 0x40;
+    bytes[8] = bytes[8] & 0x3f | 0x80;
+    let hex = bytes.iter().map(|b| format!("{b:02x}")).collect::<String>();
+    format!(
+        "opaquelocktoken:{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+fn lock_resource(
+    resource: &Resource,
+    req: &Request<Bytes>,
+    config: &ServerConfig,
+) -> Result<Response<Bytes>, StatusCode> {
+    let timeout = lock_timeout(req);
+    let (lock, status) = match xml_body(req)? {
+        // Without a body, the lock in the `If` header is refreshed
+        None => {
+            let lock = submitted_tokens(req)
+                .iter()
+                .find_map(|token| dav_store().refresh(resource.path, token, timeout))
+                .ok_or(StatusCode::PRECONDITION_FAILED)?;
+            (lock, StatusCode::OK)
+        }
+        Some(info) => {
+            if !info.is(DAV_NAMESPACE, "lockinfo") {
+                return Err(StatusCode::BAD_REQUEST);
+            }
+            let scope = info.dav_child("lockscope").ok_or(StatusCode::BAD_REQUEST)?;
+            let exclusive = scope.dav_child("exclusive").is_some();
+            if !exclusive && scope.dav_child("shared").is_none() {
+                return Err(StatusCode::BAD_REQUEST);
+            }
+            if info
+                .dav_child("locktype")
+                .and_then(|locktype| locktype.dav_child("write"))
+                .is_none()
+            {
+                return Err(StatusCode::BAD_REQUEST);
+            }
+            let infinite = match header(req, "depth") {
+                None | Some("infinity") => true,
+                Some("0") => false,
+                Some(_) => return Err(StatusCode::BAD_REQUEST),
+            };
+            let owner = info.dav_child("owner").map(|owner| {
+                let mut xml = escape_xml(&owner.text);
+                for child in &owner.children {
+                    child.write(&mut xml);
+                }
+                xml
+            });
+
+            let lock = dav_store().add_lock(DavLock {
+                token: new_lock_token(),
+                path: resource.path.to_path_buf(),
+                root: resource.url_path.to_string(),
+                exclusive,
+                infinite,
+                owner,
+                timeout,
+                expires: Instant::now() + timeout,
+            })?;
+
+            // Locking a name that is not taken creates an empty file
+            let status = if resource.path.exists() {
+                StatusCode::OK
+            } else {
+                let created = resource.path.parent().is_some_and(Path::is_dir)
+                    && fs::File::create(resource.path).is_ok();
+                if !created {
+                    dav_store().unlock(resource.path, &lock.token);
+                    return Err(StatusCode::CONFLICT);
+                }
+                StatusCode::CREATED
+            };
+            (lock, status)
+        }
+    };
+
+    let mut out = String::from(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<D:prop xmlns:D=\"DAV:\"><D:lockdiscovery>",
+    );
+    lock.write_active(&mut out);
+    out.push_str("</D:lockdiscovery></D:prop>");
+
+    let mut response = respond(req, config, status, out)?;
+    if let Ok(value) = format!("<{}>", lock.token).parse() {
+        response.headers_mut().insert("lock-token", value);
+    }
+    Ok(response)
+}
+
+fn unlock_resource(
+    resource: &Resource,
+    req: &Request<Bytes>,
+    config: &ServerConfig,
+) -> Result<Response<Bytes>, StatusCode> {
+    let token = header(req, "lock-token")
+        .map(|token| token.trim_start_matches('<').trim_end_matches('>'))
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    if !dav_store().unlock(resource.path, token) {
+        return Err(StatusCode::CONFLICT);
+    }
+    respond(req, config, StatusCode::NO_CONTENT, String::new())
+}
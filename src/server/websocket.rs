@@ -0,0 +1,572 @@
+use crate::server::errors::error;
+use crate::server::{Bytes, Request, Response, ServerConfig, StatusCode};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use http::header::{
+    CONNECTION, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY, SEC_WEBSOCKET_VERSION, UPGRADE,
+};
+use http::{HeaderMap, HeaderValue, Method, Version};
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+
+/// Appended to `Sec-WebSocket-Key` to compute `Sec-WebSocket-Accept`.
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xa;
+
+pub const NORMAL_CLOSURE: u16 = 1000;
+pub const GOING_AWAY: u16 = 1001;
+pub const PROTOCOL_ERROR: u16 = 1002;
+/// Reported to the handler when the close frame had no status code.
+pub const NO_STATUS: u16 = 1005;
+/// Reported to the handler when the connection was lost without a close frame.
+pub const ABNORMAL_CLOSURE: u16 = 1006;
+pub const INVALID_PAYLOAD: u16 = 1007;
+pub const MESSAGE_TOO_BIG: u16 = 1009;
+
+/// # WebSocketMessage
+///
+/// A complete message, after joining its fragments.
+#[derive(Clone, Debug, PartialEq)]
+pub enum WebSocketMessage {
+    Text(String),
+    Binary(Bytes),
+}
+
+/// # WebSocketEvent
+///
+/// What happened on a WebSocket connection.
+#[derive(Debug)]
+pub enum WebSocketEvent<'r> {
+    /// The handshake of the request is done.
+    Open(&'r Request<Bytes>),
+    Message(WebSocketMessage),
+    /// The connection closed with this status code and reason.
+    Close(u16, String),
+}
+
+/// Handles the events of the WebSocket connections of a route. Handlers run on the event loop
+/// of the connection, so they should not block.
+pub type WebSocketHandler = fn(socket: &WebSocket, event: WebSocketEvent);
+
+/// # Outgoing
+///
+/// What the server sends on a WebSocket connection.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outgoing {
+    Message(WebSocketMessage),
+    Close(u16, String),
+}
+
+impl Outgoing {
+    fn frame(&self) -> Bytes {
+        match self {
+            Outgoing::Message(WebSocketMessage::Text(text)) => encode_frame(TEXT, text.as_bytes()),
+            Outgoing::Message(WebSocketMessage::Binary(data)) => encode_frame(BINARY, data),
+            Outgoing::Close(code, reason) => {
+                let mut payload = code.to_be_bytes().to_vec();
+                // Control frames are at most 125 bytes
+                payload.extend(reason.bytes().take(123));
+                encode_frame(CLOSE, &payload)
+            }
+        }
+    }
+}
+
+type Sender = Box<dyn Fn(Outgoing) -> bool + Send>;
+
+/// # WebSocketHub
+///
+/// The open WebSocket connections of all servers, so messages can be sent to them from any
+/// thread. Connections are grouped by scope, the host and path of their route.
+#[derive(Default)]
+pub struct WebSocketHub {
+    next_id: AtomicU64,
+    sockets: Mutex<HashMap<u64, (Arc<str>, Sender)>>,
+}
+
+impl fmt::Debug for WebSocketHub {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebSocketHub")
+            .field("sockets", &self.lock().len())
+            .finish()
+    }
+}
+
+impl WebSocketHub {
+    /// Adds a connection that `sender` delivers messages to.
+    pub fn register(
+        &self,
+        scope: &str,
+        sender: impl Fn(Outgoing) -> bool + Send + 'static,
+    ) -> WebSocket {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let scope: Arc<str> = Arc::from(scope);
+        self.lock()
+            .insert(id, (Arc::clone(&scope), Box::new(sender)));
+        WebSocket { id, scope }
+    }
+
+    pub fn unregister(&self, id: u64) {
+        self.lock().remove(&id);
+    }
+
+    /// Returns `false` if the connection is closed.
+    pub fn send(&self, id: u64, outgoing: Outgoing) -> bool {
+        self.lock()
+            .get(&id)
+            .is_some_and(|(_, sender)| sender(outgoing))
+    }
+
+    /// Sends `message` to every connection of `scope`. Returns the number of connections.
+    pub fn broadcast(&self, scope: &str, message: WebSocketMessage) -> usize {
+        self.lock()
+            .values()
+            .filter(|(socket_scope, _)| **socket_scope == *scope)
+            .filter(|(_, sender)| sender(Outgoing::Message(message.clone())))
+            .count()
+    }
+
+    /// Number of open connections of `scope`.
+    pub fn count(&self, scope: &str) -> usize {
+        self.lock()
+            .values()
+            .filter(|(socket_scope, _)| **socket_scope == *scope)
+            .count()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<u64, (Arc<str>, Sender)>> {
+        self.sockets.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// # websockets
+///
+/// The hub of the open WebSocket connections.
+pub fn websockets() -> &'static WebSocketHub {
+    static HUB: OnceLock<WebSocketHub> = OnceLock::new();
+    HUB.get_or_init(WebSocketHub::default)
+}
+
+/// # WebSocket
+///
+/// Handle to a WebSocket connection, given to its handler.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WebSocket {
+    id: u64,
+    scope: Arc<str>,
+}
+
+impl WebSocket {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// The host and path of the route of the connection, like `localhost/ws/chat`.
+    pub fn scope(&self) -> &str {
+        &self.scope
+    }
+
+    /// Returns `false` if the connection is closed.
+    pub fn send(&self, message: WebSocketMessage) -> bool {
+        websockets().send(self.id, Outgoing::Message(message))
+    }
+
+    /// Sends `message` to every connection of the route, this one included.
+    pub fn broadcast(&self, message: WebSocketMessage) -> usize {
+        websockets().broadcast(&self.scope, message)
+    }
+
+    /// Starts the closing handshake.
+    pub fn close(&self, code: u16, reason: &str) {
+        websockets().send(self.id, Outgoing::Close(code, reason.to_string()));
+    }
+}
+
+/// # WebSocketUpgrade
+///
+/// Response extension of a `101 Switching Protocols` response to a WebSocket handshake. The
+/// connection switches to the handler once the response is sent.
+#[derive(Clone, Debug)]
+pub struct WebSocketUpgrade {
+    pub handler: WebSocketHandler,
+    pub scope: String,
+    pub request: Request<Bytes>,
+}
+
+/// # accept_key
+///
+/// The `Sec-WebSocket-Accept` value for a `Sec-WebSocket-Key`.
+pub fn accept_key(key: &str) -> String {
+    STANDARD.encode(Sha1::digest(format!("{key}{GUID}").as_bytes()))
+}
+
+/// # upgrade_websocket
+///
+/// Answers a WebSocket handshake for a route with `handler`. Requests that are not a valid
+/// handshake get `426 Upgrade Required` or `400 Bad Request`.
+pub fn upgrade_websocket(
+    request: Request<Bytes>,
+    handler: WebSocketHandler,
+    scope: String,
+    config: &ServerConfig,
+) -> Response<Bytes> {
+    let accept = match handshake_key(&request) {
+        Ok(key) => accept_key(key),
+        Err(code) => {
+            let mut response = error(code, config);
+            if code == StatusCode::UPGRADE_REQUIRED {
+                let headers = response.headers_mut();
+                headers.insert(UPGRADE, HeaderValue::from_static("websocket"));
+                headers.insert(CONNECTION, HeaderValue::from_static("Upgrade"));
+                headers.insert(SEC_WEBSOCKET_VERSION, HeaderValue::from_static("13"));
+            }
+            return response;
+        }
+    };
+
+    let mut response = Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header(UPGRADE, "websocket")
+        .header(CONNECTION, "Upgrade")
+        .header(SEC_WEBSOCKET_ACCEPT, accept)
+        .body(Bytes::new())
+        .unwrap_or_default();
+    response.extensions_mut().insert(WebSocketUpgrade {
+        handler,
+        scope,
+        request,
+    });
+    response
+}
+
+/// `handshake_key` checks the handshake headers of a request and gets its `Sec-WebSocket-Key`.
+fn handshake_key(request: &Request<Bytes>) -> Result<&str, StatusCode> {
+    let headers = request.headers();
+    if request.version() != Version::HTTP_11
+        || !has_token(headers, UPGRADE, "websocket")
+        || !has_token(headers, CONNECTION, "upgrade")
+        || headers.get(SEC_WEBSOCKET_VERSION).map(|v| v.as_bytes()) != Some(b"13")
+    {
+        return Err(StatusCode::UPGRADE_REQUIRED);
+    }
+    if request.method() != Method::GET {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let key = headers
+        .get(SEC_WEBSOCKET_KEY)
+        .and_then(|key| key.to_str().ok())
+        .map(str::trim)
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    match STANDARD.decode(key) {
+        Ok(nonce) if nonce.len() == 16 => Ok(key),
+        _ => Err(StatusCode::BAD_REQUEST),
+    }
+}
+
+fn has_token(headers: &HeaderMap, name: http::header::HeaderName, token: &str) -> bool {
+    headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|value| value.trim().eq_ignore_ascii_case(token))
+}
+
+/// # encode_frame
+///
+/// A single unmasked frame, as servers send them.
+pub fn encode_frame(opcode: u8, payload: &[u8]) -> Bytes {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        len @ 0..=125 => frame.push(len as u8),
+        len @ 126..=0xffff => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+#[derive(Debug)]
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Bytes,
+}
+
+/// `parse_frame` gets the first frame of `buf` and its length, or `None` if more bytes are needed.
+fn parse_frame(buf: &[u8], max_size: usize) -> Result<Option<(Frame, usize)>, u16> {
+    let (first, second) = match buf {
+        [first, second, ..] => (*first, *second),
+        _ => return Ok(None),
+    };
+    let fin = first & 0x80 != 0;
+    let opcode = first & 0x0f;
+
+    // No extensions are negotiated, so the reserved bits must be clear
+    if first & 0x70 != 0 || !matches!(opcode, CONTINUATION | TEXT | BINARY | CLOSE | PING | PONG) {
+        return Err(PROTOCOL_ERROR);
+    }
+    // Clients must mask their frames
+    if second & 0x80 == 0 {
+        return Err(PROTOCOL_ERROR);
+    }
+
+    let (length, mut pos) = match second & 0x7f {
+        126 => match buf.get(2..4) {
+            Some(len) => (u16::from_be_bytes([len[0], len[1]]) as u64, 4),
+            None => return Ok(None),
+        },
+        127 => match buf.get(2..10) {
+            Some(len) => (u64::from_be_bytes(len.try_into().unwrap_or_default()), 10),
+            None => return Ok(None),
+        },
+        len => (len as u64, 2),
+    };
+
+    if opcode >= CLOSE && (!fin || length > 125) {
+        return Err(PROTOCOL_ERROR);
+    }
+    if length > max_size as u64 {
+        return Err(MESSAGE_TOO_BIG);
+    }
+    let length = length as usize;
+
+    let mask = match buf.get(pos..pos + 4) {
+        Some(mask) => [mask[0], mask[1], mask[2], mask[3]],
+        None => return Ok(None),
+    };
+    pos += 4;
+    let payload = match buf.get(pos..pos + length) {
+        Some(payload) => payload
+            .iter()
+            .enumerate()
+            .map(|(i, byte)| byte ^ mask[i % 4])
+            .collect(),
+        None => return Ok(None),
+    };
+
+    Ok(Some((
+        Frame {
+            fin,
+            opcode,
+            payload,
+        },
+        pos + length,
+    )))
+}
+
+/// # WebSocketConnection
+///
+/// The server side of a WebSocket connection, without the I/O. Frames read from the client go
+/// into `receive`, which passes complete messages to the handler and answers pings. Frames to
+/// send to the client are collected until `take_output`.
+#[derive(Debug)]
+pub struct WebSocketConnection {
+    socket: WebSocket,
+    handler: WebSocketHandler,
+    input: Bytes,
+    output: Bytes,
+    /// Opcode and payload of a message that continues in the next frames.
+    fragments: Option<(u8, Bytes)>,
+    max_message_size: usize,
+    close_sent: bool,
+    /// Set once the handler was told the connection closed.
+    closed: bool,
+}
+
+impl WebSocketConnection {
+    pub fn new(socket: WebSocket, handler: WebSocketHandler, max_message_size: usize) -> Self {
+        Self {
+            socket,
+            handler,
+            input: Bytes::new(),
+            output: Bytes::new(),
+            fragments: None,
+            max_message_size,
+            close_sent: false,
+            closed: false,
+        }
+    }
+
+    /// Tells the handler the handshake of `request` is done.
+    pub fn open(&mut self, request: &Request<Bytes>) {
+        (self.handler)(&self.socket, WebSocketEvent::Open(request));
+    }
+
+    /// Adds bytes read from the client and handles the frames that are complete.
+    pub fn receive(&mut self, bytes: &[u8]) {
+        self.input.extend_from_slice(bytes);
+
+        let mut pos = 0;
+        while !self.closed {
+            match parse_frame(&self.input[pos..], self.max_message_size) {
+                Ok(Some((frame, length))) => {
+                    pos += length;
+                    if let Err(code) = self.frame(frame) {
+                        self.fail(code);
+                    }
+                }
+                Ok(None) => break,
+                Err(code) => self.fail(code),
+            }
+        }
+        self.input.drain(..pos.min(self.input.len()));
+    }
+
+    /// Queues a message or close frame, unless the connection is closing.
+    pub fn send(&mut self, outgoing: Outgoing) {
+        if self.close_sent {
+            return;
+        }
+        self.close_sent = matches!(outgoing, Outgoing::Close(..));
+        self.output.extend(outgoing.frame());
+    }
+
+    /// Tells the handler the connection closed, and removes it from the hub. Only the first
+    /// call has an effect.
+    pub fn finish(&mut self, code: u16, reason: &str) {
+        if !self.closed {
+            self.closed = true;
+            websockets().unregister(self.socket.id);
+            (self.handler)(
+                &self.socket,
+                WebSocketEvent::Close(code, reason.to_string()),
+            );
+        }
+    }
+
+    /// Frames to send to the client.
+    pub fn take_output(&mut self) -> Bytes {
+        std::mem::take(&mut self.output)
+    }
+
+    /// Returns `true` if the server started the closing handshake.
+    pub fn is_closing(&self) -> bool {
+        self.close_sent
+    }
+
+    /// Returns `true` once the connection can be closed.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    pub fn socket(&self) -> &WebSocket {
+        &self.socket
+    }
+
+    fn frame(&mut self, frame: Frame) -> Result<(), u16> {
+        match frame.opcode {
+            CONTINUATION => {
+                let (opcode, mut payload) = self.fragments.take().ok_or(PROTOCOL_ERROR)?;
+                payload.extend(frame.payload);
+                if payload.len() > self.max_message_size {
+                    return Err(MESSAGE_TOO_BIG);
+                }
+                match frame.fin {
+                    true => self.message(opcode, payload),
+                    false => {
+                        self.fragments = Some((opcode, payload));
+                        Ok(())
+                    }
+                }
+            }
+            TEXT | BINARY => {
+                if self.fragments.is_some() {
+                    return Err(PROTOCOL_ERROR);
+                }
+                match frame.fin {
+                    true => self.message(frame.opcode, frame.payload),
+                    false => {
+                        self.fragments = Some((frame.opcode, frame.payload));
+                        Ok(())
+                    }
+                }
+            }
+            PING => {
+                if !self.close_sent {
+                    self.output.extend(encode_frame(PONG, &frame.payload));
+                }
+                Ok(())
+            }
+            PONG => Ok(()),
+            _ => {
+                let (code, reason) = close_payload(&frame.payload)?;
+                // Answer with the same code, unless the server is the one closing
+                self.send(Outgoing::Close(
+                    if code == NO_STATUS {
+                        NORMAL_CLOSURE
+                    } else {
+                        code
+                    },
+                    String::new(),
+                ));
+                self.finish(code, &reason);
+                Ok(())
+            }
+        }
+    }
+
+    fn message(&mut self, opcode: u8, payload: Bytes) -> Result<(), u16> {
+        let message = match opcode {
+            TEXT => {
+                WebSocketMessage::Text(String::from_utf8(payload).map_err(|_| INVALID_PAYLOAD)?)
+            }
+            _ => WebSocketMessage::Binary(payload),
+        };
+        (self.handler)(&self.socket, WebSocketEvent::Message(message));
+        Ok(())
+    }
+
+    fn fail(&mut self, code: u16) {
+        self.send(Outgoing::Close(code, String::new()));
+        self.finish(code, "");
+    }
+}
+
+impl Drop for WebSocketConnection {
+    fn drop(&mut self) {
+        self.finish(ABNORMAL_CLOSURE, "");
+    }
+}
+
+/// `close_payload` gets the status code and reason of a close frame.
+fn close_payload(payload: &[u8]) -> Result<(u16, String), u16> {
+    match payload {
+        [] => Ok((NO_STATUS, String::new())),
+        [_] => Err(PROTOCOL_ERROR),
+        [a, b, reason @ ..] => {
+            let code = u16::from_be_bytes([*a, *b]);
+            let valid = matches!(code, 1000..=1003 | 1007..=1011 | 3000..=4999);
+            if !valid {
+                return Err(PROTOCOL_ERROR);
+            }
+            let reason = String::from_utf8(reason.to_vec()).map_err(|_| INVALID_PAYLOAD)?;
+            Ok((code, reason))
+        }
+    }
+}
+
+/// # chat
+///
+/// Demo WebSocket handler that sends every message to all clients connected to the route.
+pub fn chat(socket: &WebSocket, event: WebSocketEvent) {
+    if let WebSocketEvent::Message(message) = event {
+        socket.broadcast(message);
+    }
+}
@@ -1,8 +1,14 @@
-use config::route::Settings;
+use config::route::{BasicAuth, BearerAuth, Jwt, Settings, Tus, Upload};
 use http::StatusCode;
 use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
 
-use crate::server::{cookie_demo, update_cookie, validate_cookie, Cgi};
+use crate::log::{LogLevel, LogRotation, LogSettings, COMBINED_LOG_FORMAT};
+use crate::server::{
+    chat, cookie_demo, events_demo, form_demo, health_check, prometheus_metrics, server_status,
+    update_cookie, validate_cookie, webdav_methods, whoami, Cgi,
+};
 pub use crate::server_config::*;
 
 // Function to configure the server settings
@@ -30,6 +36,8 @@ pub fn server_config() -> Vec<ServerConfig<'static>> {
                 methods: vec![http::Method::POST],
                 // Handler function for the route. Change 'update_cookie' to your custom function if required.
                 handler: Some(update_cookie),
+                // WebSocket handler for routes that upgrade the connection. See the '/ws/chat' route.
+                websocket: None,
                 // Route-specific settings. Leave as 'None' for default settings.
                 settings: None,
             },
@@ -38,18 +46,116 @@ pub fn server_config() -> Vec<ServerConfig<'static>> {
                 url_path: "/api/get-cookie",
                 methods: vec![http::Method::GET],
                 handler: Some(validate_cookie),
+                websocket: None,
                 settings: None,
             },
             Route {
                 url_path: "/api/cookie-demo",
                 methods: vec![http::Method::GET],
                 handler: Some(cookie_demo),
+                websocket: None,
                 settings: None,
             },
+            Route {
+                // Messages sent to the route are broadcast to every client connected to it.
+                url_path: "/ws/chat",
+                methods: vec![http::Method::GET],
+                handler: None,
+                websocket: Some(chat),
+                settings: None,
+            },
+            Route {
+                // GET streams the events of the 'demo' channel, POST publishes its body to them.
+                url_path: "/api/events",
+                methods: vec![http::Method::GET, http::Method::POST],
+                handler: Some(events_demo),
+                websocket: None,
+                settings: None,
+            },
+            Route {
+                // Responds with the fields and files of urlencoded and multipart forms as JSON.
+                url_path: "/api/form",
+                methods: vec![http::Method::POST],
+                handler: Some(form_demo),
+                websocket: None,
+                settings: None,
+            },
+            Route {
+                // Counters and histograms of the server in the Prometheus text format.
+                url_path: "/metrics",
+                methods: vec![http::Method::GET],
+                handler: Some(prometheus_metrics),
+                websocket: None,
+                settings: Some(Settings {
+                    // The routes and traffic of the server are for admins, like the status page.
+                    // Scrapers authenticate with basic auth.
+                    basic_auth: Some(BasicAuth {
+                        realm: "metrics",
+                        htpasswd_path: "/.htpasswd",
+                    }),
+                    access_rules: vec![
+                        AccessRule::Allow("127.0.0.0/8"),
+                        AccessRule::Allow("::1"),
+                        AccessRule::Deny("all"),
+                    ],
+                    ..Settings::default()
+                }),
+            },
+            Route {
+                // Liveness check for load balancers. Answers '200 OK' while the server is up.
+                url_path: "/healthz",
+                methods: vec![http::Method::GET, http::Method::HEAD],
+                handler: Some(health_check),
+                websocket: None,
+                settings: None,
+            },
+            Route {
+                // Uptime, servers, connections, request totals and recent errors, in HTML or
+                // with '?format=json' in JSON.
+                url_path: "/status",
+                methods: vec![http::Method::GET],
+                handler: Some(server_status),
+                websocket: None,
+                settings: Some(Settings {
+                    // The page shows clients and errors, so keep it to admins on this machine.
+                    basic_auth: Some(BasicAuth {
+                        realm: "status",
+                        htpasswd_path: "/.htpasswd",
+                    }),
+                    access_rules: vec![
+                        AccessRule::Allow("127.0.0.0/8"),
+                        AccessRule::Allow("::1"),
+                        AccessRule::Deny("all"),
+                    ],
+                    ..Settings::default()
+                }),
+            },
+            Route {
+                url_path: "/api/whoami",
+                methods: vec![http::Method::GET],
+                handler: Some(whoami),
+                websocket: None,
+                settings: Some(Settings {
+                    // Require a static token or a HS256 JWT in the 'Authorization: Bearer' header.
+                    bearer_auth: Some(BearerAuth {
+                        realm: "api",
+                        tokens: vec!["change-me-to-a-long-random-token"],
+                        jwt: Some(Jwt {
+                            secret: "change-me-to-a-long-random-secret",
+                            issuer: Some("localhost"),
+                            audience: Some("localhost-api"),
+                            leeway: 30,
+                        }),
+                        required_scopes: vec![],
+                    }),
+                    ..Settings::default()
+                }),
+            },
             Route {
                 url_path: "/cgi",
                 methods: vec![http::Method::GET],
                 handler: None, // No specific handler means processing is defined by 'settings'.
+                websocket: None,
                 settings: Some(Settings {
                     // Configuration for CGI scripts.
                     cgi_def: Some(HashMap::from([
@@ -61,77 +167,223 @@ pub fn server_config() -> Vec<ServerConfig<'static>> {
                     ])),
                     // Enable directory listing for this route. Set to 'false' to disable.
                     list_directory: true,
+                    // Spawning a process per request is expensive, so limit each client further.
+                    rate_limit: Some(RateLimit {
+                        per_second: 5.0,
+                        burst: 10,
+                    }),
                     // Additional CGI settings can be configured here.
-                    // Leave as 'None' for defaults or specify to customize behavior.
-                    http_redirections: None,
-                    redirect_status_code: None,
-                    root_path: None,
-                    default_if_url_is_dir: None,
-                    default_if_request_is_dir: None,
+                    // Leave out the others for defaults or specify to customize behavior.
+                    ..Settings::default()
                 }),
             },
             Route {
                 url_path: "/test.txt",
                 methods: vec![http::Method::GET, http::Method::POST],
                 handler: None,
+                websocket: None,
                 settings: Some(Settings {
                     http_redirections: Some(vec!["/redirection-test"]),
                     redirect_status_code: Some(StatusCode::from_u16(301).unwrap()),
                     root_path: Some("/files"),
-                    default_if_url_is_dir: None,
-                    default_if_request_is_dir: None,
-                    cgi_def: None,
-                    list_directory: false,
+                    ..Settings::default()
                 }),
             },
             Route {
                 url_path: "/mega-dir",
                 methods: vec![http::Method::GET],
                 handler: None,
+                websocket: None,
                 settings: Some(Settings {
-                    http_redirections: None,
-                    redirect_status_code: None,
                     root_path: Some("/files"),
                     default_if_url_is_dir: Some("/dir.html"),
-                    default_if_request_is_dir: None,
-                    cgi_def: None,
-                    list_directory: false,
+                    ..Settings::default()
                 }),
             },
             Route {
                 url_path: "/src",
                 methods: vec![http::Method::GET],
                 handler: None,
+                websocket: None,
                 settings: Some(Settings {
-                    http_redirections: None,
-                    redirect_status_code: None,
-                    root_path: None,
                     default_if_url_is_dir: Some("/does-not-exist-mate"),
-                    default_if_request_is_dir: None,
-                    cgi_def: None,
-                    list_directory: false,
+                    ..Settings::default()
                 }),
             },
             Route {
                 url_path: "/files",
+                // WebDAV clients ask for the features of the share with OPTIONS.
+                methods: [
+                    vec![
+                        http::Method::GET,
+                        http::Method::HEAD,
+                        http::Method::OPTIONS,
+                        http::Method::POST,
+                        http::Method::PUT,
+                        http::Method::PATCH,
+                        http::Method::DELETE,
+                    ],
+                    webdav_methods(),
+                ]
+                .concat(),
+                handler: None,
+                websocket: None,
+                settings: Some(Settings {
+                    list_directory: true,
+                    // Also serve the files as a WebDAV share, to mount as a network drive.
+                    webdav: true,
+                    create_parent_dirs: true,
+                    // WebDAV clients delete folders with everything in them.
+                    recursive_delete: true,
+                    // Require a user from the htpasswd file. Set to 'None' to allow anyone.
+                    basic_auth: Some(BasicAuth {
+                        realm: "files",
+                        htpasswd_path: "/.htpasswd",
+                    }),
+                    // Only allow uploads from this machine and private networks.
+                    access_rules: vec![
+                        AccessRule::Allow("127.0.0.0/8"),
+                        AccessRule::Allow("::1"),
+                        AccessRule::Allow("10.0.0.0/8"),
+                        AccessRule::Allow("172.16.0.0/12"),
+                        AccessRule::Allow("192.168.0.0/16"),
+                        AccessRule::Deny("all"),
+                    ],
+                    ..Settings::default()
+                }),
+            },
+            Route {
+                url_path: "/uploads",
+                methods: vec![http::Method::GET, http::Method::POST],
+                handler: None,
+                websocket: None,
+                settings: Some(Settings {
+                    // Files are stored in './files/uploads'.
+                    root_path: Some("/files"),
+                    list_directory: true,
+                    // Store the files of 'multipart/form-data' forms posted to the route.
+                    upload: Some(Upload {
+                        max_file_size: Some(10 * 1024 * 1024),
+                        max_total_size: Some(50 * 1024 * 1024),
+                        allowed_extensions: vec!["txt", "pdf", "png", "jpg", "jpeg", "gif"],
+                    }),
+                    basic_auth: Some(BasicAuth {
+                        realm: "files",
+                        htpasswd_path: "/.htpasswd",
+                    }),
+                    ..Settings::default()
+                }),
+            },
+            Route {
+                url_path: "/tus",
                 methods: vec![
-                    http::Method::GET,
+                    http::Method::OPTIONS,
                     http::Method::POST,
-                    http::Method::PUT,
+                    http::Method::HEAD,
                     http::Method::PATCH,
                     http::Method::DELETE,
                 ],
                 handler: None,
+                websocket: None,
                 settings: Some(Settings {
-                    http_redirections: None,
-                    redirect_status_code: None,
-                    root_path: None,
-                    default_if_url_is_dir: None,
-                    default_if_request_is_dir: None,
-                    cgi_def: None,
-                    list_directory: true,
+                    // Finished uploads are stored in './files/tus'.
+                    root_path: Some("/files"),
+                    // Resumable uploads. Unfinished uploads are removed a day after their last part.
+                    tus: Some(Tus {
+                        max_size: Some(1024 * 1024 * 1024),
+                        expiration: Some(Duration::from_secs(24 * 60 * 60)),
+                    }),
+                    basic_auth: Some(BasicAuth {
+                        realm: "files",
+                        htpasswd_path: "/.htpasswd",
+                    }),
+                    ..Settings::default()
                 }),
             },
         ],
+
+        // Secrets used to sign and encrypt cookies. New cookies use the first key.
+        // To rotate, put a new key first and keep the old ones until their cookies expire.
+        cookie_keys: vec!["change-me-to-a-long-random-secret"],
+
+        // Allow or deny clients for every route, checked in order. Leave empty to allow everyone.
+        access_rules: vec![],
+
+        // Proxies that are trusted to set the 'X-Forwarded-For' header. Leave empty when not behind a proxy.
+        trusted_proxies: vec![],
+
+        // Maximum number of open connections, in total and from a single client. 'None' for no limit.
+        max_connections: Some(1024),
+        max_connections_per_ip: Some(64),
+
+        // Requests per second a single client can make to any route. 'None' for no limit.
+        rate_limit: Some(RateLimit {
+            per_second: 50.0,
+            burst: 100,
+        }),
+
+        // Limits for the request line and headers. Larger requests are rejected before the body is read.
+        max_request_line_length: 8 * 1024,
+        max_header_count: 100,
+        max_header_bytes: 16 * 1024,
+
+        // Clients that stay silent, or send the headers or the body too slowly, are disconnected.
+        // A request cut off by a timeout gets '408 Request Timeout'. 'None' to wait forever.
+        idle_timeout: Some(Duration::from_secs(10)),
+        header_timeout: Some(Duration::from_secs(10)),
+        body_timeout: Some(Duration::from_secs(60)),
+
+        // Time to wait for the next request on a connection. 'None' closes the connection after each response.
+        keep_alive_timeout: Some(Duration::from_secs(5)),
+
+        // Event loop threads for the connections, and threads that run handlers, CGI and file
+        // responses so slow ones don't block the event loops. '1' and '0' serve everything on one thread.
+        workers: 4,
+        handler_threads: 8,
+
+        // HTTPS on some of the ports, for example:
+        // tls: Some(Tls {
+        //     ports: vec![8443],
+        //     certificates: vec![Certificate {
+        //         server_names: vec!["localhost"],
+        //         cert_path: "./certs/localhost.pem",
+        //         key_path: "./certs/localhost-key.pem",
+        //     }],
+        //     redirect_http: true,
+        // }),
+        tls: None,
+
+        // HTTP/2 over TLS with ALPN, and over plain HTTP for clients that ask for it.
+        http2: true,
+
+        // Comment sent on idle event streams, like '/api/events', to keep them open.
+        event_stream_heartbeat: Some(Duration::from_secs(15)),
+
+        // Line written to 'access.log' after every response. Set to 'None' to keep no access log.
+        access_log: Some(COMBINED_LOG_FORMAT),
     }]
 }
+
+// Function to configure the server and client logs, shared by all servers
+pub fn log_settings() -> LogSettings {
+    LogSettings {
+        // Least severe lines that are written: Error, Warn, Info, Debug or Trace.
+        level: LogLevel::Info,
+
+        // Write each line as a JSON object, for log collectors. Set to 'false' for plain text.
+        json: false,
+
+        // Directory of 'server.log', 'client.log' and 'access.log'. Overridden by '--log-dir <path>'.
+        dir: PathBuf::from("./logs"),
+
+        // Replace the log files when they would grow past 10 MB. Use 'LogRotation::Daily' to
+        // replace them every day, or 'LogRotation::Startup' to only replace them on startup.
+        rotation: LogRotation::Size(10 * 1024 * 1024),
+
+        // Replaced files kept per log. Set to 'None' to keep all of them.
+        retention: Some(10),
+
+        // Gzip replaced files.
+        compress: true,
+    }
+}
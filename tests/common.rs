@@ -1,34 +1,103 @@
-use lazy_static::lazy_static;
 use localhost::log;
-use localhost::log::{init_logs, LogFileType};
-use localhost::server::{content_type, start};
-use localhost::server_config::server_config;
+use localhost::log::{init_logs, LogFileType, LogLevel};
+use localhost::server::{content_type, start, ServerHandle};
+use localhost::server_config::{log_settings, server_config, ServerConfig};
 use reqwest::blocking::Client;
 use reqwest::header::CONTENT_TYPE;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Read;
-use std::sync::{Mutex, Once};
-use std::thread;
+use std::net::SocketAddr;
+use std::ops::Deref;
+use std::sync::{Once, OnceLock};
 
-lazy_static! {
-    static ref SERVER_STARTED: Mutex<()> = Mutex::new(());
-    static ref SERVER_INITIALIZATION: Once = Once::new();
+/// Credentials of the user in the htpasswd file generated for the tests.
+pub const TEST_USER: &str = "tester";
+pub const TEST_PASSWORD: &str = "tester-password";
+
+/// # TestServer
+///
+/// A server started for a test. It is shut down and its threads are joined when dropped,
+/// also when the test fails.
+pub struct TestServer {
+    handle: ServerHandle,
+    pub addr: SocketAddr,
+}
+
+#[allow(dead_code)]
+impl TestServer {
+    /// Starts a server with `config`. It is bound when this returns.
+    pub fn start(config: ServerConfig<'static>) -> Self {
+        let handle = start(vec![config]).expect("Failed to start server");
+        let addr = handle.local_addrs()[0];
+        Self { handle, addr }
+    }
+
+    /// URL of `path` on the server.
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{path}", self.addr)
+    }
 }
 
-pub fn setup() {
-    SERVER_INITIALIZATION.call_once(|| {
-        // Start the server only once
-        let _ = thread::spawn(|| {
-            init_logs();
-            start(server_config());
-        });
-    });
+impl Deref for TestServer {
+    type Target = ServerHandle;
+
+    fn deref(&self) -> &ServerHandle {
+        &self.handle
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.handle.shutdown();
+        self.handle.wait();
+    }
+}
 
-    // Wait for the server to start (this is a simplistic example, adjust as needed)
-    thread::sleep(std::time::Duration::from_secs(1));
-    println!("Server setup completed");
+/// The demo server on a free port, with the htpasswd file of the tests.
+#[allow(dead_code)]
+pub fn test_config() -> ServerConfig<'static> {
+    let mut config = with_test_htpasswd(server_config()).remove(0);
+    config.ports = vec![0];
+    config
+}
+
+/// Starts the demo server for a test, with the logs of the demo configuration.
+#[allow(dead_code)]
+pub fn setup() -> TestServer {
+    static LOGS: Once = Once::new();
+    LOGS.call_once(|| init_logs(log_settings()));
+    TestServer::start(test_config())
+}
+
+/// Writes an htpasswd file with the test user, outside the served directories, and returns its path.
+#[allow(dead_code)]
+pub fn test_htpasswd() -> &'static str {
+    static PATH: OnceLock<&'static str> = OnceLock::new();
+    PATH.get_or_init(|| {
+        let path = format!("/target/test-htpasswd-{}", std::process::id());
+        let hash = bcrypt::hash(TEST_PASSWORD, 4).unwrap();
+        fs::create_dir_all("./target").unwrap();
+        fs::write(format!(".{path}"), format!("{TEST_USER}:{hash}\n")).unwrap();
+        Box::leak(path.into_boxed_str())
+    })
+}
+
+/// Points the routes protected by basic authentication to the htpasswd file of the tests.
+#[allow(dead_code)]
+pub fn with_test_htpasswd(mut configs: Vec<ServerConfig<'static>>) -> Vec<ServerConfig<'static>> {
+    let settings = configs
+        .iter_mut()
+        .flat_map(|config| config.routes.iter_mut())
+        .filter_map(|route| route.settings.as_mut());
+    for settings in settings {
+        if let Some(auth) = settings.basic_auth.as_mut() {
+            auth.htpasswd_path = test_htpasswd();
+        }
+    }
+    configs
 }
 
+#[allow(dead_code)]
 pub fn send_request(
     client: &Client,
     url: &str,
@@ -47,8 +116,10 @@ pub fn send_request(
         _ => client.get(url),
     };
 
+    // Credentials for the routes protected by basic authentication
     request_builder = request_builder
         .header(CONTENT_TYPE, content_type(url))
+        .basic_auth(TEST_USER, Some(TEST_PASSWORD))
         .body(body);
 
     let debug_info = request_builder.try_clone().expect("Body is a stream.");
@@ -56,6 +127,7 @@ pub fn send_request(
         Ok(r) => r,
         Err(e) => {
             log!(
+                LogLevel::Error,
                 LogFileType::Server,
                 format!("Test failed {e}. Request builder: {debug_info:?}")
             );
@@ -64,6 +136,7 @@ pub fn send_request(
     };
     response
 }
+#[allow(dead_code)]
 pub fn get_buffer(path: &str) -> Vec<u8> {
     let mut file = File::open(path).unwrap();
     let mut buf = Vec::new();
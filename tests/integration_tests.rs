@@ -1,5 +1,4 @@
 use common::setup;
-use reqwest;
 
 mod common;
 mod test_config {
@@ -15,11 +14,162 @@ mod test_config {
         }
     }
 }
+mod test_lifecycle {
+    use crate::common::{test_config, TestServer};
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn drains_in_flight_requests() {
+        let server = TestServer::start(test_config());
+        let addr = server.addr;
+
+        // A request that is still being sent when the shutdown starts
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /test.txt HTTP/1.1\r\nHost: localhost\r\n")
+            .unwrap();
+        thread::sleep(Duration::from_millis(100));
+
+        server.shutdown_with_timeout(Duration::from_secs(5));
+        thread::sleep(Duration::from_millis(100));
+        assert!(
+            TcpStream::connect(addr).is_err(),
+            "listener should be closed"
+        );
+
+        stream.write_all(b"\r\n").unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        assert!(String::from_utf8_lossy(&response).starts_with("HTTP/1.1 200"));
+
+        server.wait();
+    }
+
+    fn get(addr: std::net::SocketAddr) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /test.txt HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        String::from_utf8_lossy(&response).into_owned()
+    }
+
+    fn wait_for_addrs_change(
+        server: &localhost::server::ServerHandle,
+        old: &[std::net::SocketAddr],
+    ) -> Vec<std::net::SocketAddr> {
+        for _ in 0..50 {
+            let addrs = server.local_addrs();
+            if addrs != old {
+                return addrs;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        server.local_addrs()
+    }
+
+    #[test]
+    fn serves_on_workers_and_handler_threads() {
+        let mut config = test_config();
+        config.workers = 3;
+        config.handler_threads = 2;
+        config.rate_limit = None;
+        config.max_connections_per_ip = None;
+        let server = TestServer::start(config);
+        let addr = server.addr;
+
+        let clients = (0..8)
+            .map(|_| thread::spawn(move || (0..5).map(|_| get(addr)).collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+        for client in clients {
+            for response in client.join().unwrap() {
+                assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+            }
+        }
+
+        // Pipelined requests on a kept alive connection come back in order from the handler threads
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(
+                b"GET /test.txt HTTP/1.1\r\nHost: localhost\r\n\r\n\
+                  GET /no-such-file HTTP/1.1\r\nConnection: close\r\n\r\n",
+            )
+            .unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        let response = String::from_utf8_lossy(&response);
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.contains("HTTP/1.1 404"));
+    }
+
+    #[test]
+    fn reload_moves_listeners() {
+        let config = test_config();
+        let server = TestServer::start(config.clone());
+        let old_addrs = server.local_addrs();
+
+        let mut in_flight = TcpStream::connect(old_addrs[0]).unwrap();
+        in_flight.write_all(b"GET /test.txt HTTP/1.1\r\n").unwrap();
+        thread::sleep(Duration::from_millis(100));
+
+        // Port 0 binds a new port, so the old listener is closed
+        server.reload(vec![config.clone()]);
+        let new_addrs = wait_for_addrs_change(&server, &old_addrs);
+        assert_eq!(new_addrs.len(), 1);
+        assert_ne!(new_addrs, old_addrs);
+        assert!(TcpStream::connect(old_addrs[0]).is_err());
+        assert!(get(new_addrs[0]).starts_with("HTTP/1.1 200"));
+
+        // The open connection finishes on the old configuration
+        in_flight.write_all(b"Connection: close\r\n\r\n").unwrap();
+        let mut response = Vec::new();
+        in_flight.read_to_end(&mut response).unwrap();
+        assert!(String::from_utf8_lossy(&response).starts_with("HTTP/1.1 200"));
+
+        // A configuration without usable ports is rejected and the server keeps running
+        let mut broken = config;
+        broken.host = "invalid host";
+        server.reload(vec![broken]);
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(server.local_addrs(), new_addrs);
+        assert!(get(new_addrs[0]).starts_with("HTTP/1.1 200"));
+    }
+
+    #[test]
+    fn reload_keeps_connection_counts() {
+        let mut config = test_config();
+        config.max_connections_per_ip = Some(1);
+        let server = TestServer::start(config.clone());
+        let old_addrs = server.local_addrs();
+
+        let mut in_flight = TcpStream::connect(old_addrs[0]).unwrap();
+        in_flight.write_all(b"GET /test.txt HTTP/1.1\r\n").unwrap();
+        thread::sleep(Duration::from_millis(100));
+
+        // The connection opened before the reload still counts against the limit
+        server.reload(vec![config]);
+        let new_addrs = wait_for_addrs_change(&server, &old_addrs);
+        let mut rejected = TcpStream::connect(new_addrs[0]).unwrap();
+        let mut response = Vec::new();
+        rejected.read_to_end(&mut response).unwrap();
+        assert!(String::from_utf8_lossy(&response).starts_with("HTTP/1.1 429"));
+
+        in_flight.write_all(b"Connection: close\r\n\r\n").unwrap();
+        let mut response = Vec::new();
+        in_flight.read_to_end(&mut response).unwrap();
+        assert!(String::from_utf8_lossy(&response).starts_with("HTTP/1.1 200"));
+        thread::sleep(Duration::from_millis(100));
+        assert!(get(new_addrs[0]).starts_with("HTTP/1.1 200"));
+    }
+}
 mod test_handle_client {
     use lazy_static::lazy_static;
     use std::thread;
     use std::time::Duration;
-    const HOST: &str = "http://127.0.0.1:8080";
     lazy_static! {
         static ref CLIENT: Client = Client::new();
     }
@@ -32,11 +182,11 @@ mod test_handle_client {
 
     #[test]
     fn cgi_request() {
-        setup();
+        let server = setup();
         let valid_endpoint = "/cgi/php.php";
         let resp = send_request(
             &CLIENT,
-            &format!("{HOST}{valid_endpoint}"),
+            &server.url(valid_endpoint),
             Bytes::new(),
             Method::GET,
         );
@@ -47,7 +197,7 @@ mod test_handle_client {
         let invalid_endpoint = "/cgi/php.kek";
         let resp = send_request(
             &CLIENT,
-            &format!("{HOST}{invalid_endpoint}"),
+            &server.url(invalid_endpoint),
             Bytes::new(),
             Method::GET,
         );
@@ -57,12 +207,12 @@ mod test_handle_client {
 
     #[test]
     fn bad_request() {
-        setup();
+        let server = setup();
         // Not found test
         let valid_endpoint = "/";
         let resp = send_request(
             &CLIENT,
-            &format!("{HOST}{valid_endpoint}"),
+            &server.url(valid_endpoint),
             Bytes::new(),
             Method::GET,
         );
@@ -72,12 +222,12 @@ mod test_handle_client {
 
     #[test]
     fn not_found() {
-        setup();
+        let server = setup();
         // Not found test
         let invalid_endpoint = "/oga-boga";
         let resp = send_request(
             &CLIENT,
-            &format!("{HOST}{invalid_endpoint}"),
+            &server.url(invalid_endpoint),
             Bytes::new(),
             Method::GET,
         );
@@ -87,12 +237,12 @@ mod test_handle_client {
 
     #[test]
     fn redirections() {
-        setup();
+        let server = setup();
         // Not found test
         let valid_endpoint = "/redirection-test";
         let resp = send_request(
             &CLIENT,
-            &format!("{HOST}{valid_endpoint}"),
+            &server.url(valid_endpoint),
             Bytes::new(),
             Method::GET,
         );
@@ -102,12 +252,12 @@ mod test_handle_client {
 
     #[test]
     fn handlers() {
-        setup();
+        let server = setup();
         // Not found test
         let valid_endpoint = "/api/update-cookie";
         let resp = send_request(
             &CLIENT,
-            &format!("{HOST}{valid_endpoint}"),
+            &server.url(valid_endpoint),
             Bytes::new(),
             Method::POST,
         );
@@ -119,7 +269,7 @@ mod test_handle_client {
         let invalid_endpoint = "/api/get-cookie";
         let resp = send_request(
             &CLIENT,
-            &format!("{HOST}{invalid_endpoint}"),
+            &server.url(invalid_endpoint),
             Bytes::new(),
             Method::GET,
         );
@@ -130,12 +280,12 @@ mod test_handle_client {
     #[test]
     #[ignore]
     fn default_file_exists() {
-        setup();
+        let server = setup();
         // Not found test
         let valid_endpoint = "/mega-dir";
         let resp = send_request(
             &CLIENT,
-            &format!("{HOST}{valid_endpoint}"),
+            &server.url(valid_endpoint),
             Bytes::new(),
             Method::GET,
         );
@@ -143,14 +293,84 @@ mod test_handle_client {
         assert!(resp.status().is_success());
     }
 
+    #[test]
+    fn basic_auth_required() {
+        let server = setup();
+        let resp = CLIENT
+            .put(server.url("/files/unauthorized.txt"))
+            .body("should not be written")
+            .send()
+            .unwrap();
+
+        assert_eq!(resp.status().as_u16(), 401);
+        assert!(resp
+            .headers()
+            .get("www-authenticate")
+            .is_some_and(|challenge| challenge.to_str().unwrap().starts_with("Basic realm=")));
+        // The 401 page of files/default_errors
+        assert!(resp.text().unwrap().contains("error-style.css"));
+        assert!(!std::path::Path::new("./files/unauthorized.txt").exists());
+    }
+
+    #[test]
+    fn request_limits() {
+        use std::io::{Read, Write};
+        use std::net::TcpStream;
+
+        let server = setup();
+        let status_of = |request: String| {
+            let mut stream = TcpStream::connect(server.addr).unwrap();
+            stream.write_all(request.as_bytes()).unwrap();
+            let mut response = Vec::new();
+            let _ = stream.read_to_end(&mut response);
+            String::from_utf8_lossy(&response)
+                .split_whitespace()
+                .nth(1)
+                .unwrap_or_default()
+                .to_string()
+        };
+
+        let long_uri = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(10_000));
+        assert_eq!(status_of(long_uri), "414");
+
+        let many_headers = format!("GET / HTTP/1.1\r\n{}\r\n", "X-Foo: bar\r\n".repeat(200));
+        assert_eq!(status_of(many_headers), "431");
+
+        // Rejected from the headers alone, without sending the body
+        let large_body = "POST /files/large.txt HTTP/1.1\r\nContent-Length: 99999999999999\r\n\r\n";
+        assert_eq!(status_of(large_body.to_string()), "413");
+    }
+
+    #[test]
+    fn keep_alive() {
+        use std::io::{Read, Write};
+        use std::net::TcpStream;
+
+        let server = setup();
+        let mut stream = TcpStream::connect(server.addr).unwrap();
+        let request = "GET /test.txt HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        stream.write_all(request.as_bytes()).unwrap();
+
+        // The connection stays open, so read only the first response
+        let mut buffer = [0; 4096];
+        let n = stream.read(&mut buffer).unwrap();
+        assert!(String::from_utf8_lossy(&buffer[..n]).starts_with("HTTP/1.1 200"));
+
+        let request = "GET /test.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        assert!(String::from_utf8_lossy(&response).starts_with("HTTP/1.1 200"));
+    }
+
     #[test]
     fn directory_listing() {
-        setup();
+        let server = setup();
         // Not found test
         let valid_endpoint = "/files";
         let resp = send_request(
             &CLIENT,
-            &format!("{HOST}{valid_endpoint}"),
+            &server.url(valid_endpoint),
             Bytes::new(),
             Method::GET,
         );
@@ -165,8 +385,16 @@ mod test_handle_client {
 
         fn check_response(valid: bool, response: reqwest::blocking::Response, buf: Bytes) {
             if valid {
-                assert_eq!(response.status(), reqwest::StatusCode::OK);
-                assert!(response.content_length().unwrap() > buf.len() as u64);
+                assert!(matches!(response.status().as_u16(), 201 | 204));
+                let location = response
+                    .headers()
+                    .get("location")
+                    .map(|location| location.to_str().unwrap().to_string());
+                // The uploaded file is stored exactly, and not echoed back
+                assert!(response.bytes().unwrap().is_empty());
+                if let Some(location) = location {
+                    assert_eq!(std::fs::read(format!(".{location}")).unwrap(), buf);
+                }
             } else {
                 assert_ne!(response.status(), reqwest::StatusCode::OK);
             }
@@ -176,13 +404,13 @@ mod test_handle_client {
             use crate::common::get_buffer;
             #[test]
             fn post() {
-                setup();
+                let server = setup();
                 let buf = get_buffer("./files/tests/test.png");
                 let valid_endpoint = "/files/tests.png";
 
                 let response = send_request(
                     &CLIENT,
-                    &format!("{HOST}{valid_endpoint}"),
+                    &server.url(valid_endpoint),
                     buf.clone(),
                     http::Method::POST,
                 );
@@ -192,14 +420,14 @@ mod test_handle_client {
 
             #[test]
             fn put() {
-                setup();
+                let server = setup();
                 let buf = get_buffer("./files/tests/test.png");
                 let valid_endpoint = "/files/tests.png";
 
                 // Post and Delete
                 let response = send_request(
                     &CLIENT,
-                    &format!("{HOST}{valid_endpoint}"),
+                    &server.url(valid_endpoint),
                     buf.clone(),
                     http::Method::PUT,
                 );
@@ -232,8 +460,7 @@ mod test_handle_client {
                 .header(TRANSFER_ENCODING, "chunked")
                 .body(body);
 
-            let response = request_builder.send().unwrap();
-            response
+            request_builder.send().unwrap()
         }
 
         mod get {
@@ -248,22 +475,22 @@ mod test_handle_client {
             use super::*;
             #[test]
             fn valid() {
-                setup();
+                let server = setup();
 
                 let body = "Wiki\r\npedia\r\n in\r\n\r\nchunks.\r\n\r\n";
 
                 let valid_endpoint = "/test.txt";
 
-                let response = send_chunked_request(
-                    &CLIENT,
-                    &format!("{HOST}{valid_endpoint}"),
-                    body,
-                    Method::POST,
-                );
+                let response =
+                    send_chunked_request(&CLIENT, &server.url(valid_endpoint), body, Method::POST);
 
-                // Check the response status and body
-                assert_eq!(response.status(), reqwest::StatusCode::OK);
-                assert_eq!(response.bytes().unwrap_or_default(), body);
+                // The decoded body is stored next to the existing file, and not echoed back
+                assert_eq!(response.status(), reqwest::StatusCode::CREATED);
+                let location = response.headers()["location"].to_str().unwrap().to_string();
+                assert!(response.bytes().unwrap_or_default().is_empty());
+                let stored = format!("./files{location}");
+                assert_eq!(std::fs::read_to_string(&stored).unwrap(), body);
+                std::fs::remove_file(stored).unwrap();
             }
 
             #[test]
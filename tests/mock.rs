@@ -1,15 +1,15 @@
 use http::{Method, Request, StatusCode};
 use localhost::server::Cgi;
-use localhost::server_config::route::{Route, Settings};
+use localhost::server_config::route::{BasicAuth, BearerAuth, Jwt, Route, Settings};
 use localhost::server_config::ServerConfig;
 use localhost::type_aliases::Bytes;
 use std::collections::HashMap;
+use std::time::Duration;
 
 #[allow(dead_code)]
-
 // Mock functions and data for testing
 pub fn mock_route() -> Route<'static> {
-    let route = Route {
+    Route {
         methods: vec![
             Method::GET,
             Method::OPTIONS,
@@ -23,13 +23,12 @@ pub fn mock_route() -> Route<'static> {
         ],
         url_path: "/",
         handler: None,
+        websocket: None,
         settings: None,
-    };
-    route
+    }
 }
 
 #[allow(dead_code)]
-
 pub fn mock_request(
     method: Method,
     path: &str,
@@ -47,8 +46,9 @@ pub fn mock_request(
     req.body(Bytes::from(body.unwrap_or_default())).unwrap()
 }
 
+#[allow(dead_code)]
 pub fn mock_server_config() -> ServerConfig<'static> {
-    let config = ServerConfig {
+    ServerConfig {
         host: "127.0.0.1",
         ports: vec![8080],
         custom_error_path: None,
@@ -58,25 +58,22 @@ pub fn mock_server_config() -> ServerConfig<'static> {
                 url_path: "/cgi",
                 methods: vec![Method::GET],
                 handler: None,
+                websocket: None,
                 settings: Some(Settings {
-                    http_redirections: None,
-                    redirect_status_code: None,
-                    root_path: None,
-                    default_if_url_is_dir: None,
-                    default_if_request_is_dir: None,
                     cgi_def: Some(HashMap::from([
                         ("js", Cgi::JavaScript),
                         ("php", Cgi::PHP),
                         ("py", Cgi::Python),
                         ("rb", Cgi::Ruby),
                     ])),
-                    list_directory: false,
+                    ..Settings::default()
                 }),
             },
             Route {
                 url_path: "/test",
                 methods: vec![Method::GET],
                 handler: None,
+                websocket: None,
                 settings: None,
             },
             Route {
@@ -92,14 +89,10 @@ pub fn mock_server_config() -> ServerConfig<'static> {
                     Method::DELETE,
                 ],
                 handler: None,
+                websocket: None,
                 settings: Some(Settings {
-                    http_redirections: None,
-                    redirect_status_code: None,
                     root_path: Some("/files"),
-                    default_if_url_is_dir: None,
-                    default_if_request_is_dir: None,
-                    cgi_def: None,
-                    list_directory: false,
+                    ..Settings::default()
                 }),
             },
             Route {
@@ -115,14 +108,10 @@ pub fn mock_server_config() -> ServerConfig<'static> {
                     Method::DELETE,
                 ],
                 handler: None,
+                websocket: None,
                 settings: Some(Settings {
-                    http_redirections: None,
-                    redirect_status_code: None,
                     root_path: Some("/files"),
-                    default_if_url_is_dir: None,
-                    default_if_request_is_dir: None,
-                    cgi_def: None,
-                    list_directory: false,
+                    ..Settings::default()
                 }),
             },
             Route {
@@ -138,14 +127,10 @@ pub fn mock_server_config() -> ServerConfig<'static> {
                     Method::DELETE,
                 ],
                 handler: None,
+                websocket: None,
                 settings: Some(Settings {
-                    http_redirections: None,
-                    redirect_status_code: None,
                     root_path: Some("/files"),
-                    default_if_url_is_dir: None,
-                    default_if_request_is_dir: None,
-                    cgi_def: None,
-                    list_directory: false,
+                    ..Settings::default()
                 }),
             },
             Route {
@@ -161,14 +146,58 @@ pub fn mock_server_config() -> ServerConfig<'static> {
                     Method::DELETE,
                 ],
                 handler: None,
+                websocket: None,
+                settings: Some(Settings {
+                    root_path: Some("/files"),
+                    ..Settings::default()
+                }),
+            },
+            Route {
+                url_path: "/missing-dir",
+                methods: vec![
+                    Method::GET,
+                    Method::POST,
+                    Method::HEAD,
+                    Method::OPTIONS,
+                    Method::TRACE,
+                    Method::PUT,
+                    Method::PATCH,
+                    Method::DELETE,
+                ],
+                handler: None,
+                websocket: None,
+                settings: Some(Settings {
+                    root_path: Some("/files"),
+                    ..Settings::default()
+                }),
+            },
+            Route {
+                url_path: "/delete-dir-test",
+                methods: vec![
+                    Method::GET,
+                    Method::POST,
+                    Method::HEAD,
+                    Method::OPTIONS,
+                    Method::TRACE,
+                    Method::PUT,
+                    Method::PATCH,
+                    Method::DELETE,
+                ],
+                handler: None,
+                websocket: None,
+                settings: Some(Settings {
+                    root_path: Some("/files"),
+                    ..Settings::default()
+                }),
+            },
+            Route {
+                url_path: "/post-form-test",
+                methods: vec![Method::POST],
+                handler: None,
+                websocket: None,
                 settings: Some(Settings {
-                    http_redirections: None,
-                    redirect_status_code: None,
                     root_path: Some("/files"),
-                    default_if_url_is_dir: None,
-                    default_if_request_is_dir: None,
-                    cgi_def: None,
-                    list_directory: false,
+                    ..Settings::default()
                 }),
             },
             Route {
@@ -184,31 +213,77 @@ pub fn mock_server_config() -> ServerConfig<'static> {
                     Method::DELETE,
                 ],
                 handler: None,
+                websocket: None,
                 settings: Some(Settings {
-                    http_redirections: None,
-                    redirect_status_code: None,
                     root_path: Some("/files"),
-                    default_if_url_is_dir: None,
-                    default_if_request_is_dir: None,
-                    cgi_def: None,
-                    list_directory: false,
+                    ..Settings::default()
                 }),
             },
             Route {
                 url_path: "/tests/redirect.txt",
                 methods: vec![Method::GET],
                 handler: None,
+                websocket: None,
                 settings: Some(Settings {
                     http_redirections: Some(vec!["/redirection"]),
                     redirect_status_code: Some(StatusCode::TEMPORARY_REDIRECT),
                     root_path: Some("/files"),
-                    default_if_url_is_dir: None,
-                    default_if_request_is_dir: None,
-                    cgi_def: None,
-                    list_directory: false,
+                    ..Settings::default()
+                }),
+            },
+            Route {
+                url_path: "/protected",
+                methods: vec![Method::GET],
+                handler: None,
+                websocket: None,
+                settings: Some(Settings {
+                    root_path: Some("/files"),
+                    basic_auth: Some(BasicAuth {
+                        realm: "tests",
+                        htpasswd_path: "/tests/htpasswd",
+                    }),
+                    ..Settings::default()
+                }),
+            },
+            Route {
+                url_path: "/api/protected",
+                methods: vec![Method::GET],
+                handler: None,
+                websocket: None,
+                settings: Some(Settings {
+                    bearer_auth: Some(BearerAuth {
+                        realm: "tests",
+                        tokens: vec!["static-test-token"],
+                        jwt: Some(Jwt {
+                            secret: "jwt-test-secret",
+                            issuer: Some("tests"),
+                            audience: Some("api"),
+                            leeway: 0,
+                        }),
+                        required_scopes: vec!["read"],
+                    }),
+                    ..Settings::default()
                 }),
             },
         ],
-    };
-    config
+        cookie_keys: vec!["current-test-key", "previous-test-key"],
+        access_rules: vec![],
+        trusted_proxies: vec![],
+        max_connections: None,
+        max_connections_per_ip: None,
+        rate_limit: None,
+        max_request_line_length: 8 * 1024,
+        max_header_count: 100,
+        max_header_bytes: 16 * 1024,
+        idle_timeout: Some(Duration::from_secs(10)),
+        header_timeout: Some(Duration::from_secs(10)),
+        body_timeout: Some(Duration::from_secs(60)),
+        keep_alive_timeout: Some(Duration::from_secs(5)),
+        workers: 1,
+        handler_threads: 0,
+        tls: None,
+        http2: false,
+        event_stream_heartbeat: Some(Duration::from_secs(15)),
+        access_log: None,
+    }
 }
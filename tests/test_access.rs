@@ -0,0 +1,130 @@
+mod mock;
+
+use http::{Method, StatusCode};
+use localhost::server::{check_access, client_ip, is_allowed, Cidr, ClientIp};
+use localhost::server_config::AccessRule;
+use mock::*;
+use std::net::IpAddr;
+
+fn ip(addr: &str) -> IpAddr {
+    addr.parse().unwrap()
+}
+
+#[test]
+fn test_cidr_parse() {
+    assert_eq!(Cidr::parse("all"), Some(Cidr::All));
+    assert_eq!(
+        Cidr::parse("10.0.0.0/8"),
+        Some(Cidr::Network(ip("10.0.0.0"), 8))
+    );
+    assert_eq!(Cidr::parse("::1"), Some(Cidr::Network(ip("::1"), 128)));
+    assert_eq!(
+        Cidr::parse("192.168.1.1"),
+        Some(Cidr::Network(ip("192.168.1.1"), 32))
+    );
+
+    // IPv4-mapped networks are IPv4 networks, with the prefix of the IPv4 part
+    assert_eq!(
+        Cidr::parse("::ffff:10.0.0.0/104"),
+        Some(Cidr::Network(ip("10.0.0.0"), 8))
+    );
+    assert_eq!(
+        Cidr::parse("::ffff:10.0.0.1"),
+        Some(Cidr::Network(ip("10.0.0.1"), 32))
+    );
+    assert!(Cidr::parse("::ffff:10.0.0.0/104")
+        .unwrap()
+        .contains(ip("10.1.2.3")));
+
+    for invalid in [
+        "",
+        "10.0.0.0/33",
+        "::/129",
+        "::ffff:10.0.0.0/95",
+        "localhost",
+        "10.0.0.0/x",
+    ] {
+        assert!(
+            Cidr::parse(invalid).is_none(),
+            "{invalid} should be invalid"
+        );
+    }
+}
+
+#[test]
+fn test_cidr_contains() {
+    let v4 = Cidr::parse("172.16.0.0/12").unwrap();
+    assert!(v4.contains(ip("172.16.0.1")));
+    assert!(v4.contains(ip("172.31.255.255")));
+    assert!(!v4.contains(ip("172.32.0.0")));
+    assert!(!v4.contains(ip("::1")));
+    // IPv4-mapped IPv6 addresses are matched as IPv4
+    assert!(v4.contains(ip("::ffff:172.16.0.1")));
+
+    let v6 = Cidr::parse("fd00::/8").unwrap();
+    assert!(v6.contains(ip("fd12:3456::1")));
+    assert!(!v6.contains(ip("fe80::1")));
+
+    assert!(Cidr::parse("0.0.0.0/0").unwrap().contains(ip("8.8.8.8")));
+    assert!(Cidr::parse("all").unwrap().contains(ip("::")));
+}
+
+#[test]
+fn test_is_allowed_uses_first_match() {
+    let rules = [
+        AccessRule::Deny("10.0.0.13"),
+        AccessRule::Allow("10.0.0.0/8"),
+        AccessRule::Allow("::1"),
+        AccessRule::Deny("all"),
+    ];
+
+    assert!(is_allowed(&rules, ip("10.1.2.3")));
+    assert!(is_allowed(&rules, ip("::1")));
+    assert!(!is_allowed(&rules, ip("10.0.0.13")));
+    assert!(!is_allowed(&rules, ip("8.8.8.8")));
+
+    // No matching rule
+    assert!(is_allowed(&[AccessRule::Deny("10.0.0.0/8")], ip("8.8.8.8")));
+    assert!(is_allowed(&[], ip("8.8.8.8")));
+}
+
+#[test]
+fn test_client_ip() {
+    let proxies = ["10.0.0.1", "10.0.1.0/24"];
+    let req = mock_request(
+        Method::GET,
+        "/",
+        None,
+        Some(vec![("X-Forwarded-For", "6.6.6.6, 1.2.3.4, 10.0.1.5")]),
+    );
+
+    // The header is ignored unless the connection comes from a trusted proxy
+    assert_eq!(client_ip(ip("8.8.8.8"), &req, &proxies), ip("8.8.8.8"));
+    // Trusted proxies are skipped from the right, spoofed addresses on the left are ignored
+    assert_eq!(client_ip(ip("10.0.0.1"), &req, &proxies), ip("1.2.3.4"));
+
+    let req = mock_request(
+        Method::GET,
+        "/",
+        None,
+        Some(vec![("X-Forwarded-For", "garbage, 10.0.1.7")]),
+    );
+    assert_eq!(client_ip(ip("10.0.0.1"), &req, &proxies), ip("10.0.1.7"));
+
+    let req = mock_request(Method::GET, "/", None, None);
+    assert_eq!(client_ip(ip("10.0.0.1"), &req, &proxies), ip("10.0.0.1"));
+}
+
+#[test]
+fn test_check_access() {
+    let rules = [AccessRule::Allow("127.0.0.1"), AccessRule::Deny("all")];
+
+    let mut req = mock_request(Method::GET, "/", None, None);
+    assert!(check_access(&rules, &req).is_ok());
+
+    req.extensions_mut().insert(ClientIp(ip("127.0.0.1")));
+    assert!(check_access(&rules, &req).is_ok());
+
+    req.extensions_mut().insert(ClientIp(ip("192.168.0.2")));
+    assert_eq!(check_access(&rules, &req), Err(StatusCode::FORBIDDEN));
+}
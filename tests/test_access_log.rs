@@ -0,0 +1,114 @@
+mod common;
+
+use chrono::{Local, TimeZone};
+use common::{test_config, TestServer};
+use http::Response;
+use localhost::log::{
+    format_access, log_file_path, AccessEntry, LogFileType, COMBINED_LOG_FORMAT, COMMON_LOG_FORMAT,
+};
+use localhost::server_config::server_config;
+use std::time::{Duration, Instant};
+
+fn access_entry(head: &str) -> AccessEntry {
+    AccessEntry {
+        client: "127.0.0.1".parse().unwrap(),
+        head: head.to_string(),
+        received: Local.with_ymd_and_hms(2024, 10, 10, 13, 55, 36).unwrap(),
+        started: Instant::now(),
+    }
+}
+
+#[test]
+fn test_combined_log_format() {
+    let config = server_config().remove(0);
+    let entry = access_entry(
+        "GET /index.html?lang=en HTTP/1.1\r\nHost: localhost\r\nReferer: http://localhost/\r\nUser-Agent: curl/8.0\r\nAuthorization: Basic dXNlcjpwYXNz",
+    );
+    let response = Response::new(b"hello".to_vec());
+
+    let line = format_access(COMBINED_LOG_FORMAT, &entry, &config, &response);
+    let time = entry.received.format("%z");
+    assert_eq!(
+        line,
+        format!("127.0.0.1 - user [10/Oct/2024:13:55:36 {time}] \"GET /index.html?lang=en HTTP/1.1\" 200 5 \"http://localhost/\" \"curl/8.0\"")
+    );
+
+    // Missing values are a dash
+    let line = format_access(
+        COMMON_LOG_FORMAT,
+        &access_entry(""),
+        &config,
+        &Response::new(vec![]),
+    );
+    assert!(line.ends_with("] \"-\" 200 -"), "{line}");
+}
+
+#[test]
+fn test_custom_log_format() {
+    let config = server_config().remove(0);
+    let entry = access_entry("POST /form?a=\"1\" HTTP/1.1\r\nX-Custom: value");
+    let response = Response::builder()
+        .status(404)
+        .header("content-type", "text/html")
+        .body(vec![])
+        .unwrap();
+
+    let line = format_access(
+        "%m %U %q %H %s %B %{x-custom}i %{Content-Type}o %v 100%% %D",
+        &entry,
+        &config,
+        &response,
+    );
+    let (line, micros) = line.rsplit_once(' ').unwrap();
+    assert_eq!(
+        line,
+        format!(
+            "POST /form ?a=\\\"1\\\" HTTP/1.1 404 0 value text/html {} 100%",
+            config.host
+        )
+    );
+    assert!(micros.parse::<u64>().is_ok());
+}
+
+#[test]
+fn test_writes_a_line_per_response() {
+    let mut config = test_config();
+    config.access_log = Some("%h \"%r\" %>s %{User-Agent}i");
+    config.trusted_proxies = vec!["127.0.0.1"];
+    let server = TestServer::start(config);
+
+    let url = server.url("/test.txt?access-log");
+    let client = reqwest::blocking::Client::new();
+    let status = client
+        .get(&url)
+        .header("user-agent", "access-log-test")
+        .send()
+        .unwrap()
+        .status()
+        .as_u16();
+    // Requests forwarded by a trusted proxy are logged with the address of the client
+    client
+        .get(&url)
+        .header("user-agent", "access-log-proxy-test")
+        .header("x-forwarded-for", "203.0.113.7")
+        .send()
+        .unwrap();
+    drop(server);
+
+    let request = "\"GET /test.txt?access-log HTTP/1.1\"";
+    let expected = [
+        format!("127.0.0.1 {request} {status} access-log-test"),
+        format!("203.0.113.7 {request} {status} access-log-proxy-test"),
+    ];
+    for _ in 0..50 {
+        let log = std::fs::read_to_string(log_file_path(LogFileType::Access)).unwrap_or_default();
+        if expected
+            .iter()
+            .all(|expected| log.lines().any(|line| line == expected))
+        {
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    panic!("{expected:?} not in access.log");
+}
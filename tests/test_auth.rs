@@ -0,0 +1,270 @@
+mod mock;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use http::header::{AUTHORIZATION, WWW_AUTHENTICATE};
+use http::{Method, StatusCode};
+use localhost::server::{
+    authorize, basic_credentials, encode_jwt, get_route, verify_jwt, verify_password, whoami,
+    AuthenticatedUser, BearerError, JwtClaims,
+};
+use localhost::server_config::route::Jwt;
+use mock::*;
+use serde_json::{json, Map, Value};
+
+// "bcrypt:secret", "sha:secret", "plain:secret" and "bcrypt:wrong"
+const BCRYPT_USER: &str = "Basic YmNyeXB0OnNlY3JldA==";
+const SHA_USER: &str = "Basic c2hhOnNlY3JldA==";
+const PLAIN_USER: &str = "Basic cGxhaW46c2VjcmV0";
+const WRONG_PASSWORD: &str = "Basic YmNyeXB0Ondyb25n";
+
+#[test]
+fn test_verify_password() {
+    let bcrypt = "$2b$05$4JYUl657THCjnfoEa74qeuWmAi8bmvqoWSiOd8dKL7peSA6AFKDea";
+    assert!(verify_password("secret", bcrypt));
+    assert!(!verify_password("Secret", bcrypt));
+
+    let sha = "{SHA}5en6G6MezRroT3XKqkdPOmY/BfQ=";
+    assert!(verify_password("secret", sha));
+    assert!(!verify_password("secrets", sha));
+
+    // Plain text passwords are not accepted
+    assert!(!verify_password("secret", "secret"));
+}
+
+#[test]
+fn test_basic_credentials() {
+    let req = &mock_request(
+        Method::GET,
+        "/",
+        None,
+        Some(vec![(AUTHORIZATION.as_str(), SHA_USER)]),
+    );
+    assert_eq!(
+        basic_credentials(req),
+        Some(("sha".to_string(), "secret".to_string()))
+    );
+
+    for header in ["Bearer c2hhOnNlY3JldA==", "Basic !!!", "Basic bm9jb2xvbg=="] {
+        let req = &mock_request(
+            Method::GET,
+            "/",
+            None,
+            Some(vec![(AUTHORIZATION.as_str(), header)]),
+        );
+        assert!(
+            basic_credentials(req).is_none(),
+            "{header} should be rejected"
+        );
+    }
+}
+
+#[test]
+fn test_authorize_valid_user() {
+    let config = &mock_server_config();
+    for header in [BCRYPT_USER, SHA_USER] {
+        let mut req = mock_request(
+            Method::GET,
+            "/protected",
+            None,
+            Some(vec![(AUTHORIZATION.as_str(), header)]),
+        );
+        let route = get_route(&req, config).unwrap();
+
+        assert!(authorize(&route, &mut req, config).is_ok());
+        assert!(req.extensions().get::<AuthenticatedUser>().is_some());
+    }
+}
+
+#[test]
+fn test_authorize_invalid_user() {
+    let config = &mock_server_config();
+    for headers in [
+        None,
+        Some(vec![(AUTHORIZATION.as_str(), PLAIN_USER)]),
+        Some(vec![(AUTHORIZATION.as_str(), WRONG_PASSWORD)]),
+    ] {
+        let mut req = mock_request(Method::GET, "/protected", None, headers);
+        let route = get_route(&req, config).unwrap();
+
+        let resp = authorize(&route, &mut req, config).unwrap_err();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            resp.headers()[WWW_AUTHENTICATE],
+            "Basic realm=\"tests\", charset=\"UTF-8\""
+        );
+        assert!(req.extensions().get::<AuthenticatedUser>().is_none());
+    }
+}
+
+#[test]
+fn test_authorize_unprotected_route() {
+    let config = &mock_server_config();
+    let mut req = mock_request(Method::GET, "/test.txt", None, None);
+    let route = get_route(&req, config).unwrap();
+
+    assert!(authorize(&route, &mut req, config).is_ok());
+}
+
+const NOW: u64 = 1_700_000_000;
+
+fn claims(value: Value) -> Map<String, Value> {
+    value.as_object().unwrap().clone()
+}
+
+fn test_jwt() -> Jwt<'static> {
+    Jwt {
+        secret: "jwt-test-secret",
+        issuer: Some("tests"),
+        audience: Some("api"),
+        leeway: 10,
+    }
+}
+
+#[test]
+fn test_verify_jwt() {
+    let jwt = &test_jwt();
+    let valid = claims(
+        json!({"sub": "svc", "iss": "tests", "aud": ["other", "api"], "exp": NOW + 60, "nbf": NOW}),
+    );
+    let token = encode_jwt(&valid, jwt.secret);
+    assert_eq!(verify_jwt(&token, jwt, NOW), Ok(valid));
+
+    // Inside the leeway
+    let token = encode_jwt(
+        &claims(json!({"iss": "tests", "aud": "api", "exp": NOW - 5})),
+        jwt.secret,
+    );
+    assert!(verify_jwt(&token, jwt, NOW).is_ok());
+
+    let invalid = [
+        (
+            json!({"iss": "tests", "aud": "api", "exp": NOW - 11}),
+            "The token has expired",
+        ),
+        (
+            json!({"iss": "tests", "aud": "api", "nbf": NOW + 11}),
+            "The token is not valid yet",
+        ),
+        (json!({"iss": "someone", "aud": "api"}), "Invalid issuer"),
+        (json!({"aud": "api"}), "Invalid issuer"),
+        (
+            json!({"iss": "tests", "aud": ["other"]}),
+            "Invalid audience",
+        ),
+    ];
+    for (claims_value, description) in invalid {
+        let token = encode_jwt(&claims(claims_value), jwt.secret);
+        assert_eq!(
+            verify_jwt(&token, jwt, NOW),
+            Err(BearerError::InvalidToken(description))
+        );
+    }
+}
+
+#[test]
+fn test_verify_jwt_signature() {
+    let jwt = &test_jwt();
+    let payload = claims(json!({"iss": "tests", "aud": "api"}));
+
+    let forged = encode_jwt(&payload, "another-secret");
+    assert_eq!(
+        verify_jwt(&forged, jwt, NOW),
+        Err(BearerError::InvalidToken("Invalid signature"))
+    );
+
+    // An unsigned token with the signature of a valid one
+    let token = encode_jwt(&payload, jwt.secret);
+    let (_, rest) = token.split_once('.').unwrap();
+    let unsigned = format!("{}.{rest}", URL_SAFE_NO_PAD.encode(r#"{"alg":"none"}"#));
+    assert_eq!(
+        verify_jwt(&unsigned, jwt, NOW),
+        Err(BearerError::InvalidToken("Unsupported algorithm"))
+    );
+
+    for malformed in ["", "abc", "a.b.c", &token[1..]] {
+        assert!(
+            verify_jwt(malformed, jwt, NOW).is_err(),
+            "{malformed} should be rejected"
+        );
+    }
+}
+
+fn bearer_request(token: Option<&str>) -> http::Request<Vec<u8>> {
+    let header = token.map(|token| format!("Bearer {token}"));
+    let headers = header.as_deref().map(|h| vec![(AUTHORIZATION.as_str(), h)]);
+    mock_request(Method::GET, "/api/protected", None, headers)
+}
+
+fn authorize_bearer(
+    token: Option<&str>,
+) -> Result<http::Request<Vec<u8>>, Box<http::Response<Vec<u8>>>> {
+    let config = &mock_server_config();
+    let mut req = bearer_request(token);
+    let route = get_route(&req, config).unwrap();
+    authorize(&route, &mut req, config).map(|_| req)
+}
+
+#[test]
+fn test_authorize_bearer_token() {
+    let resp = authorize_bearer(None).unwrap_err();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(resp.headers()[WWW_AUTHENTICATE], "Bearer realm=\"tests\"");
+
+    let req = authorize_bearer(Some("static-test-token")).unwrap();
+    assert!(req.extensions().get::<JwtClaims>().is_none());
+
+    let resp = authorize_bearer(Some("unknown-token")).unwrap_err();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    assert!(resp.headers()[WWW_AUTHENTICATE]
+        .to_str()
+        .unwrap()
+        .contains("error=\"invalid_token\""));
+}
+
+#[test]
+fn test_authorize_bearer_jwt() {
+    let secret = "jwt-test-secret";
+    let token = encode_jwt(
+        &claims(json!({"sub": "svc", "iss": "tests", "aud": "api", "scope": "read write"})),
+        secret,
+    );
+    let req = authorize_bearer(Some(&token)).unwrap();
+    assert_eq!(
+        req.extensions().get::<AuthenticatedUser>(),
+        Some(&AuthenticatedUser("svc".to_string()))
+    );
+    assert!(req
+        .extensions()
+        .get::<JwtClaims>()
+        .is_some_and(|c| c.0["scope"] == "read write"));
+
+    let config = &mock_server_config();
+    let resp = whoami(&req, config).unwrap();
+    let body: Value = serde_json::from_slice(resp.body()).unwrap();
+    assert_eq!(body["user"], "svc");
+    assert_eq!(body["claims"]["iss"], "tests");
+
+    // Valid token without the required scope
+    let token = encode_jwt(
+        &claims(json!({"iss": "tests", "aud": "api", "scope": "write"})),
+        secret,
+    );
+    let resp = authorize_bearer(Some(&token)).unwrap_err();
+    assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    assert!(resp.headers()[WWW_AUTHENTICATE]
+        .to_str()
+        .unwrap()
+        .contains("error=\"insufficient_scope\""));
+
+    let token = encode_jwt(
+        &claims(json!({"iss": "tests", "aud": "api", "exp": 1})),
+        secret,
+    );
+    let resp = authorize_bearer(Some(&token)).unwrap_err();
+    assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    assert!(resp.headers()[WWW_AUTHENTICATE]
+        .to_str()
+        .unwrap()
+        .contains("error_description=\"The token has expired\""));
+}
@@ -1,7 +1,7 @@
 mod mock;
 
 use http::StatusCode;
-use localhost::server::execute_cgi_script;
+use localhost::server::{cgi_env, execute_cgi_script};
 use mock::*;
 
 #[test]
@@ -50,3 +50,29 @@ fn add_headers_to_request() {
     let req = &mock_request(http::Method::GET, "/cgi/php.php", None, Some(headers));
     assert!(execute_cgi_script(req, conf).is_ok());
 }
+
+#[test]
+fn test_cgi_env() {
+    let conf = &mock_server_config();
+    let headers = vec![("Accept", "bytes"), ("Cookie", "yummy"), ("Kek", "")];
+    let req = &mock_request(
+        http::Method::GET,
+        "/cgi/javascript.js/path/to/file?a=1",
+        None,
+        Some(headers),
+    );
+    let vars = cgi_env(req, conf, "js");
+    let var = |name: &str| {
+        vars.iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value.as_str())
+    };
+
+    assert_eq!(var("HTTP_ACCEPT"), Some("bytes"));
+    assert_eq!(var("COOKIE"), Some("yummy"));
+    assert_eq!(var("QUERY_STRING"), Some("a=1"));
+    assert_eq!(var("REQUEST_METHOD"), Some("GET"));
+    assert_eq!(var("PATH_INFO"), Some("/path/to/file"));
+    // The variables are only given to the script, not set on the server process
+    assert!(std::env::var("HTTP_ACCEPT").is_err());
+}
@@ -0,0 +1,198 @@
+mod common;
+
+use common::{test_config, TestServer};
+use http::{HeaderMap, HeaderValue, Request};
+use localhost::server::{
+    multipart_boundary, parse_multipart, parse_urlencoded, urlencoded_form, FormPart,
+    MultipartError, MultipartEvent, MultipartLimits, MultipartParser,
+};
+use serde_json::Value;
+
+const BODY: &str = "preamble\r\n\
+--XyZ\r\n\
+Content-Disposition: form-data; name=\"title\"\r\n\
+\r\n\
+Hello\r\n--world\r\n\
+--XyZ\r\n\
+Content-Disposition: form-data; name=\"upload\"; filename=\"../a \\\"b\\\".txt\"\r\n\
+Content-Type: text/plain\r\n\
+\r\n\
+file\r\ncontents\r\n\
+--XyZ\r\n\
+Content-Disposition: form-data; name=\"other\"; filename=\"plain.txt\"; filename*=UTF-8''na%C3%AFve.txt\r\n\
+\r\n\
+\r\n\
+--XyZ--\r\n\
+epilogue";
+
+fn multipart_request(body: &str) -> Request<Vec<u8>> {
+    Request::builder()
+        .method("POST")
+        .uri("/api/form")
+        .header("content-type", "multipart/form-data; boundary=\"XyZ\"")
+        .body(body.as_bytes().to_vec())
+        .unwrap()
+}
+
+#[test]
+fn test_parse_urlencoded() {
+    let fields = parse_urlencoded(b"a=1&b=hello+world&c=%E2%9C%93&&d&a=2&bad=%zz%4");
+    let fields = fields
+        .iter()
+        .map(|(name, value)| (name.as_str(), value.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        fields,
+        [
+            ("a", "1"),
+            ("b", "hello world"),
+            ("c", "\u{2713}"),
+            ("d", ""),
+            ("a", "2"),
+            ("bad", "%zz%4"),
+        ]
+    );
+
+    let request = Request::builder()
+        .header(
+            "content-type",
+            "application/x-www-form-urlencoded; charset=utf-8",
+        )
+        .body(b"name=value".to_vec())
+        .unwrap();
+    assert_eq!(
+        urlencoded_form(&request).unwrap(),
+        [("name".to_string(), "value".to_string())]
+    );
+    let request = Request::new(b"name=value".to_vec());
+    assert_eq!(urlencoded_form(&request).unwrap_err().as_u16(), 415);
+}
+
+#[test]
+fn test_multipart_boundary() {
+    let mut headers = HeaderMap::new();
+    let mut boundary = |value: &'static str| {
+        headers.insert("content-type", HeaderValue::from_static(value));
+        multipart_boundary(&headers)
+    };
+    assert_eq!(
+        boundary("multipart/form-data; boundary=abc"),
+        Some("abc".to_string())
+    );
+    assert_eq!(
+        boundary("Multipart/Form-Data; charset=utf-8; Boundary=\"a b:c\""),
+        Some("a b:c".to_string())
+    );
+    assert_eq!(boundary("multipart/form-data"), None);
+    assert_eq!(boundary("multipart/mixed; boundary=abc"), None);
+    assert_eq!(boundary("multipart/form-data; boundary=\"\""), None);
+}
+
+#[test]
+fn test_parse_multipart() {
+    let parts = parse_multipart(&multipart_request(BODY), MultipartLimits::default()).unwrap();
+    assert_eq!(parts.len(), 3);
+
+    assert_eq!(parts[0].headers.name.as_deref(), Some("title"));
+    assert_eq!(parts[0].headers.filename, None);
+    assert_eq!(parts[0].data, b"Hello\r\n--world");
+
+    // File names are given as the client sent them
+    assert_eq!(parts[1].headers.filename.as_deref(), Some("../a \"b\".txt"));
+    assert_eq!(parts[1].headers.content_type.as_deref(), Some("text/plain"));
+    assert_eq!(parts[1].data, b"file\r\ncontents");
+
+    assert_eq!(parts[2].headers.filename.as_deref(), Some("na\u{ef}ve.txt"));
+    assert!(parts[2].data.is_empty());
+}
+
+#[test]
+fn test_multipart_streaming() {
+    // The body split at every byte gives the same parts
+    let mut parser = MultipartParser::new("XyZ", MultipartLimits::default());
+    let mut parts: Vec<FormPart> = Vec::new();
+    for byte in BODY.as_bytes() {
+        for event in parser.feed(&[*byte]).unwrap() {
+            match event {
+                MultipartEvent::Part(headers) => parts.push(FormPart {
+                    headers,
+                    data: vec![],
+                }),
+                MultipartEvent::Data(data) => parts.last_mut().unwrap().data.extend(data),
+                MultipartEvent::PartEnd => {}
+            }
+        }
+    }
+    parser.finish().unwrap();
+    assert_eq!(
+        parts,
+        parse_multipart(&multipart_request(BODY), MultipartLimits::default()).unwrap()
+    );
+}
+
+#[test]
+fn test_multipart_errors() {
+    let parse = |body: &str, limits: MultipartLimits| {
+        parse_multipart(&multipart_request(body), limits).unwrap_err()
+    };
+
+    let limits = MultipartLimits {
+        max_part_size: Some(10),
+        ..MultipartLimits::default()
+    };
+    assert_eq!(parse(BODY, limits), MultipartError::PartTooLarge);
+    assert_eq!(parse(BODY, limits).status().as_u16(), 413);
+
+    let limits = MultipartLimits {
+        max_total_size: Some(20),
+        ..MultipartLimits::default()
+    };
+    assert_eq!(parse(BODY, limits), MultipartError::BodyTooLarge);
+
+    let limits = MultipartLimits {
+        max_parts: 2,
+        ..MultipartLimits::default()
+    };
+    assert_eq!(parse(BODY, limits), MultipartError::TooManyParts);
+
+    let error = parse(&BODY[..BODY.len() - 20], MultipartLimits::default());
+    assert_eq!(error, MultipartError::Incomplete);
+    assert_eq!(error.status().as_u16(), 400);
+
+    let error = parse(
+        "--XyZ\r\nno colon\r\n\r\ndata\r\n--XyZ--",
+        MultipartLimits::default(),
+    );
+    assert!(matches!(error, MultipartError::Malformed(_)));
+}
+
+#[test]
+fn test_form_route() {
+    let server = TestServer::start(test_config());
+    let url = server.url("/api/form");
+    let client = reqwest::blocking::Client::new();
+
+    let body = client
+        .post(&url)
+        .header("content-type", "multipart/form-data; boundary=XyZ")
+        .body(BODY)
+        .send()
+        .unwrap()
+        .text()
+        .unwrap();
+    let form: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(form["fields"]["title"], "Hello\r\n--world");
+    assert_eq!(form["files"][0]["filename"], "../a \"b\".txt");
+    assert_eq!(form["files"][0]["size"], 14);
+
+    let body = client
+        .post(&url)
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body("name=a+b")
+        .send()
+        .unwrap()
+        .text()
+        .unwrap();
+    let form: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(form["fields"]["name"], "a b");
+}
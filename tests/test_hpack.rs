@@ -0,0 +1,113 @@
+use localhost::server::{huffman_decode, huffman_encode, Decoder, Encoder, HpackError};
+
+fn hex(s: &str) -> Vec<u8> {
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+fn pairs(headers: &[(&str, &str)]) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+#[test]
+fn test_decode_rfc_requests_with_huffman() {
+    // RFC 7541 C.4, three requests sharing the dynamic table
+    let mut decoder = Decoder::default();
+
+    let headers = decoder
+        .decode(&hex("828684418cf1e3c2e5f23a6ba0ab90f4ff"))
+        .unwrap();
+    assert_eq!(
+        headers,
+        pairs(&[
+            (":method", "GET"),
+            (":scheme", "http"),
+            (":path", "/"),
+            (":authority", "www.example.com"),
+        ])
+    );
+
+    let headers = decoder.decode(&hex("828684be5886a8eb10649cbf")).unwrap();
+    assert_eq!(
+        headers,
+        pairs(&[
+            (":method", "GET"),
+            (":scheme", "http"),
+            (":path", "/"),
+            (":authority", "www.example.com"),
+            ("cache-control", "no-cache"),
+        ])
+    );
+
+    let headers = decoder
+        .decode(&hex("828785bf408825a849e95ba97d7f8925a849e95bb8e8b4bf"))
+        .unwrap();
+    assert_eq!(
+        headers,
+        pairs(&[
+            (":method", "GET"),
+            (":scheme", "https"),
+            (":path", "/index.html"),
+            (":authority", "www.example.com"),
+            ("custom-key", "custom-value"),
+        ])
+    );
+}
+
+#[test]
+fn test_decode_rfc_request_without_huffman() {
+    // RFC 7541 C.3.1
+    let mut decoder = Decoder::default();
+    let headers = decoder
+        .decode(&hex("828684410f7777772e6578616d706c652e636f6d"))
+        .unwrap();
+    assert_eq!(headers[3], (":authority".into(), "www.example.com".into()));
+}
+
+#[test]
+fn test_decode_errors() {
+    let mut decoder = Decoder::default();
+    assert_eq!(decoder.decode(&[0x80]), Err(HpackError::InvalidIndex(0)));
+    assert_eq!(decoder.decode(&[0xbe]), Err(HpackError::InvalidIndex(62)));
+    assert_eq!(
+        decoder.decode(&[0x41, 0x05, b'a']),
+        Err(HpackError::Truncated)
+    );
+    assert!(decoder.decode(&[0x3f, 0xe1, 0x7f]).is_err());
+}
+
+#[test]
+fn test_huffman() {
+    assert_eq!(
+        huffman_encode(b"www.example.com"),
+        hex("f1e3c2e5f23a6ba0ab90f4ff")
+    );
+    assert_eq!(
+        huffman_decode(&hex("f1e3c2e5f23a6ba0ab90f4ff")).unwrap(),
+        b"www.example.com"
+    );
+
+    let bytes = (0..=255).collect::<Vec<u8>>();
+    assert_eq!(huffman_decode(&huffman_encode(&bytes)).unwrap(), bytes);
+}
+
+#[test]
+fn test_encode_round_trip() {
+    let headers = [
+        (":status", "200"),
+        ("content-type", "text/html"),
+        ("content-length", "1234"),
+        ("set-cookie", "id=1; HttpOnly"),
+        ("x-custom", "Value with spaces"),
+    ];
+    let block = Encoder::new().encode(headers);
+    assert_eq!(Decoder::default().decode(&block).unwrap(), pairs(&headers));
+
+    // Exact matches of the static table are a single byte
+    assert_eq!(Encoder::new().encode([(":status", "200")]), vec![0x88]);
+}
@@ -0,0 +1,300 @@
+mod common;
+
+use common::{test_config, TestServer};
+use http::{Response, StatusCode};
+use localhost::server::{h2c_upgrade, request_head, Decoder, Encoder, Event, Http2, PREFACE};
+use localhost::server_config::server_config;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::Duration;
+
+type Frame = (u8, u8, u32, Vec<u8>);
+
+fn frame(kind: u8, flags: u8, id: u32, payload: &[u8]) -> Vec<u8> {
+    let mut frame = (payload.len() as u32).to_be_bytes()[1..].to_vec();
+    frame.extend_from_slice(&[kind, flags]);
+    frame.extend_from_slice(&id.to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+fn parse_frames(mut bytes: &[u8]) -> Vec<Frame> {
+    let mut frames = Vec::new();
+    while bytes.len() >= 9 {
+        let length = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]) as usize;
+        let id = u32::from_be_bytes([bytes[5], bytes[6], bytes[7], bytes[8]]);
+        frames.push((bytes[3], bytes[4], id, bytes[9..9 + length].to_vec()));
+        bytes = &bytes[9 + length..];
+    }
+    frames
+}
+
+fn get_headers(path: &str) -> Vec<u8> {
+    Encoder::new().encode([
+        (":method", "GET"),
+        (":scheme", "http"),
+        (":path", path),
+        (":authority", "localhost"),
+    ])
+}
+
+/// Client preface, empty SETTINGS and a GET request on stream 1.
+fn client_start() -> Vec<u8> {
+    let mut bytes = PREFACE.to_vec();
+    bytes.extend(frame(0x4, 0, 0, &[]));
+    bytes.extend(frame(0x1, 0x5, 1, &get_headers("/test.txt")));
+    bytes
+}
+
+#[test]
+fn test_receives_requests_and_settings() {
+    let config = server_config().remove(0);
+    let mut http2 = Http2::new(&config);
+
+    // The preface may arrive in pieces
+    let bytes = client_start();
+    assert!(http2.receive(&bytes[..10]).unwrap().is_empty());
+    let events = http2.receive(&bytes[10..]).unwrap();
+    assert_eq!(
+        events,
+        vec![Event::Request(
+            1,
+            (
+                "GET /test.txt HTTP/1.1\r\nhost: localhost".to_string(),
+                vec![]
+            )
+        )]
+    );
+
+    let frames = parse_frames(&http2.take_output());
+    // Our SETTINGS, then the ACK of the client's
+    assert_eq!((frames[0].0, frames[0].1), (0x4, 0));
+    assert_eq!((frames[1].0, frames[1].1), (0x4, 0x1));
+    assert!(http2.is_handling());
+
+    // PING is answered with the same payload
+    http2.receive(&frame(0x6, 0, 0, b"12345678")).unwrap();
+    assert_eq!(
+        parse_frames(&http2.take_output()),
+        vec![(0x6, 0x1, 0, b"12345678".to_vec())]
+    );
+}
+
+#[test]
+fn test_response_respects_flow_control() {
+    let config = server_config().remove(0);
+    let mut http2 = Http2::new(&config);
+    http2.receive(&client_start()).unwrap();
+    http2.take_output();
+
+    let body = vec![b'a'; 100_000];
+    http2.send_response(1, Response::new(body.clone()));
+    let frames = parse_frames(&http2.take_output());
+
+    let (kind, flags, id, block) = &frames[0];
+    assert_eq!((*kind, *flags, *id), (0x1, 0x4, 1));
+    let headers = Decoder::default().decode(block).unwrap();
+    assert!(headers.contains(&(":status".into(), "200".into())));
+    assert!(headers.contains(&("content-length".into(), "100000".into())));
+
+    // Only the initial window of 65535 bytes is sent, in frames of at most 16384
+    let data = frames[1..].iter().map(|f| f.3.len()).collect::<Vec<_>>();
+    assert_eq!(data.iter().sum::<usize>(), 65_535);
+    assert!(data.iter().all(|size| *size <= 16_384));
+    assert!(frames[1..].iter().all(|f| f.0 == 0x0 && f.1 == 0));
+    assert!(!http2.is_idle());
+
+    // The rest follows once the client opens the windows
+    let increment = 50_000u32.to_be_bytes();
+    let mut update = frame(0x8, 0, 0, &increment);
+    update.extend(frame(0x8, 0, 1, &increment));
+    http2.receive(&update).unwrap();
+    let frames = parse_frames(&http2.take_output());
+    assert_eq!(frames.iter().map(|f| f.3.len()).sum::<usize>(), 34_465);
+    assert_eq!(frames.last().unwrap().1, 0x1);
+    assert!(http2.is_idle());
+}
+
+#[test]
+fn test_refuses_streams_over_the_limit() {
+    let config = server_config().remove(0);
+    let mut http2 = Http2::new(&config);
+    http2.receive(&client_start()).unwrap();
+
+    // Responses held back by flow control keep their streams open
+    for id in (3..200).step_by(2) {
+        http2
+            .receive(&frame(0x1, 0x5, id, &get_headers("/test.txt")))
+            .unwrap();
+    }
+    for id in (1..200).step_by(2) {
+        http2.send_response(id, Response::new(vec![b'a'; 100_000]));
+    }
+    http2.take_output();
+
+    let events = http2
+        .receive(&frame(0x1, 0x5, 201, &get_headers("/test.txt")))
+        .unwrap();
+    assert!(events.is_empty());
+    assert_eq!(
+        parse_frames(&http2.take_output()),
+        vec![(0x3, 0, 201, vec![0, 0, 0, 0x7])]
+    );
+}
+
+#[test]
+fn test_connection_errors() {
+    let config = server_config().remove(0);
+
+    let mut http2 = Http2::new(&config);
+    assert!(http2.receive(b"GET / HTTP/1.1\r\n\r\n").is_err());
+    assert!(http2.is_closed());
+
+    // A header block that doesn't decode breaks the connection
+    let mut http2 = Http2::new(&config);
+    let mut bytes = PREFACE.to_vec();
+    bytes.extend(frame(0x4, 0, 0, &[]));
+    bytes.extend(frame(0x1, 0x5, 1, &[0x80]));
+    assert!(http2.receive(&bytes).is_err());
+    let frames = parse_frames(&http2.take_output());
+    assert_eq!(frames.last().unwrap().0, 0x7);
+
+    // The body size limit is checked as DATA arrives
+    let mut config = server_config().remove(0);
+    config.body_size_limit = 10;
+    let mut http2 = Http2::new(&config);
+    let mut bytes = PREFACE.to_vec();
+    bytes.extend(frame(0x4, 0, 0, &[]));
+    bytes.extend(frame(0x1, 0x4, 1, &get_headers("/test.txt")));
+    bytes.extend(frame(0x0, 0, 1, &[0; 20]));
+    assert_eq!(
+        http2.receive(&bytes).unwrap(),
+        vec![Event::Rejected(1, StatusCode::PAYLOAD_TOO_LARGE)]
+    );
+}
+
+#[test]
+fn test_request_head() {
+    let headers = [
+        (":method", "POST"),
+        (":scheme", "https"),
+        (":authority", "example.com"),
+        (":path", "/form?a=1"),
+        ("cookie", "a=1"),
+        ("content-length", "3"),
+        ("cookie", "b=2"),
+    ]
+    .map(|(name, value)| (name.to_string(), value.to_string()));
+    assert_eq!(
+        request_head(&headers, 3).unwrap(),
+        "POST /form?a=1 HTTP/1.1\r\nhost: example.com\r\ncookie: a=1; b=2\r\ncontent-length: 3"
+    );
+
+    // Wrong body length, missing path, connection headers, pseudo headers after fields
+    assert!(request_head(&headers, 4).is_none());
+    assert!(request_head(&headers[..3], 3).is_none());
+    for bad in [
+        ("connection", "close"),
+        ("X-Upper", "1"),
+        (":method", "GET"),
+    ] {
+        let mut headers = headers.to_vec();
+        headers.push((bad.0.to_string(), bad.1.to_string()));
+        assert!(request_head(&headers, 3).is_none(), "{bad:?}");
+    }
+}
+
+#[test]
+fn test_h2c_upgrade_header() {
+    let head = "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade, HTTP2-Settings\r\nUpgrade: h2c\r\nHTTP2-Settings: AAMAAABkAAQAAP__";
+    assert_eq!(
+        h2c_upgrade(head).unwrap(),
+        vec![0, 3, 0, 0, 0, 100, 0, 4, 0, 0, 255, 255]
+    );
+    assert!(h2c_upgrade("GET / HTTP/1.1\r\nUpgrade: h2c").is_none());
+    assert!(h2c_upgrade(&head.replace("HTTP/1.1", "HTTP/1.0")).is_none());
+}
+
+fn http2_server(handler_threads: usize) -> TestServer {
+    let mut config = test_config();
+    config.http2 = true;
+    config.handler_threads = handler_threads;
+    TestServer::start(config)
+}
+
+#[test]
+fn test_serves_http2_with_prior_knowledge() {
+    for handler_threads in [0, 2] {
+        let server = http2_server(handler_threads);
+        let url = server.url("/test.txt");
+        let client = reqwest::blocking::Client::builder()
+            .http2_prior_knowledge()
+            .build()
+            .unwrap();
+
+        // Concurrent requests share the connection
+        let requests = (0..8)
+            .map(|_| {
+                let (client, url) = (client.clone(), url.clone());
+                thread::spawn(move || client.get(url).send().unwrap())
+            })
+            .collect::<Vec<_>>();
+        for request in requests {
+            let response = request.join().unwrap();
+            assert_eq!(response.version(), reqwest::Version::HTTP_2);
+            assert_eq!(response.status(), 200);
+            assert!(!response.text().unwrap().is_empty());
+        }
+    }
+}
+
+#[test]
+fn test_upgrades_from_http1_with_h2c() {
+    let server = http2_server(0);
+    let mut stream = TcpStream::connect(server.addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream
+        .write_all(b"GET /test.txt HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade, HTTP2-Settings\r\nUpgrade: h2c\r\nHTTP2-Settings: \r\n\r\n")
+        .unwrap();
+    let mut bytes = PREFACE.to_vec();
+    bytes.extend(frame(0x4, 0, 0, &[]));
+    stream.write_all(&bytes).unwrap();
+
+    let mut received = Vec::new();
+    let mut buffer = [0; 4096];
+    let switching = b"HTTP/1.1 101 Switching Protocols\r\n";
+    // Read until the response on stream 1 has ended
+    let frames = loop {
+        let n = stream.read(&mut buffer).unwrap();
+        assert!(n > 0, "connection closed");
+        received.extend_from_slice(&buffer[..n]);
+
+        if let Some(end) = received.windows(4).position(|w| w == b"\r\n\r\n") {
+            let frames = parse_frames(&received[end + 4..]);
+            if frames.iter().any(|f| f.2 == 1 && f.1 & 0x1 != 0) {
+                break frames;
+            }
+        }
+    };
+    assert!(received.starts_with(switching));
+
+    let (_, _, _, block) = frames.iter().find(|f| f.0 == 0x1 && f.2 == 1).unwrap();
+    let headers = Decoder::default().decode(block).unwrap();
+    assert!(headers.contains(&(":status".into(), "200".into())));
+}
+
+#[test]
+fn test_rejects_http2_when_disabled() {
+    let mut config = test_config();
+    config.http2 = false;
+    let server = TestServer::start(config);
+
+    let mut stream = TcpStream::connect(server.addr).unwrap();
+    stream.write_all(PREFACE).unwrap();
+    let mut response = vec![0; 1024];
+    let n = stream.read(&mut response).unwrap();
+    assert!(String::from_utf8_lossy(&response[..n]).starts_with("HTTP/1.1 505"));
+}
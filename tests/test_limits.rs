@@ -0,0 +1,145 @@
+mod mock;
+
+use http::header::RETRY_AFTER;
+use http::StatusCode;
+use localhost::server::{check_limits, too_many_requests, ConnectionCounter, RateLimiter};
+use localhost::server_config::{RateLimit, ServerConfig};
+use mock::*;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const LIMIT: RateLimit = RateLimit {
+    per_second: 0.5,
+    burst: 3,
+};
+
+fn ip(addr: &str) -> IpAddr {
+    addr.parse().unwrap()
+}
+
+#[test]
+fn test_burst_then_reject() {
+    let limiter = RateLimiter::new();
+    let now = Instant::now();
+    let client = ip("10.0.0.1");
+
+    for _ in 0..LIMIT.burst {
+        assert!(limiter.check_at(client, "server", &LIMIT, now).is_ok());
+    }
+    // One token every two seconds
+    assert_eq!(limiter.check_at(client, "server", &LIMIT, now), Err(2));
+    assert_eq!(
+        limiter.check_at(client, "server", &LIMIT, now + Duration::from_secs(1)),
+        Err(1)
+    );
+    assert!(limiter
+        .check_at(client, "server", &LIMIT, now + Duration::from_secs(2))
+        .is_ok());
+}
+
+#[test]
+fn test_buckets_are_per_client_and_scope() {
+    let limiter = RateLimiter::new();
+    let now = Instant::now();
+
+    for _ in 0..LIMIT.burst {
+        assert!(limiter
+            .check_at(ip("10.0.0.1"), "server", &LIMIT, now)
+            .is_ok());
+    }
+    assert!(limiter
+        .check_at(ip("10.0.0.1"), "server", &LIMIT, now)
+        .is_err());
+
+    assert!(limiter
+        .check_at(ip("10.0.0.2"), "server", &LIMIT, now)
+        .is_ok());
+    assert!(limiter
+        .check_at(ip("10.0.0.1"), "server/cgi", &LIMIT, now)
+        .is_ok());
+
+    // Clones share the buckets
+    let clone = limiter.clone();
+    assert!(clone
+        .check_at(ip("10.0.0.1"), "server", &LIMIT, now)
+        .is_err());
+}
+
+#[test]
+fn test_refill_is_capped_at_burst() {
+    let limiter = RateLimiter::new();
+    let now = Instant::now();
+    let client = ip("::1");
+
+    assert!(limiter.check_at(client, "server", &LIMIT, now).is_ok());
+    let later = now + Duration::from_secs(3600);
+    for _ in 0..LIMIT.burst {
+        assert!(limiter.check_at(client, "server", &LIMIT, later).is_ok());
+    }
+    assert!(limiter.check_at(client, "server", &LIMIT, later).is_err());
+}
+
+#[test]
+fn test_too_many_requests() {
+    let config = &mock_server_config();
+    let resp = too_many_requests(7, config);
+
+    assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(resp.headers()[RETRY_AFTER], "7");
+}
+
+#[test]
+fn test_check_limits() {
+    let mut config = ServerConfig {
+        rate_limit: Some(LIMIT),
+        ..mock_server_config()
+    };
+    assert!(check_limits(&config).is_ok());
+
+    for per_second in [0.0, -1.0, f64::NAN] {
+        config.rate_limit = Some(RateLimit {
+            per_second,
+            burst: 3,
+        });
+        assert!(check_limits(&config).is_err());
+    }
+
+    config.rate_limit = None;
+    let settings = config.routes[0].settings.as_mut().unwrap();
+    settings.rate_limit = Some(RateLimit {
+        per_second: 0.0,
+        burst: 1,
+    });
+    assert!(check_limits(&config).is_err());
+}
+
+#[test]
+fn test_connection_counter() {
+    let config = ServerConfig {
+        max_connections: Some(3),
+        max_connections_per_ip: Some(2),
+        ..mock_server_config()
+    };
+    let counter = Arc::new(ConnectionCounter::new());
+    let peer = |addr: &str| addr.parse::<SocketAddr>().unwrap();
+
+    let first = counter.open(peer("10.0.0.1:1000"), &config).unwrap();
+    let _second = counter.open(peer("10.0.0.1:1001"), &config).unwrap();
+    assert_eq!(
+        counter.open(peer("10.0.0.1:1002"), &config).unwrap_err(),
+        StatusCode::TOO_MANY_REQUESTS
+    );
+
+    let _third = counter.open(peer("10.0.0.2:1000"), &config).unwrap();
+    assert_eq!(
+        counter.open(peer("10.0.0.3:1000"), &config).unwrap_err(),
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+    assert_eq!(counter.total(), 3);
+
+    // Closing a connection frees its place
+    drop(first);
+    assert_eq!(counter.total(), 2);
+    assert!(counter.open(peer("10.0.0.1:1003"), &config).is_ok());
+}
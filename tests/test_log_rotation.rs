@@ -0,0 +1,74 @@
+use flate2::read::GzDecoder;
+use localhost::log::{
+    append_to_log, flush_logs, init_logs, log_file_path, rotated_files, LogFileType, LogRotation,
+    LogSettings,
+};
+use std::fs;
+use std::io::Read;
+use std::path::PathBuf;
+use std::time::Duration;
+
+fn log_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("localhost-{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+#[test]
+fn test_rotates_by_size_with_retention_and_compression() {
+    let dir = log_dir("rotation");
+    init_logs(LogSettings {
+        dir: dir.clone(),
+        rotation: LogRotation::Size(100),
+        retention: Some(2),
+        compress: true,
+        ..LogSettings::default()
+    });
+    let path = log_file_path(LogFileType::Access);
+    assert_eq!(path, dir.join("access.log"));
+
+    for i in 0..10 {
+        append_to_log(LogFileType::Access, &format!("{i:0>39}"));
+    }
+    flush_logs();
+    // Two lines of 40 bytes fit in a file
+    assert_eq!(fs::metadata(&path).unwrap().len(), 80);
+
+    // Compression and pruning happen in the background
+    let mut rotated = Vec::new();
+    for _ in 0..100 {
+        rotated = rotated_files(&path);
+        if rotated.len() == 2 && rotated.iter().all(|file| file.extension().unwrap() == "gz") {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    assert_eq!(rotated.len(), 2, "{rotated:?}");
+
+    // The newest rotated file has the two lines before the current ones
+    let mut lines = String::new();
+    GzDecoder::new(fs::File::open(&rotated[1]).unwrap())
+        .read_to_string(&mut lines)
+        .unwrap();
+    assert_eq!(lines, format!("{:0>39}\n{:0>39}\n", 6, 7));
+
+    // Starting again rotates the current file
+    init_logs(LogSettings {
+        dir: dir.clone(),
+        ..LogSettings::default()
+    });
+    assert!(!path.exists());
+    assert_eq!(rotated_files(&path).len(), 3);
+
+    // A directory that can't be created doesn't stop the server
+    let file = dir.join("file");
+    fs::write(&file, "").unwrap();
+    init_logs(LogSettings {
+        dir: file.join("logs"),
+        ..LogSettings::default()
+    });
+    append_to_log(LogFileType::Server, "lost");
+    flush_logs();
+
+    let _ = fs::remove_dir_all(&dir);
+}
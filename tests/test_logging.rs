@@ -0,0 +1,81 @@
+mod common;
+
+use common::{test_config, TestServer};
+use localhost::log::{
+    current_request_id, flush_logs, init_logs, log_file_path, request_id, with_request_id,
+    LogFileType, LogLevel, LogSettings,
+};
+
+#[test]
+fn test_request_id() {
+    assert_eq!(request_id(Some(" abc-123.4_5:6 ")), "abc-123.4_5:6");
+
+    // Ids that aren't safe to log or send back are replaced
+    for header in [None, Some(""), Some("a b"), Some("a\"b")] {
+        let id = request_id(header);
+        assert_eq!(id.len(), 16);
+        assert!(id.chars().all(|c| c.is_ascii_hexdigit()));
+    }
+    assert_ne!(request_id(None), request_id(None));
+    assert_eq!(request_id(Some(&"a".repeat(129))).len(), 16);
+
+    assert_eq!(
+        with_request_id("outer", current_request_id).unwrap(),
+        "outer"
+    );
+    assert!(current_request_id().is_none());
+}
+
+#[test]
+fn test_json_lines_with_levels() {
+    init_logs(LogSettings {
+        level: LogLevel::Info,
+        json: true,
+        ..LogSettings::default()
+    });
+
+    with_request_id("json-test", || {
+        localhost::log!(
+            LogLevel::Warn,
+            LogFileType::Server,
+            "json test \"quoted\"".to_string()
+        );
+        localhost::log!(
+            LogLevel::Debug,
+            LogFileType::Server,
+            "json test debug".to_string()
+        );
+    });
+    flush_logs();
+
+    let log = std::fs::read_to_string(log_file_path(LogFileType::Server)).unwrap();
+    let lines = log
+        .lines()
+        .filter(|line| line.contains("json test"))
+        .collect::<Vec<_>>();
+    // Lines below the level are dropped
+    assert_eq!(lines.len(), 1, "{log}");
+
+    let line: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+    assert_eq!(line["level"], "warn");
+    assert_eq!(line["message"], "json test \"quoted\"");
+    assert_eq!(line["request_id"], "json-test");
+    assert_eq!(line["file"], "tests/test_logging.rs");
+}
+
+#[test]
+fn test_responses_carry_the_request_id() {
+    let server = TestServer::start(test_config());
+    let url = server.url("/test.txt");
+    let client = reqwest::blocking::Client::new();
+
+    let response = client
+        .get(&url)
+        .header("x-request-id", "from-proxy-1")
+        .send()
+        .unwrap();
+    assert_eq!(response.headers()["x-request-id"], "from-proxy-1");
+
+    let response = client.get(&url).send().unwrap();
+    assert_eq!(response.headers()["x-request-id"].len(), 16);
+}
@@ -2,12 +2,9 @@ use std::fs;
 mod mock;
 use mock::*;
 
-use http::{
-    header::{CONTENT_LENGTH, CONTENT_TYPE},
-    Method, StatusCode,
-};
+use http::{header::LOCATION, Method, StatusCode};
 
-use localhost::server::{content_type, get_method, handle_method, method_is_allowed};
+use localhost::server::{get_method, handle_method, method_is_allowed};
 mod test_misc {
     use super::*;
     use rand::distributions::Alphanumeric;
@@ -198,21 +195,10 @@ fn test_handle_method_put() {
     assert!(result.is_ok());
     let response = result.unwrap();
 
-    // Check response status
-    assert_eq!(response.status(), StatusCode::OK);
-
-    // Check response headers
-    assert_eq!(
-        response.headers().get(CONTENT_TYPE).unwrap(),
-        &content_type(test_file_path)
-    );
-    assert_eq!(
-        response.headers().get(CONTENT_LENGTH).unwrap(),
-        &test_body_content.len().to_string()
-    );
-
-    // Check response body
-    assert_eq!(response.body(), &test_body_content.as_bytes().to_vec());
+    // A new file is created, and the content is not echoed back
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(response.headers().get(LOCATION).unwrap(), test_file_path);
+    assert!(response.body().is_empty());
 
     // Verify that the file was created and contains the correct content
     let file_path = format!("./files{}", test_file_path);
@@ -220,10 +206,69 @@ fn test_handle_method_put() {
     let file_content = fs::read_to_string(file_path).expect("Failed to read file");
     assert_eq!(file_content, test_body_content);
 
+    // Replacing the file has no content to respond with
+    let request = mock_request(Method::PUT, test_file_path, Some("Replaced"), None);
+    let response = handle_method(&route, &request, &config).unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(fs::read_to_string(&cloned_file_path).unwrap(), "Replaced");
+
     // Clean up: remove the test file
     fs::remove_file(cloned_file_path).expect("Failed to remove test file");
 }
 
+#[test]
+fn test_handle_method_post_forms() {
+    let route = mock_route();
+    let config = mock_server_config();
+
+    // Urlencoded forms are stored as the JSON of their fields
+    let request = mock_request(
+        Method::POST,
+        "/post-form-test.json",
+        Some("name=a+b&tag=x&tag=y"),
+        Some(vec![("content-type", "application/x-www-form-urlencoded")]),
+    );
+    let response = handle_method(&route, &request, &config).unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let location = response.headers()[LOCATION].to_str().unwrap();
+    let stored = fs::read_to_string(format!("./files{location}")).unwrap();
+    assert_eq!(stored, r#"{"name":"a b","tag":["x","y"]}"#);
+    fs::remove_file(format!("./files{location}")).unwrap();
+
+    // The files of multipart forms are stored in the directory of the path
+    let dir = "./files/post-form-test";
+    let _ = fs::remove_dir_all(dir);
+    fs::create_dir_all(dir).unwrap();
+    let request = mock_request(
+        Method::POST,
+        "/post-form-test",
+        Some(
+            "--XyZ\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+             \r\nhello\r\n--XyZ--\r\n",
+        ),
+        Some(vec![("content-type", "multipart/form-data; boundary=XyZ")]),
+    );
+    let response = handle_method(&route, &request, &config).unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(response.headers()[LOCATION], "/post-form-test/a.txt");
+    assert_eq!(fs::read_to_string(format!("{dir}/a.txt")).unwrap(), "hello");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_handle_method_put_missing_directory() {
+    let route = mock_route();
+    let config = mock_server_config();
+
+    // Parent directories are only created if the route allows it
+    let request = mock_request(Method::PUT, "/missing-dir/test.txt", Some("content"), None);
+    assert!(matches!(
+        handle_method(&route, &request, &config),
+        Err(StatusCode::CONFLICT)
+    ));
+    assert!(!std::path::Path::new("./files/missing-dir").exists());
+}
+
 mod test_patch {
     use super::*;
     use localhost::type_aliases::Bytes;
@@ -243,14 +288,13 @@ mod test_patch {
         // Step 2: Modify the file content using PATCH
         let patch_request =
             mock_request(Method::PATCH, test_file_path, Some(modified_content), None);
-        let body = match handle_method(&route, &patch_request, &config) {
-            Ok(resp) => resp.body().clone(),
-            _ => panic!(),
-        };
+        let response = handle_method(&route, &patch_request, &config).unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(response.body().is_empty());
         // Assert that the content is now updated
-        assert_eq!(Bytes::from(modified_content), body);
-        // Clean up: remove the test file
         let file_path = format!("./files{}", test_file_path);
+        assert_eq!(Bytes::from(modified_content), fs::read(&file_path).unwrap());
+        // Clean up: remove the test file
         fs::remove_file(file_path).expect("Failed to remove test file");
     }
 }
@@ -293,3 +337,36 @@ fn test_handle_method_delete_non_existing_file() {
     let result = handle_method(&route, &delete_request, &config);
     assert!(matches!(result, Err(StatusCode::NOT_FOUND)));
 }
+
+#[test]
+fn test_handle_method_delete_outside_root() {
+    let route = mock_route();
+    let config = mock_server_config();
+
+    let delete_request = mock_request(Method::DELETE, "/delete-dir-test/../../src", None, None);
+    let result = handle_method(&route, &delete_request, &config);
+    assert!(matches!(result, Err(StatusCode::FORBIDDEN)));
+    assert!(std::path::Path::new("./src").is_dir());
+}
+
+#[test]
+fn test_handle_method_delete_directory() {
+    let route = mock_route();
+    let config = mock_server_config();
+    fs::create_dir_all("./files/delete-dir-test").unwrap();
+    fs::write("./files/delete-dir-test/a.txt", "content").unwrap();
+
+    // Directories with files are only removed if the route allows recursive deletes
+    let delete_dir = mock_request(Method::DELETE, "/delete-dir-test", None, None);
+    assert!(matches!(
+        handle_method(&route, &delete_dir, &config),
+        Err(StatusCode::CONFLICT)
+    ));
+
+    let delete_file = mock_request(Method::DELETE, "/delete-dir-test/a.txt", None, None);
+    let response = handle_method(&route, &delete_file, &config).unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = handle_method(&route, &delete_dir, &config).unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert!(!std::path::Path::new("./files/delete-dir-test").exists());
+}
@@ -0,0 +1,108 @@
+mod common;
+
+use common::{test_config, TestServer, TEST_PASSWORD, TEST_USER};
+use http::StatusCode;
+use localhost::server::{metrics, Metrics};
+use std::time::Duration;
+
+#[test]
+fn test_render() {
+    let metrics = Metrics::default();
+    metrics.record_request(
+        "/a\"b",
+        "GET",
+        StatusCode::OK,
+        500,
+        Duration::from_millis(20),
+    );
+    metrics.record_request(
+        "/a\"b",
+        "BREW",
+        StatusCode::NOT_FOUND,
+        50,
+        Duration::from_secs(20),
+    );
+    metrics.record_request(
+        "/a\"b",
+        "PROPFIND",
+        StatusCode::MULTI_STATUS,
+        50,
+        Duration::from_millis(20),
+    );
+    metrics.record_cgi(Duration::from_millis(3), true);
+    let text = metrics.render();
+
+    // Label values are escaped, and unknown methods share a label
+    assert!(
+        text.contains("http_requests_total{route=\"/a\\\"b\",method=\"GET\",status=\"200\"} 1\n")
+    );
+    assert!(
+        text.contains("http_requests_total{route=\"/a\\\"b\",method=\"other\",status=\"404\"} 1\n")
+    );
+    assert!(text
+        .contains("http_requests_total{route=\"/a\\\"b\",method=\"PROPFIND\",status=\"207\"} 1\n"));
+
+    // Buckets are cumulative
+    assert!(
+        text.contains("http_request_duration_seconds_bucket{route=\"/a\\\"b\",le=\"0.01\"} 0\n")
+    );
+    assert!(
+        text.contains("http_request_duration_seconds_bucket{route=\"/a\\\"b\",le=\"0.025\"} 2\n")
+    );
+    assert!(
+        text.contains("http_request_duration_seconds_bucket{route=\"/a\\\"b\",le=\"+Inf\"} 3\n")
+    );
+    assert!(text.contains("http_request_duration_seconds_count{route=\"/a\\\"b\"} 3\n"));
+    assert!(text.contains("http_response_size_bytes_sum{route=\"/a\\\"b\"} 600\n"));
+
+    assert!(text.contains("# TYPE cgi_duration_seconds histogram\n"));
+    assert!(text.contains("cgi_duration_seconds_bucket{le=\"0.005\"} 1\n"));
+    assert!(text.contains("cgi_failures_total 1\n"));
+}
+
+#[test]
+fn test_active_connections() {
+    let metrics: &'static Metrics = Box::leak(Box::default());
+    let connection = metrics.connection_opened();
+    assert!(metrics.render().contains("connections_active 1\n"));
+    drop(connection);
+
+    let text = metrics.render();
+    assert!(text.contains("connections_active 0\n"));
+    assert!(text.contains("connections_accepted_total 1\n"));
+}
+
+#[test]
+fn test_metrics_route() {
+    let server = TestServer::start(test_config());
+    let base = server.url("");
+
+    let client = reqwest::blocking::Client::new();
+    client
+        .get(format!("{base}/test.txt"))
+        .send()
+        .unwrap()
+        .bytes()
+        .unwrap();
+    let response = client.get(format!("{base}/metrics")).send().unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+    let response = client
+        .get(format!("{base}/metrics"))
+        .basic_auth(TEST_USER, Some(TEST_PASSWORD))
+        .send()
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        response.headers()["content-type"],
+        "text/plain; version=0.0.4; charset=utf-8"
+    );
+    let text = response.text().unwrap();
+    drop(server);
+
+    assert!(
+        text.contains("http_requests_total{route=\"/test.txt\",method=\"GET\",status=\"200\"}"),
+        "{text}"
+    );
+    assert!(text.contains("# TYPE connections_accepted_total counter"));
+    assert!(metrics().render().contains("connections_timed_out_total"));
+}
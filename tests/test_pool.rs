@@ -0,0 +1,51 @@
+use localhost::server::HandlerPool;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn test_runs_jobs_on_threads() {
+    let pool = HandlerPool::new(4);
+    assert_eq!(pool.size(), 4);
+
+    let (sender, receiver) = mpsc::channel();
+    for i in 0..16 {
+        let sender = sender.clone();
+        assert!(pool.execute(move || sender.send((i, thread::current().id())).unwrap()));
+    }
+    drop(sender);
+
+    let mut results = receiver.iter().collect::<Vec<_>>();
+    results.sort_by_key(|(i, _)| *i);
+    assert_eq!(
+        results.iter().map(|(i, _)| *i).collect::<Vec<_>>(),
+        (0..16).collect::<Vec<_>>()
+    );
+    assert!(results.iter().all(|(_, id)| *id != thread::current().id()));
+}
+
+#[test]
+fn test_slow_job_does_not_block_others() {
+    let pool = HandlerPool::new(2);
+    let (sender, receiver) = mpsc::channel();
+
+    let slow = sender.clone();
+    pool.execute(move || {
+        thread::sleep(Duration::from_millis(500));
+        slow.send("slow").unwrap();
+    });
+    pool.execute(move || sender.send("fast").unwrap());
+
+    assert_eq!(receiver.recv().unwrap(), "fast");
+    assert_eq!(receiver.recv().unwrap(), "slow");
+}
+
+#[test]
+fn test_survives_panicking_job() {
+    let pool = HandlerPool::new(1);
+    pool.execute(|| panic!("handler failed"));
+
+    let (sender, receiver) = mpsc::channel();
+    pool.execute(move || sender.send(()).unwrap());
+    assert!(receiver.recv_timeout(Duration::from_secs(5)).is_ok());
+}
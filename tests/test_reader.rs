@@ -0,0 +1,281 @@
+mod mock;
+
+use http::{Method, StatusCode};
+use localhost::server::{ReadState, RequestReader, Timeout};
+use localhost::server_config::route::{Route, Settings, Tus};
+use localhost::server_config::ServerConfig;
+use mock::*;
+use std::io::Cursor;
+use std::time::{Duration, Instant};
+
+fn config() -> ServerConfig<'static> {
+    ServerConfig {
+        body_size_limit: 10,
+        max_request_line_length: 32,
+        max_header_count: 2,
+        max_header_bytes: 64,
+        header_timeout: Some(Duration::from_secs(5)),
+        body_timeout: Some(Duration::from_secs(20)),
+        ..mock_server_config()
+    }
+}
+
+fn read_all(bytes: &[u8]) -> ReadState {
+    let config = config();
+    let mut reader = RequestReader::default();
+    // A byte at a time, like a slow client
+    for byte in bytes {
+        match reader.push(&[*byte], &config) {
+            ReadState::Pending => {}
+            state => return state,
+        }
+    }
+    ReadState::Pending
+}
+
+#[test]
+fn test_complete_request() {
+    let state = read_all(b"POST /foo HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\nhello");
+    assert_eq!(
+        state,
+        ReadState::Complete(
+            "POST /foo HTTP/1.1\r\nHost: a\r\nContent-Length: 5".to_string(),
+            b"hello".to_vec()
+        )
+    );
+
+    // Without a body the request is complete after the head
+    let state = read_all(b"GET / HTTP/1.1\r\n\r\n");
+    assert_eq!(
+        state,
+        ReadState::Complete("GET / HTTP/1.1".to_string(), vec![])
+    );
+
+    assert_eq!(
+        read_all(b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhel"),
+        ReadState::Pending
+    );
+}
+
+#[test]
+fn test_read_from_stream() {
+    let config = config();
+    let mut reader = RequestReader::default();
+    let mut stream = Cursor::new(b"GET / HTTP/1.1\r\n\r\n".to_vec());
+    assert!(matches!(
+        reader.read_from(&mut stream, &config),
+        Ok(ReadState::Complete(..))
+    ));
+
+    let mut reader = RequestReader::default();
+    let mut stream = Cursor::new(b"GET / HT".to_vec());
+    assert!(matches!(
+        reader.read_from(&mut stream, &config),
+        Ok(ReadState::Closed)
+    ));
+    assert!(!reader.is_empty());
+}
+
+#[test]
+fn test_pipelined_requests() {
+    let config = config();
+    let mut reader = RequestReader::default();
+    let state = reader.push(
+        b"POST /a HTTP/1.1\r\nContent-Length: 2\r\n\r\nhiGET /b HTTP/1.1\r\n\r\nGET /c",
+        &config,
+    );
+    assert_eq!(
+        state,
+        ReadState::Complete(
+            "POST /a HTTP/1.1\r\nContent-Length: 2".to_string(),
+            b"hi".to_vec()
+        )
+    );
+
+    // The second request was already received
+    let mut stream = Cursor::new(Vec::new());
+    assert_eq!(
+        reader.read_from(&mut stream, &config).unwrap(),
+        ReadState::Complete("GET /b HTTP/1.1".to_string(), vec![])
+    );
+    assert_eq!(
+        reader.read_from(&mut stream, &config).unwrap(),
+        ReadState::Closed
+    );
+    assert!(!reader.is_empty());
+}
+
+#[test]
+fn test_streamed_body() {
+    let config = ServerConfig {
+        max_request_line_length: 64,
+        routes: vec![Route {
+            url_path: "/tus",
+            methods: vec![Method::PATCH],
+            handler: None,
+            websocket: None,
+            settings: Some(Settings {
+                tus: Some(Tus {
+                    max_size: None,
+                    expiration: None,
+                }),
+                ..Settings::default()
+            }),
+        }],
+        ..config()
+    };
+    let head = format!(
+        "PATCH /tus/{} HTTP/1.1\r\nContent-Length: 20",
+        "0".repeat(32)
+    );
+
+    // The body of a tus part is passed on as it arrives, over the body size limit
+    let mut reader = RequestReader::default();
+    let state = reader.push(format!("{head}\r\n\r\n0123").as_bytes(), &config);
+    assert_eq!(state, ReadState::Streaming(head.clone()));
+    assert!(!reader.is_empty());
+    let mut stream = Cursor::new(b"456789abcdefghijGET / HTTP/1.1\r\n\r\n".to_vec());
+    assert_eq!(
+        reader.read_from(&mut stream, &config).unwrap(),
+        ReadState::BodyPart(b"0123456789abcdefghij".to_vec(), true)
+    );
+    assert_eq!(
+        reader.read_from(&mut stream, &config).unwrap(),
+        ReadState::Complete("GET / HTTP/1.1".to_string(), vec![])
+    );
+
+    // What was received is passed on when the client leaves
+    let mut reader = RequestReader::default();
+    let state = reader.push(format!("{head}\r\n\r\n01").as_bytes(), &config);
+    assert_eq!(state, ReadState::Streaming(head));
+    let mut stream = Cursor::new(Vec::new());
+    assert_eq!(
+        reader.read_from(&mut stream, &config).unwrap(),
+        ReadState::BodyPart(b"01".to_vec(), false)
+    );
+    assert_eq!(
+        reader.read_from(&mut stream, &config).unwrap(),
+        ReadState::Closed
+    );
+}
+
+#[test]
+fn test_request_line_limit() {
+    let uri = "a".repeat(40);
+    assert_eq!(
+        read_all(format!("GET /{uri} HTTP/1.1\r\n\r\n").as_bytes()),
+        ReadState::Rejected(StatusCode::URI_TOO_LONG)
+    );
+}
+
+#[test]
+fn test_header_limits() {
+    assert_eq!(
+        read_all(b"GET / HTTP/1.1\r\nA: 1\r\nB: 2\r\nC: 3\r\n\r\n"),
+        ReadState::Rejected(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE)
+    );
+
+    let long = "x".repeat(70);
+    assert_eq!(
+        read_all(format!("GET / HTTP/1.1\r\nA: {long}\r\n\r\n").as_bytes()),
+        ReadState::Rejected(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE)
+    );
+}
+
+#[test]
+fn test_body_limit() {
+    // Rejected before any of the body is read
+    assert_eq!(
+        read_all(b"POST / HTTP/1.1\r\nContent-Length: 11\r\n\r\n"),
+        ReadState::Rejected(StatusCode::PAYLOAD_TOO_LARGE)
+    );
+    assert_eq!(
+        read_all(b"POST / HTTP/1.1\r\nContent-Length: ten\r\n\r\n"),
+        ReadState::Rejected(StatusCode::BAD_REQUEST)
+    );
+
+    // Repeated lengths must agree
+    assert_eq!(
+        read_all(b"POST / HTTP/1.1\r\nContent-Length: 2\r\nContent-Length: 3\r\n\r\n"),
+        ReadState::Rejected(StatusCode::BAD_REQUEST)
+    );
+    assert!(matches!(
+        read_all(b"POST / HTTP/1.1\r\nContent-Length: 2\r\nContent-Length: 2\r\n\r\nhi"),
+        ReadState::Complete(_, body) if body == b"hi"
+    ));
+}
+
+#[test]
+fn test_chunked_body() {
+    let body = b"4\r\nTest\r\n5\r\n12345\r\n0\r\n\r\n";
+    let mut request = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+    request.extend_from_slice(body);
+    assert!(
+        matches!(read_all(&request), ReadState::Complete(_, b) if b == body),
+        "the raw chunked body should be returned"
+    );
+
+    let mut request = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+    request.extend_from_slice(b"6\r\n123456\r\n5\r\n12345\r\n0\r\n\r\n");
+    assert_eq!(
+        read_all(&request),
+        ReadState::Rejected(StatusCode::PAYLOAD_TOO_LARGE)
+    );
+
+    let mut request = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+    request.extend_from_slice(b"zz\r\n");
+    assert_eq!(
+        read_all(&request),
+        ReadState::Rejected(StatusCode::BAD_REQUEST)
+    );
+    // The data of a chunk must end with CRLF
+    let mut request = b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n".to_vec();
+    request.extend_from_slice(b"4\r\nTestXX0\r\n\r\n");
+    assert_eq!(
+        read_all(&request),
+        ReadState::Rejected(StatusCode::BAD_REQUEST)
+    );
+}
+
+#[test]
+fn test_deadline() {
+    let config = config();
+    let now = Instant::now();
+    let mut reader = RequestReader::new(now);
+    assert_eq!(
+        reader.deadline(&config),
+        Some((now + Duration::from_secs(5), Timeout::Header))
+    );
+
+    let later = now + Duration::from_secs(1);
+    let state = reader.push_at(
+        b"POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\n",
+        &config,
+        later,
+    );
+    assert_eq!(state, ReadState::Pending);
+    assert_eq!(
+        reader.deadline(&config),
+        Some((later + Duration::from_secs(20), Timeout::Body))
+    );
+
+    // The header timeout of the next request starts with its first byte
+    let done = later + Duration::from_secs(1);
+    assert!(matches!(
+        reader.push_at(b"hello", &config, done),
+        ReadState::Complete(..)
+    ));
+    assert_eq!(reader.deadline(&config), None);
+    let next = done + Duration::from_secs(3);
+    reader.push_at(b"GET", &config, next);
+    assert_eq!(
+        reader.deadline(&config),
+        Some((next + Duration::from_secs(5), Timeout::Header))
+    );
+
+    let no_timeouts = ServerConfig {
+        header_timeout: None,
+        ..config
+    };
+    assert_eq!(RequestReader::new(now).deadline(&no_timeouts), None);
+}
@@ -1,9 +1,9 @@
 mod mock;
 
 use http::{StatusCode, Version};
-use localhost::server::content_type;
 use localhost::server::informational::informational;
 use localhost::server::redirections::redirect;
+use localhost::server::{content_type, format_response};
 use mock::*;
 use std::collections::HashMap;
 #[test]
@@ -77,3 +77,21 @@ fn test_content_type() {
         assert_eq!(content_type(input), expected);
     }
 }
+
+#[test]
+fn test_format_response_adds_content_length() {
+    let resp = http::Response::builder()
+        .status(StatusCode::OK)
+        .body(b"hello".to_vec())
+        .unwrap();
+    let formatted = String::from_utf8(format_response(resp)).unwrap();
+    assert!(formatted.contains("content-length: 5\r\n"));
+    assert!(formatted.ends_with("\r\n\r\nhello"));
+
+    let resp = http::Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(vec![])
+        .unwrap();
+    let formatted = String::from_utf8(format_response(resp)).unwrap();
+    assert!(!formatted.contains("content-length"));
+}
@@ -33,7 +33,9 @@ mod test_get_route {
         let config = &mock_server_config();
         let route = get_route(req, config);
 
-        assert!(route.is_err_and(|(code, path)| { code == StatusCode::NOT_FOUND && path == "" }));
+        assert!(
+            route.is_err_and(|(code, path)| { code == StatusCode::NOT_FOUND && path.is_empty() })
+        );
     }
 
     #[test]
@@ -42,7 +44,8 @@ mod test_get_route {
         let config = &mock_server_config();
         let route = get_route(req, config);
 
-        assert!(route
-            .is_err_and(|(code, path)| { code == StatusCode::METHOD_NOT_ALLOWED && path == "" }));
+        assert!(route.is_err_and(|(code, path)| {
+            code == StatusCode::METHOD_NOT_ALLOWED && path.is_empty()
+        }));
     }
 }
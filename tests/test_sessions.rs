@@ -1,7 +1,12 @@
 use crate::mock::{mock_request, mock_server_config};
+use chrono::{TimeZone, Utc};
 use http::header::{COOKIE, SET_COOKIE};
-use http::Method;
-use localhost::server::{cookie_demo, update_cookie, validate_cookie};
+use http::{Method, StatusCode};
+use localhost::server::signing::{decrypt, encrypt, sign, verify};
+use localhost::server::{
+    cookie_demo, get_cookie, get_cookies, get_private_cookie, get_signed_cookie,
+    parse_cookie_header, update_cookie, validate_cookie, SameSite, SetCookie,
+};
 
 mod mock;
 #[test]
@@ -10,19 +15,28 @@ fn test_update_cookie() {
 
     let req = &mock_request(Method::POST, "", None, None);
     let resp = update_cookie(req, conf).unwrap();
-    assert!(resp.headers().contains_key(SET_COOKIE));
+    assert!(resp.headers().get(SET_COOKIE).is_some_and(|header| header
+        .to_str()
+        .is_ok_and(|cookie| cookie.starts_with("grit:lab=cookie.") && cookie.contains("Path=/"))));
 
+    let session = resp.headers()[SET_COOKIE]
+        .to_str()
+        .unwrap()
+        .split(';')
+        .next()
+        .unwrap()
+        .to_string();
     let req = &mock_request(
         Method::POST,
         "",
         None,
-        Some(vec![("cookie", "grit:lab=cookie")]),
+        Some(vec![("cookie", &format!("theme=dark; {session}"))]),
     );
 
     let resp = update_cookie(req, conf).unwrap();
     assert!(resp.headers().get(SET_COOKIE).is_some_and(|header| header
         .to_str()
-        .is_ok_and(|cookie| cookie.contains("path=/;"))));
+        .is_ok_and(|cookie| cookie.starts_with("grit:lab=;") && cookie.contains("Max-Age=0"))));
 }
 
 #[test]
@@ -41,13 +55,155 @@ fn test_cookie_demo() {
 #[test]
 fn test_validate_cookie() {
     let conf = &mock_server_config();
+    let session = sign("grit:lab", "cookie", &conf.cookie_keys).unwrap();
     let req = &mock_request(
         Method::POST,
         "",
         None,
-        Some(vec![(COOKIE.as_str(), "grit:lab=cookie")]),
+        Some(vec![(COOKIE.as_str(), &format!("a=1; grit:lab={session}"))]),
     );
     let resp = validate_cookie(req, conf).unwrap();
 
-    assert!(resp.headers().contains_key(COOKIE));
+    assert!(resp.status().is_success());
+    assert!(!resp.headers().contains_key(COOKIE));
+
+    // Unsigned session cookies are forged
+    let req = &mock_request(
+        Method::POST,
+        "",
+        None,
+        Some(vec![(COOKIE.as_str(), "a=1; grit:lab=cookie")]),
+    );
+    assert!(validate_cookie(req, conf).is_err_and(|code| code == StatusCode::UNAUTHORIZED));
+}
+
+#[test]
+fn test_parse_cookie_header() {
+    let cookies = parse_cookie_header(r#"a=1;  b="two words" ; broken; =nameless; c="#);
+    assert_eq!(
+        cookies,
+        vec![
+            ("a".to_string(), "1".to_string()),
+            ("b".to_string(), "two words".to_string()),
+            ("c".to_string(), "".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn test_get_cookies_from_multiple_headers() {
+    let req = &mock_request(
+        Method::GET,
+        "",
+        None,
+        Some(vec![
+            (COOKIE.as_str(), "a=1; b=2"),
+            (COOKIE.as_str(), "a=3"),
+        ]),
+    );
+
+    assert_eq!(get_cookies(req).len(), 3);
+    assert_eq!(get_cookie(req, "a").as_deref(), Some("1"));
+    assert_eq!(get_cookie(req, "b").as_deref(), Some("2"));
+    assert!(get_cookie(req, "c").is_none());
+}
+
+#[test]
+fn test_set_cookie_builder() {
+    let cookie = SetCookie::new("id", "42")
+        .path("/")
+        .domain("example.com")
+        .max_age(3600)
+        .expires(Utc.with_ymd_and_hms(2030, 1, 2, 3, 4, 5).unwrap())
+        .http_only()
+        .same_site(SameSite::Strict);
+
+    assert_eq!(
+        cookie.to_string(),
+        "id=42; Path=/; Domain=example.com; Max-Age=3600; \
+         Expires=Wed, 02 Jan 2030 03:04:05 GMT; HttpOnly; SameSite=Strict"
+    );
+
+    // SameSite=None implies Secure
+    let cookie = SetCookie::new("id", "42").same_site(SameSite::None);
+    assert_eq!(cookie.to_string(), "id=42; Secure; SameSite=None");
+
+    let cookie = SetCookie::removal("id");
+    assert_eq!(
+        cookie.to_string(),
+        "id=; Max-Age=0; Expires=Thu, 01 Jan 1970 00:00:00 GMT"
+    );
+}
+
+#[test]
+fn test_signed_cookies() {
+    let keys = ["current", "previous"];
+
+    let signed = sign("user", "alice", &keys).unwrap();
+    assert!(signed.starts_with("alice."));
+    assert_eq!(verify("user", &signed, &keys).as_deref(), Some("alice"));
+
+    // Signed with a rotated out key that is still listed
+    let old = sign("user", "bob", &["previous"]).unwrap();
+    assert_eq!(verify("user", &old, &keys).as_deref(), Some("bob"));
+
+    // Unknown key, tampered value and a value moved to another cookie
+    assert!(verify("user", &sign("user", "eve", &["unknown"]).unwrap(), &keys).is_none());
+    assert!(verify("user", &signed.replacen("alice", "admin", 1), &keys).is_none());
+    assert!(verify("admin", &signed, &keys).is_none());
+    assert!(verify("user", "alice", &keys).is_none());
+    assert!(sign("user", "alice", &[]).is_none());
+}
+
+#[test]
+fn test_encrypted_cookies() {
+    let keys = ["current", "previous"];
+
+    let encrypted = encrypt("cart", "item=1", &keys).unwrap();
+    assert!(!encrypted.contains("item"));
+    assert_eq!(
+        decrypt("cart", &encrypted, &keys).as_deref(),
+        Some("item=1")
+    );
+    assert_eq!(
+        decrypt("cart", &encrypted, &["previous", "current"]).as_deref(),
+        Some("item=1")
+    );
+
+    assert!(decrypt("cart", &encrypted, &["previous"]).is_none());
+    assert!(decrypt("other", &encrypted, &keys).is_none());
+    assert!(decrypt("cart", "bm9wZQ", &keys).is_none());
+}
+
+#[test]
+fn test_get_signed_and_private_cookie() {
+    let conf = &mock_server_config();
+    let signed = SetCookie::new("id", "42")
+        .signed(&conf.cookie_keys)
+        .unwrap();
+    let private = SetCookie::new("cart", "apples")
+        .encrypted(&conf.cookie_keys)
+        .unwrap();
+    let header = format!("id=forged; id={}; cart={}", signed.value(), private.value());
+    let req = &mock_request(
+        Method::GET,
+        "",
+        None,
+        Some(vec![(COOKIE.as_str(), &header)]),
+    );
+
+    assert_eq!(
+        get_signed_cookie(req, "id", &conf.cookie_keys).as_deref(),
+        Some("42")
+    );
+    assert_eq!(
+        get_private_cookie(req, "cart", &conf.cookie_keys).as_deref(),
+        Some("apples")
+    );
+    assert!(get_signed_cookie(req, "cart", &conf.cookie_keys).is_none());
+    assert!(get_signed_cookie(req, "id", &["other-key"]).is_none());
+
+    assert!(SetCookie::new("id", "42")
+        .signed(&[])
+        .is_err_and(|code| code == StatusCode::INTERNAL_SERVER_ERROR));
 }
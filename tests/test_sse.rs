@@ -0,0 +1,183 @@
+mod common;
+
+use common::{test_config, TestServer};
+use http::{Method, Request, Response, StatusCode};
+use localhost::server::{event_hub, event_stream, ServerSentEvent};
+use localhost::server_config::route::Route;
+use localhost::server_config::ServerConfig;
+use localhost::type_aliases::Bytes;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn test_event_format() {
+    let event = ServerSentEvent::new("first\nsecond\r\nthird")
+        .event("update")
+        .id("7")
+        .retry(3000);
+    assert_eq!(
+        event.to_string(),
+        "id: 7\nevent: update\nretry: 3000\ndata: first\ndata: second\ndata: third\n\n"
+    );
+
+    // Line breaks can't end a field early
+    assert_eq!(
+        ServerSentEvent::new("").event("a\nb").to_string(),
+        "event: a b\ndata: \n\n"
+    );
+}
+
+#[test]
+fn test_replays_missed_events() {
+    let hub = event_hub();
+    for data in ["one", "two", "three"] {
+        hub.publish("test/replay", ServerSentEvent::new(data));
+    }
+
+    let (id, missed) = hub.subscribe("test/replay", Some("1"), |_| true);
+    let missed = missed.iter().map(|e| e.data.as_str()).collect::<Vec<_>>();
+    assert_eq!(missed, ["two", "three"]);
+    hub.unsubscribe("test/replay", id);
+
+    // Unknown ids have nothing to replay
+    let (id, missed) = hub.subscribe("test/replay", Some("unknown"), |_| true);
+    assert!(missed.is_empty());
+    assert_eq!(hub.subscribers("test/replay"), 1);
+    hub.unsubscribe("test/replay", id);
+
+    // Subscribers that can't be reached are dropped
+    hub.subscribe("test/replay", None, |_| false);
+    assert_eq!(hub.publish("test/replay", ServerSentEvent::new("four")), 0);
+}
+
+fn subscribe(addr: SocketAddr, path: &str, last_event_id: Option<&str>) -> (TcpStream, String) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let last_event_id = last_event_id
+        .map(|id| format!("Last-Event-ID: {id}\r\n"))
+        .unwrap_or_default();
+    stream
+        .write_all(
+            format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n{last_event_id}\r\n").as_bytes(),
+        )
+        .unwrap();
+
+    let mut head = Vec::new();
+    let mut byte = [0];
+    while !head.ends_with(b"\r\n\r\n") {
+        stream.read_exact(&mut byte).unwrap();
+        head.push(byte[0]);
+    }
+    (stream, String::from_utf8(head).unwrap())
+}
+
+/// Reads until `expected` arrived, and returns everything read.
+fn read_until(stream: &mut TcpStream, expected: &str) -> String {
+    let mut received = Vec::new();
+    let mut buffer = [0; 1024];
+    while !String::from_utf8_lossy(&received).contains(expected) {
+        let n = stream.read(&mut buffer).unwrap();
+        assert!(n > 0, "closed: {}", String::from_utf8_lossy(&received));
+        received.extend_from_slice(&buffer[..n]);
+    }
+    String::from_utf8(received).unwrap()
+}
+
+fn wait_for_subscribers(channel: &str, count: usize) {
+    for _ in 0..50 {
+        if event_hub().subscribers(channel) >= count {
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn test_streams_published_events() {
+    let mut config = test_config();
+    config.handler_threads = 2;
+    config.event_stream_heartbeat = Some(Duration::from_millis(200));
+    let server = TestServer::start(config);
+    let addr = server.addr;
+
+    let (mut stream, head) = subscribe(addr, "/api/events", None);
+    assert!(head.starts_with("HTTP/1.1 200"), "{head}");
+    assert!(head.contains("content-type: text/event-stream"));
+    assert!(head.contains("transfer-encoding: chunked"));
+    wait_for_subscribers("demo", 1);
+
+    let client = reqwest::blocking::Client::new();
+    let url = format!("http://{addr}/api/events");
+    let response = client.post(&url).body("hello").send().unwrap();
+    assert_eq!(response.status(), 200);
+
+    // Each event is a chunk of the response
+    let received = read_until(&mut stream, "data: hello\n\n\r\n");
+    let id = received
+        .lines()
+        .find_map(|line| line.strip_prefix("id: "))
+        .unwrap()
+        .to_string();
+
+    // Quiet streams get a heartbeat
+    read_until(&mut stream, ": heartbeat\n\n");
+
+    // A client that reconnects gets what it missed
+    drop(stream);
+    client.post(&url).body("missed").send().unwrap();
+    let (mut stream, _) = subscribe(addr, "/api/events", Some(&id));
+    read_until(&mut stream, "data: missed\n\n");
+
+    // Shutting down ends the stream with the last chunk
+    server.shutdown();
+    let mut rest = String::new();
+    let _ = stream.read_to_string(&mut rest);
+    assert!(rest.ends_with("0\r\n\r\n"), "{rest:?}");
+    server.wait();
+}
+
+fn backlog_events(
+    req: &Request<Bytes>,
+    _conf: &ServerConfig,
+) -> Result<Response<Bytes>, StatusCode> {
+    Ok(event_stream(req, "test/backlog"))
+}
+
+#[test]
+fn test_disconnects_clients_that_fall_behind() {
+    let mut config = test_config();
+    config.routes.push(Route {
+        url_path: "/test/events",
+        methods: vec![Method::GET],
+        handler: Some(backlog_events),
+        websocket: None,
+        settings: None,
+    });
+    let server = TestServer::start(config);
+
+    let (mut stream, head) = subscribe(server.addr, "/test/events", None);
+    assert!(head.starts_with("HTTP/1.1 200"), "{head}");
+    wait_for_subscribers("test/backlog", 1);
+
+    // More than the socket buffers and the backlog hold, while the client doesn't read
+    let data = "a".repeat(100_000);
+    for _ in 0..100 {
+        event_hub().publish("test/backlog", ServerSentEvent::new(data.clone()));
+    }
+    for _ in 0..50 {
+        if event_hub().subscribers("test/backlog") == 0 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(event_hub().subscribers("test/backlog"), 0);
+
+    // The client gets what was sent before it fell behind, then the end of the connection
+    let mut received = Vec::new();
+    let _ = stream.read_to_end(&mut received);
+    assert!(received.len() < 100 * data.len());
+}
@@ -0,0 +1,93 @@
+mod common;
+
+use common::{test_config, TestServer, TEST_PASSWORD, TEST_USER};
+use localhost::log;
+use localhost::log::{LogFileType, LogLevel};
+use localhost::server::status_json;
+use serde_json::Value;
+
+#[test]
+fn test_health_check() {
+    let server = TestServer::start(test_config());
+
+    let url = server.url("/healthz");
+    let response = reqwest::blocking::get(url).unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.text().unwrap(), "ok\n");
+}
+
+#[test]
+fn test_status_page() {
+    let server = TestServer::start(test_config());
+    let addr = server.addr;
+    let client = reqwest::blocking::Client::new();
+
+    // The route asks for the credentials of an admin
+    let status = client
+        .get(format!("http://{addr}/status"))
+        .send()
+        .unwrap()
+        .status();
+    assert_eq!(status.as_u16(), 401);
+
+    let body = client
+        .get(format!("http://{addr}/status?format=json"))
+        .basic_auth(TEST_USER, Some(TEST_PASSWORD))
+        .send()
+        .unwrap()
+        .text()
+        .unwrap();
+    let status: Value = serde_json::from_str(&body).unwrap();
+    let server_status = status["servers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|server| server["ports"][0]["port"] == addr.port())
+        .expect("server not listed");
+    assert_eq!(server_status["host"], "127.0.0.1");
+    assert!(server_status["routes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|route| route["path"] == "/healthz"));
+    // The connection of the request itself is open
+    assert!(status["connections"]["list"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|connection| connection["port"] == addr.port()));
+    assert!(status["requests"]["total"].as_u64().unwrap() >= 1);
+
+    let page = client
+        .get(format!("http://{addr}/status"))
+        .header("accept", "text/html")
+        .basic_auth(TEST_USER, Some(TEST_PASSWORD))
+        .send()
+        .unwrap()
+        .text()
+        .unwrap();
+    assert!(page.contains("<h1>Server status</h1>"));
+
+    drop(server);
+
+    // Stopped servers are not listed
+    assert!(!status_json()["servers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|server| server["ports"][0]["port"] == addr.port()));
+}
+
+#[test]
+fn test_recent_errors() {
+    log!(
+        LogLevel::Error,
+        LogFileType::Server,
+        "status <test> error".to_string()
+    );
+    let status = status_json();
+    let errors = status["recent_errors"].as_array().unwrap();
+    assert!(errors
+        .iter()
+        .any(|error| error["message"] == "status <test> error"));
+}
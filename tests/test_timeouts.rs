@@ -0,0 +1,77 @@
+mod mock;
+
+use localhost::server::{connection_deadline, Deadlines, RequestReader, Timeout};
+use localhost::server_config::ServerConfig;
+use mio::Token;
+use mock::*;
+use std::time::{Duration, Instant};
+
+fn config() -> ServerConfig<'static> {
+    ServerConfig {
+        idle_timeout: Some(Duration::from_secs(3)),
+        header_timeout: Some(Duration::from_secs(5)),
+        body_timeout: Some(Duration::from_secs(20)),
+        keep_alive_timeout: Some(Duration::from_secs(2)),
+        ..mock_server_config()
+    }
+}
+
+#[test]
+fn test_connection_deadline() {
+    let config = config();
+    let now = Instant::now();
+    let secs = Duration::from_secs;
+
+    // The idle timeout is shorter than the header timeout of a new connection
+    let reader = RequestReader::new(now);
+    assert_eq!(
+        connection_deadline(&reader, now, 0, &config),
+        Some((now + secs(3), Timeout::Idle))
+    );
+
+    // A client that keeps trickling bytes runs into the header timeout
+    let later = now + secs(4);
+    assert_eq!(
+        connection_deadline(&reader, later, 0, &config),
+        Some((now + secs(5), Timeout::Header))
+    );
+
+    // Between requests only the keep-alive timeout applies
+    let mut reader = RequestReader::default();
+    reader.push(b"GET / HTTP/1.1\r\n\r\n", &config);
+    assert_eq!(
+        connection_deadline(&reader, later, 1, &config),
+        Some((later + secs(2), Timeout::KeepAlive))
+    );
+
+    let no_timeouts = ServerConfig {
+        idle_timeout: None,
+        header_timeout: None,
+        ..config
+    };
+    assert_eq!(
+        connection_deadline(&RequestReader::new(now), now, 0, &no_timeouts),
+        None
+    );
+}
+
+#[test]
+fn test_deadlines_pop_in_order() {
+    let now = Instant::now();
+    let mut deadlines = Deadlines::new();
+    deadlines.schedule(Token(1), now + Duration::from_secs(3));
+    deadlines.schedule(Token(2), now + Duration::from_secs(1));
+    deadlines.schedule(Token(3), now + Duration::from_secs(2));
+
+    assert_eq!(
+        deadlines.peek(),
+        Some((now + Duration::from_secs(1), Token(2)))
+    );
+    assert_eq!(deadlines.pop_expired(now), None);
+
+    let later = now + Duration::from_secs(2);
+    assert_eq!(deadlines.pop_expired(later).map(|(_, t)| t), Some(Token(2)));
+    assert_eq!(deadlines.pop_expired(later).map(|(_, t)| t), Some(Token(3)));
+    assert_eq!(deadlines.pop_expired(later), None);
+    assert_eq!(deadlines.len(), 1);
+}
@@ -0,0 +1,291 @@
+mod common;
+
+use common::{test_config, TestServer};
+use http::Method;
+use localhost::server::tls_config;
+use localhost::server_config::route::{Route, Settings};
+use localhost::server_config::{server_config, Certificate, ServerConfig, Tls};
+use rustls::pki_types::{CertificateDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+struct TestCert {
+    der: CertificateDer<'static>,
+    cert_path: &'static str,
+    key_path: &'static str,
+}
+
+/// Writes a self-signed certificate for `name` to a temporary directory.
+fn generate_cert(name: &str) -> TestCert {
+    let rcgen::CertifiedKey { cert, key_pair } =
+        rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+
+    let dir = std::env::temp_dir().join(format!("localhost-tls-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let write = |file: String, contents: String| -> &'static str {
+        let path: PathBuf = dir.join(file);
+        std::fs::write(&path, contents).unwrap();
+        Box::leak(path.to_string_lossy().into_owned().into_boxed_str())
+    };
+
+    TestCert {
+        der: cert.der().clone(),
+        cert_path: write(format!("{name}.pem"), cert.pem()),
+        key_path: write(format!("{name}-key.pem"), key_pair.serialize_pem()),
+    }
+}
+
+fn certificate(name: &'static str, cert: &TestCert) -> Certificate<'static> {
+    Certificate {
+        server_names: vec![name],
+        cert_path: cert.cert_path,
+        key_path: cert.key_path,
+    }
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+fn tls_client(
+    addr: SocketAddr,
+    name: &str,
+    roots: &[&TestCert],
+) -> StreamOwned<ClientConnection, TcpStream> {
+    let mut root_store = RootCertStore::empty();
+    for cert in roots {
+        root_store.add(cert.der.clone()).unwrap();
+    }
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let config = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(root_store)
+        .with_no_client_auth();
+
+    let server_name = ServerName::try_from(name.to_string()).unwrap();
+    let conn = ClientConnection::new(Arc::new(config), server_name).unwrap();
+    StreamOwned::new(conn, TcpStream::connect(addr).unwrap())
+}
+
+fn https_get(
+    addr: SocketAddr,
+    name: &str,
+    roots: &[&TestCert],
+) -> (String, CertificateDer<'static>) {
+    let mut tls = tls_client(addr, name, roots);
+    tls.write_all(
+        format!("GET /test.txt HTTP/1.1\r\nHost: {name}\r\nConnection: close\r\n\r\n").as_bytes(),
+    )
+    .unwrap();
+    let mut response = Vec::new();
+    // Fails if the server closes the connection without close_notify
+    tls.read_to_end(&mut response).unwrap();
+
+    let peer_cert = tls.conn.peer_certificates().unwrap()[0].clone();
+    (String::from_utf8_lossy(&response).into_owned(), peer_cert)
+}
+
+fn start_tls(config: ServerConfig<'static>) -> (TestServer, Vec<SocketAddr>) {
+    let server = TestServer::start(config);
+    let addrs = server.local_addrs();
+    (server, addrs)
+}
+
+#[test]
+fn test_serves_https_and_selects_certificate_by_sni() {
+    let localhost = generate_cert("localhost");
+    let other = generate_cert("other.test");
+
+    let mut config = test_config();
+    config.tls = Some(Tls {
+        ports: vec![0],
+        certificates: vec![
+            certificate("localhost", &localhost),
+            certificate("other.test", &other),
+        ],
+        redirect_http: false,
+    });
+    let (_server, addrs) = start_tls(config);
+
+    let (response, cert) = https_get(addrs[0], "localhost", &[&localhost, &other]);
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+    assert_eq!(cert, localhost.der);
+
+    let (response, cert) = https_get(addrs[0], "other.test", &[&localhost, &other]);
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+    assert_eq!(cert, other.der);
+
+    // Plain HTTP on a TLS port fails the handshake
+    let mut plain = TcpStream::connect(addrs[0]).unwrap();
+    plain
+        .write_all(b"GET /test.txt HTTP/1.1\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut response = Vec::new();
+    let _ = plain.read_to_end(&mut response);
+    assert!(!String::from_utf8_lossy(&response).contains("HTTP/1.1 200"));
+}
+
+#[test]
+fn test_finishes_responses_to_slow_readers() {
+    let localhost = generate_cert("localhost");
+    let root = format!("/target/test-tls-{}", std::process::id());
+    let dir = Path::new(".").join(&root[1..]).join("big");
+    fs::create_dir_all(&dir).unwrap();
+    let data = (0..16 << 20).map(|i| (i % 251) as u8).collect::<Vec<u8>>();
+    fs::write(dir.join("data.bin"), &data).unwrap();
+
+    let mut config = test_config();
+    config.workers = 1;
+    config.handler_threads = 0;
+    let (https_port, http_port) = (free_port(), free_port());
+    config.ports = vec![https_port, http_port];
+    config.tls = Some(Tls {
+        ports: vec![https_port],
+        certificates: vec![certificate("localhost", &localhost)],
+        redirect_http: false,
+    });
+    config.routes.push(Route {
+        url_path: "/big",
+        methods: vec![Method::GET],
+        handler: None,
+        websocket: None,
+        settings: Some(Settings {
+            root_path: Some(Box::leak(root.clone().into_boxed_str())),
+            ..Settings::default()
+        }),
+    });
+    let (_server, addrs) = start_tls(config);
+    let request = b"GET /big/data.bin HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n";
+
+    let mut tls = tls_client(addrs[0], "localhost", &[&localhost]);
+    tls.write_all(request).unwrap();
+    tls.flush().unwrap();
+    let mut plain = TcpStream::connect(addrs[1]).unwrap();
+    plain.write_all(request).unwrap();
+    std::thread::sleep(Duration::from_millis(300));
+
+    // The server goes on with other clients while the responses wait
+    let (response, _) = https_get(addrs[0], "localhost", &[&localhost]);
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+
+    for response in [read_all(&mut tls), read_all(&mut plain)] {
+        assert!(response.starts_with(b"HTTP/1.1 200"));
+        assert!(response.ends_with(&data));
+    }
+    let _ = fs::remove_dir_all(Path::new(".").join(&root[1..]));
+}
+
+/// Reads a response in small pieces, like a slow client.
+fn read_all(stream: &mut impl Read) -> Vec<u8> {
+    let mut response = Vec::new();
+    let mut buffer = [0; 4096];
+    loop {
+        match stream.read(&mut buffer).unwrap() {
+            0 => return response,
+            n => response.extend_from_slice(&buffer[..n]),
+        }
+    }
+}
+
+#[test]
+fn test_redirects_http_to_https() {
+    let localhost = generate_cert("localhost");
+    let (http_port, https_port) = (free_port(), free_port());
+
+    let mut config = test_config();
+    config.ports = vec![http_port, https_port];
+    config.tls = Some(Tls {
+        ports: vec![https_port],
+        certificates: vec![certificate("localhost", &localhost)],
+        redirect_http: true,
+    });
+    let (_server, _) = start_tls(config);
+
+    let mut plain = TcpStream::connect(("127.0.0.1", http_port)).unwrap();
+    plain
+        .write_all(
+            format!("GET /test.txt?x=1 HTTP/1.1\r\nHost: localhost:{http_port}\r\nConnection: close\r\n\r\n")
+                .as_bytes(),
+        )
+        .unwrap();
+    let mut response = String::new();
+    plain.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 308"), "{response}");
+    assert!(response.contains(&format!(
+        "location: https://localhost:{https_port}/test.txt?x=1"
+    )));
+
+    let addr = SocketAddr::from(([127, 0, 0, 1], https_port));
+    let (response, _) = https_get(addr, "localhost", &[&localhost]);
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+}
+
+#[test]
+fn test_negotiates_http2_with_alpn() {
+    let localhost = generate_cert("localhost");
+    let mut root_store = RootCertStore::empty();
+    root_store.add(localhost.der.clone()).unwrap();
+
+    for http2 in [true, false] {
+        let mut config = test_config();
+        config.http2 = http2;
+        config.tls = Some(Tls {
+            ports: vec![0],
+            certificates: vec![certificate("localhost", &localhost)],
+            redirect_http: false,
+        });
+        let server_config = tls_config(&config).unwrap().unwrap();
+        let (_server, addrs) = start_tls(config);
+
+        let mut client_config =
+            ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_root_certificates(root_store.clone())
+                .with_no_client_auth();
+        client_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+        let server_name = ServerName::try_from("localhost").unwrap();
+        let mut conn = ClientConnection::new(Arc::new(client_config), server_name).unwrap();
+        let mut sock = TcpStream::connect(addrs[0]).unwrap();
+        while conn.is_handshaking() {
+            conn.complete_io(&mut sock).unwrap();
+        }
+
+        let expected: &[u8] = if http2 { b"h2" } else { b"http/1.1" };
+        assert_eq!(conn.alpn_protocol(), Some(expected));
+        assert_eq!(server_config.alpn_protocols[0], expected);
+    }
+}
+
+#[test]
+fn test_tls_config_errors() {
+    let mut config = server_config().remove(0);
+    assert!(tls_config(&config).unwrap().is_none());
+
+    config.tls = Some(Tls {
+        ports: vec![8443],
+        certificates: vec![Certificate {
+            server_names: vec!["localhost"],
+            cert_path: "./no-such-cert.pem",
+            key_path: "./no-such-key.pem",
+        }],
+        redirect_http: false,
+    });
+    assert!(tls_config(&config)
+        .unwrap_err()
+        .contains("./no-such-cert.pem"));
+
+    config.tls.as_mut().unwrap().certificates.clear();
+    assert!(tls_config(&config).is_err());
+}
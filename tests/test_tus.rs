@@ -0,0 +1,280 @@
+mod common;
+
+use common::{test_config, TestServer};
+use http::Method;
+use localhost::server::parse_upload_metadata;
+use localhost::server_config::route::{Route, Settings, Tus};
+use localhost::server_config::ServerConfig;
+use reqwest::blocking::{Client, RequestBuilder};
+use serde_json::Value;
+use std::fs;
+use std::io::Write;
+use std::net::TcpStream;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Where the unfinished uploads of the `/tus` route are kept.
+const STATE_DIR: &str = "./.tus/tus";
+
+#[test]
+fn test_parse_upload_metadata() {
+    let metadata = parse_upload_metadata("filename d29ybGQudHh0, is_private").unwrap();
+    assert_eq!(
+        metadata,
+        [
+            ("filename".to_string(), b"world.txt".to_vec()),
+            ("is_private".to_string(), vec![]),
+        ]
+    );
+    assert_eq!(parse_upload_metadata("filename not-base64!"), None);
+    assert_eq!(parse_upload_metadata("a YQ==,a Yg=="), None);
+    assert_eq!(parse_upload_metadata("a YQ== Yg=="), None);
+    assert_eq!(parse_upload_metadata("a,,b"), None);
+}
+
+fn tus_config(root: &str, max_size: u64) -> ServerConfig<'static> {
+    let mut config = test_config();
+    config.routes = vec![Route {
+        url_path: "/tus",
+        methods: vec![
+            Method::OPTIONS,
+            Method::POST,
+            Method::HEAD,
+            Method::PATCH,
+            Method::DELETE,
+        ],
+        handler: None,
+        websocket: None,
+        settings: Some(Settings {
+            root_path: Some(Box::leak(root.to_string().into_boxed_str())),
+            tus: Some(Tus {
+                max_size: Some(max_size),
+                expiration: Some(Duration::from_secs(60)),
+            }),
+            ..Settings::default()
+        }),
+    }];
+    config
+}
+
+fn start_server(root: &str) -> TestServer {
+    TestServer::start(tus_config(root, 100))
+}
+
+fn patch(client: &Client, url: &str, offset: u64, part: &'static str) -> RequestBuilder {
+    client
+        .patch(url)
+        .header("tus-resumable", "1.0.0")
+        .header("content-type", "application/offset+octet-stream")
+        .header("upload-offset", offset)
+        .body(part)
+}
+
+fn create(client: &Client, base: &str, length: u64) -> reqwest::blocking::Response {
+    client
+        .post(format!("{base}/tus"))
+        .header("tus-resumable", "1.0.0")
+        .header("upload-length", length)
+        .header("upload-metadata", "filename aGVsbG8udHh0")
+        .send()
+        .unwrap()
+}
+
+#[test]
+fn test_tus_route() {
+    let root = format!("/target/test-tus-{}", std::process::id());
+    let dir = Path::new(".").join(&root[1..]).join("tus");
+    let _ = fs::remove_dir_all(&dir);
+    let client = Client::new();
+
+    let server = start_server(&root);
+    let base = server.url("");
+
+    let response = client
+        .request(reqwest::Method::OPTIONS, format!("{base}/tus"))
+        .send()
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 204);
+    assert_eq!(response.headers()["tus-version"], "1.0.0");
+    assert_eq!(response.headers()["tus-max-size"], "100");
+
+    let response = client
+        .post(format!("{base}/tus"))
+        .header("upload-length", 11)
+        .send()
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 412);
+    assert_eq!(create(&client, &base, 101).status().as_u16(), 413);
+
+    let response = create(&client, &base, 11);
+    assert_eq!(response.status().as_u16(), 201);
+    assert!(response.headers().contains_key("upload-expires"));
+    let location = response.headers()["location"].to_str().unwrap().to_string();
+    assert!(location.starts_with("/tus/"));
+    let url = format!("{base}{location}");
+    let finished = location.rsplit('/').next().unwrap().to_string();
+
+    let response = patch(&client, &url, 0, "hello ").send().unwrap();
+    assert_eq!(response.status().as_u16(), 204);
+    assert_eq!(response.headers()["upload-offset"], "6");
+    let response = patch(&client, &url, 3, "lo world").send().unwrap();
+    assert_eq!(response.status().as_u16(), 409);
+    // The part was not read, so the connection is not kept
+    assert_eq!(response.headers()["connection"], "close");
+    let response = client
+        .patch(&url)
+        .header("tus-resumable", "1.0.0")
+        .header("content-type", "text/plain")
+        .header("upload-offset", 6)
+        .body("world")
+        .send()
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 415);
+    let response = patch(&client, &url, 6, "world and more").send().unwrap();
+    assert_eq!(response.status().as_u16(), 413);
+
+    // Uploads are resumed after a restart
+    drop(server);
+    let server = start_server(&root);
+    let base = server.url("");
+    let url = format!("{base}{location}");
+
+    let response = client
+        .head(&url)
+        .header("tus-resumable", "1.0.0")
+        .send()
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["upload-offset"], "6");
+    assert_eq!(response.headers()["upload-length"], "11");
+    assert_eq!(
+        response.headers()["upload-metadata"],
+        "filename aGVsbG8udHh0"
+    );
+    assert_eq!(response.headers()["cache-control"], "no-store");
+
+    let response = patch(&client, &url, 6, "world").send().unwrap();
+    assert_eq!(response.status().as_u16(), 204);
+    assert_eq!(response.headers()["upload-offset"], "11");
+    assert_eq!(
+        fs::read_to_string(dir.join("hello.txt")).unwrap(),
+        "hello world"
+    );
+
+    // Terminated uploads are gone
+    let location = create(&client, &base, 5).headers()["location"]
+        .to_str()
+        .unwrap()
+        .to_string();
+    let url = format!("{base}{location}");
+    let response = client
+        .delete(&url)
+        .header("tus-resumable", "1.0.0")
+        .send()
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 204);
+    let response = client
+        .head(&url)
+        .header("tus-resumable", "1.0.0")
+        .send()
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 404);
+
+    // Expired uploads are removed
+    let location = create(&client, &base, 5).headers()["location"]
+        .to_str()
+        .unwrap()
+        .to_string();
+    let id = location.rsplit('/').next().unwrap();
+    let info_path = Path::new(STATE_DIR).join(format!("{id}.json"));
+    let mut info: Value = serde_json::from_str(&fs::read_to_string(&info_path).unwrap()).unwrap();
+    info["expires"] = "2000-01-01T00:00:00+00:00".into();
+    fs::write(&info_path, info.to_string()).unwrap();
+    let response = patch(&client, &format!("{base}{location}"), 0, "hello")
+        .send()
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 404);
+    assert!(!info_path.exists());
+    assert!(!Path::new(STATE_DIR).join(format!("{id}.bin")).exists());
+
+    drop(server);
+    let _ = fs::remove_file(Path::new(STATE_DIR).join(format!("{finished}.json")));
+    let _ = fs::remove_dir_all(Path::new(".").join(&root[1..]));
+}
+
+#[test]
+fn test_streams_parts_to_disk() {
+    let root = format!("/target/test-tus-stream-{}", std::process::id());
+    let dir = Path::new(".").join(&root[1..]).join("tus");
+    let _ = fs::remove_dir_all(&dir);
+    let client = Client::new();
+
+    let mut config = tus_config(&root, 1024 * 1024);
+    config.body_size_limit = 1024;
+    let server = TestServer::start(config);
+    let base = server.url("");
+    let data = (0..200_000).map(|i| (i % 251) as u8).collect::<Vec<u8>>();
+
+    let location = create(&client, &base, data.len() as u64).headers()["location"]
+        .to_str()
+        .unwrap()
+        .to_string();
+    let url = format!("{base}{location}");
+    let offset = || {
+        let response = client
+            .head(&url)
+            .header("tus-resumable", "1.0.0")
+            .send()
+            .unwrap();
+        response.headers()["upload-offset"]
+            .to_str()
+            .unwrap()
+            .to_string()
+    };
+
+    // A client that leaves in the middle of a part keeps what it sent
+    let mut stream = TcpStream::connect(server.addr).unwrap();
+    write!(
+        stream,
+        "PATCH {location} HTTP/1.1\r\nHost: localhost\r\nTus-Resumable: 1.0.0\r\n\
+         Content-Type: application/offset+octet-stream\r\nUpload-Offset: 0\r\n\
+         Content-Length: {}\r\n\r\n",
+        data.len()
+    )
+    .unwrap();
+    stream.write_all(&data[..100_000]).unwrap();
+    thread::sleep(Duration::from_millis(300));
+    drop(stream);
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while offset() != "100000" {
+        assert!(Instant::now() < deadline, "offset is {}", offset());
+        thread::sleep(Duration::from_millis(50));
+    }
+    // The upload is busy until the server notices the client left
+    thread::sleep(Duration::from_millis(200));
+
+    // Parts are not limited by the body size limit of the server
+    let response = client
+        .patch(&url)
+        .header("tus-resumable", "1.0.0")
+        .header("content-type", "application/offset+octet-stream")
+        .header("upload-offset", 100_000)
+        .body(data[100_000..].to_vec())
+        .send()
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 204);
+    assert_eq!(response.headers()["upload-offset"], "200000");
+    assert_eq!(fs::read(dir.join("hello.txt")).unwrap(), data);
+
+    // The state of the uploads is not in the directory the route serves
+    let id = location.rsplit('/').next().unwrap();
+    let info_path = Path::new(STATE_DIR).join(format!("{id}.json"));
+    assert!(info_path.exists());
+    assert!(!dir.join(".tus").exists());
+
+    drop(server);
+    let _ = fs::remove_file(info_path);
+    let _ = fs::remove_dir_all(Path::new(".").join(&root[1..]));
+}
@@ -0,0 +1,170 @@
+mod common;
+
+use common::{test_config, TestServer};
+use http::Method;
+use localhost::server::sanitize_filename;
+use localhost::server_config::route::{Route, Settings, Upload};
+use serde_json::Value;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+
+#[test]
+fn test_sanitize_filename() {
+    assert_eq!(
+        sanitize_filename("report.pdf").as_deref(),
+        Some("report.pdf")
+    );
+    assert_eq!(
+        sanitize_filename("../../etc/passwd").as_deref(),
+        Some("passwd")
+    );
+    assert_eq!(
+        sanitize_filename("C:\\Users\\me\\my photo (1).JPG").as_deref(),
+        Some("my_photo__1_.JPG")
+    );
+    assert_eq!(sanitize_filename(".htpasswd").as_deref(), Some("htpasswd"));
+    assert_eq!(sanitize_filename("con.txt").as_deref(), Some("_con.txt"));
+    assert_eq!(
+        sanitize_filename("na\u{ef}ve.txt").as_deref(),
+        Some("na_ve.txt")
+    );
+    assert_eq!(sanitize_filename(".."), None);
+    assert_eq!(sanitize_filename("dir/"), None);
+
+    let long = format!("{}.txt", "a".repeat(300));
+    let sanitized = sanitize_filename(&long).unwrap();
+    assert_eq!(sanitized.len(), 255);
+    assert!(sanitized.ends_with("aaa.txt"));
+}
+
+fn multipart(files: &[(&str, &str)]) -> String {
+    let mut body =
+        "--XyZ\r\nContent-Disposition: form-data; name=\"note\"\r\n\r\nhi\r\n".to_string();
+    for (filename, contents) in files {
+        body.push_str(&format!(
+            "--XyZ\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{filename}\"\r\nContent-Type: text/plain\r\n\r\n{contents}\r\n"
+        ));
+    }
+    body + "--XyZ--\r\n"
+}
+
+/// Names of the files in `dir`.
+fn files_in(dir: &Path) -> Vec<String> {
+    let mut names = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+#[test]
+fn test_upload_route() {
+    let root = format!("/target/test-upload-{}", std::process::id());
+    let dir = Path::new(".").join(&root[1..]).join("up");
+    let _ = fs::remove_dir_all(&dir);
+
+    let mut config = test_config();
+    config.routes = vec![Route {
+        url_path: "/up",
+        methods: vec![Method::GET, Method::POST],
+        handler: None,
+        websocket: None,
+        settings: Some(Settings {
+            root_path: Some(Box::leak(root.clone().into_boxed_str())),
+            upload: Some(Upload {
+                max_file_size: Some(20),
+                max_total_size: None,
+                allowed_extensions: vec!["txt"],
+            }),
+            ..Settings::default()
+        }),
+    }];
+    let server = TestServer::start(config);
+    let url = server.url("/up");
+    let client = reqwest::blocking::Client::new();
+    let post = |body: String| {
+        client
+            .post(&url)
+            .header("content-type", "multipart/form-data; boundary=XyZ")
+            .body(body)
+            .send()
+            .unwrap()
+    };
+
+    let response = post(multipart(&[
+        ("a.txt", "first"),
+        ("../x/evil name.txt", "second"),
+    ]));
+    assert_eq!(response.status().as_u16(), 201);
+    let locations = response
+        .headers()
+        .get_all("location")
+        .iter()
+        .map(|value| value.to_str().unwrap().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(locations, ["/up/a.txt", "/up/evil_name.txt"]);
+    let summary: Value = serde_json::from_str(&response.text().unwrap()).unwrap();
+    assert_eq!(summary["files"][1]["filename"], "../x/evil name.txt");
+    assert_eq!(summary["files"][1]["size"], 6);
+    assert_eq!(fs::read_to_string(dir.join("a.txt")).unwrap(), "first");
+
+    // Existing files are kept
+    let response = post(multipart(&[("a.txt", "third")]));
+    assert_eq!(response.headers()["location"], "/up/a(1).txt");
+    assert_eq!(fs::read_to_string(dir.join("a(1).txt")).unwrap(), "third");
+
+    // Rejected requests store nothing, not even the files before the rejected one
+    let expected = files_in(&dir);
+    let response = post(multipart(&[("b.txt", "ok"), ("c.exe", "no")]));
+    assert_eq!(response.status().as_u16(), 415);
+    let response = post(multipart(&[
+        ("b.txt", "ok"),
+        ("d.txt", "far too large for the limit"),
+    ]));
+    assert_eq!(response.status().as_u16(), 413);
+    let response = post(multipart(&[("b.txt", "ok")])[..40].to_string());
+    assert_eq!(response.status().as_u16(), 400);
+    let response = client.post(&url).body("raw body").send().unwrap();
+    assert_eq!(response.status().as_u16(), 415);
+    assert_eq!(files_in(&dir), expected);
+
+    // Nor do they create the directory of the path
+    let response = client
+        .post(server.url("/up/new"))
+        .header("content-type", "multipart/form-data; boundary=XyZ")
+        .body(multipart(&[("b.txt", "ok"), ("c.exe", "no")]))
+        .send()
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 415);
+    assert!(!dir.join("new").exists());
+    assert_eq!(files_in(&dir), expected);
+
+    // Bodies larger than the limits allow are rejected by their length
+    let large = multipart(&[("e.txt", "ok")]) + &" ".repeat(1 << 20);
+    assert_eq!(post(large).status().as_u16(), 413);
+    assert_eq!(files_in(&dir), expected);
+
+    // Paths can't lead out of the route
+    let mut stream = TcpStream::connect(server.addr).unwrap();
+    let body = multipart(&[("e.txt", "escaped")]);
+    stream
+        .write_all(
+            format!(
+                "POST /up/../escaped HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+                 Content-Type: multipart/form-data; boundary=XyZ\r\nContent-Length: {}\r\n\r\n{body}",
+                body.len()
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 403"), "{response}");
+    assert!(!Path::new(".").join(&root[1..]).join("escaped").exists());
+
+    drop(server);
+    let _ = fs::remove_dir_all(Path::new(".").join(&root[1..]));
+}
@@ -0,0 +1,406 @@
+mod common;
+
+use common::{test_config, TestServer};
+use http::Method;
+use localhost::server::{parse_xml, webdav_methods, DAV_NAMESPACE};
+use localhost::server_config::route::{BasicAuth, Route, Settings};
+use localhost::server_config::AccessRule;
+use reqwest::blocking::{Client, Response};
+use std::fs;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+
+#[test]
+fn test_parse_xml() {
+    let xml = "<?xml version=\"1.0\"?>\n<!-- comment -->\
+        <d:propfind xmlns:d=\"DAV:\" xmlns='urn:x'>\
+        <d:prop><d:getetag/><color>a &lt; b &#x41;<![CDATA[<c>]]></color></d:prop>\
+        </d:propfind>";
+    let root = parse_xml(xml).unwrap();
+    assert!(root.is(DAV_NAMESPACE, "propfind"));
+    let prop = root.dav_child("prop").unwrap();
+    assert!(prop.children[0].is("DAV:", "getetag"));
+    assert!(prop.children[1].is("urn:x", "color"));
+    assert_eq!(prop.children[1].text, "a < b A<c>");
+
+    // Mismatched tags, unknown prefixes and entity declarations are rejected
+    assert_eq!(parse_xml("<a><b></a></b>"), None);
+    assert_eq!(parse_xml("<x:a/>"), None);
+    assert_eq!(
+        parse_xml("<!DOCTYPE a [<!ENTITY e \"e\">]><a>&e;</a>"),
+        None
+    );
+    assert_eq!(parse_xml("<a>"), None);
+}
+
+/// Sends a request with a WebDAV method and headers.
+fn dav(client: &Client, method: &str, url: &str, headers: &[(&str, &str)], body: &str) -> Response {
+    let mut request = client
+        .request(reqwest::Method::from_bytes(method.as_bytes()).unwrap(), url)
+        .body(body.to_string());
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    request.send().unwrap()
+}
+
+const LOCK_BODY: &str = "<?xml version=\"1.0\"?><D:lockinfo xmlns:D=\"DAV:\">\
+    <D:lockscope><D:exclusive/></D:lockscope><D:locktype><D:write/></D:locktype>\
+    <D:owner><D:href>mailto:someone@example.com</D:href></D:owner></D:lockinfo>";
+
+#[test]
+fn test_webdav_route() {
+    let root = format!("/target/test-webdav-{}", std::process::id());
+    let dir = Path::new(".").join(&root[1..]).join("dav");
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("a.txt"), "hello").unwrap();
+
+    let mut config = test_config();
+    config.routes = vec![Route {
+        url_path: "/dav",
+        methods: [
+            vec![Method::GET, Method::OPTIONS, Method::PUT, Method::DELETE],
+            webdav_methods(),
+        ]
+        .concat(),
+        handler: None,
+        websocket: None,
+        settings: Some(Settings {
+            root_path: Some(Box::leak(root.clone().into_boxed_str())),
+            list_directory: true,
+            webdav: true,
+            recursive_delete: true,
+            ..Settings::default()
+        }),
+    }];
+    let server = TestServer::start(config);
+    let base = server.url("/dav");
+    let client = Client::new();
+
+    let response = dav(&client, "OPTIONS", &base, &[], "");
+    assert_eq!(response.headers()["dav"], "1, 2");
+
+    // Collections
+    let response = dav(&client, "MKCOL", &format!("{base}/sub"), &[], "");
+    assert_eq!(response.status().as_u16(), 201);
+    assert!(dir.join("sub").is_dir());
+    let response = dav(&client, "MKCOL", &format!("{base}/sub"), &[], "");
+    assert_eq!(response.status().as_u16(), 405);
+    let response = dav(&client, "MKCOL", &format!("{base}/none/sub"), &[], "");
+    assert_eq!(response.status().as_u16(), 409);
+
+    // Properties
+    let response = dav(&client, "PROPFIND", &base, &[("depth", "1")], "");
+    assert_eq!(response.status().as_u16(), 207);
+    let body = response.text().unwrap();
+    let multistatus = parse_xml(&body).unwrap();
+    assert_eq!(multistatus.children.len(), 3);
+    assert!(body.contains("<D:href>/dav/a.txt</D:href>"));
+    assert!(body.contains("<D:href>/dav/sub/</D:href>"));
+    assert!(body.contains("<D:getcontentlength>5</D:getcontentlength>"));
+    assert!(body.contains("<D:collection/>"));
+
+    let response = dav(
+        &client,
+        "PROPPATCH",
+        &format!("{base}/a.txt"),
+        &[],
+        "<D:propertyupdate xmlns:D=\"DAV:\" xmlns:Z=\"urn:z\">\
+         <D:set><D:prop><Z:color>red</Z:color></D:prop></D:set></D:propertyupdate>",
+    );
+    assert_eq!(response.status().as_u16(), 207);
+    let props = "<D:propfind xmlns:D=\"DAV:\"><D:prop><Z:color xmlns:Z=\"urn:z\"/><D:missing/>\
+         </D:prop></D:propfind>";
+    let body = dav(
+        &client,
+        "PROPFIND",
+        &format!("{base}/a.txt"),
+        &[("depth", "0")],
+        props,
+    )
+    .text()
+    .unwrap();
+    assert!(body.contains("<color xmlns=\"urn:z\">red</color>"));
+    assert!(body.contains("<D:missing></D:missing></D:prop><D:status>HTTP/1.1 404 Not Found"));
+
+    // Copying and moving, with the properties of the resource
+    let copy = dav(
+        &client,
+        "COPY",
+        &format!("{base}/a.txt"),
+        &[("destination", &format!("{base}/sub/b.txt"))],
+        "",
+    );
+    assert_eq!(copy.status().as_u16(), 201);
+    assert_eq!(fs::read_to_string(dir.join("sub/b.txt")).unwrap(), "hello");
+    let response = dav(
+        &client,
+        "COPY",
+        &format!("{base}/a.txt"),
+        &[("destination", "/dav/sub/b.txt"), ("overwrite", "F")],
+        "",
+    );
+    assert_eq!(response.status().as_u16(), 412);
+    let response = dav(
+        &client,
+        "MOVE",
+        &format!("{base}/sub"),
+        &[("destination", "/dav/sub/inside")],
+        "",
+    );
+    assert_eq!(response.status().as_u16(), 403);
+    let response = dav(
+        &client,
+        "MOVE",
+        &format!("{base}/sub"),
+        &[("destination", "/dav/moved")],
+        "",
+    );
+    assert_eq!(response.status().as_u16(), 201);
+    assert!(dir.join("moved/b.txt").is_file() && !dir.join("sub").exists());
+    let body = dav(
+        &client,
+        "PROPFIND",
+        &format!("{base}/moved/b.txt"),
+        &[("depth", "0")],
+        props,
+    )
+    .text()
+    .unwrap();
+    assert!(body.contains(">red</color>"));
+
+    // Locks
+    let url = format!("{base}/a.txt");
+    let response = dav(
+        &client,
+        "LOCK",
+        &url,
+        &[("timeout", "Second-60")],
+        LOCK_BODY,
+    );
+    assert_eq!(response.status().as_u16(), 200);
+    let token = response.headers()["lock-token"]
+        .to_str()
+        .unwrap()
+        .to_string();
+    assert!(token.starts_with("<opaquelocktoken:"));
+    assert!(response
+        .text()
+        .unwrap()
+        .contains("mailto:someone@example.com"));
+
+    let response = dav(&client, "LOCK", &url, &[], LOCK_BODY);
+    assert_eq!(response.status().as_u16(), 423);
+    let response = client.put(&url).body("changed").send().unwrap();
+    assert_eq!(response.status().as_u16(), 423);
+    let response = dav(&client, "DELETE", &url, &[], "");
+    assert_eq!(response.status().as_u16(), 423);
+    let response = dav(&client, "DELETE", &base, &[], "");
+    assert_eq!(response.status().as_u16(), 423);
+
+    let submitted = format!("({token})");
+    let response = client
+        .put(&url)
+        .header("if", &submitted)
+        .body("changed")
+        .send()
+        .unwrap();
+    assert!(response.status().is_success());
+    let response = dav(&client, "LOCK", &url, &[("if", &submitted)], "");
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = dav(&client, "UNLOCK", &url, &[("lock-token", &token)], "");
+    assert_eq!(response.status().as_u16(), 204);
+    let response = dav(&client, "UNLOCK", &url, &[("lock-token", &token)], "");
+    assert_eq!(response.status().as_u16(), 409);
+
+    // Locking an unmapped name creates the file
+    let response = dav(&client, "LOCK", &format!("{base}/new.txt"), &[], LOCK_BODY);
+    assert_eq!(response.status().as_u16(), 201);
+    assert!(dir.join("new.txt").is_file());
+
+    let response = dav(&client, "DELETE", &format!("{base}/moved"), &[], "");
+    assert_eq!(response.status().as_u16(), 204);
+    assert!(!dir.join("moved").exists());
+
+    // Every method decodes the path, so names with spaces are the same file for all of them
+    let url = format!("{base}/My%20Doc.txt");
+    let response = client.put(&url).body("spaced").send().unwrap();
+    assert_eq!(response.status().as_u16(), 201);
+    assert_eq!(
+        fs::read_to_string(dir.join("My Doc.txt")).unwrap(),
+        "spaced"
+    );
+    assert_eq!(client.get(&url).send().unwrap().text().unwrap(), "spaced");
+    let body = dav(&client, "PROPFIND", &base, &[("depth", "1")], "")
+        .text()
+        .unwrap();
+    assert!(body.contains("<D:href>/dav/My%20Doc.txt</D:href>"));
+
+    drop(server);
+    let _ = fs::remove_dir_all(Path::new(".").join(&root[1..]));
+}
+
+#[test]
+fn test_keeps_collections_without_recursive_delete() {
+    let root = format!("/target/test-webdav-keep-{}", std::process::id());
+    let dir = Path::new(".").join(&root[1..]).join("dav");
+    let _ = fs::remove_dir_all(Path::new(".").join(&root[1..]));
+    fs::create_dir_all(dir.join("full")).unwrap();
+    fs::create_dir_all(dir.join("empty")).unwrap();
+    fs::write(dir.join("a.txt"), "hello").unwrap();
+    fs::write(dir.join("full/b.txt"), "kept").unwrap();
+
+    let mut config = test_config();
+    let root_path: &'static str = Box::leak(root.clone().into_boxed_str());
+    config.routes = vec![
+        Route {
+            url_path: "/dav",
+            methods: [vec![Method::DELETE], webdav_methods()].concat(),
+            handler: None,
+            websocket: None,
+            settings: Some(Settings {
+                root_path: Some(root_path),
+                webdav: true,
+                ..Settings::default()
+            }),
+        },
+        Route {
+            url_path: "/plain",
+            methods: vec![Method::PUT],
+            handler: None,
+            websocket: None,
+            settings: Some(Settings {
+                root_path: Some(root_path),
+                create_parent_dirs: true,
+                ..Settings::default()
+            }),
+        },
+    ];
+    let server = TestServer::start(config);
+    let base = server.url("/dav");
+    let client = Client::new();
+
+    // Collections with members are neither deleted nor overwritten
+    let response = dav(&client, "DELETE", &format!("{base}/full"), &[], "");
+    assert_eq!(response.status().as_u16(), 409);
+    let response = dav(
+        &client,
+        "COPY",
+        &format!("{base}/a.txt"),
+        &[("destination", "/dav/full")],
+        "",
+    );
+    assert_eq!(response.status().as_u16(), 409);
+    assert_eq!(fs::read_to_string(dir.join("full/b.txt")).unwrap(), "kept");
+
+    // Empty ones are
+    let response = dav(
+        &client,
+        "COPY",
+        &format!("{base}/a.txt"),
+        &[("destination", "/dav/empty")],
+        "",
+    );
+    assert_eq!(response.status().as_u16(), 204);
+    assert!(dir.join("empty").is_file());
+
+    // Writes don't leave the root of the route
+    let mut stream = TcpStream::connect(server.addr).unwrap();
+    stream
+        .write_all(
+            b"PUT /plain/../escaped/c.txt HTTP/1.1\r\nHost: localhost\r\n\
+              Content-Length: 2\r\nConnection: close\r\n\r\nhi",
+        )
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 403"), "{response}");
+    assert!(!Path::new(".").join(&root[1..]).join("escaped").exists());
+
+    drop(server);
+    let _ = fs::remove_dir_all(Path::new(".").join(&root[1..]));
+}
+
+#[test]
+fn test_destination_checks_its_route() {
+    let root = format!("/target/test-webdav-destination-{}", std::process::id());
+    let root_dir = Path::new(".").join(&root[1..]);
+    let _ = fs::remove_dir_all(&root_dir);
+    for name in ["dav", "private", "internal"] {
+        fs::create_dir_all(root_dir.join(name)).unwrap();
+    }
+    fs::write(root_dir.join("dav/a.txt"), "hello").unwrap();
+
+    let root_path: &'static str = Box::leak(root.clone().into_boxed_str());
+    let route = |url_path, settings| Route {
+        url_path,
+        methods: webdav_methods(),
+        handler: None,
+        websocket: None,
+        settings: Some(Settings {
+            root_path: Some(root_path),
+            webdav: true,
+            ..settings
+        }),
+    };
+    let mut config = test_config();
+    config.routes = vec![
+        route("/dav", Settings::default()),
+        route(
+            "/private",
+            Settings {
+                basic_auth: Some(BasicAuth {
+                    realm: "tests",
+                    htpasswd_path: "/tests/htpasswd",
+                }),
+                ..Settings::default()
+            },
+        ),
+        route(
+            "/internal",
+            Settings {
+                access_rules: vec![AccessRule::Deny("all")],
+                ..Settings::default()
+            },
+        ),
+    ];
+    let server = TestServer::start(config);
+    let source = server.url("/dav/a.txt");
+    let client = Client::new();
+
+    // The destination route lets in the same clients as it would for its own requests
+    let response = dav(
+        &client,
+        "COPY",
+        &source,
+        &[("destination", "/internal/a.txt")],
+        "",
+    );
+    assert_eq!(response.status().as_u16(), 403);
+    assert!(!root_dir.join("internal/a.txt").exists());
+
+    let response = dav(
+        &client,
+        "COPY",
+        &source,
+        &[("destination", "/private/a.txt")],
+        "",
+    );
+    assert_eq!(response.status().as_u16(), 401);
+    assert!(!root_dir.join("private/a.txt").exists());
+
+    let response = client
+        .request(reqwest::Method::from_bytes(b"COPY").unwrap(), &source)
+        .header("destination", "/private/a.txt")
+        .basic_auth("sha", Some("secret"))
+        .send()
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 201);
+    assert!(root_dir.join("private/a.txt").is_file());
+
+    drop(server);
+    let _ = fs::remove_dir_all(root_dir);
+}
@@ -0,0 +1,239 @@
+mod common;
+
+use common::{test_config, TestServer};
+use localhost::server::{
+    accept_key, websockets, WebSocket, WebSocketConnection, WebSocketEvent, WebSocketMessage,
+};
+use std::cell::RefCell;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+thread_local! {
+    static EVENTS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+fn record(_: &WebSocket, event: WebSocketEvent) {
+    let event = match event {
+        WebSocketEvent::Open(request) => format!("open {}", request.uri()),
+        WebSocketEvent::Message(WebSocketMessage::Text(text)) => format!("text {text}"),
+        WebSocketEvent::Message(WebSocketMessage::Binary(data)) => format!("binary {data:?}"),
+        WebSocketEvent::Close(code, reason) => format!("close {code} {reason}"),
+    };
+    EVENTS.with(|events| events.borrow_mut().push(event));
+}
+
+fn take_events() -> Vec<String> {
+    EVENTS.with(|events| events.take())
+}
+
+/// A masked client frame.
+fn client_frame(first: u8, payload: &[u8]) -> Vec<u8> {
+    let mask = [0x37, 0xfa, 0x21, 0x3d];
+    let mut frame = vec![first];
+    match payload.len() {
+        len @ 0..=125 => frame.push(0x80 | len as u8),
+        len => {
+            frame.push(0x80 | 126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(&mask);
+    frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+    frame
+}
+
+fn connection() -> WebSocketConnection {
+    let socket = websockets().register("test/ws", |_| true);
+    WebSocketConnection::new(socket, record, 1024)
+}
+
+#[test]
+fn test_accept_key() {
+    // RFC 6455 section 1.3
+    assert_eq!(
+        accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+        "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+    );
+}
+
+#[test]
+fn test_receives_fragmented_messages_and_pings() {
+    let mut ws = connection();
+
+    // RFC 6455 section 5.7, a masked "Hello"
+    ws.receive(&[
+        0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+    ]);
+    assert_eq!(take_events(), vec!["text Hello"]);
+
+    // A ping between the fragments is answered right away
+    let mut bytes = client_frame(0x01, b"Hel");
+    bytes.extend(client_frame(0x89, b"hi"));
+    bytes.extend(client_frame(0x80, b"lo"));
+    ws.receive(&bytes[..7]);
+    ws.receive(&bytes[7..]);
+    assert_eq!(take_events(), vec!["text Hello"]);
+    assert_eq!(ws.take_output(), vec![0x8a, 0x02, b'h', b'i']);
+
+    ws.receive(&client_frame(0x82, &[1, 2, 3]));
+    assert_eq!(take_events(), vec!["binary [1, 2, 3]"]);
+
+    // The close is answered with the same code
+    ws.receive(&client_frame(0x88, b"\x03\xe8bye"));
+    assert_eq!(take_events(), vec!["close 1000 bye"]);
+    assert_eq!(ws.take_output(), vec![0x88, 0x02, 0x03, 0xe8]);
+    assert!(ws.is_closed());
+}
+
+#[test]
+fn test_protocol_errors_close_the_connection() {
+    let cases: [(Vec<u8>, u16); 4] = [
+        // Unmasked frame
+        (vec![0x81, 0x01, b'a'], 1002),
+        // Continuation without a first fragment
+        (client_frame(0x80, b"a"), 1002),
+        // Invalid UTF-8 in a text message
+        (client_frame(0x81, &[0xff, 0xfe]), 1007),
+        // Larger than the limit
+        (client_frame(0x82, &[0; 2000]), 1009),
+    ];
+
+    for (bytes, code) in cases {
+        let mut ws = connection();
+        ws.receive(&bytes);
+        assert_eq!(take_events(), vec![format!("close {code} ")]);
+        let output = ws.take_output();
+        assert_eq!(output[..2], [0x88, 0x02]);
+        assert_eq!(u16::from_be_bytes([output[2], output[3]]), code);
+        assert!(ws.is_closed());
+    }
+
+    // Dropping a connection that never closed reports an abnormal closure
+    drop(connection());
+    assert_eq!(take_events(), vec!["close 1006 "]);
+}
+
+fn handshake(addr: SocketAddr) -> TcpStream {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream
+        .write_all(
+            b"GET /ws/chat HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+        )
+        .unwrap();
+
+    let mut response = Vec::new();
+    let mut byte = [0];
+    while !response.ends_with(b"\r\n\r\n") {
+        stream.read_exact(&mut byte).unwrap();
+        response.push(byte[0]);
+    }
+    let response = String::from_utf8(response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 101"), "{response}");
+    assert!(response.contains("sec-websocket-accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo="));
+    stream
+}
+
+fn read_frame(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+    let mut head = [0; 2];
+    stream.read_exact(&mut head).unwrap();
+    let len = match head[1] & 0x7f {
+        126 => {
+            let mut len = [0; 2];
+            stream.read_exact(&mut len).unwrap();
+            u16::from_be_bytes(len) as usize
+        }
+        len => len as usize,
+    };
+    let mut payload = vec![0; len];
+    stream.read_exact(&mut payload).unwrap();
+    (head[0], payload)
+}
+
+#[test]
+fn test_chat_broadcasts_to_connected_clients() {
+    for handler_threads in [0, 2] {
+        let mut config = test_config();
+        config.handler_threads = handler_threads;
+        let server = TestServer::start(config);
+        let addr = server.addr;
+
+        let mut first = handshake(addr);
+        let mut second = handshake(addr);
+        for _ in 0..50 {
+            if websockets().count("localhost/ws/chat") >= 2 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        first.write_all(&client_frame(0x81, b"hello")).unwrap();
+        assert_eq!(read_frame(&mut first), (0x81, b"hello".to_vec()));
+        assert_eq!(read_frame(&mut second), (0x81, b"hello".to_vec()));
+
+        first.write_all(&client_frame(0x88, b"\x03\xe8")).unwrap();
+        assert_eq!(read_frame(&mut first), (0x88, b"\x03\xe8".to_vec()));
+        let mut rest = Vec::new();
+        first.read_to_end(&mut rest).unwrap();
+
+        // The other client is told the server is going away
+        server.shutdown();
+        assert_eq!(read_frame(&mut second), (0x88, b"\x03\xe9".to_vec()));
+        server.wait();
+    }
+}
+
+#[test]
+fn test_keeps_messages_for_clients_that_read_late() {
+    let mut config = test_config();
+    config.workers = 1;
+    config.handler_threads = 0;
+    let server = TestServer::start(config);
+
+    let mut first = handshake(server.addr);
+    let mut second = handshake(server.addr);
+    for _ in 0..50 {
+        if websockets().count("localhost/ws/chat") >= 2 {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+
+    // More than the socket buffers hold, while neither client reads
+    let message = vec![b'a'; 60_000];
+    for _ in 0..100 {
+        first.write_all(&client_frame(0x81, &message)).unwrap();
+    }
+
+    // The server goes on with other clients meanwhile
+    let mut other = TcpStream::connect(server.addr).unwrap();
+    other
+        .write_all(b"GET /test.txt HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    other.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{response}");
+
+    for client in [&mut first, &mut second] {
+        for _ in 0..100 {
+            assert_eq!(read_frame(client), (0x81, message.clone()));
+        }
+    }
+}
+
+#[test]
+fn test_requires_a_websocket_handshake() {
+    let server = TestServer::start(test_config());
+
+    let mut stream = TcpStream::connect(server.addr).unwrap();
+    stream
+        .write_all(b"GET /ws/chat HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 426"), "{response}");
+    assert!(response.contains("upgrade: websocket"));
+}